@@ -0,0 +1,125 @@
+use anyhow::Result;
+use arrow::compute::concat_batches;
+use arrow::record_batch::RecordBatch;
+use std::collections::HashMap;
+
+use crate::config::LatchRule;
+use mariposa_core::topic::pattern_matches;
+
+/// Latest rows published on a latched topic
+#[derive(Clone)]
+pub(crate) struct CachedValue {
+    pub(crate) publisher_id: u64,
+    pub(crate) timestamp_ns: i64,
+    pub(crate) batch: RecordBatch,
+}
+
+/// Last-value cache for latched topics, like latched topics in ROS
+pub(crate) struct ValueCache {
+    rules: Vec<LatchRule>,
+    /// Depths of topics the broker publishes on itself
+    owned: HashMap<String, usize>,
+    /// Non-zero depths publishers advertised
+    advertised: HashMap<String, usize>,
+    values: HashMap<String, CachedValue>,
+}
+
+impl ValueCache {
+    pub(crate) fn new(rules: Vec<LatchRule>) -> Self {
+        Self {
+            rules,
+            owned: HashMap::new(),
+            advertised: HashMap::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Latch a topic the broker publishes on itself with the given depth
+    pub(crate) fn own(&mut self, topic: &str, depth: usize) {
+        self.owned.insert(topic.to_string(), depth);
+        self.trim(topic);
+    }
+
+    /// Latch a topic with the depth a publisher advertised, in rows
+    ///
+    /// Zero means the publisher has no preference, and topics the broker owns
+    /// or the config latches keep their depth either way.
+    pub(crate) fn set_depth(&mut self, topic: &str, depth: usize) {
+        if depth == 0 {
+            return;
+        }
+        self.advertised.insert(topic.to_string(), depth);
+        self.trim(topic);
+    }
+
+    /// Number of rows kept for a topic, from the broker, the config or its
+    /// advertisement in that order
+    pub(crate) fn depth(&self, topic: &str) -> usize {
+        if let Some(depth) = self.owned.get(topic) {
+            return *depth;
+        }
+        self.rules
+            .iter()
+            .find(|rule| pattern_matches(&rule.pattern, topic))
+            .map(|rule| rule.depth)
+            .or_else(|| self.advertised.get(topic).copied())
+            .unwrap_or(0)
+    }
+
+    /// Drop the cached rows of a topic beyond its depth
+    fn trim(&mut self, topic: &str) {
+        let depth = self.depth(topic);
+        if depth == 0 {
+            self.values.remove(topic);
+        } else if let Some(value) = self.values.get_mut(topic) {
+            value.batch = last_rows(&value.batch, depth);
+        }
+    }
+
+    /// Record a published batch if its topic is latched
+    pub(crate) fn update(&mut self, topic: &str, publisher_id: u64, timestamp_ns: i64, batch: &RecordBatch) -> Result<()> {
+        let depth = self.depth(topic);
+        if depth == 0 {
+            return Ok(());
+        }
+
+        let batch = match self.values.get(topic) {
+            // Keep appending while the schema stays the same, otherwise start over
+            Some(cached) if cached.batch.schema() == batch.schema() && batch.num_rows() < depth => {
+                concat_batches(&batch.schema(), [&cached.batch, batch])?
+            }
+            _ => batch.clone(),
+        };
+        self.values.insert(
+            topic.to_string(),
+            CachedValue {
+                publisher_id,
+                timestamp_ns,
+                batch: last_rows(&batch, depth),
+            },
+        );
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, topic: &str) -> Option<CachedValue> {
+        self.values.get(topic).cloned()
+    }
+
+    /// Cached values of every topic matching a subscription pattern
+    pub(crate) fn matching(&self, pattern: &str) -> Vec<(String, CachedValue)> {
+        self.values
+            .iter()
+            .filter(|(topic, _)| pattern_matches(pattern, topic))
+            .map(|(topic, value)| (topic.clone(), value.clone()))
+            .collect()
+    }
+}
+
+fn last_rows(batch: &RecordBatch, depth: usize) -> RecordBatch {
+    let rows = batch.num_rows();
+    if rows <= depth {
+        batch.clone()
+    } else {
+        batch.slice(rows - depth, depth)
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::listener::Credentials;
+use mariposa_core::logfile::LogConfig;
+use mariposa_core::topic::pattern_matches;
+
+/// Endpoint the broker listens on when none is given
+pub const DEFAULT_BIND_ADDR: &str = "0.0.0.0:7400";
+
+/// Latch every topic matching `pattern`, keeping its last `depth` rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatchRule {
+    pub pattern: String,
+    pub depth: usize,
+}
+
+/// What to do with a new batch when a client's send queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued batch to make room
+    DropOldest,
+    /// Discard the new batch
+    #[default]
+    DropNewest,
+    /// Make the publisher wait until the client catches up; other
+    /// subscribers still get the batch, but wait with the publisher for the
+    /// ones after it
+    Block,
+    /// Close the connection to the slow client
+    Disconnect,
+}
+
+/// Topics that batches refused under `SchemaMismatch::Quarantine` are moved
+/// under, keeping their original topic as the rest of the name
+pub const QUARANTINE_PREFIX: &str = "_quarantine";
+
+/// What to do with a batch whose schema does not match its topic's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchemaMismatch {
+    /// Drop the batch and tell the publisher
+    #[default]
+    Reject,
+    /// Publish the batch on `_quarantine/<topic>` instead and tell the publisher
+    Quarantine,
+}
+
+/// Topics the broker records itself, see `mariposa_core::logfile`
+///
+/// The recording drops its oldest batches when it falls behind, whatever the
+/// `overflow_policy`.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub patterns: Vec<String>,
+    pub log: LogConfig,
+}
+
+/// Settings for a broker instance
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    /// Endpoint to accept client connections on, a TCP address or a Unix
+    /// socket such as `unix:///run/mariposa.sock`
+    pub bind_addr: String,
+    /// File mode of the Unix socket, which decides who may connect at all.
+    /// `None` leaves it to the umask
+    pub socket_mode: Option<u32>,
+    /// Users allowed to publish, identified by the peer credentials of their
+    /// Unix socket connection. The same users may provide and call services
+    /// and set parameters. When this and `publish_gids` are both empty
+    /// anyone may publish; otherwise TCP clients, whose credentials are
+    /// unknown, may only subscribe
+    pub publish_uids: Vec<u32>,
+    /// Groups allowed to publish, see `publish_uids`
+    pub publish_gids: Vec<u32>,
+    /// Name reported to clients in the handshake
+    pub name: String,
+    /// Number of batches buffered per client before the overflow policy applies
+    pub client_queue_len: usize,
+    /// How to handle a client whose queue is full
+    pub overflow_policy: OverflowPolicy,
+    /// Most writes per second to each client; batches queued in between are
+    /// merged per topic. `None` writes as soon as anything is queued
+    pub max_send_rate: Option<f64>,
+    /// How long a new connection has to send its hello before it is closed
+    pub handshake_timeout: Duration,
+    /// How long to wait for connections to drain when shutting down
+    pub shutdown_timeout: Duration,
+    /// Topics whose latest rows are cached for late subscribers, in addition
+    /// to those that publishers advertise as latched
+    pub latched_topics: Vec<LatchRule>,
+    /// How to handle batches that do not match the schema registered for
+    /// their topic
+    pub schema_mismatch: SchemaMismatch,
+    /// Unicast or multicast address, such as `239.255.0.1:7401`, that batches
+    /// on best-effort topics are sent to over UDP instead of the client
+    /// connections
+    pub udp_target: Option<String>,
+    /// Hops multicast datagrams may travel
+    pub udp_ttl: u32,
+    /// Patterns of the topics sent to `udp_target`; every other topic is
+    /// reliable and delivered over the client connections
+    pub best_effort_topics: Vec<String>,
+    /// TCP address to serve Arrow Flight on, such as `0.0.0.0:7402`
+    pub flight_addr: Option<String>,
+    /// Record topics to Arrow IPC files on the broker's host
+    pub recording: Option<Recording>,
+    /// File the parameter store is loaded from when the broker binds, if it
+    /// exists, and saved to when the broker stops
+    pub parameter_file: Option<PathBuf>,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: DEFAULT_BIND_ADDR.to_string(),
+            socket_mode: Some(0o660),
+            publish_uids: Vec::new(),
+            publish_gids: Vec::new(),
+            name: "mariposa".to_string(),
+            client_queue_len: 1024,
+            overflow_policy: OverflowPolicy::default(),
+            max_send_rate: None,
+            handshake_timeout: Duration::from_secs(5),
+            shutdown_timeout: Duration::from_secs(2),
+            latched_topics: Vec::new(),
+            schema_mismatch: SchemaMismatch::default(),
+            udp_target: None,
+            udp_ttl: 1,
+            best_effort_topics: Vec::new(),
+            flight_addr: None,
+            recording: None,
+            parameter_file: None,
+        }
+    }
+}
+
+impl BrokerConfig {
+    pub(crate) fn is_best_effort(&self, topic: &str) -> bool {
+        self.best_effort_topics.iter().any(|pattern| pattern_matches(pattern, topic))
+    }
+
+    /// Whether a client connected with the given peer credentials may publish
+    pub(crate) fn may_publish(&self, credentials: Option<&Credentials>) -> bool {
+        if self.publish_uids.is_empty() && self.publish_gids.is_empty() {
+            return true;
+        }
+        match credentials {
+            #[cfg(unix)]
+            Some(credentials) => {
+                self.publish_uids.contains(&credentials.uid()) || self.publish_gids.contains(&credentials.gid())
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{anyhow, bail, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufWriter, ReadHalf, WriteHalf};
+use tokio::sync::watch;
+
+use crate::listener::Peer;
+use crate::queue::{coalesce, ClientQueue};
+use crate::server::{BrokerState, ClientHandle, Outgoing};
+use mariposa_core::parameter::ParameterValue;
+use mariposa_core::wire::{
+    read_frame, write_frame, BatchDecoder, BatchEncoder, ControlMessage, FrameKind, Transport, PROTOCOL_VERSION,
+};
+
+type Reader = ReadHalf<Box<dyn Transport>>;
+type Writer = WriteHalf<Box<dyn Transport>>;
+
+/// Run a client connection from handshake to close
+pub(crate) async fn handle(
+    state: Arc<BrokerState>,
+    stream: Box<dyn Transport>,
+    peer: Peer,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    let client_name = match tokio::time::timeout(state.config.handshake_timeout, read_frame(&mut reader)).await {
+        Err(_) => bail!("no handshake within {:?}", state.config.handshake_timeout),
+        Ok(frame) => {
+            let frame = frame?.ok_or_else(|| anyhow!("closed during handshake"))?;
+            match ControlMessage::from_frame(&frame)? {
+                ControlMessage::Hello { client_name, protocol_version } if protocol_version == PROTOCOL_VERSION => {
+                    client_name
+                }
+                ControlMessage::Hello { protocol_version, .. } => {
+                    let reason = format!(
+                        "protocol version {} is not supported, broker speaks {}",
+                        protocol_version, PROTOCOL_VERSION
+                    );
+                    write_frame(&mut writer, &ControlMessage::Reject { reason: reason.clone() }.to_frame()?).await?;
+                    bail!(reason);
+                }
+                other => bail!("expected hello, got {:?}", other),
+            }
+        }
+    };
+
+    let may_publish = state.config.may_publish(peer.credentials.as_ref());
+    let client_id = state.next_client_id();
+    let welcome = ControlMessage::Welcome {
+        client_id,
+        broker_name: state.config.name.clone(),
+    };
+    write_frame(&mut writer, &welcome.to_frame()?).await?;
+
+    let queue = Arc::new(ClientQueue::new(state.config.client_queue_len, state.config.overflow_policy));
+    state.register(
+        client_id,
+        ClientHandle {
+            name: client_name,
+            addr: peer.addr,
+            queue: queue.clone(),
+        },
+    );
+
+    let send_interval = state
+        .config
+        .max_send_rate
+        .filter(|rate| *rate > 0.0)
+        .map(|rate| Duration::from_secs_f64(1.0 / rate));
+    let send_task = tokio::spawn(send_loop(writer, queue.clone(), send_interval));
+    let receive_task = tokio::spawn(receive_loop(
+        state.clone(),
+        client_id,
+        may_publish,
+        reader,
+        queue.clone(),
+        shutdown,
+    ));
+
+    // Unregister even if the receive task failed, so that calls this client
+    // was answering fail now rather than at their timeout
+    let closed = receive_task.await;
+    state.unregister(client_id);
+    let closed = closed?;
+
+    // Say goodbye on broker shutdown; the send task exits once the queue is drained
+    if let Ok(Closed::BrokerShutdown) = closed {
+        queue.push_control(Outgoing::Control(ControlMessage::Goodbye));
+    }
+    queue.close();
+    let sent = send_task.await?;
+
+    match closed? {
+        // Writes may fail once the client has hung up, which is expected
+        Closed::ClientLeft => Ok(()),
+        Closed::BrokerShutdown => sent,
+        Closed::Overflowed => bail!("send queue overflowed"),
+    }
+}
+
+/// Why the receive side of a connection stopped
+enum Closed {
+    ClientLeft,
+    BrokerShutdown,
+    /// The client fell behind under `OverflowPolicy::Disconnect`
+    Overflowed,
+}
+
+/// Read frames from the client until it leaves or the broker shuts down
+async fn receive_loop(
+    state: Arc<BrokerState>,
+    client_id: u64,
+    may_publish: bool,
+    mut reader: Reader,
+    queue: Arc<ClientQueue>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<Closed> {
+    let mut decoder = BatchDecoder::new();
+    loop {
+        let frame = tokio::select! {
+            _ = shutdown.changed() => return Ok(Closed::BrokerShutdown),
+            _ = queue.overflowed() => return Ok(Closed::Overflowed),
+            frame = read_frame(&mut reader) => match frame? {
+                Some(frame) => frame,
+                None => return Ok(Closed::ClientLeft),
+            },
+        };
+
+        match frame.header.kind {
+            FrameKind::Control => match ControlMessage::from_frame(&frame)? {
+                ControlMessage::Subscribe { subscription_id, pattern } => {
+                    state.subscribe(client_id, subscription_id, &pattern).await
+                }
+                ControlMessage::Unsubscribe { subscription_id } => state.unsubscribe(client_id, subscription_id),
+                // Latching decides what publishers' batches are kept, so only they may set it
+                ControlMessage::Advertise { topic, .. } if !may_publish => {
+                    log::warn!("client {} is not authorized to advertise '{}'", client_id, topic)
+                }
+                ControlMessage::Advertise { topic, latch_depth } => {
+                    state.advertise(client_id, &topic, latch_depth as usize)
+                }
+                ControlMessage::ListTopics { request_id } => {
+                    let topics = state.topics()?;
+                    queue.push_control(Outgoing::Control(ControlMessage::TopicList { request_id, topics }));
+                }
+                // Services act on behalf of publishers, so they take the same rights
+                ControlMessage::AdvertiseService { service } if !may_publish => {
+                    log::warn!("client {} is not authorized to provide service '{}'", client_id, service)
+                }
+                ControlMessage::CallService { call_id, .. } if !may_publish => {
+                    queue.push_control(Outgoing::Control(ControlMessage::ServiceError {
+                        call_id,
+                        reason: "client is not authorized to call services".to_string(),
+                    }));
+                }
+                ControlMessage::ServiceResponse { .. } | ControlMessage::ServiceError { .. } if !may_publish => {
+                    log::warn!("client {} is not authorized to answer service calls", client_id)
+                }
+                ControlMessage::AdvertiseService { service } => state.advertise_service(client_id, &service),
+                ControlMessage::UnadvertiseService { service } => state.unadvertise_service(client_id, &service),
+                ControlMessage::CallService {
+                    call_id,
+                    service,
+                    timeout_ms,
+                    request,
+                } => state.call_service(client_id, call_id, &service, timeout_ms, request),
+                answer @ (ControlMessage::ServiceResponse { .. } | ControlMessage::ServiceError { .. }) => {
+                    state.answer_call(client_id, answer)
+                }
+                ControlMessage::GetParameters { request_id, pattern } => {
+                    let reply = parameter_reply(request_id, state.get_parameters(&pattern));
+                    queue.push_control(Outgoing::Control(reply));
+                }
+                ControlMessage::SetParameter {
+                    request_id,
+                    name,
+                    value,
+                } => {
+                    let set = if may_publish {
+                        state.set_parameter(&name, value).await.map(|x| vec![x])
+                    } else {
+                        Err(anyhow!("client is not authorized to set parameters"))
+                    };
+                    queue.push_control(Outgoing::Control(parameter_reply(request_id, set)));
+                }
+                ControlMessage::PauseBatches => queue.set_paused(true),
+                ControlMessage::ResumeBatches => queue.set_paused(false),
+                ControlMessage::Goodbye => return Ok(Closed::ClientLeft),
+                other => log::warn!("client {} sent unexpected {:?}", client_id, other),
+            },
+            FrameKind::Schema | FrameKind::Batch if !may_publish => {
+                state.refuse(client_id, &frame.header.topic, "client is not authorized to publish")
+            }
+            FrameKind::Schema | FrameKind::Batch => {
+                let header = frame.header;
+                if let Some(batch) = decoder.decode(&header, frame.payload)? {
+                    // Publishing waits on full queues under `OverflowPolicy::Block`
+                    tokio::select! {
+                        _ = shutdown.changed() => return Ok(Closed::BrokerShutdown),
+                        _ = state.publish(client_id, &header.topic, header.timestamp_ns, batch) => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Answer a parameter request with the parameters or the reason it failed
+fn parameter_reply(request_id: u32, parameters: Result<Vec<(String, ParameterValue)>>) -> ControlMessage {
+    match parameters {
+        Ok(parameters) => ControlMessage::ParameterList { request_id, parameters },
+        Err(e) => ControlMessage::ParameterError {
+            request_id,
+            reason: format!("{:#}", e),
+        },
+    }
+}
+
+/// Encode queued messages and write them to the client
+///
+/// With a send interval, at most one write happens per interval and batches
+/// queued in the meantime are merged per topic.
+async fn send_loop(writer: Writer, queue: Arc<ClientQueue>, send_interval: Option<Duration>) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let mut encoder = BatchEncoder::new(0);
+
+    while let Some(mut messages) = queue.pop_all().await {
+        // Only a rate limited client has batches pile up between flushes
+        if send_interval.is_some() {
+            messages = coalesce(messages)?;
+        }
+        for message in messages {
+            write_outgoing(&mut writer, &mut encoder, message).await?;
+        }
+        writer.flush().await?;
+        if let Some(interval) = send_interval {
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    writer.shutdown().await?;
+    Ok(())
+}
+
+async fn write_outgoing(
+    writer: &mut BufWriter<Writer>,
+    encoder: &mut BatchEncoder,
+    message: Outgoing,
+) -> Result<()> {
+    match message {
+        Outgoing::Batch {
+            topic,
+            publisher_id,
+            timestamp_ns,
+            batch,
+            subscription_ids,
+        } => {
+            for frame in encoder.encode_from(publisher_id, &topic, &batch, timestamp_ns, &subscription_ids)? {
+                write_frame(writer, &frame).await?;
+            }
+        }
+        Outgoing::Control(message) => write_frame(writer, &message.to_frame()?).await?,
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+// Arrow Flight endpoint of the broker
+//
+// Serves the Arrow Flight gRPC service on its own TCP port so any Flight
+// client can use the broker without the mariposa wire protocol:
+//
+//   ListFlights    one FlightInfo per topic with a registered schema
+//   GetFlightInfo  the same for a single topic, named by a path descriptor
+//   GetSchema      the registered schema of a topic
+//   DoGet          ticket "live:<topic>" streams batches as they are
+//                  published, "cache:<topic>" returns the latched rows
+//   DoPut          publishes batches on the topic of the path descriptor
+//
+// A path descriptor names the topic either as one element or as its levels,
+// which are joined with '/'. The other Flight methods are not implemented.
+//
+// A live stream is a subscriber like any other, except that it drops its
+// oldest batches instead of making publishers wait when the reader falls
+// behind, even if the broker is configured to block.
+
+// Handlers fail with `tonic::Status`, which is large but what gRPC needs
+#[allow(clippy::result_large_err)]
+mod service;
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) use service::serve;
+
+// Constants
+/// Ticket prefix of a stream of live batches
+pub(crate) const LIVE_TICKET: &str = "live:";
+/// Ticket prefix of the latched rows of a topic
+pub(crate) const CACHE_TICKET: &str = "cache:";
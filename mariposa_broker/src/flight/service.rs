@@ -0,0 +1,356 @@
+use anyhow::Result;
+use arrow::array::{new_null_array, ArrayRef};
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::ipc::writer::IpcWriteOptions;
+use arrow::record_batch::RecordBatch;
+use arrow_flight::decode::{DecodedPayload, FlightDataDecoder};
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaAsIpc, SchemaResult, Ticket,
+};
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::config::OverflowPolicy;
+use crate::flight::{CACHE_TICKET, LIVE_TICKET};
+use crate::queue::ClientQueue;
+use crate::server::{now_ns, BrokerState, ClientHandle, Outgoing};
+use mariposa_core::topic::validate_topic;
+
+/// Serve Flight clients on `listener` until the broker shuts down
+pub(crate) async fn serve(
+    listener: TcpListener,
+    state: Arc<BrokerState>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let incoming = stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await.map(|(stream, _)| stream);
+        Some((accepted, listener))
+    });
+    let mut signal = shutdown.clone();
+    Server::builder()
+        .add_service(FlightServiceServer::new(BrokerFlight { state, shutdown }))
+        .serve_with_incoming_shutdown(incoming, async move {
+            let _ = signal.changed().await;
+        })
+        .await?;
+    Ok(())
+}
+
+struct BrokerFlight {
+    state: Arc<BrokerState>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl BrokerFlight {
+    fn registered(&self, topic: &str) -> Result<SchemaRef, Status> {
+        self.state
+            .schema(topic)
+            .ok_or_else(|| Status::not_found(format!("nothing was published on '{}'", topic)))
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for BrokerFlight {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+
+    async fn handshake(
+        &self,
+        _: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("the broker needs no handshake"))
+    }
+
+    async fn list_flights(&self, _: Request<Criteria>) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let infos: Vec<_> = self
+            .state
+            .schemas()
+            .into_iter()
+            .map(|(topic, schema)| flight_info(&topic, &schema))
+            .collect();
+        Ok(Response::new(stream::iter(infos).boxed()))
+    }
+
+    async fn get_flight_info(&self, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        let topic = descriptor_topic(request.get_ref())?;
+        let schema = self.registered(&topic)?;
+        Ok(Response::new(flight_info(&topic, &schema)?))
+    }
+
+    async fn poll_flight_info(&self, _: Request<FlightDescriptor>) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("topics are served as they are published"))
+    }
+
+    async fn get_schema(&self, request: Request<FlightDescriptor>) -> Result<Response<SchemaResult>, Status> {
+        let topic = descriptor_topic(request.get_ref())?;
+        let schema = self.registered(&topic)?;
+        let schema = SchemaAsIpc::new(&schema, &IpcWriteOptions::default())
+            .try_into()
+            .map_err(|e: arrow::error::ArrowError| Status::internal(e.to_string()))?;
+        Ok(Response::new(schema))
+    }
+
+    async fn do_get(&self, request: Request<Ticket>) -> Result<Response<Self::DoGetStream>, Status> {
+        let addr = request
+            .remote_addr()
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "flight".to_string());
+        let ticket = String::from_utf8(request.into_inner().ticket.to_vec())
+            .map_err(|_| Status::invalid_argument("ticket is not UTF-8"))?;
+
+        if let Some(topic) = ticket.strip_prefix(CACHE_TICKET) {
+            let value = self
+                .state
+                .cached(topic)
+                .ok_or_else(|| Status::not_found(format!("no latched value for '{}'", topic)))?;
+            let data = FlightDataEncoderBuilder::new()
+                .build(stream::once(async move { Ok(value.batch) }))
+                .map_err(Status::from);
+            return Ok(Response::new(data.boxed()));
+        }
+
+        let topic = ticket
+            .strip_prefix(LIVE_TICKET)
+            .ok_or_else(|| Status::invalid_argument(format!("unknown ticket '{}'", ticket)))?;
+        validate_topic(topic).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let feed = LiveFeed::start(self.state.clone(), topic, addr, self.shutdown.clone()).await;
+
+        // Announce the schema right away if the topic already has one
+        let mut encoder = FlightDataEncoderBuilder::new();
+        if let Some(schema) = &feed.schema {
+            encoder = encoder.with_schema(schema.clone());
+        }
+        let batches = stream::unfold(feed, |mut feed| async move {
+            let batch = feed.next_batch().await?;
+            Some((Ok(batch), feed))
+        });
+        Ok(Response::new(encoder.build(batches).map_err(Status::from).boxed()))
+    }
+
+    async fn do_put(&self, request: Request<Streaming<FlightData>>) -> Result<Response<Self::DoPutStream>, Status> {
+        if !self.state.config.may_publish(None) {
+            return Err(Status::permission_denied(
+                "Flight clients are not authorized to publish",
+            ));
+        }
+        let feed = PutFeed {
+            client_id: self.state.next_client_id(),
+            state: self.state.clone(),
+            input: FlightDataDecoder::new(request.into_inner().map_err(FlightError::from)),
+            topic: None,
+            shutdown: self.shutdown.clone(),
+        };
+        let results = stream::unfold(Some(feed), |feed| async move {
+            let mut feed = feed?;
+            match feed.publish_next().await {
+                Ok(Some(result)) => Some((Ok(result), Some(feed))),
+                Ok(None) => None,
+                // Stop at the first error
+                Err(status) => Some((Err(status), None)),
+            }
+        });
+        Ok(Response::new(results.boxed()))
+    }
+
+    async fn do_exchange(
+        &self,
+        _: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("use DoPut and DoGet"))
+    }
+
+    async fn do_action(&self, _: Request<Action>) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("the broker has no actions"))
+    }
+
+    async fn list_actions(&self, _: Request<Empty>) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("the broker has no actions"))
+    }
+}
+
+/// Topic named by a path descriptor
+fn descriptor_topic(descriptor: &FlightDescriptor) -> Result<String, Status> {
+    if descriptor.path.is_empty() {
+        return Err(Status::invalid_argument("expected a path descriptor naming a topic"));
+    }
+    let topic = descriptor.path.join("/");
+    validate_topic(&topic).map_err(|e| Status::invalid_argument(e.to_string()))?;
+    Ok(topic)
+}
+
+fn flight_info(topic: &str, schema: &Schema) -> Result<FlightInfo, Status> {
+    // No location means this same server
+    let endpoint = |prefix: &str| FlightEndpoint::new().with_ticket(Ticket::new(format!("{}{}", prefix, topic)));
+    Ok(FlightInfo::new()
+        .try_with_schema(schema)
+        .map_err(|e| Status::internal(e.to_string()))?
+        .with_descriptor(FlightDescriptor::new_path(vec![topic.to_string()]))
+        .with_endpoint(endpoint(CACHE_TICKET))
+        .with_endpoint(endpoint(LIVE_TICKET))
+        // Unknown, a topic has no end
+        .with_total_records(-1)
+        .with_total_bytes(-1)
+        .with_ordered(true))
+}
+
+/// Batches of one topic for a `DoGet` stream, taken from a client queue the
+/// broker routes to like any other subscriber
+struct LiveFeed {
+    state: Arc<BrokerState>,
+    client_id: u64,
+    queue: Arc<ClientQueue>,
+    shutdown: watch::Receiver<bool>,
+    /// Schema of the stream, fixed by the registry or the first batch
+    schema: Option<SchemaRef>,
+    pending: VecDeque<RecordBatch>,
+}
+
+impl LiveFeed {
+    async fn start(state: Arc<BrokerState>, topic: &str, addr: String, shutdown: watch::Receiver<bool>) -> Self {
+        let client_id = state.next_client_id();
+        // A Flight reader must never make publishers wait for it
+        let policy = match state.config.overflow_policy {
+            OverflowPolicy::Block => OverflowPolicy::DropOldest,
+            policy => policy,
+        };
+        let queue = Arc::new(ClientQueue::new(state.config.client_queue_len, policy));
+        state.register(
+            client_id,
+            ClientHandle {
+                name: "flight".to_string(),
+                addr,
+                queue: queue.clone(),
+            },
+        );
+        state.subscribe(client_id, 0, topic).await;
+        Self {
+            schema: state.schema(topic),
+            state,
+            client_id,
+            queue,
+            shutdown,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Wait for the next batch, or `None` once the broker shuts down or the
+    /// queue is closed
+    async fn next_batch(&mut self) -> Option<RecordBatch> {
+        loop {
+            if let Some(batch) = self.pending.pop_front() {
+                return Some(batch);
+            }
+            let messages = tokio::select! {
+                _ = self.shutdown.changed() => return None,
+                messages = self.queue.pop_all() => messages?,
+            };
+            for message in messages {
+                if let Outgoing::Batch { topic, batch, .. } = message {
+                    let schema = self.schema.get_or_insert_with(|| batch.schema()).clone();
+                    match conform(&batch, &schema) {
+                        Ok(batch) => self.pending.push_back(batch),
+                        Err(e) => log::warn!(
+                            "dropped a batch on '{}' for Flight client {}: {:#}",
+                            topic,
+                            self.client_id,
+                            e
+                        ),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for LiveFeed {
+    fn drop(&mut self) {
+        self.state.unregister(self.client_id);
+    }
+}
+
+/// Bring a batch to the schema of a stream
+///
+/// Publishers may leave out nullable columns or add ones the stream was
+/// started without, so missing columns are filled with nulls and extra ones
+/// dropped.
+fn conform(batch: &RecordBatch, schema: &SchemaRef) -> Result<RecordBatch> {
+    if batch.schema() == *schema {
+        return Ok(batch.clone());
+    }
+    let columns: Vec<ArrayRef> = schema
+        .fields()
+        .iter()
+        .map(|field| match batch.column_by_name(field.name()) {
+            Some(column) => column.clone(),
+            None => new_null_array(field.data_type(), batch.num_rows()),
+        })
+        .collect();
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Batches of a `DoPut` stream, published as they are decoded
+struct PutFeed {
+    state: Arc<BrokerState>,
+    client_id: u64,
+    input: FlightDataDecoder,
+    /// Topic named by the descriptor of the first message
+    topic: Option<String>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl PutFeed {
+    /// Read messages up to the next batch and publish it, or return `None`
+    /// once the client has sent everything
+    async fn publish_next(&mut self) -> Result<Option<PutResult>, Status> {
+        loop {
+            let data = tokio::select! {
+                _ = self.shutdown.changed() => return Err(Status::unavailable("broker is shutting down")),
+                data = self.input.next() => match data {
+                    Some(data) => data.map_err(|e| match e {
+                        FlightError::Tonic(status) => status,
+                        e => Status::invalid_argument(e.to_string()),
+                    })?,
+                    None => return Ok(None),
+                },
+            };
+            let topic = match (&self.topic, &data.inner.flight_descriptor) {
+                (Some(topic), _) => topic.clone(),
+                (None, Some(descriptor)) => {
+                    let topic = descriptor_topic(descriptor)?;
+                    self.topic = Some(topic.clone());
+                    topic
+                }
+                (None, None) => return Err(Status::invalid_argument("the first message needs a descriptor")),
+            };
+            let DecodedPayload::RecordBatch(batch) = data.payload else {
+                continue;
+            };
+            self.state
+                .publish(self.client_id, &topic, now_ns(), batch)
+                .await
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            return Ok(Some(PutResult::default()));
+        }
+    }
+}
+
+impl Drop for PutFeed {
+    fn drop(&mut self) {
+        self.state.unregister(self.client_id);
+    }
+}
@@ -0,0 +1,185 @@
+use arrow::array::{Float64Array, Int32Array, StringArray};
+use arrow::record_batch::RecordBatch;
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::{FlightClient, FlightDescriptor, PutResult, Ticket};
+use futures::{stream, StreamExt, TryStreamExt};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tonic::transport::Channel;
+use tonic::Code;
+
+use crate::{Broker, BrokerConfig, LatchRule, OverflowPolicy};
+
+async fn start_flight_broker(config: BrokerConfig) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    let config = BrokerConfig {
+        bind_addr: "127.0.0.1:0".to_string(),
+        flight_addr: Some("127.0.0.1:0".to_string()),
+        ..config
+    };
+    let broker = Broker::bind(config).await.unwrap();
+    let addr = broker.flight_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        broker
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+    });
+    (addr, stop, task)
+}
+
+async fn client(addr: SocketAddr) -> FlightClient {
+    let channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    FlightClient::new(channel)
+}
+
+fn descriptor(topic: &str) -> FlightDescriptor {
+    FlightDescriptor::new_path(vec![topic.to_string()])
+}
+
+fn code(error: FlightError) -> Code {
+    match error {
+        FlightError::Tonic(status) => status.code(),
+        other => panic!("expected a gRPC status, got {}", other),
+    }
+}
+
+async fn do_put(
+    client: &mut FlightClient,
+    topic: &str,
+    batches: &[RecordBatch],
+) -> Result<Vec<PutResult>, FlightError> {
+    let data = FlightDataEncoderBuilder::new()
+        .with_flight_descriptor(Some(descriptor(topic)))
+        .build(stream::iter(batches.to_vec()).map(Ok));
+    client.do_put(data).await?.try_collect().await
+}
+
+async fn next_batch(data: &mut FlightRecordBatchStream) -> Option<RecordBatch> {
+    tokio::time::timeout(Duration::from_secs(2), data.next())
+        .await
+        .unwrap()
+        .map(|x| x.unwrap())
+}
+
+fn pose(x: &[f64]) -> RecordBatch {
+    RecordBatch::try_from_iter(vec![("x", Arc::new(Float64Array::from(x.to_vec())) as _)]).unwrap()
+}
+
+#[tokio::test]
+async fn flight_put_then_list_schema_and_cache() {
+    let (addr, stop, task) = start_flight_broker(BrokerConfig {
+        latched_topics: vec![LatchRule {
+            pattern: "robot/pose".to_string(),
+            depth: 1,
+        }],
+        ..Default::default()
+    })
+    .await;
+    let mut client = client(addr).await;
+
+    let results = do_put(&mut client, "robot/pose", &[pose(&[1.0]), pose(&[2.0, 3.0])])
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 2);
+
+    let flights: Vec<_> = client.list_flights("").await.unwrap().try_collect().await.unwrap();
+    assert_eq!(flights.len(), 1);
+    let info = flights[0].clone();
+    assert_eq!(info.flight_descriptor.clone().unwrap().path, vec!["robot/pose"]);
+    let tickets: Vec<_> = info.endpoint.iter().map(|x| x.ticket.clone().unwrap().ticket).collect();
+    assert_eq!(tickets, vec!["cache:robot/pose", "live:robot/pose"]);
+    assert_eq!(info.try_decode_schema().unwrap(), *pose(&[]).schema());
+
+    let schema = client.get_schema(descriptor("robot/pose")).await.unwrap();
+    assert_eq!(schema, *pose(&[]).schema());
+
+    // The cache keeps the last row only
+    let mut data = client.do_get(Ticket::new("cache:robot/pose")).await.unwrap();
+    assert_eq!(next_batch(&mut data).await.unwrap(), pose(&[3.0]));
+    assert!(next_batch(&mut data).await.is_none());
+
+    let _ = stop.send(());
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn flight_live_stream_follows_publishers() {
+    let (addr, stop, task) = start_flight_broker(BrokerConfig::default()).await;
+    let mut reader = client(addr).await;
+    let mut writer = client(addr).await;
+
+    let mut data = reader.do_get(Ticket::new("live:robot/pose")).await.unwrap();
+    do_put(&mut writer, "robot/pose", &[pose(&[1.0])]).await.unwrap();
+    do_put(&mut writer, "robot/other", &[pose(&[9.0])]).await.unwrap();
+    do_put(&mut writer, "robot/pose", &[pose(&[2.0])]).await.unwrap();
+
+    assert_eq!(next_batch(&mut data).await.unwrap(), pose(&[1.0]));
+    assert_eq!(next_batch(&mut data).await.unwrap(), pose(&[2.0]));
+
+    // Shutting down ends the stream
+    let _ = stop.send(());
+    assert!(next_batch(&mut data).await.is_none());
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn flight_readers_never_block_publishers() {
+    let (addr, stop, task) = start_flight_broker(BrokerConfig {
+        client_queue_len: 2,
+        overflow_policy: OverflowPolicy::Block,
+        ..Default::default()
+    })
+    .await;
+    let mut reader = client(addr).await;
+    let mut writer = client(addr).await;
+
+    // The reader never reads, so its queue fills once the transport buffers do
+    let _data = reader.do_get(Ticket::new("live:robot/pose")).await.unwrap();
+    let batches: Vec<_> = (0..100).map(|i| pose(&[i as f64; 10_000])).collect();
+    let results = tokio::time::timeout(Duration::from_secs(10), do_put(&mut writer, "robot/pose", &batches))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(results.len(), 100);
+
+    let _ = stop.send(());
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn flight_rejects_bad_requests() {
+    let (addr, stop, task) = start_flight_broker(BrokerConfig::default()).await;
+    let mut client = client(addr).await;
+
+    do_put(&mut client, "robot/pose", &[pose(&[1.0])]).await.unwrap();
+    let mismatch = RecordBatch::try_from_iter(vec![
+        ("x", Arc::new(Int32Array::from(vec![1])) as _),
+        ("frame", Arc::new(StringArray::from(vec!["map"])) as _),
+    ])
+    .unwrap();
+    let error = do_put(&mut client, "robot/pose", &[mismatch]).await.unwrap_err();
+    assert_eq!(code(error), Code::InvalidArgument);
+
+    let error = client.do_get(Ticket::new("everything")).await.unwrap_err();
+    assert_eq!(code(error), Code::InvalidArgument);
+    let error = client.do_get(Ticket::new("cache:robot/pose")).await.unwrap_err();
+    assert_eq!(code(error), Code::NotFound);
+
+    let error = client.get_schema(descriptor("robot/missing")).await.unwrap_err();
+    assert_eq!(code(error), Code::NotFound);
+
+    let _ = stop.send(());
+    task.await.unwrap();
+}
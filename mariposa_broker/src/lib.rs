@@ -0,0 +1,22 @@
+// Arrow based pub/sub broker
+
+mod cache;
+pub mod config;
+mod connection;
+mod flight;
+mod listener;
+mod parameters;
+mod queue;
+mod recorder;
+mod registry;
+mod router;
+mod server;
+mod services;
+
+#[cfg(test)]
+mod tests;
+
+pub use config::{
+    BrokerConfig, LatchRule, OverflowPolicy, Recording, SchemaMismatch, DEFAULT_BIND_ADDR, QUARANTINE_PREFIX,
+};
+pub use server::Broker;
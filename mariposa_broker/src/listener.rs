@@ -0,0 +1,133 @@
+use anyhow::{bail, Result};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use std::fs::{self, DirBuilder, Permissions};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use mariposa_core::wire::{Endpoint, Transport};
+
+/// Credentials of a peer process, taken from its Unix socket
+#[cfg(unix)]
+pub(crate) type Credentials = tokio::net::unix::UCred;
+/// Credentials of a peer process, never known without Unix sockets
+#[cfg(not(unix))]
+pub(crate) enum Credentials {}
+
+/// Who is on the other end of a connection
+pub(crate) struct Peer {
+    /// Printable address, for logs
+    pub(crate) addr: String,
+    /// Credentials of the peer process, only known for Unix sockets
+    pub(crate) credentials: Option<Credentials>,
+}
+
+/// Socket the broker accepts clients on
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix { listener: UnixListener, path: PathBuf },
+}
+
+impl Listener {
+    /// Bind an endpoint, giving a Unix socket file the mode `socket_mode`
+    ///
+    /// A socket file left behind by a broker that is gone is replaced.
+    pub(crate) async fn bind(endpoint: &Endpoint, socket_mode: Option<u32>) -> Result<Self> {
+        match endpoint {
+            Endpoint::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => bail!("unix domain sockets are not supported on this platform"),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        bail!("'{}' exists and is not a socket", path.display());
+                    }
+                    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                        bail!("another broker is listening on '{}'", path.display());
+                    }
+                    fs::remove_file(path)?;
+                }
+                let listener = match socket_mode {
+                    Some(mode) => bind_with_mode(path, mode)?,
+                    None => UnixListener::bind(path)?,
+                };
+                Ok(Listener::Unix {
+                    listener,
+                    path: path.clone(),
+                })
+            }
+        }
+    }
+
+    /// Endpoint clients can connect to, with the actual port for TCP
+    pub(crate) fn endpoint(&self) -> Result<Endpoint> {
+        Ok(match self {
+            Listener::Tcp(listener) => Endpoint::Tcp(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Listener::Unix { path, .. } => Endpoint::Unix(path.clone()),
+        })
+    }
+
+    pub(crate) async fn accept(&self) -> Result<(Box<dyn Transport>, Peer)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                let peer = Peer {
+                    addr: addr.to_string(),
+                    credentials: None,
+                };
+                Ok((Box::new(stream), peer))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                let (stream, _) = listener.accept().await?;
+                // SO_PEERCRED, taken when the peer connected
+                let credentials = stream.peer_cred()?;
+                let peer = Peer {
+                    addr: match credentials.pid() {
+                        Some(pid) => format!("pid {} (uid {})", pid, credentials.uid()),
+                        None => format!("uid {}", credentials.uid()),
+                    },
+                    credentials: Some(credentials),
+                };
+                Ok((Box::new(stream), peer))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix { path, .. } = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Bind a Unix socket that nobody can connect to before it has its mode
+///
+/// The socket is created in a private directory next to `path`, given its
+/// mode there and only then moved into place.
+#[cfg(unix)]
+fn bind_with_mode(path: &Path, mode: u32) -> Result<UnixListener> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let staging = path.with_file_name(format!(".{}.{}", name, std::process::id()));
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("socket");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, Permissions::from_mode(mode))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&staging);
+    Ok(bound?)
+}
@@ -1,3 +1,95 @@
-fn main() {
-    println!("Hello, world!");
+use anyhow::{anyhow, Context};
+use mariposa_broker::{Broker, BrokerConfig, LatchRule, Recording, SchemaMismatch};
+use mariposa_core::logfile::LogConfig;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    // Usage: mariposa_broker [bind_addr | unix:///path] [--latch pattern=depth]... [--quarantine]
+    //        [--socket-mode octal] [--publish-uid uid]... [--publish-gid gid]...
+    //        [--udp addr:port] [--best-effort pattern]... [--flight addr:port]
+    //        [--record dir] [--record-topic pattern]... [--split-size bytes] [--split-duration seconds]
+    //        [--parameters file]
+    let mut config = BrokerConfig::default();
+    let mut record_dir = None;
+    let mut record_topics = Vec::new();
+    let mut split_size = None;
+    let mut split_duration = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--latch" {
+            let rule = args.next().ok_or_else(|| anyhow!("--latch needs a pattern=depth argument"))?;
+            config.latched_topics.push(parse_latch(&rule)?);
+        } else if arg == "--quarantine" {
+            config.schema_mismatch = SchemaMismatch::Quarantine;
+        } else if arg == "--socket-mode" {
+            let mode = args.next().ok_or_else(|| anyhow!("--socket-mode needs an octal mode"))?;
+            let mode = u32::from_str_radix(&mode, 8).with_context(|| format!("invalid socket mode '{}'", mode))?;
+            config.socket_mode = Some(mode);
+        } else if arg == "--publish-uid" {
+            config.publish_uids.push(parse_id(args.next(), &arg)?);
+        } else if arg == "--publish-gid" {
+            config.publish_gids.push(parse_id(args.next(), &arg)?);
+        } else if arg == "--udp" {
+            config.udp_target = Some(args.next().ok_or_else(|| anyhow!("--udp needs a target address"))?);
+        } else if arg == "--best-effort" {
+            let pattern = args.next().ok_or_else(|| anyhow!("--best-effort needs a topic pattern"))?;
+            config.best_effort_topics.push(pattern);
+        } else if arg == "--flight" {
+            config.flight_addr = Some(args.next().ok_or_else(|| anyhow!("--flight needs an address"))?);
+        } else if arg == "--record" {
+            record_dir = Some(args.next().ok_or_else(|| anyhow!("--record needs a directory"))?);
+        } else if arg == "--record-topic" {
+            record_topics.push(args.next().ok_or_else(|| anyhow!("--record-topic needs a topic pattern"))?);
+        } else if arg == "--split-size" {
+            let size = args.next().ok_or_else(|| anyhow!("--split-size needs a size in bytes"))?;
+            split_size = Some(size.parse().with_context(|| format!("invalid split size '{}'", size))?);
+        } else if arg == "--split-duration" {
+            let seconds = args.next().ok_or_else(|| anyhow!("--split-duration needs a number of seconds"))?;
+            let seconds: f64 = seconds.parse().with_context(|| format!("invalid split duration '{}'", seconds))?;
+            split_duration = Some(Duration::try_from_secs_f64(seconds)?);
+        } else if arg == "--parameters" {
+            let path = args.next().ok_or_else(|| anyhow!("--parameters needs a file"))?;
+            config.parameter_file = Some(path.into());
+        } else {
+            config.bind_addr = arg;
+        }
+    }
+    if let Some(dir) = record_dir {
+        if record_topics.is_empty() {
+            record_topics.push("#".to_string());
+        }
+        config.recording = Some(Recording {
+            patterns: record_topics,
+            log: LogConfig {
+                max_segment_bytes: split_size,
+                max_segment_duration: split_duration,
+                ..LogConfig::new(dir)
+            },
+        });
+    }
+
+    let broker = Broker::bind(config).await?;
+    log::info!("mariposa broker listening on {}", broker.endpoint()?);
+    if let Some(addr) = broker.flight_addr() {
+        log::info!("serving Arrow Flight on {}", addr);
+    }
+    broker.run().await
+}
+
+fn parse_latch(rule: &str) -> anyhow::Result<LatchRule> {
+    let (pattern, depth) = rule
+        .split_once('=')
+        .ok_or_else(|| anyhow!("latch rule '{}' should look like pattern=depth", rule))?;
+    Ok(LatchRule {
+        pattern: pattern.to_string(),
+        depth: depth.parse().with_context(|| format!("invalid latch depth in '{}'", rule))?,
+    })
+}
+
+fn parse_id(value: Option<String>, flag: &str) -> anyhow::Result<u32> {
+    let value = value.ok_or_else(|| anyhow!("{} needs a numeric id", flag))?;
+    value.parse().with_context(|| format!("invalid id '{}' for {}", value, flag))
 }
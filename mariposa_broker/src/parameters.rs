@@ -0,0 +1,97 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use mariposa_core::parameter::{parameter_name, ParameterValue};
+use mariposa_core::topic::{pattern_matches, validate_pattern};
+
+/// Parameters hosted by the broker, by name without a leading `/`
+#[derive(Default)]
+pub(crate) struct ParameterStore {
+    values: BTreeMap<String, ParameterValue>,
+}
+
+impl ParameterStore {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a store written by `save`
+    ///
+    /// Every line is `name = value` with the value written as a literal.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut store = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("{}:{}: expected 'name = value'", path.display(), index + 1))?;
+            value
+                .parse()
+                .and_then(|value| store.set(name.trim(), value))
+                .with_context(|| format!("{}:{}", path.display(), index + 1))?;
+        }
+        Ok(store)
+    }
+
+    /// Write every parameter to `path`, replacing it only once the whole
+    /// store is written
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let text: String = self
+            .values
+            .iter()
+            .map(|(name, value)| format!("{} = {}\n", name, value))
+            .collect();
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, text).with_context(|| format!("failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, path).with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Set a parameter, returning its normalized name and whether its value
+    /// changed
+    ///
+    /// A parameter keeps the type it was first set with.
+    pub(crate) fn set(&mut self, name: &str, value: ParameterValue) -> Result<(String, bool)> {
+        let name = parameter_name(name)?;
+        if let Some(current) = self.values.get(&name) {
+            if !current.same_type(&value) {
+                bail!(
+                    "parameter '{}' holds a {}, not a {}",
+                    name,
+                    current.type_name(),
+                    value.type_name()
+                );
+            }
+            if *current == value {
+                return Ok((name, false));
+            }
+        }
+        self.values.insert(name.clone(), value);
+        Ok((name, true))
+    }
+
+    /// Parameters whose names match a subscription style pattern, by name
+    pub(crate) fn matching(&self, pattern: &str) -> Result<Vec<(String, ParameterValue)>> {
+        validate_pattern(pattern)?;
+        Ok(self
+            .values
+            .iter()
+            .filter(|(name, _)| pattern_matches(pattern, name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect())
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &ParameterValue)> {
+        self.values.iter()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.values.len()
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use arrow::compute::concat_batches;
+use arrow::record_batch::RecordBatch;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use crate::config::OverflowPolicy;
+use crate::server::Outgoing;
+
+/// Result of offering a batch to a client queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pushed {
+    Queued,
+    /// Queued after discarding the oldest pending batch
+    DroppedOldest,
+    /// The queue was full and the batch was discarded
+    DroppedNewest,
+    /// The queue was full and the client is being disconnected
+    Disconnected,
+    /// The client has already gone
+    Closed,
+}
+
+struct QueueState {
+    messages: VecDeque<Outgoing>,
+    /// Number of batches in `messages`, control messages are not counted
+    batches: usize,
+    closed: bool,
+    overflowed: bool,
+    /// The client asked for batches to be held back
+    paused: bool,
+}
+
+/// Bounded queue of messages waiting to be sent to one client
+///
+/// Only batches count towards the capacity and are subject to the overflow
+/// policy. Control messages are always queued so that session traffic such as
+/// a goodbye is never lost.
+pub(crate) struct ClientQueue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    readable: Notify,
+    writable: Notify,
+    overflow: Notify,
+    /// Batches discarded because the queue was full
+    dropped: AtomicU64,
+}
+
+impl ClientQueue {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                messages: VecDeque::new(),
+                batches: 0,
+                closed: false,
+                overflowed: false,
+                paused: false,
+            }),
+            capacity: capacity.max(1),
+            policy,
+            readable: Notify::new(),
+            writable: Notify::new(),
+            overflow: Notify::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of batches the overflow policy discarded so far
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queue a batch, applying the overflow policy if the queue is full
+    ///
+    /// With `OverflowPolicy::Block` this waits for the sender to make room.
+    pub(crate) async fn push(&self, message: Outgoing) -> Pushed {
+        loop {
+            let writable = self.writable.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Pushed::Closed;
+                }
+                if state.batches < self.capacity {
+                    state.batches += 1;
+                    state.messages.push_back(message);
+                    self.readable.notify_waiters();
+                    return Pushed::Queued;
+                }
+                match self.policy {
+                    OverflowPolicy::DropNewest => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Pushed::DroppedNewest;
+                    }
+                    OverflowPolicy::DropOldest => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        if let Some(index) = state.messages.iter().position(|x| matches!(x, Outgoing::Batch { .. })) {
+                            state.messages.remove(index);
+                        }
+                        state.messages.push_back(message);
+                        self.readable.notify_waiters();
+                        return Pushed::DroppedOldest;
+                    }
+                    OverflowPolicy::Disconnect => {
+                        state.closed = true;
+                        state.overflowed = true;
+                        state.messages.clear();
+                        state.batches = 0;
+                        self.readable.notify_waiters();
+                        self.writable.notify_waiters();
+                        self.overflow.notify_waiters();
+                        return Pushed::Disconnected;
+                    }
+                    OverflowPolicy::Block => {}
+                }
+            }
+            writable.await;
+        }
+    }
+
+    /// Queue a control message regardless of capacity
+    pub(crate) fn push_control(&self, message: Outgoing) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        state.messages.push_back(message);
+        self.readable.notify_waiters();
+        true
+    }
+
+    /// Wait for messages and take everything queued so far
+    ///
+    /// While paused only control messages are taken, and the batches left
+    /// behind keep counting towards the capacity. Returns `None` once the
+    /// queue is closed and has nothing left to take.
+    pub(crate) async fn pop_all(&self) -> Option<Vec<Outgoing>> {
+        loop {
+            let readable = self.readable.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.paused {
+                    let (batches, controls): (VecDeque<_>, VecDeque<_>) =
+                        state.messages.drain(..).partition(|x| matches!(x, Outgoing::Batch { .. }));
+                    state.messages = batches;
+                    if !controls.is_empty() {
+                        return Some(controls.into());
+                    }
+                } else if !state.messages.is_empty() {
+                    state.batches = 0;
+                    let messages = state.messages.drain(..).collect();
+                    self.writable.notify_waiters();
+                    return Some(messages);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    /// Hold back batches, or hand them out again
+    pub(crate) fn set_paused(&self, paused: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = paused;
+        self.readable.notify_waiters();
+    }
+
+    /// Stop accepting messages; whatever is already queued is still sent
+    pub(crate) fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.readable.notify_waiters();
+        self.writable.notify_waiters();
+    }
+
+    /// Wait until the queue overflows under `OverflowPolicy::Disconnect`
+    pub(crate) async fn overflowed(&self) {
+        loop {
+            let overflow = self.overflow.notified();
+            if self.state.lock().unwrap().overflowed {
+                return;
+            }
+            overflow.await;
+        }
+    }
+}
+
+/// Merge queued batches of the same topic, publisher and subscriptions into one
+/// batch each
+///
+/// Batches are concatenated while their schema stays the same and take the
+/// timestamp of the newest one. Order is kept within a topic, and each merged
+/// batch sits where its first part was queued. Batches are never merged across
+/// a control message, so it stays between the batches queued around it.
+pub(crate) fn coalesce(messages: Vec<Outgoing>) -> Result<Vec<Outgoing>> {
+    let mut out: Vec<Outgoing> = Vec::with_capacity(messages.len());
+    let mut parts: HashMap<usize, Vec<RecordBatch>> = HashMap::new();
+    let mut open: HashMap<(String, u64, Vec<u32>), usize> = HashMap::new();
+
+    for message in messages {
+        if let Outgoing::Batch {
+            topic,
+            publisher_id,
+            timestamp_ns,
+            batch,
+            subscription_ids,
+        } = &message
+        {
+            let key = (topic.clone(), *publisher_id, subscription_ids.clone());
+            if let Some(&index) = open.get(&key) {
+                if let Outgoing::Batch {
+                    batch: first,
+                    timestamp_ns: latest,
+                    ..
+                } = &mut out[index]
+                {
+                    if first.schema() == batch.schema() {
+                        *latest = *timestamp_ns;
+                        parts.entry(index).or_default().push(batch.clone());
+                        continue;
+                    }
+                }
+            }
+            open.insert(key, out.len());
+        } else {
+            open.clear();
+        }
+        out.push(message);
+    }
+
+    for (index, rest) in parts {
+        if let Outgoing::Batch { batch, .. } = &mut out[index] {
+            let schema = batch.schema();
+            *batch = concat_batches(&schema, std::iter::once(&*batch).chain(&rest))?;
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::config::OverflowPolicy;
+use crate::queue::ClientQueue;
+use crate::server::{now_ns, BrokerState, ClientHandle, Outgoing};
+use mariposa_core::logfile::{BackgroundLogWriter, LogEntry, LogWriter};
+
+/// Internal subscriber recording topics on the broker's host
+pub(crate) struct Recorder {
+    state: Arc<BrokerState>,
+    client_id: u64,
+    queue: Arc<ClientQueue>,
+    writer: BackgroundLogWriter,
+}
+
+impl Recorder {
+    /// Subscribe to every topic matching `patterns`
+    ///
+    /// The recorder is routed to like any other subscriber, so a topic
+    /// matching several patterns is still recorded once. It drops its oldest
+    /// batches when it falls behind whatever the broker's overflow policy,
+    /// which could otherwise hold up publishers or end the recording.
+    pub(crate) async fn start(state: Arc<BrokerState>, writer: LogWriter, patterns: &[String]) -> Self {
+        let client_id = state.next_client_id();
+        let queue = Arc::new(ClientQueue::new(state.config.client_queue_len, OverflowPolicy::DropOldest));
+        state.register(
+            client_id,
+            ClientHandle {
+                name: "recorder".to_string(),
+                addr: "broker".to_string(),
+                queue: queue.clone(),
+            },
+        );
+        for (subscription_id, pattern) in patterns.iter().enumerate() {
+            state.subscribe(client_id, subscription_id as u32, pattern).await;
+        }
+        Self {
+            writer: BackgroundLogWriter::spawn(writer, state.config.client_queue_len),
+            state,
+            client_id,
+            queue,
+        }
+    }
+
+    /// Record until the broker shuts down and return the segment directories
+    pub(crate) async fn run(self, mut shutdown: watch::Receiver<bool>) -> Result<Vec<PathBuf>> {
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                messages = self.queue.pop_all() => match messages {
+                    Some(messages) => self.write(messages),
+                    None => {
+                        log::warn!("recorder stopped before the broker shut down");
+                        break;
+                    }
+                },
+            }
+        }
+        // Keep what was routed to the recorder before it went away
+        self.state.unregister(self.client_id);
+        self.queue.close();
+        while let Some(messages) = self.queue.pop_all().await {
+            self.write(messages);
+        }
+
+        let writer = self.writer;
+        let dropped = self.queue.dropped() + writer.dropped();
+        if dropped > 0 {
+            log::warn!("recorder dropped {} batches", dropped);
+        }
+        tokio::task::spawn_blocking(move || writer.finish()).await?
+    }
+
+    fn write(&self, messages: Vec<Outgoing>) {
+        let log_time_ns = now_ns();
+        for message in messages {
+            if let Outgoing::Batch {
+                topic,
+                publisher_id,
+                timestamp_ns,
+                batch,
+                ..
+            } = message
+            {
+                self.writer.try_write(LogEntry {
+                    topic,
+                    publisher_id,
+                    publish_time_ns: timestamp_ns,
+                    log_time_ns,
+                    batch,
+                });
+            }
+        }
+    }
+}
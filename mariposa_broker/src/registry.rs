@@ -0,0 +1,118 @@
+use anyhow::{bail, Result};
+use arrow::datatypes::{Field, Schema, SchemaRef};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use mariposa_core::ptars::message_type;
+use mariposa_core::wire::TopicInfo;
+
+/// Type of a topic, fixed by the first batch published on it
+struct Registered {
+    schema: SchemaRef,
+    message_type: Option<String>,
+}
+
+/// Schema and protobuf type of every topic the broker has seen
+#[derive(Default)]
+pub(crate) struct SchemaRegistry {
+    topics: BTreeMap<String, Registered>,
+    /// (client, topic) pairs already told about a mismatch, so a publisher
+    /// hears about each bad topic once rather than for every batch
+    notified: HashSet<(u64, String)>,
+}
+
+impl SchemaRegistry {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the schema of a topic on first use, or check a batch schema
+    /// against it
+    ///
+    /// A compatible schema that adds nullable columns widens the registered
+    /// one, so later publishers may send either version.
+    pub(crate) fn check(&mut self, topic: &str, schema: &Schema) -> Result<()> {
+        let message_type = message_type(schema).map(|x| x.to_string());
+        let Some(registered) = self.topics.get_mut(topic) else {
+            self.topics.insert(
+                topic.to_string(),
+                Registered {
+                    schema: Arc::new(schema.clone()),
+                    message_type,
+                },
+            );
+            return Ok(());
+        };
+
+        if let (Some(expected), Some(actual)) = (&registered.message_type, &message_type) {
+            if expected != actual {
+                bail!("topic '{}' carries {}, not {}", topic, expected, actual);
+            }
+        }
+        let added = compatible(&registered.schema, schema)?;
+        if !added.is_empty() {
+            let mut fields: Vec<Field> = registered.schema.fields().iter().map(|x| x.as_ref().clone()).collect();
+            fields.extend(added);
+            registered.schema = Arc::new(Schema::new_with_metadata(fields, registered.schema.metadata().clone()));
+        }
+        if registered.message_type.is_none() {
+            registered.message_type = message_type;
+        }
+        Ok(())
+    }
+
+    /// Whether `client_id` still has to be told that `topic` was refused
+    pub(crate) fn should_notify(&mut self, client_id: u64, topic: &str) -> bool {
+        self.notified.insert((client_id, topic.to_string()))
+    }
+
+    pub(crate) fn remove_client(&mut self, client_id: u64) {
+        self.notified.retain(|(id, _)| *id != client_id);
+    }
+
+    pub(crate) fn schema(&self, topic: &str) -> Option<SchemaRef> {
+        self.topics.get(topic).map(|x| x.schema.clone())
+    }
+
+    pub(crate) fn schemas(&self) -> Vec<(String, SchemaRef)> {
+        self.topics.iter().map(|(topic, x)| (topic.clone(), x.schema.clone())).collect()
+    }
+
+    pub(crate) fn topics(&self) -> Result<Vec<TopicInfo>> {
+        self.topics
+            .iter()
+            .map(|(topic, x)| TopicInfo::new(topic, x.message_type.as_deref(), &x.schema))
+            .collect()
+    }
+}
+
+/// Check a schema against a registered one and return its extra columns
+///
+/// Columns in both must have the same type, and a column only one side has
+/// must be nullable.
+fn compatible(registered: &Schema, schema: &Schema) -> Result<Vec<Field>> {
+    for field in registered.fields() {
+        match schema.field_with_name(field.name()) {
+            Ok(other) if !other.data_type().equals_datatype(field.data_type()) => bail!(
+                "column '{}' is {}, expected {}",
+                field.name(),
+                other.data_type(),
+                field.data_type()
+            ),
+            Ok(_) => {}
+            Err(_) if field.is_nullable() => {}
+            Err(_) => bail!("missing non-nullable column '{}'", field.name()),
+        }
+    }
+
+    let mut added = Vec::new();
+    for field in schema.fields() {
+        if registered.field_with_name(field.name()).is_err() {
+            if !field.is_nullable() {
+                bail!("added column '{}' must be nullable", field.name());
+            }
+            added.push(field.as_ref().clone());
+        }
+    }
+    Ok(added)
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use mariposa_core::topic::TopicMatcher;
+
+/// Subscriptions of every connected client
+pub(crate) struct Router {
+    matcher: TopicMatcher<(u64, u32)>,
+    patterns: HashMap<u64, HashMap<u32, String>>,
+}
+
+impl Router {
+    pub(crate) fn new() -> Self {
+        Self {
+            matcher: TopicMatcher::new(),
+            patterns: HashMap::new(),
+        }
+    }
+
+    /// Register (or replace) a client's subscription
+    pub(crate) fn subscribe(&mut self, client_id: u64, subscription_id: u32, pattern: &str) -> Result<()> {
+        self.matcher.insert(pattern, (client_id, subscription_id))?;
+        let previous = self
+            .patterns
+            .entry(client_id)
+            .or_default()
+            .insert(subscription_id, pattern.to_string());
+        if let Some(previous) = previous.filter(|x| x != pattern) {
+            self.matcher.remove(&previous, &(client_id, subscription_id));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unsubscribe(&mut self, client_id: u64, subscription_id: u32) {
+        let pattern = self
+            .patterns
+            .get_mut(&client_id)
+            .and_then(|subscriptions| subscriptions.remove(&subscription_id));
+        if let Some(pattern) = pattern {
+            self.matcher.remove(&pattern, &(client_id, subscription_id));
+        }
+    }
+
+    pub(crate) fn remove_client(&mut self, client_id: u64) {
+        for (subscription_id, pattern) in self.patterns.remove(&client_id).unwrap_or_default() {
+            self.matcher.remove(&pattern, &(client_id, subscription_id));
+        }
+    }
+
+    /// Clients with at least one subscription matching `topic`, each listed
+    /// once with the ids of every subscription that matched
+    pub(crate) fn route(&self, topic: &str) -> Vec<(u64, Vec<u32>)> {
+        let mut targets: HashMap<u64, Vec<u32>> = HashMap::new();
+        for (client_id, subscription_id) in self.matcher.matches(topic) {
+            targets.entry(client_id).or_default().push(subscription_id);
+        }
+        let mut targets: Vec<(u64, Vec<u32>)> = targets.into_iter().collect();
+        for (_, subscription_ids) in &mut targets {
+            subscription_ids.sort_unstable();
+        }
+        targets.sort_unstable_by_key(|(client_id, _)| *client_id);
+        targets
+    }
+}
@@ -0,0 +1,540 @@
+use anyhow::{bail, Result};
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::cache::{CachedValue, ValueCache};
+use crate::config::{BrokerConfig, SchemaMismatch, QUARANTINE_PREFIX};
+use crate::connection;
+use crate::flight;
+use crate::recorder::Recorder;
+use crate::listener::Listener;
+use crate::parameters::ParameterStore;
+use crate::queue::{ClientQueue, Pushed};
+use crate::registry::SchemaRegistry;
+use crate::router::Router;
+use crate::services::{FailedCall, ServiceTable};
+use mariposa_core::logfile::LogWriter;
+use mariposa_core::parameter::{parameter_topic, ParameterValue, PARAMETER_PREFIX};
+use mariposa_core::topic::{validate_pattern, validate_topic};
+use mariposa_core::udp::UdpPublisher;
+use mariposa_core::wire::{ControlMessage, Endpoint, TopicInfo};
+
+/// Message queued for delivery to a single client
+pub(crate) enum Outgoing {
+    Batch {
+        topic: String,
+        publisher_id: u64,
+        timestamp_ns: i64,
+        batch: RecordBatch,
+        /// Subscriptions of the receiving client that matched the topic
+        subscription_ids: Vec<u32>,
+    },
+    Control(ControlMessage),
+}
+
+/// A connected client as seen by the rest of the broker
+pub(crate) struct ClientHandle {
+    pub(crate) name: String,
+    pub(crate) addr: String,
+    pub(crate) queue: Arc<ClientQueue>,
+}
+
+/// State shared by every connection task
+pub(crate) struct BrokerState {
+    pub(crate) config: BrokerConfig,
+    clients: Mutex<HashMap<u64, ClientHandle>>,
+    router: RwLock<Router>,
+    cache: Mutex<ValueCache>,
+    registry: Mutex<SchemaRegistry>,
+    services: Mutex<ServiceTable>,
+    parameters: Mutex<ParameterStore>,
+    /// Sender for best-effort topics, if a UDP target is configured
+    udp: Option<UdpPublisher>,
+    next_client_id: AtomicU64,
+}
+
+impl BrokerState {
+    fn new(config: BrokerConfig, udp: Option<UdpPublisher>, parameters: ParameterStore) -> Result<Self> {
+        // Latch the loaded parameters so that the first watchers get them
+        let mut cache = ValueCache::new(config.latched_topics.clone());
+        for (name, value) in parameters.iter() {
+            let topic = parameter_topic(name);
+            cache.own(&topic, 1);
+            cache.update(&topic, 0, now_ns(), &value.to_batch(name)?)?;
+        }
+        Ok(Self {
+            udp,
+            cache: Mutex::new(cache),
+            config,
+            clients: Mutex::new(HashMap::new()),
+            router: RwLock::new(Router::new()),
+            registry: Mutex::new(SchemaRegistry::new()),
+            services: Mutex::new(ServiceTable::new()),
+            parameters: Mutex::new(parameters),
+            // Publisher id 0 is reserved for the broker itself
+            next_client_id: AtomicU64::new(1),
+        })
+    }
+
+    pub(crate) fn next_client_id(&self) -> u64 {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn register(&self, client_id: u64, handle: ClientHandle) {
+        log::info!("client {} '{}' connected from {}", client_id, handle.name, handle.addr);
+        self.clients.lock().unwrap().insert(client_id, handle);
+    }
+
+    pub(crate) fn unregister(&self, client_id: u64) {
+        self.router.write().unwrap().remove_client(client_id);
+        self.registry.lock().unwrap().remove_client(client_id);
+        let failed = self.services.lock().unwrap().remove_client(client_id);
+        for call in failed {
+            self.fail_call(call);
+        }
+        if let Some(handle) = self.clients.lock().unwrap().remove(&client_id) {
+            log::info!("client {} '{}' disconnected", client_id, handle.name);
+        }
+    }
+
+    pub(crate) async fn subscribe(&self, client_id: u64, subscription_id: u32, pattern: &str) {
+        if let Err(e) = self.router.write().unwrap().subscribe(client_id, subscription_id, pattern) {
+            log::warn!("client {} sent an invalid subscription: {}", client_id, e);
+            return;
+        }
+        log::debug!("client {} subscribed to '{}' as {}", client_id, pattern, subscription_id);
+
+        // Hand latched values to the new subscriber straight away
+        let cached = self.cache.lock().unwrap().matching(pattern);
+        let Some((name, queue)) = self.client_queue(client_id) else {
+            return;
+        };
+        for (topic, value) in cached {
+            let message = Outgoing::Batch {
+                topic: topic.clone(),
+                publisher_id: value.publisher_id,
+                timestamp_ns: value.timestamp_ns,
+                batch: value.batch,
+                subscription_ids: vec![subscription_id],
+            };
+            report_push(client_id, &name, &topic, queue.push(message).await);
+        }
+    }
+
+    pub(crate) fn unsubscribe(&self, client_id: u64, subscription_id: u32) {
+        self.router.write().unwrap().unsubscribe(client_id, subscription_id);
+    }
+
+    pub(crate) fn advertise(&self, client_id: u64, topic: &str, latch_depth: usize) {
+        if let Err(e) = validate_topic(topic) {
+            log::warn!("client {} advertised an invalid topic: {}", client_id, e);
+            return;
+        }
+        log::debug!("client {} advertised '{}' (latch depth {})", client_id, topic, latch_depth);
+        self.cache.lock().unwrap().set_depth(topic, latch_depth);
+    }
+
+    pub(crate) fn advertise_service(&self, client_id: u64, service: &str) {
+        if let Err(e) = validate_topic(service) {
+            log::warn!("client {} advertised an invalid service: {}", client_id, e);
+            return;
+        }
+        match self.services.lock().unwrap().advertise(client_id, service) {
+            Ok(()) => log::debug!("client {} provides service '{}'", client_id, service),
+            Err(e) => log::warn!("refused service advertised by client {}: {}", client_id, e),
+        }
+    }
+
+    pub(crate) fn unadvertise_service(&self, client_id: u64, service: &str) {
+        self.services.lock().unwrap().unadvertise(client_id, service);
+    }
+
+    /// Forward a call to the provider of its service, failing it straight
+    /// away if there is none and later if the provider does not answer in time
+    pub(crate) fn call_service(
+        self: &Arc<Self>,
+        caller: u64,
+        call_id: u32,
+        service: &str,
+        timeout_ms: u32,
+        request: Vec<u8>,
+    ) {
+        let started = self.services.lock().unwrap().start(caller, call_id, service);
+        let Some((provider, id)) = started else {
+            self.fail_call(FailedCall {
+                caller,
+                call_id,
+                reason: format!("no provider for service '{}'", service),
+            });
+            return;
+        };
+        if let Some((_, queue)) = self.client_queue(provider) {
+            queue.push_control(Outgoing::Control(ControlMessage::CallService {
+                call_id: id,
+                service: service.to_string(),
+                timeout_ms,
+                request,
+            }));
+        }
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(timeout_ms as u64)).await;
+            let expired = state.services.lock().unwrap().expire(id);
+            if let Some(call) = expired {
+                state.fail_call(call);
+            }
+        });
+    }
+
+    /// Hand a provider's `ServiceResponse` or `ServiceError` back to the
+    /// caller under the caller's own call id
+    pub(crate) fn answer_call(&self, provider: u64, mut answer: ControlMessage) {
+        let call_id = match &mut answer {
+            ControlMessage::ServiceResponse { call_id, .. } | ControlMessage::ServiceError { call_id, .. } => call_id,
+            _ => return,
+        };
+        let Some((caller, caller_call_id)) = self.services.lock().unwrap().finish(provider, *call_id) else {
+            log::debug!("client {} answered call {} after it ended", provider, call_id);
+            return;
+        };
+        *call_id = caller_call_id;
+        if let Some((_, queue)) = self.client_queue(caller) {
+            queue.push_control(Outgoing::Control(answer));
+        }
+    }
+
+    fn fail_call(&self, call: FailedCall) {
+        log::debug!("call {} of client {} failed: {}", call.call_id, call.caller, call.reason);
+        if let Some((_, queue)) = self.client_queue(call.caller) {
+            queue.push_control(Outgoing::Control(ControlMessage::ServiceError {
+                call_id: call.call_id,
+                reason: call.reason,
+            }));
+        }
+    }
+
+    /// Parameters whose names match a pattern
+    pub(crate) fn get_parameters(&self, pattern: &str) -> Result<Vec<(String, ParameterValue)>> {
+        self.parameters.lock().unwrap().matching(pattern)
+    }
+
+    /// Set a parameter and publish the change on its latched topic
+    pub(crate) async fn set_parameter(&self, name: &str, value: ParameterValue) -> Result<(String, ParameterValue)> {
+        let (name, changed) = self.parameters.lock().unwrap().set(name, value.clone())?;
+        if changed {
+            log::debug!("parameter '{}' set to {}", name, value);
+            let topic = parameter_topic(&name);
+            self.cache.lock().unwrap().own(&topic, 1);
+            self.forward(0, &topic, now_ns(), value.to_batch(&name)?).await;
+        }
+        Ok((name, value))
+    }
+
+    /// Write the parameters to the configured file, if any
+    fn save_parameters(&self) -> Result<()> {
+        let Some(path) = &self.config.parameter_file else {
+            return Ok(());
+        };
+        let parameters = self.parameters.lock().unwrap();
+        parameters.save(path)?;
+        log::info!("saved {} parameters to {}", parameters.len(), path.display());
+        Ok(())
+    }
+
+    /// Every topic with a registered schema
+    pub(crate) fn topics(&self) -> Result<Vec<TopicInfo>> {
+        self.registry.lock().unwrap().topics()
+    }
+
+    pub(crate) fn schema(&self, topic: &str) -> Option<SchemaRef> {
+        self.registry.lock().unwrap().schema(topic)
+    }
+
+    pub(crate) fn schemas(&self) -> Vec<(String, SchemaRef)> {
+        self.registry.lock().unwrap().schemas()
+    }
+
+    /// Latched rows of a topic
+    pub(crate) fn cached(&self, topic: &str) -> Option<CachedValue> {
+        self.cache.lock().unwrap().get(topic)
+    }
+
+    /// Check a batch received from a publisher against its topic's schema and
+    /// forward it to every subscribed client
+    ///
+    /// Fails with the reason if the batch was refused, even when it was
+    /// quarantined instead.
+    pub(crate) async fn publish(&self, publisher_id: u64, topic: &str, timestamp_ns: i64, batch: RecordBatch) -> Result<()> {
+        if let Err(e) = validate_topic(topic) {
+            log::warn!("client {} published to an invalid topic: {}", publisher_id, e);
+            return Err(e);
+        }
+        if topic.trim_start_matches('/').split('/').next() == Some(PARAMETER_PREFIX) {
+            let reason = format!("only the broker publishes under '{}', set parameters instead", PARAMETER_PREFIX);
+            self.refuse(publisher_id, topic, &reason);
+            bail!(reason);
+        }
+
+        let checked = self.registry.lock().unwrap().check(topic, &batch.schema());
+        if let Err(e) = checked {
+            self.refuse(publisher_id, topic, &e.to_string());
+            if self.config.schema_mismatch == SchemaMismatch::Quarantine {
+                let quarantine = format!("{}/{}", QUARANTINE_PREFIX, topic);
+                self.forward(publisher_id, &quarantine, timestamp_ns, batch).await;
+            }
+            return Err(e);
+        }
+        self.forward(publisher_id, topic, timestamp_ns, batch).await;
+        Ok(())
+    }
+
+    /// Log a refused batch and tell its publisher, once per topic
+    pub(crate) fn refuse(&self, publisher_id: u64, topic: &str, reason: &str) {
+        if !self.registry.lock().unwrap().should_notify(publisher_id, topic) {
+            return;
+        }
+        log::warn!("refused batches from client {} on '{}': {}", publisher_id, topic, reason);
+        if let Some((_, queue)) = self.client_queue(publisher_id) {
+            queue.push_control(Outgoing::Control(ControlMessage::PublishRejected {
+                topic: topic.to_string(),
+                reason: reason.to_string(),
+            }));
+        }
+    }
+
+    /// Cache a batch if its topic is latched and hand it to the subscribers
+    ///
+    /// With a UDP target, batches on best-effort topics only go out over UDP,
+    /// so client connections never carry them twice.
+    async fn forward(&self, publisher_id: u64, topic: &str, timestamp_ns: i64, batch: RecordBatch) {
+        if let Err(e) = self.cache.lock().unwrap().update(topic, publisher_id, timestamp_ns, &batch) {
+            log::warn!("failed to cache latched value for '{}': {}", topic, e);
+        }
+
+        if let Some(udp) = &self.udp {
+            if self.config.is_best_effort(topic) {
+                if let Err(e) = udp.send(publisher_id, topic, timestamp_ns, &batch).await {
+                    log::warn!("failed to send '{}' to {}: {}", topic, udp.target(), e);
+                }
+                return;
+            }
+        }
+
+        let targets = self.router.read().unwrap().route(topic);
+        if targets.is_empty() {
+            return;
+        }
+
+        // Collect the queues first so no lock is held while a push waits
+        // A client gets the batch once, tagged with every subscription it
+        // should be handed to
+        let queues: Vec<_> = targets
+            .into_iter()
+            .filter_map(|(client_id, subscription_ids)| {
+                let (name, queue) = self.client_queue(client_id)?;
+                Some((client_id, name, queue, subscription_ids))
+            })
+            .collect();
+        // Push to every queue at once, so that under `OverflowPolicy::Block` a
+        // full queue holds up the publisher but not the other subscribers
+        let pushes = queues.into_iter().map(|(client_id, name, queue, subscription_ids)| {
+            let message = Outgoing::Batch {
+                topic: topic.to_string(),
+                publisher_id,
+                timestamp_ns,
+                batch: batch.clone(),
+                subscription_ids,
+            };
+            async move { report_push(client_id, &name, topic, queue.push(message).await) }
+        });
+        join_all(pushes).await;
+    }
+
+    fn client_queue(&self, client_id: u64) -> Option<(String, Arc<ClientQueue>)> {
+        let clients = self.clients.lock().unwrap();
+        let client = clients.get(&client_id)?;
+        Some((client.name.clone(), client.queue.clone()))
+    }
+}
+
+pub(crate) fn now_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+fn report_push(client_id: u64, name: &str, topic: &str, pushed: Pushed) {
+    match pushed {
+        Pushed::Queued | Pushed::Closed => {}
+        Pushed::DroppedOldest => {
+            log::warn!("queue for client {} '{}' is full, dropped its oldest batch", client_id, name)
+        }
+        Pushed::DroppedNewest => log::warn!("queue for client {} '{}' is full, dropping '{}'", client_id, name, topic),
+        Pushed::Disconnected => log::warn!("queue for client {} '{}' overflowed, disconnecting", client_id, name),
+    }
+}
+
+/// Pub/sub broker accepting client connections over TCP or a Unix socket
+pub struct Broker {
+    listener: Listener,
+    /// Listener of the Arrow Flight service, if enabled
+    flight: Option<TcpListener>,
+    /// Writer for the configured recording, created up front so a bad
+    /// directory fails `bind`
+    recording: Option<LogWriter>,
+    state: Arc<BrokerState>,
+}
+
+impl Broker {
+    /// Bind the listening socket described by the config
+    pub async fn bind(config: BrokerConfig) -> Result<Self> {
+        for pattern in &config.best_effort_topics {
+            validate_pattern(pattern)?;
+        }
+        let udp = match &config.udp_target {
+            Some(target) => Some(UdpPublisher::bind(target.parse()?, config.udp_ttl).await?),
+            None => None,
+        };
+        let listener = Listener::bind(&Endpoint::parse(&config.bind_addr)?, config.socket_mode).await?;
+        let flight = match &config.flight_addr {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+        let recording = match &config.recording {
+            Some(recording) => {
+                for pattern in &recording.patterns {
+                    validate_pattern(pattern)?;
+                }
+                Some(LogWriter::create(recording.log.clone())?)
+            }
+            None => None,
+        };
+        let parameters = match &config.parameter_file {
+            Some(path) if path.exists() => ParameterStore::load(path)?,
+            _ => ParameterStore::new(),
+        };
+        Ok(Self {
+            listener,
+            flight,
+            recording,
+            state: Arc::new(BrokerState::new(config, udp, parameters)?),
+        })
+    }
+
+    /// Endpoint the broker is actually listening on
+    pub fn endpoint(&self) -> Result<Endpoint> {
+        self.listener.endpoint()
+    }
+
+    /// Address the broker is actually listening on, if it listens on TCP
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self.listener.endpoint()? {
+            Endpoint::Tcp(addr) => Ok(addr.parse()?),
+            Endpoint::Unix(path) => anyhow::bail!("broker listens on unix socket '{}'", path.display()),
+        }
+    }
+
+    /// Address the Arrow Flight service is listening on, if enabled
+    pub fn flight_addr(&self) -> Option<SocketAddr> {
+        self.flight.as_ref().and_then(|x| x.local_addr().ok())
+    }
+
+    /// Serve clients until SIGINT is received
+    pub async fn run(self) -> Result<()> {
+        self.run_until(async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                log::error!("failed to listen for SIGINT: {}", e);
+                std::future::pending::<()>().await;
+            }
+            log::info!("SIGINT received, shutting down");
+        })
+        .await
+    }
+
+    /// Serve clients until `shutdown` completes, then close every connection
+    pub async fn run_until<F: Future<Output = ()>>(self, shutdown: F) -> Result<()> {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        let mut flight_task = self
+            .flight
+            .map(|listener| tokio::spawn(flight::serve(listener, self.state.clone(), shutdown_rx.clone())));
+        let recorder = match (self.recording, &self.state.config.recording) {
+            (Some(writer), Some(recording)) => {
+                let recorder = Recorder::start(self.state.clone(), writer, &recording.patterns).await;
+                Some(tokio::spawn(recorder.run(shutdown_rx.clone())))
+            }
+            _ => None,
+        };
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = self.listener.accept() => {
+                    let (stream, peer) = match accepted {
+                        Ok(x) => x,
+                        Err(e) => {
+                            log::warn!("failed to accept connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let state = self.state.clone();
+                    let shutdown_rx = shutdown_rx.clone();
+                    connections.spawn(async move {
+                        let addr = peer.addr.clone();
+                        if let Err(e) = connection::handle(state, stream, peer, shutdown_rx).await {
+                            log::warn!("connection from {} closed: {:#}", addr, e);
+                        }
+                    });
+                }
+                // Reap finished connections so the set does not grow unbounded
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+
+        drop(self.listener);
+        let _ = shutdown_tx.send(true);
+        let drained = tokio::time::timeout(self.state.config.shutdown_timeout, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            log::warn!("{} connections did not close in time", connections.len());
+            connections.abort_all();
+        }
+        if let Some(task) = &mut flight_task {
+            match tokio::time::timeout(self.state.config.shutdown_timeout, &mut *task).await {
+                Ok(Ok(Err(e))) => log::warn!("Flight service failed: {:#}", e),
+                Ok(_) => {}
+                Err(_) => {
+                    log::warn!("Flight service did not stop in time");
+                    task.abort();
+                }
+            }
+        }
+        if let Some(task) = recorder {
+            match task.await? {
+                Ok(segments) => log::info!("recorded {} segments", segments.len()),
+                Err(e) => log::warn!("recording failed: {:#}", e),
+            }
+        }
+        if let Err(e) = self.state.save_parameters() {
+            log::error!("failed to save parameters: {:#}", e);
+        }
+        log::info!("broker stopped");
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// A call forwarded to a provider and not answered yet
+struct PendingCall {
+    service: String,
+    caller: u64,
+    /// Id the caller chose for the call
+    call_id: u32,
+    provider: u64,
+}
+
+/// A call that ends without a response from its provider
+pub(crate) struct FailedCall {
+    pub(crate) caller: u64,
+    pub(crate) call_id: u32,
+    pub(crate) reason: String,
+}
+
+/// Providers of every advertised service and the calls in flight
+///
+/// Calls are forwarded under broker-wide ids, since the ids callers choose
+/// are only unique per client.
+#[derive(Default)]
+pub(crate) struct ServiceTable {
+    providers: HashMap<String, u64>,
+    pending: HashMap<u32, PendingCall>,
+    next_id: u32,
+}
+
+impl ServiceTable {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make a client the provider of a service
+    ///
+    /// Fails if another client provides it already; it stays theirs until
+    /// they unadvertise it or disconnect.
+    pub(crate) fn advertise(&mut self, client_id: u64, service: &str) -> Result<()> {
+        match self.providers.get(service) {
+            Some(provider) if *provider != client_id => {
+                bail!("service '{}' is already provided by client {}", service, provider)
+            }
+            _ => {
+                self.providers.insert(service.to_string(), client_id);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn unadvertise(&mut self, client_id: u64, service: &str) {
+        if self.providers.get(service) == Some(&client_id) {
+            self.providers.remove(service);
+        }
+    }
+
+    /// Register a call and return the provider and the id to forward it
+    /// under, or None if nobody provides the service
+    pub(crate) fn start(&mut self, caller: u64, call_id: u32, service: &str) -> Option<(u64, u32)> {
+        let provider = *self.providers.get(service)?;
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.insert(
+            id,
+            PendingCall {
+                service: service.to_string(),
+                caller,
+                call_id,
+                provider,
+            },
+        );
+        Some((provider, id))
+    }
+
+    /// Finish a call answered by `provider`, returning its caller and the
+    /// caller's id for it
+    ///
+    /// Answers from another client, or to calls that already timed out, are
+    /// ignored.
+    pub(crate) fn finish(&mut self, provider: u64, id: u32) -> Option<(u64, u32)> {
+        if self.pending.get(&id)?.provider != provider {
+            return None;
+        }
+        let call = self.pending.remove(&id)?;
+        Some((call.caller, call.call_id))
+    }
+
+    /// Fail a call that is still pending after its timeout
+    pub(crate) fn expire(&mut self, id: u32) -> Option<FailedCall> {
+        let call = self.pending.remove(&id)?;
+        Some(FailedCall {
+            caller: call.caller,
+            call_id: call.call_id,
+            reason: format!("service '{}' did not answer in time", call.service),
+        })
+    }
+
+    /// Drop the services of a client that went away and fail the calls it
+    /// was answering
+    pub(crate) fn remove_client(&mut self, client_id: u64) -> Vec<FailedCall> {
+        self.providers.retain(|_, x| *x != client_id);
+        let ids: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, x)| x.provider == client_id || x.caller == client_id)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| self.pending.remove(&id))
+            .filter(|x| x.caller != client_id)
+            .map(|x| FailedCall {
+                caller: x.caller,
+                call_id: x.call_id,
+                reason: format!("no provider for service '{}': its provider disconnected", x.service),
+            })
+            .collect()
+    }
+}
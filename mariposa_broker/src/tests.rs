@@ -0,0 +1,1171 @@
+use arrow::array::{Float64Array, Int32Array, StringArray, UInt64Array};
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
+use std::net::SocketAddr;
+use std::sync::Arc;
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use crate::parameters::ParameterStore;
+use crate::queue::{coalesce, ClientQueue, Pushed};
+use crate::registry::SchemaRegistry;
+use crate::server::Outgoing;
+use crate::{Broker, BrokerConfig, LatchRule, OverflowPolicy, Recording, SchemaMismatch};
+use mariposa_core::logfile::{LogConfig, PUBLISHER_COLUMN, TOPIC_KEY};
+use mariposa_core::parameter::ParameterValue;
+use mariposa_core::udp::UdpSubscriber;
+use mariposa_core::wire::{
+    decode_batch, encode_batch, read_frame, write_frame, BatchDecoder, BatchEncoder, ControlMessage, FrameHeader,
+    FrameKind, PROTOCOL_VERSION,
+};
+
+async fn start_broker() -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    start_broker_with(BrokerConfig::default()).await
+}
+
+async fn start_broker_with(config: BrokerConfig) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    let config = BrokerConfig {
+        bind_addr: "127.0.0.1:0".to_string(),
+        ..config
+    };
+    let broker = Broker::bind(config).await.unwrap();
+    let addr = broker.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        broker
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+    });
+    (addr, stop, task)
+}
+
+async fn send<S: AsyncWrite + Unpin>(stream: &mut S, message: ControlMessage) {
+    write_frame(stream, &message.to_frame().unwrap()).await.unwrap();
+}
+
+async fn receive_control<S: AsyncRead + Unpin>(stream: &mut S) -> ControlMessage {
+    let frame = read_frame(stream).await.unwrap().unwrap();
+    ControlMessage::from_frame(&frame).unwrap()
+}
+
+async fn connect(addr: SocketAddr, name: &str) -> (TcpStream, u64) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let client_id = handshake(&mut stream, name).await;
+    (stream, client_id)
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &Path, name: &str) -> (UnixStream, u64) {
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    let client_id = handshake(&mut stream, name).await;
+    (stream, client_id)
+}
+
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, name: &str) -> u64 {
+    send(
+        stream,
+        ControlMessage::Hello {
+            client_name: name.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+        },
+    )
+    .await;
+    match receive_control(stream).await {
+        ControlMessage::Welcome { client_id, .. } => client_id,
+        other => panic!("expected welcome, got {:?}", other),
+    }
+}
+
+async fn publish<S: AsyncWrite + Unpin>(stream: &mut S, encoder: &mut BatchEncoder, topic: &str, batch: &RecordBatch) {
+    for frame in encoder.encode(topic, batch, 0, &[]).unwrap() {
+        write_frame(stream, &frame).await.unwrap();
+    }
+}
+
+async fn receive_tagged<S: AsyncRead + Unpin>(stream: &mut S, decoder: &mut BatchDecoder) -> (FrameHeader, RecordBatch) {
+    loop {
+        let frame = read_frame(stream).await.unwrap().unwrap();
+        assert_ne!(frame.header.kind, FrameKind::Control);
+        if let Some(batch) = decoder.decode(&frame.header, frame.payload).unwrap() {
+            return (frame.header, batch);
+        }
+    }
+}
+
+async fn receive_batch<S: AsyncRead + Unpin>(stream: &mut S, decoder: &mut BatchDecoder) -> (String, u64, RecordBatch) {
+    let (header, batch) = receive_tagged(stream, decoder).await;
+    (header.topic, header.publisher_id, batch)
+}
+
+fn speed_batch(id: i32, speed: f64) -> RecordBatch {
+    let ids = Arc::new(Int32Array::from(vec![id])) as _;
+    let speeds = Arc::new(Float64Array::from(vec![speed])) as _;
+    RecordBatch::try_from_iter([("id", ids), ("speed_kph", speeds)]).unwrap()
+}
+
+#[tokio::test]
+async fn test_handshake_assigns_ids() {
+    let (addr, stop, task) = start_broker().await;
+
+    let (_a, id_a) = connect(addr, "camera").await;
+    let (_b, id_b) = connect(addr, "tracker").await;
+    assert_ne!(id_a, 0);
+    assert_ne!(id_a, id_b);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handshake_rejects_other_versions() {
+    let (addr, stop, task) = start_broker().await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    send(
+        &mut stream,
+        ControlMessage::Hello {
+            client_name: "old".to_string(),
+            protocol_version: PROTOCOL_VERSION + 1,
+        },
+    )
+    .await;
+    assert!(matches!(receive_control(&mut stream).await, ControlMessage::Reject { .. }));
+    assert!(read_frame(&mut stream).await.unwrap().is_none());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_publish_reaches_subscriber() {
+    let (addr, stop, task) = start_broker().await;
+
+    let (mut subscriber, subscriber_id) = connect(addr, "tracker").await;
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut subscriber_encoder = BatchEncoder::new(subscriber_id);
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "vehicle/speed".to_string(),
+        },
+    )
+    .await;
+    // Frames on one connection are handled in order, so once our own publish
+    // comes back the subscription is active
+    publish(&mut subscriber, &mut subscriber_encoder, "vehicle/speed", &speed_batch(0, 0.0)).await;
+    let (_, from, _) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(from, subscriber_id);
+
+    publish(&mut publisher, &mut publisher_encoder, "vehicle/other", &speed_batch(1, 50.0)).await;
+    publish(&mut publisher, &mut publisher_encoder, "vehicle/speed", &speed_batch(2, 131.5)).await;
+    let (topic, from, batch) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(topic, "vehicle/speed");
+    assert_eq!(from, publisher_id);
+    assert_eq!(batch, speed_batch(2, 131.5));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_says_goodbye() {
+    let (addr, stop, task) = start_broker().await;
+    let (mut client, _) = connect(addr, "camera").await;
+
+    stop.send(()).unwrap();
+    assert_eq!(receive_control(&mut client).await, ControlMessage::Goodbye);
+    assert!(read_frame(&mut client).await.unwrap().is_none());
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_service_calls_are_routed_by_correlation_id() {
+    let (addr, stop, task) = start_broker().await;
+    let (mut provider, _) = connect(addr, "camera").await;
+    let (mut first, _) = connect(addr, "planner").await;
+    let (mut second, _) = connect(addr, "tracker").await;
+
+    let call = |speed: f64| ControlMessage::CallService {
+        call_id: 7,
+        service: "camera/set_exposure".to_string(),
+        timeout_ms: 5000,
+        request: encode_batch(&speed_batch(1, speed)).unwrap(),
+    };
+    send(&mut first, call(1.0)).await;
+    assert_eq!(
+        receive_control(&mut first).await,
+        ControlMessage::ServiceError {
+            call_id: 7,
+            reason: "no provider for service 'camera/set_exposure'".to_string(),
+        }
+    );
+
+    send(&mut provider, ControlMessage::AdvertiseService { service: "camera/set_exposure".to_string() }).await;
+    // Answered once the advertisement before it has been handled
+    send(&mut provider, ControlMessage::ListTopics { request_id: 1 }).await;
+    receive_control(&mut provider).await;
+
+    // Both callers chose the same id, the provider sees two distinct ones
+    send(&mut first, call(1.0)).await;
+    let ControlMessage::CallService { call_id: first_id, .. } = receive_control(&mut provider).await else {
+        panic!("expected a call");
+    };
+    send(&mut second, call(2.0)).await;
+    let ControlMessage::CallService { call_id: second_id, request, .. } = receive_control(&mut provider).await else {
+        panic!("expected a call");
+    };
+    assert_ne!(first_id, second_id);
+    assert_eq!(decode_batch(&request).unwrap(), speed_batch(1, 2.0));
+
+    let response = encode_batch(&speed_batch(2, 4.0)).unwrap();
+    send(&mut provider, ControlMessage::ServiceResponse { call_id: second_id, response: response.clone() }).await;
+    send(&mut provider, ControlMessage::ServiceError { call_id: first_id, reason: "busy".to_string() }).await;
+    assert_eq!(receive_control(&mut second).await, ControlMessage::ServiceResponse { call_id: 7, response });
+    assert_eq!(
+        receive_control(&mut first).await,
+        ControlMessage::ServiceError {
+            call_id: 7,
+            reason: "busy".to_string(),
+        }
+    );
+
+    // Another client cannot take the service over while its provider is there
+    send(&mut second, ControlMessage::AdvertiseService { service: "camera/set_exposure".to_string() }).await;
+    send(&mut second, ControlMessage::ListTopics { request_id: 2 }).await;
+    receive_control(&mut second).await;
+    send(&mut first, call(3.0)).await;
+    let ControlMessage::CallService { call_id, .. } = receive_control(&mut provider).await else {
+        panic!("expected a call");
+    };
+    send(&mut provider, ControlMessage::ServiceError { call_id, reason: "busy".to_string() }).await;
+    receive_control(&mut first).await;
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_calls_fail_once_their_provider_drops() {
+    let (addr, stop, task) = start_broker().await;
+    let (mut provider, _) = connect(addr, "camera").await;
+    let (mut caller, _) = connect(addr, "planner").await;
+
+    send(&mut provider, ControlMessage::AdvertiseService { service: "camera/set_exposure".to_string() }).await;
+    send(&mut provider, ControlMessage::ListTopics { request_id: 1 }).await;
+    receive_control(&mut provider).await;
+    let call = ControlMessage::CallService {
+        call_id: 3,
+        service: "camera/set_exposure".to_string(),
+        timeout_ms: 60_000,
+        request: encode_batch(&speed_batch(1, 1.0)).unwrap(),
+    };
+    send(&mut caller, call).await;
+    assert!(matches!(receive_control(&mut provider).await, ControlMessage::CallService { .. }));
+    drop(provider);
+
+    // Long before the call times out
+    let failed = tokio::time::timeout(Duration::from_secs(5), receive_control(&mut caller)).await.unwrap();
+    assert_eq!(
+        failed,
+        ControlMessage::ServiceError {
+            call_id: 3,
+            reason: "no provider for service 'camera/set_exposure': its provider disconnected".to_string(),
+        }
+    );
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_unauthorized_clients_cannot_use_services() {
+    let config = BrokerConfig {
+        publish_uids: vec![0],
+        ..BrokerConfig::default()
+    };
+    let (addr, stop, task) = start_broker_with(config).await;
+    let (mut intruder, _) = connect(addr, "intruder").await;
+
+    send(&mut intruder, ControlMessage::AdvertiseService { service: "camera/set_exposure".to_string() }).await;
+    let call = ControlMessage::CallService {
+        call_id: 1,
+        service: "camera/set_exposure".to_string(),
+        timeout_ms: 5000,
+        request: encode_batch(&speed_batch(1, 1.0)).unwrap(),
+    };
+    send(&mut intruder, call).await;
+    match receive_control(&mut intruder).await {
+        ControlMessage::ServiceError { call_id: 1, reason } => assert!(reason.contains("not authorized"), "{}", reason),
+        other => panic!("expected an error, got {:?}", other),
+    }
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_parameters_are_typed_watched_and_saved() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("parameters.conf");
+    std::fs::write(&path, "# limits\n/speed/limit_kph = 50.0\n").unwrap();
+    let config = BrokerConfig {
+        parameter_file: Some(path.clone()),
+        ..BrokerConfig::default()
+    };
+    let (addr, stop, task) = start_broker_with(config).await;
+    let (mut client, client_id) = connect(addr, "planner").await;
+    let mut encoder = BatchEncoder::new(client_id);
+    let mut decoder = BatchDecoder::new();
+
+    // Watchers get the loaded value first, then every change
+    let watch = ControlMessage::Subscribe {
+        subscription_id: 0,
+        pattern: "_parameters/**".to_string(),
+    };
+    send(&mut client, watch).await;
+    let (topic, from, batch) = receive_batch(&mut client, &mut decoder).await;
+    assert_eq!((topic.as_str(), from), ("_parameters/speed/limit_kph", 0));
+    assert_eq!(batch, ParameterValue::Float(50.0).to_batch("speed/limit_kph").unwrap());
+
+    let set = |request_id, name: &str, value| ControlMessage::SetParameter {
+        request_id,
+        name: name.to_string(),
+        value,
+    };
+    send(&mut client, set(1, "/camera/exposure", ParameterValue::Float(0.01))).await;
+    let (topic, _, batch) = receive_batch(&mut client, &mut decoder).await;
+    assert_eq!(topic, "_parameters/camera/exposure");
+    assert_eq!(batch, ParameterValue::Float(0.01).to_batch("camera/exposure").unwrap());
+    let exposure = ("camera/exposure".to_string(), ParameterValue::Float(0.01));
+    assert_eq!(
+        receive_control(&mut client).await,
+        ControlMessage::ParameterList {
+            request_id: 1,
+            parameters: vec![exposure.clone()],
+        }
+    );
+
+    send(&mut client, set(2, "speed/limit_kph", ParameterValue::from("fast"))).await;
+    match receive_control(&mut client).await {
+        ControlMessage::ParameterError { request_id: 2, reason } => {
+            assert_eq!(reason, "parameter 'speed/limit_kph' holds a float, not a string")
+        }
+        other => panic!("expected an error, got {:?}", other),
+    }
+    send(&mut client, ControlMessage::GetParameters { request_id: 3, pattern: "**".to_string() }).await;
+    assert_eq!(
+        receive_control(&mut client).await,
+        ControlMessage::ParameterList {
+            request_id: 3,
+            parameters: vec![exposure, ("speed/limit_kph".to_string(), ParameterValue::Float(50.0))],
+        }
+    );
+
+    // Nobody but the broker publishes parameter changes
+    publish(&mut client, &mut encoder, "_parameters/camera/exposure", &speed_batch(1, 2.0)).await;
+    assert!(matches!(
+        receive_control(&mut client).await,
+        ControlMessage::PublishRejected { .. }
+    ));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(saved, "camera/exposure = 0.01\nspeed/limit_kph = 50.0\n");
+}
+
+#[test]
+fn test_parameter_file_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("parameters.conf");
+    let mut store = ParameterStore::new();
+    store.set("/camera/exposure", ParameterValue::Float(0.01)).unwrap();
+    store.set("camera/label", ParameterValue::from("a = b # c")).unwrap();
+    store.set("drive/enabled", ParameterValue::Bool(true)).unwrap();
+    assert!(store.set("speed=fast", ParameterValue::Int(1)).is_err());
+    assert!(store.set("speed limit", ParameterValue::Int(1)).is_err());
+    store.save(&path).unwrap();
+
+    let loaded = ParameterStore::load(&path).unwrap();
+    assert_eq!(loaded.matching("**").unwrap(), store.matching("**").unwrap());
+}
+
+#[tokio::test]
+async fn test_blocked_subscriber_does_not_hold_up_others() {
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        client_queue_len: 1,
+        overflow_policy: OverflowPolicy::Block,
+        ..BrokerConfig::default()
+    })
+    .await;
+    let subscribe = || ControlMessage::Subscribe {
+        subscription_id: 0,
+        pattern: "vehicle/speed".to_string(),
+    };
+    // The slow subscriber connects first, so it is pushed to first
+    let (mut slow, _) = connect(addr, "logger").await;
+    send(&mut slow, subscribe()).await;
+    send(&mut slow, ControlMessage::PauseBatches).await;
+    send(&mut slow, ControlMessage::ListTopics { request_id: 1 }).await;
+    receive_control(&mut slow).await;
+    let (mut fast, _) = connect(addr, "tracker").await;
+    send(&mut fast, subscribe()).await;
+    send(&mut fast, ControlMessage::ListTopics { request_id: 1 }).await;
+    receive_control(&mut fast).await;
+
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut encoder = BatchEncoder::new(publisher_id);
+    for id in 0..4 {
+        publish(&mut publisher, &mut encoder, "vehicle/speed", &speed_batch(id, 1.0)).await;
+    }
+    // The second batch fills the slow queue, yet reaches the fast subscriber
+    let mut decoder = BatchDecoder::new();
+    for id in 0..2 {
+        let (_, _, batch) = tokio::time::timeout(Duration::from_secs(5), receive_batch(&mut fast, &mut decoder))
+            .await
+            .unwrap();
+        assert_eq!(batch, speed_batch(id, 1.0));
+    }
+
+    send(&mut slow, ControlMessage::ResumeBatches).await;
+    let mut slow_decoder = BatchDecoder::new();
+    for id in 0..4 {
+        assert_eq!(receive_batch(&mut slow, &mut slow_decoder).await.2, speed_batch(id, 1.0));
+    }
+    for id in 2..4 {
+        assert_eq!(receive_batch(&mut fast, &mut decoder).await.2, speed_batch(id, 1.0));
+    }
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_wildcard_subscription() {
+    let (addr, stop, task) = start_broker().await;
+
+    let (mut subscriber, subscriber_id) = connect(addr, "tracker").await;
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut subscriber_encoder = BatchEncoder::new(subscriber_id);
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "vehicle/*/speed".to_string(),
+        },
+    )
+    .await;
+    publish(&mut subscriber, &mut subscriber_encoder, "vehicle/0/speed", &speed_batch(0, 0.0)).await;
+    receive_batch(&mut subscriber, &mut decoder).await;
+
+    publish(&mut publisher, &mut publisher_encoder, "vehicle/7/plate", &speed_batch(7, 0.0)).await;
+    publish(&mut publisher, &mut publisher_encoder, "vehicle/7/speed", &speed_batch(7, 96.0)).await;
+    let (topic, _, batch) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(topic, "vehicle/7/speed");
+    assert_eq!(batch, speed_batch(7, 96.0));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_latched_topic_replays_to_late_subscriber() {
+    let (addr, stop, task) = start_broker().await;
+
+    let (mut publisher, publisher_id) = connect(addr, "planner").await;
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut publisher_decoder = BatchDecoder::new();
+    send(
+        &mut publisher,
+        ControlMessage::Advertise {
+            topic: "mission/state".to_string(),
+            latch_depth: 2,
+        },
+    )
+    .await;
+    send(
+        &mut publisher,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "mission/state".to_string(),
+        },
+    )
+    .await;
+    for id in 0..3 {
+        publish(&mut publisher, &mut publisher_encoder, "mission/state", &speed_batch(id, id as f64)).await;
+        receive_batch(&mut publisher, &mut publisher_decoder).await;
+    }
+
+    let (mut subscriber, _) = connect(addr, "dashboard").await;
+    let mut decoder = BatchDecoder::new();
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "mission/*".to_string(),
+        },
+    )
+    .await;
+    let (topic, from, batch) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(topic, "mission/state");
+    assert_eq!(from, publisher_id);
+    let ids = batch.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+    assert_eq!(ids.values(), &[1, 2]);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_advertising_without_latch_keeps_configured_latch() {
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        latched_topics: vec![LatchRule {
+            pattern: "mission/*".to_string(),
+            depth: 1,
+        }],
+        ..BrokerConfig::default()
+    })
+    .await;
+
+    let (mut publisher, publisher_id) = connect(addr, "planner").await;
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut publisher_decoder = BatchDecoder::new();
+    send(
+        &mut publisher,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "mission/state".to_string(),
+        },
+    )
+    .await;
+    publish(&mut publisher, &mut publisher_encoder, "mission/state", &speed_batch(1, 1.0)).await;
+    receive_batch(&mut publisher, &mut publisher_decoder).await;
+    // Zero means no preference, not "stop latching"
+    send(
+        &mut publisher,
+        ControlMessage::Advertise {
+            topic: "mission/state".to_string(),
+            latch_depth: 0,
+        },
+    )
+    .await;
+    publish(&mut publisher, &mut publisher_encoder, "mission/state", &speed_batch(2, 2.0)).await;
+    receive_batch(&mut publisher, &mut publisher_decoder).await;
+
+    let (mut subscriber, _) = connect(addr, "dashboard").await;
+    let mut decoder = BatchDecoder::new();
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "mission/state".to_string(),
+        },
+    )
+    .await;
+    let (_, from, batch) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(from, publisher_id);
+    assert_eq!(batch, speed_batch(2, 2.0));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+fn outgoing(topic: &str, id: i32) -> Outgoing {
+    Outgoing::Batch {
+        topic: topic.to_string(),
+        publisher_id: 1,
+        timestamp_ns: id as i64,
+        batch: speed_batch(id, 0.0),
+        subscription_ids: vec![0],
+    }
+}
+
+fn outgoing_ids(messages: &[Outgoing]) -> Vec<Vec<i32>> {
+    messages
+        .iter()
+        .map(|message| match message {
+            Outgoing::Batch { batch, .. } => {
+                batch.column(0).as_any().downcast_ref::<Int32Array>().unwrap().values().to_vec()
+            }
+            Outgoing::Control(_) => vec![],
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_queue_drop_newest() {
+    let queue = ClientQueue::new(2, OverflowPolicy::DropNewest);
+    assert_eq!(queue.push(outgoing("a", 0)).await, Pushed::Queued);
+    assert_eq!(queue.push(outgoing("a", 1)).await, Pushed::Queued);
+    assert_eq!(queue.push(outgoing("a", 2)).await, Pushed::DroppedNewest);
+    // Control messages do not count towards the capacity
+    assert!(queue.push_control(Outgoing::Control(ControlMessage::Goodbye)));
+    assert_eq!(outgoing_ids(&queue.pop_all().await.unwrap()), vec![vec![0], vec![1], vec![]]);
+}
+
+#[tokio::test]
+async fn test_queue_drop_oldest() {
+    let queue = ClientQueue::new(2, OverflowPolicy::DropOldest);
+    for id in 0..4 {
+        queue.push(outgoing("a", id)).await;
+    }
+    assert_eq!(outgoing_ids(&queue.pop_all().await.unwrap()), vec![vec![2], vec![3]]);
+    assert_eq!(queue.dropped(), 2);
+}
+
+#[tokio::test]
+async fn test_queue_disconnect() {
+    let queue = ClientQueue::new(1, OverflowPolicy::Disconnect);
+    assert_eq!(queue.push(outgoing("a", 0)).await, Pushed::Queued);
+    assert_eq!(queue.push(outgoing("a", 1)).await, Pushed::Disconnected);
+    queue.overflowed().await;
+    assert_eq!(queue.push(outgoing("a", 2)).await, Pushed::Closed);
+    assert!(queue.pop_all().await.is_none());
+}
+
+#[tokio::test]
+async fn test_queue_block_waits_for_room() {
+    let queue = Arc::new(ClientQueue::new(1, OverflowPolicy::Block));
+    queue.push(outgoing("a", 0)).await;
+
+    let pusher = tokio::spawn({
+        let queue = queue.clone();
+        async move { queue.push(outgoing("a", 1)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!pusher.is_finished());
+
+    assert_eq!(outgoing_ids(&queue.pop_all().await.unwrap()), vec![vec![0]]);
+    assert_eq!(pusher.await.unwrap(), Pushed::Queued);
+    assert_eq!(outgoing_ids(&queue.pop_all().await.unwrap()), vec![vec![1]]);
+
+    queue.close();
+    assert_eq!(queue.push(outgoing("a", 2)).await, Pushed::Closed);
+}
+
+#[tokio::test]
+async fn test_queue_pause_holds_back_batches() {
+    let queue = ClientQueue::new(2, OverflowPolicy::DropNewest);
+    queue.set_paused(true);
+    queue.push(outgoing("a", 0)).await;
+    queue.push_control(Outgoing::Control(ControlMessage::Goodbye));
+    queue.push(outgoing("a", 1)).await;
+    // Control messages still go out, held batches still take up room
+    assert_eq!(outgoing_ids(&queue.pop_all().await.unwrap()), vec![Vec::<i32>::new()]);
+    assert_eq!(queue.push(outgoing("a", 2)).await, Pushed::DroppedNewest);
+
+    queue.set_paused(false);
+    assert_eq!(outgoing_ids(&queue.pop_all().await.unwrap()), vec![vec![0], vec![1]]);
+}
+
+#[test]
+fn test_coalesce_merges_per_topic() {
+    let messages = vec![
+        outgoing("a", 0),
+        outgoing("b", 1),
+        outgoing("a", 2),
+        Outgoing::Control(ControlMessage::Goodbye),
+        outgoing("a", 3),
+    ];
+    let merged = coalesce(messages).unwrap();
+    assert_eq!(outgoing_ids(&merged), vec![vec![0, 2], vec![1], vec![], vec![3]]);
+    match &merged[0] {
+        Outgoing::Batch { timestamp_ns, .. } => assert_eq!(*timestamp_ns, 2),
+        Outgoing::Control(_) => panic!("expected a batch"),
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limited_client_gets_merged_batches() {
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        max_send_rate: Some(5.0),
+        ..BrokerConfig::default()
+    })
+    .await;
+
+    let (mut subscriber, subscriber_id) = connect(addr, "tracker").await;
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut subscriber_encoder = BatchEncoder::new(subscriber_id);
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "vehicle/speed".to_string(),
+        },
+    )
+    .await;
+    // The echo is written straight away, the publishes below land while the
+    // sender waits out its interval
+    publish(&mut subscriber, &mut subscriber_encoder, "vehicle/speed", &speed_batch(0, 0.0)).await;
+    receive_batch(&mut subscriber, &mut decoder).await;
+
+    for id in 1..4 {
+        publish(&mut publisher, &mut publisher_encoder, "vehicle/speed", &speed_batch(id, 80.0)).await;
+    }
+    let (_, from, batch) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(from, publisher_id);
+    let ids = batch.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+    assert_eq!(ids.values(), &[1, 2, 3]);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_overflow_disconnects_slow_client() {
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        client_queue_len: 1,
+        overflow_policy: OverflowPolicy::Disconnect,
+        max_send_rate: Some(1.0),
+        ..BrokerConfig::default()
+    })
+    .await;
+
+    let (mut subscriber, subscriber_id) = connect(addr, "tracker").await;
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut subscriber_encoder = BatchEncoder::new(subscriber_id);
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "vehicle/speed".to_string(),
+        },
+    )
+    .await;
+    publish(&mut subscriber, &mut subscriber_encoder, "vehicle/speed", &speed_batch(0, 0.0)).await;
+    receive_batch(&mut subscriber, &mut decoder).await;
+
+    // One batch fits while the sender waits, the second overflows the queue
+    for id in 1..3 {
+        publish(&mut publisher, &mut publisher_encoder, "vehicle/speed", &speed_batch(id, 80.0)).await;
+    }
+    assert!(read_frame(&mut subscriber).await.unwrap().is_none());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_overlapping_subscriptions_get_one_tagged_batch() {
+    let (addr, stop, task) = start_broker().await;
+
+    let (mut subscriber, subscriber_id) = connect(addr, "tracker").await;
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut subscriber_encoder = BatchEncoder::new(subscriber_id);
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+
+    for (subscription_id, pattern) in [(1, "topic/*"), (2, "topic/a"), (3, "topic/b"), (4, "sync")] {
+        send(
+            &mut subscriber,
+            ControlMessage::Subscribe {
+                subscription_id,
+                pattern: pattern.to_string(),
+            },
+        )
+        .await;
+    }
+    publish(&mut subscriber, &mut subscriber_encoder, "sync", &speed_batch(0, 0.0)).await;
+    let (header, _) = receive_tagged(&mut subscriber, &mut decoder).await;
+    assert_eq!(header.subscription_ids, vec![4]);
+
+    publish(&mut publisher, &mut publisher_encoder, "topic/a", &speed_batch(1, 10.0)).await;
+    publish(&mut publisher, &mut publisher_encoder, "topic/c", &speed_batch(2, 20.0)).await;
+    let (header, batch) = receive_tagged(&mut subscriber, &mut decoder).await;
+    assert_eq!(header.topic, "topic/a");
+    assert_eq!(header.subscription_ids, vec![1, 2]);
+    assert_eq!(batch, speed_batch(1, 10.0));
+    // The next frame is the second publish, so the first was not sent twice
+    let (header, _) = receive_tagged(&mut subscriber, &mut decoder).await;
+    assert_eq!(header.topic, "topic/c");
+    assert_eq!(header.subscription_ids, vec![1]);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+/// A batch that reuses the `speed_kph` column name with another type
+fn label_batch(id: i32, label: &str) -> RecordBatch {
+    let ids = Arc::new(Int32Array::from(vec![id])) as _;
+    let labels = Arc::new(StringArray::from(vec![label])) as _;
+    RecordBatch::try_from_iter([("id", ids), ("speed_kph", labels)]).unwrap()
+}
+
+#[test]
+fn test_registry_allows_added_nullable_columns() {
+    let mut registry = SchemaRegistry::new();
+    let speed = speed_batch(0, 0.0);
+    registry.check("vehicle/speed", &speed.schema()).unwrap();
+    registry.check("vehicle/speed", &speed.schema()).unwrap();
+
+    let heading = Arc::new(Float64Array::from(vec![Some(90.0)])) as _;
+    let widened = RecordBatch::try_from_iter_with_nullable([
+        ("id", speed.column(0).clone(), false),
+        ("speed_kph", speed.column(1).clone(), false),
+        ("heading", heading, true),
+    ])
+    .unwrap();
+    registry.check("vehicle/speed", &widened.schema()).unwrap();
+    // The older schema is still accepted since the new column may be null
+    registry.check("vehicle/speed", &speed.schema()).unwrap();
+
+    let required = Arc::new(Float64Array::from(vec![1.0])) as _;
+    let extended = RecordBatch::try_from_iter([
+        ("id", speed.column(0).clone()),
+        ("speed_kph", speed.column(1).clone()),
+        ("altitude", required),
+    ])
+    .unwrap();
+    assert!(registry.check("vehicle/speed", &extended.schema()).is_err());
+    assert!(registry.check("vehicle/speed", &label_batch(0, "fast").schema()).is_err());
+
+    let topics = registry.topics().unwrap();
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0].topic, "vehicle/speed");
+    assert_eq!(topics[0].message_type, None);
+    assert_eq!(topics[0].schema().unwrap(), widened.schema());
+}
+
+#[tokio::test]
+async fn test_mismatching_publish_is_rejected() {
+    let (addr, stop, task) = start_broker().await;
+
+    let (mut subscriber, subscriber_id) = connect(addr, "tracker").await;
+    let (mut publisher, publisher_id) = connect(addr, "tagger").await;
+    let mut subscriber_encoder = BatchEncoder::new(subscriber_id);
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "vehicle/speed".to_string(),
+        },
+    )
+    .await;
+    publish(&mut subscriber, &mut subscriber_encoder, "vehicle/speed", &speed_batch(0, 0.0)).await;
+    receive_batch(&mut subscriber, &mut decoder).await;
+
+    publish(&mut publisher, &mut publisher_encoder, "vehicle/speed", &label_batch(1, "fast")).await;
+    match receive_control(&mut publisher).await {
+        ControlMessage::PublishRejected { topic, reason } => {
+            assert_eq!(topic, "vehicle/speed");
+            assert!(reason.contains("speed_kph"), "{}", reason);
+        }
+        other => panic!("expected a rejection, got {:?}", other),
+    }
+
+    // The refused batch never reaches the subscriber
+    publish(&mut subscriber, &mut subscriber_encoder, "vehicle/speed", &speed_batch(2, 20.0)).await;
+    let (_, from, batch) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(from, subscriber_id);
+    assert_eq!(batch, speed_batch(2, 20.0));
+
+    send(&mut subscriber, ControlMessage::ListTopics { request_id: 3 }).await;
+    match receive_control(&mut subscriber).await {
+        ControlMessage::TopicList { request_id, topics } => {
+            assert_eq!(request_id, 3);
+            assert_eq!(topics.len(), 1);
+            assert_eq!(topics[0].topic, "vehicle/speed");
+            assert_eq!(topics[0].schema().unwrap(), speed_batch(0, 0.0).schema());
+        }
+        other => panic!("expected a topic list, got {:?}", other),
+    }
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_mismatching_publish_is_quarantined() {
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        schema_mismatch: SchemaMismatch::Quarantine,
+        ..BrokerConfig::default()
+    })
+    .await;
+
+    let (mut subscriber, subscriber_id) = connect(addr, "tracker").await;
+    let (mut publisher, publisher_id) = connect(addr, "tagger").await;
+    let mut subscriber_encoder = BatchEncoder::new(subscriber_id);
+    let mut publisher_encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "_quarantine/#".to_string(),
+        },
+    )
+    .await;
+    publish(&mut subscriber, &mut subscriber_encoder, "vehicle/speed", &speed_batch(0, 0.0)).await;
+    publish(&mut subscriber, &mut subscriber_encoder, "_quarantine/sync", &speed_batch(0, 0.0)).await;
+    receive_batch(&mut subscriber, &mut decoder).await;
+
+    publish(&mut publisher, &mut publisher_encoder, "vehicle/speed", &label_batch(1, "fast")).await;
+    assert!(matches!(
+        receive_control(&mut publisher).await,
+        ControlMessage::PublishRejected { .. }
+    ));
+    let (topic, from, batch) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(topic, "_quarantine/vehicle/speed");
+    assert_eq!(from, publisher_id);
+    assert_eq!(batch, label_batch(1, "fast"));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+/// Start a broker on a Unix socket inside `dir`
+#[cfg(unix)]
+async fn start_unix_broker(dir: &Path, config: BrokerConfig) -> (oneshot::Sender<()>, JoinHandle<()>) {
+    let config = BrokerConfig {
+        bind_addr: format!("unix://{}", dir.join("mariposa.sock").display()),
+        ..config
+    };
+    let broker = Broker::bind(config).await.unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        broker
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+    });
+    (stop, task)
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_transport() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mariposa.sock");
+    let (stop, task) = start_unix_broker(dir.path(), BrokerConfig::default()).await;
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+    // Nothing is left of the directory the socket was bound in
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    let (mut client, client_id) = connect_unix(&path, "autopilot").await;
+    let mut encoder = BatchEncoder::new(client_id);
+    let mut decoder = BatchDecoder::new();
+    send(
+        &mut client,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "vehicle/speed".to_string(),
+        },
+    )
+    .await;
+    publish(&mut client, &mut encoder, "vehicle/speed", &speed_batch(1, 12.5)).await;
+    let (topic, from, batch) = receive_batch(&mut client, &mut decoder).await;
+    assert_eq!(topic, "vehicle/speed");
+    assert_eq!(from, client_id);
+    assert_eq!(batch, speed_batch(1, 12.5));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_only_authorized_peers_publish() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mariposa.sock");
+    let uid = std::fs::metadata(dir.path()).unwrap().uid();
+    let config = BrokerConfig {
+        publish_uids: vec![uid],
+        ..BrokerConfig::default()
+    };
+    let (stop, task) = start_unix_broker(dir.path(), config).await;
+
+    let (mut publisher, publisher_id) = connect_unix(&path, "autopilot").await;
+    let mut encoder = BatchEncoder::new(publisher_id);
+    let mut decoder = BatchDecoder::new();
+    send(
+        &mut publisher,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "vehicle/speed".to_string(),
+        },
+    )
+    .await;
+    publish(&mut publisher, &mut encoder, "vehicle/speed", &speed_batch(1, 12.5)).await;
+    receive_batch(&mut publisher, &mut decoder).await;
+    drop(publisher);
+
+    // Another user's process may still connect and subscribe, but not publish
+    let config = BrokerConfig {
+        publish_uids: vec![uid.wrapping_add(1)],
+        ..BrokerConfig::default()
+    };
+    let other = tempfile::tempdir().unwrap();
+    let (other_stop, other_task) = start_unix_broker(other.path(), config).await;
+    let (mut intruder, intruder_id) = connect_unix(&other.path().join("mariposa.sock"), "intruder").await;
+    let mut encoder = BatchEncoder::new(intruder_id);
+    publish(&mut intruder, &mut encoder, "vehicle/speed", &speed_batch(2, 99.0)).await;
+    match receive_control(&mut intruder).await {
+        ControlMessage::PublishRejected { topic, reason } => {
+            assert_eq!(topic, "vehicle/speed");
+            assert!(reason.contains("not authorized"), "{}", reason);
+        }
+        other => panic!("expected a rejection, got {:?}", other),
+    }
+
+    for (stop, task) in [(stop, task), (other_stop, other_task)] {
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+}
+
+#[test]
+fn test_tcp_clients_cannot_publish_when_restricted() {
+    let open = BrokerConfig::default();
+    assert!(open.may_publish(None));
+    let restricted = BrokerConfig {
+        publish_gids: vec![0],
+        ..BrokerConfig::default()
+    };
+    assert!(!restricted.may_publish(None));
+}
+
+#[tokio::test]
+async fn test_best_effort_topics_go_out_over_udp() {
+    let mut listener = UdpSubscriber::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        udp_target: Some(listener.local_addr().unwrap().to_string()),
+        best_effort_topics: vec!["telemetry/#".to_string()],
+        ..BrokerConfig::default()
+    })
+    .await;
+
+    let (mut subscriber, _) = connect(addr, "ground").await;
+    send(
+        &mut subscriber,
+        ControlMessage::Subscribe {
+            subscription_id: 0,
+            pattern: "#".to_string(),
+        },
+    )
+    .await;
+    send(&mut subscriber, ControlMessage::ListTopics { request_id: 1 }).await;
+    receive_control(&mut subscriber).await;
+
+    let (mut publisher, publisher_id) = connect(addr, "autopilot").await;
+    let mut encoder = BatchEncoder::new(publisher_id);
+    publish(&mut publisher, &mut encoder, "telemetry/attitude", &speed_batch(2, 45.0)).await;
+    publish(&mut publisher, &mut encoder, "mission/state", &speed_batch(1, 0.0)).await;
+
+    // Only the best-effort topic arrives over UDP
+    let (header, batch) = tokio::time::timeout(Duration::from_secs(5), listener.recv()).await.unwrap().unwrap();
+    assert_eq!(header.topic, "telemetry/attitude");
+    assert_eq!(header.publisher_id, publisher_id);
+    assert_eq!(batch, speed_batch(2, 45.0));
+    // and only the reliable one over the connection
+    let mut decoder = BatchDecoder::new();
+    let (topic, _, _) = receive_batch(&mut subscriber, &mut decoder).await;
+    assert_eq!(topic, "mission/state");
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_recording_writes_matching_topics() {
+    let dir = tempfile::tempdir().unwrap();
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        recording: Some(Recording {
+            patterns: vec!["vehicle/#".to_string(), "vehicle/speed".to_string()],
+            log: LogConfig::new(dir.path()),
+        }),
+        ..BrokerConfig::default()
+    })
+    .await;
+
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut encoder = BatchEncoder::new(publisher_id);
+    publish(&mut publisher, &mut encoder, "vehicle/speed", &speed_batch(1, 10.0)).await;
+    publish(&mut publisher, &mut encoder, "mission/state", &speed_batch(2, 0.0)).await;
+    // Frames of one connection are handled in order, so both batches are
+    // routed once the broker answers this
+    send(&mut publisher, ControlMessage::ListTopics { request_id: 1 }).await;
+    receive_control(&mut publisher).await;
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+
+    let segments: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|x| x.unwrap().path()).collect();
+    assert_eq!(segments.len(), 1);
+    assert!(!segments[0].join("mission%2Fstate.arrow").exists());
+    let file = std::fs::File::open(segments[0].join("vehicle%2Fspeed.arrow")).unwrap();
+    let reader = FileReader::try_new(file, None).unwrap();
+    assert_eq!(reader.schema().metadata()[TOPIC_KEY], "vehicle/speed");
+    // Overlapping patterns still record each batch once
+    let batches: Vec<_> = reader.map(|x| x.unwrap()).collect();
+    assert_eq!(batches.len(), 1);
+    let publishers = batches[0].column_by_name(PUBLISHER_COLUMN).unwrap();
+    assert_eq!(publishers.as_any().downcast_ref::<UInt64Array>().unwrap().value(0), publisher_id);
+    assert_eq!(batches[0].project(&[3, 4]).unwrap().columns(), speed_batch(1, 10.0).columns());
+}
+
+#[tokio::test]
+async fn test_recording_outlives_the_disconnect_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        client_queue_len: 1,
+        overflow_policy: OverflowPolicy::Disconnect,
+        recording: Some(Recording {
+            patterns: vec!["vehicle/speed".to_string()],
+            log: LogConfig::new(dir.path()),
+        }),
+        ..BrokerConfig::default()
+    })
+    .await;
+
+    let (mut publisher, publisher_id) = connect(addr, "camera").await;
+    let mut encoder = BatchEncoder::new(publisher_id);
+    for id in 0..200 {
+        publish(&mut publisher, &mut encoder, "vehicle/speed", &speed_batch(id, 10.0)).await;
+    }
+    send(&mut publisher, ControlMessage::ListTopics { request_id: 1 }).await;
+    receive_control(&mut publisher).await;
+    // Give the recorder time to catch up before the last batch
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    publish(&mut publisher, &mut encoder, "vehicle/speed", &speed_batch(200, 10.0)).await;
+    send(&mut publisher, ControlMessage::ListTopics { request_id: 2 }).await;
+    receive_control(&mut publisher).await;
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+
+    let segments: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|x| x.unwrap().path()).collect();
+    let file = std::fs::File::open(segments[0].join("vehicle%2Fspeed.arrow")).unwrap();
+    let last = FileReader::try_new(file, None).unwrap().last().unwrap().unwrap();
+    assert_eq!(last.project(&[3, 4]).unwrap().columns(), speed_batch(200, 10.0).columns());
+}
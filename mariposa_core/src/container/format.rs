@@ -0,0 +1,292 @@
+use anyhow::{bail, ensure, Result};
+use arrow::datatypes::SchemaRef;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::container::{MAGIC, VERSION};
+use crate::wire::{decode_schema, encode_schema, put_bytes, put_string, ByteReader};
+
+pub(crate) const HEADER_LEN: usize = 8;
+const RECORD_HEADER_LEN: usize = 5;
+const CHECKSUM_LEN: usize = 4;
+/// Footer record with its 8 byte body, then the trailing magic
+pub(crate) const FOOTER_LEN: usize = RECORD_HEADER_LEN + 8 + CHECKSUM_LEN + MAGIC.len();
+
+/// What a record holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Opcode {
+    Topic = 1,
+    Chunk = 2,
+    ChunkIndex = 3,
+    Footer = 4,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(Opcode::Topic),
+            2 => Ok(Opcode::Chunk),
+            3 => Ok(Opcode::ChunkIndex),
+            4 => Ok(Opcode::Footer),
+            _ => bail!("unknown record opcode {}", value),
+        }
+    }
+}
+
+/// A topic with one schema, which chunks refer to by id
+#[derive(Debug, Clone)]
+pub(crate) struct TopicRecord {
+    pub id: u16,
+    pub topic: String,
+    /// Schema of the batches as published, without the log columns
+    pub schema: SchemaRef,
+}
+
+impl TopicRecord {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.id.to_le_bytes());
+        put_string(&mut body, &self.topic)?;
+        put_bytes(&mut body, &encode_schema(&self.schema)?)?;
+        Ok(body)
+    }
+
+    pub fn decode(body: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(body);
+        let id = reader.u16()?;
+        let topic = reader.string()?;
+        let schema = decode_schema(&reader.bytes()?)?;
+        ensure!(reader.remaining() == 0, "trailing bytes in topic record");
+        Ok(Self { id, topic, schema })
+    }
+}
+
+/// Where a chunk is in the file and what it holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChunkIndex {
+    pub topic_id: u16,
+    pub start_ns: i64,
+    pub end_ns: i64,
+    pub messages: u32,
+    /// Offset of the chunk record
+    pub offset: u64,
+    /// Length of the whole chunk record
+    pub length: u64,
+}
+
+impl ChunkIndex {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        self.put_range(&mut body);
+        body.extend_from_slice(&self.offset.to_le_bytes());
+        body.extend_from_slice(&self.length.to_le_bytes());
+        body
+    }
+
+    pub fn decode(body: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(body);
+        let mut index = Self::take_range(&mut reader)?;
+        index.offset = reader.u64()?;
+        index.length = reader.u64()?;
+        ensure!(reader.remaining() == 0, "trailing bytes in chunk index record");
+        Ok(index)
+    }
+
+    /// Body of the chunk record this indexes
+    pub fn encode_chunk(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut body = Vec::with_capacity(payload.len() + 32);
+        self.put_range(&mut body);
+        put_bytes(&mut body, payload)?;
+        Ok(body)
+    }
+
+    /// Index a chunk record found at `offset`, returning it and the Arrow IPC
+    /// stream it holds
+    pub fn decode_chunk(body: &[u8], offset: u64) -> Result<(Self, &[u8])> {
+        let mut reader = ByteReader::new(body);
+        let mut index = Self::take_range(&mut reader)?;
+        let len = reader.u32()? as usize;
+        let payload = reader.take(len)?;
+        ensure!(reader.remaining() == 0, "trailing bytes in chunk record");
+        index.offset = offset;
+        index.length = record_len(body.len());
+        Ok((index, payload))
+    }
+
+    fn put_range(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.topic_id.to_le_bytes());
+        out.extend_from_slice(&self.start_ns.to_le_bytes());
+        out.extend_from_slice(&self.end_ns.to_le_bytes());
+        out.extend_from_slice(&self.messages.to_le_bytes());
+    }
+
+    fn take_range(reader: &mut ByteReader) -> Result<Self> {
+        Ok(Self {
+            topic_id: reader.u16()?,
+            start_ns: reader.u64()? as i64,
+            end_ns: reader.u64()? as i64,
+            messages: reader.u32()?,
+            offset: 0,
+            length: 0,
+        })
+    }
+}
+
+/// Bytes a record with a body of `body_len` occupies
+pub(crate) fn record_len(body_len: usize) -> u64 {
+    (RECORD_HEADER_LEN + body_len + CHECKSUM_LEN) as u64
+}
+
+pub(crate) fn file_header() -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = VERSION;
+    header
+}
+
+pub(crate) fn check_header(header: &[u8]) -> Result<()> {
+    ensure!(header.len() >= HEADER_LEN && header[..4] == MAGIC, "not a mariposa log container");
+    ensure!(header[4] == VERSION, "unsupported container version {}", header[4]);
+    Ok(())
+}
+
+pub(crate) fn encode_record(opcode: Opcode, body: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(record_len(body.len()) as usize);
+    out.push(opcode as u8);
+    out.extend_from_slice(&u32::try_from(body.len())?.to_le_bytes());
+    out.extend_from_slice(body);
+    let checksum = crc32fast::hash(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    Ok(out)
+}
+
+/// Decode a whole record, checking its length and checksum
+pub(crate) fn decode_record(bytes: &[u8]) -> Result<(Opcode, &[u8])> {
+    ensure!(bytes.len() >= RECORD_HEADER_LEN + CHECKSUM_LEN, "record is too short");
+    let body_len = u32::from_le_bytes(bytes[1..RECORD_HEADER_LEN].try_into()?) as usize;
+    ensure!(record_len(body_len) == bytes.len() as u64, "record length does not match its header");
+    let (record, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    let expected = u32::from_le_bytes(checksum.try_into()?);
+    let actual = crc32fast::hash(record);
+    ensure!(expected == actual, "record checksum mismatch: expected {:08x}, got {:08x}", expected, actual);
+    Ok((Opcode::try_from(bytes[0])?, &record[RECORD_HEADER_LEN..]))
+}
+
+/// Read the next record, or `None` if the `remaining` bytes of data end before
+/// a whole record
+pub(crate) fn read_record(reader: &mut impl Read, remaining: u64) -> Result<Option<(Opcode, Vec<u8>)>> {
+    let mut bytes = vec![0; RECORD_HEADER_LEN];
+    if remaining < record_len(0) || !read_all(reader, &mut bytes)? {
+        return Ok(None);
+    }
+    let body_len = u32::from_le_bytes(bytes[1..].try_into()?) as usize;
+    if record_len(body_len) > remaining {
+        return Ok(None);
+    }
+    bytes.resize(record_len(body_len) as usize, 0);
+    if !read_all(reader, &mut bytes[RECORD_HEADER_LEN..])? {
+        return Ok(None);
+    }
+    let (opcode, body) = decode_record(&bytes)?;
+    Ok(Some((opcode, body.to_vec())))
+}
+
+fn read_all(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write the summary starting at `offset`, then the footer
+pub(crate) fn write_summary(
+    out: &mut impl Write,
+    offset: u64,
+    topics: &[TopicRecord],
+    chunks: &[ChunkIndex],
+) -> Result<()> {
+    for topic in topics {
+        out.write_all(&encode_record(Opcode::Topic, &topic.encode()?)?)?;
+    }
+    for chunk in chunks {
+        out.write_all(&encode_record(Opcode::ChunkIndex, &chunk.encode())?)?;
+    }
+    out.write_all(&encode_record(Opcode::Footer, &offset.to_le_bytes())?)?;
+    out.write_all(&MAGIC)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Offset of the summary, if `tail` holds a valid footer
+pub(crate) fn decode_footer(tail: &[u8]) -> Option<u64> {
+    if tail.len() != FOOTER_LEN || tail[FOOTER_LEN - MAGIC.len()..] != MAGIC {
+        return None;
+    }
+    match decode_record(&tail[..FOOTER_LEN - MAGIC.len()]) {
+        Ok((Opcode::Footer, body)) => Some(u64::from_le_bytes(body.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Topics and chunks of a file
+pub(crate) struct Contents {
+    pub topics: Vec<TopicRecord>,
+    pub chunks: Vec<ChunkIndex>,
+    /// End of the last topic or chunk record
+    pub end: u64,
+}
+
+/// Read the summary of a file whose footer points at `offset`
+pub(crate) fn read_summary(reader: &mut (impl Read + Seek), offset: u64, len: u64) -> Result<Contents> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut contents = Contents {
+        topics: Vec::new(),
+        chunks: Vec::new(),
+        end: offset,
+    };
+    let mut position = offset;
+    loop {
+        let Some((opcode, body)) = read_record(reader, len.saturating_sub(position))? else {
+            bail!("summary is cut short");
+        };
+        position += record_len(body.len());
+        match opcode {
+            Opcode::Topic => contents.topics.push(TopicRecord::decode(&body)?),
+            Opcode::ChunkIndex => contents.chunks.push(ChunkIndex::decode(&body)?),
+            Opcode::Footer => return Ok(contents),
+            Opcode::Chunk => bail!("chunk record in the summary"),
+        }
+    }
+}
+
+/// Rebuild the contents of a file from its records, up to the first one that
+/// is cut short or damaged
+pub(crate) fn scan(reader: &mut (impl Read + Seek), len: u64) -> Result<Contents> {
+    reader.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+    let mut contents = Contents {
+        topics: Vec::new(),
+        chunks: Vec::new(),
+        end: HEADER_LEN as u64,
+    };
+    loop {
+        let (opcode, body) = match read_record(reader, len.saturating_sub(contents.end)) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) => {
+                log::debug!("container records end at {}: {:#}", contents.end, e);
+                break;
+            }
+        };
+        match opcode {
+            Opcode::Topic => contents.topics.push(TopicRecord::decode(&body)?),
+            Opcode::Chunk => contents.chunks.push(ChunkIndex::decode_chunk(&body, contents.end)?.0),
+            // Everything from the summary on is rewritten anyway
+            Opcode::ChunkIndex | Opcode::Footer => break,
+        }
+        contents.end += record_len(body.len());
+    }
+    Ok(contents)
+}
@@ -0,0 +1,37 @@
+// Single file log container with a seekable time index, similar to MCAP
+//
+// A container holds the entries of a recording in per-topic chunks, each a
+// self-contained Arrow IPC stream with the log columns of `crate::logfile`.
+// A summary at the end indexes every chunk by topic and time range, so the
+// reader can binary search its way to a timestamp instead of scanning:
+//
+//   magic "MRPL" | version u8 | reserved [u8; 3]
+//   topic and chunk records, as they were written
+//   summary: topic records, then one chunk index record per chunk
+//   footer record (summary offset) | magic "MRPL"
+//
+//   record: opcode u8 | body_len u32 | body | crc32 u32
+//
+//   topic:       topic_id u16 | topic | Arrow IPC schema
+//   chunk:       topic_id u16 | start_ns i64 | end_ns i64 | messages u32 | Arrow IPC stream
+//   chunk index: topic_id u16 | start_ns i64 | end_ns i64 | messages u32 | offset u64 | length u64
+//
+// All integers are little endian. A topic gets a new id whenever its schema
+// changes. Chunks are written whole and flushed, so a file cut short by a crash
+// loses at most the chunks still open; without a valid footer the reader scans
+// the records up to the first torn one, and `recover` rewrites the summary.
+
+mod format;
+mod reader;
+mod writer;
+
+#[cfg(test)]
+mod tests;
+
+pub use reader::{ContainerReader, TopicSummary};
+pub use writer::{recover, ContainerConfig, ContainerWriter};
+
+// Constants
+pub const MAGIC: [u8; 4] = *b"MRPL";
+pub const VERSION: u8 = 1;
+pub const FILE_EXTENSION: &str = "mlog";
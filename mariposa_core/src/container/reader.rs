@@ -0,0 +1,219 @@
+use anyhow::{anyhow, bail, Result};
+use arrow::datatypes::SchemaRef;
+use arrow::ipc::reader::StreamReader;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::container::format::{
+    check_header, decode_footer, decode_record, read_summary, scan, ChunkIndex, Contents, Opcode, TopicRecord,
+    FOOTER_LEN, HEADER_LEN,
+};
+use crate::logfile::{to_entry, LogEntry};
+
+/// What a container holds for one topic and schema
+#[derive(Debug, Clone)]
+pub struct TopicSummary {
+    pub topic: String,
+    /// Schema of the batches as published
+    pub schema: SchemaRef,
+    pub messages: u64,
+    pub chunks: usize,
+    /// Receive time of the first and last message, if there are any
+    pub time_range: Option<(i64, i64)>,
+}
+
+/// Reads a container back in receive order across its topics, from the start
+/// or from any point in time
+pub struct ContainerReader {
+    path: PathBuf,
+    file: BufReader<File>,
+    topics: Vec<TopicRecord>,
+    chunks: Vec<ChunkIndex>,
+    recovered: bool,
+    cursors: Vec<TopicCursor>,
+}
+
+/// Position in the chunks of one topic id
+struct TopicCursor {
+    topic: String,
+    /// Indices into `ContainerReader::chunks`, in time order
+    chunks: Vec<usize>,
+    /// Next chunk to load
+    position: usize,
+    /// Entries of the loaded chunk not returned yet
+    entries: VecDeque<LogEntry>,
+}
+
+impl ContainerReader {
+    /// Open a container, scanning its records if it has no valid summary
+    /// because writing it was cut short
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let len = file.get_ref().metadata()?.len();
+        let mut header = [0; HEADER_LEN];
+        file.read_exact(&mut header)
+            .map_err(|_| anyhow!("'{}' is not a mariposa log container", path.display()))?;
+        check_header(&header).map_err(|e| anyhow!("'{}': {}", path.display(), e))?;
+
+        let mut footer = None;
+        if len >= (HEADER_LEN + FOOTER_LEN) as u64 {
+            let mut tail = [0; FOOTER_LEN];
+            file.seek(SeekFrom::Start(len - FOOTER_LEN as u64))?;
+            file.read_exact(&mut tail)?;
+            footer = decode_footer(&tail);
+        }
+        let recovered = footer.is_none();
+        let Contents { topics, chunks, .. } = match footer {
+            Some(offset) => read_summary(&mut file, offset, len)
+                .map_err(|e| anyhow!("failed to read the summary of '{}': {:#}", path.display(), e))?,
+            None => {
+                log::warn!("'{}' has no summary, scanning its chunks", path.display());
+                scan(&mut file, len)?
+            }
+        };
+
+        let mut cursors: Vec<_> = topics
+            .iter()
+            .map(|x| TopicCursor {
+                topic: x.topic.clone(),
+                chunks: Vec::new(),
+                position: 0,
+                entries: VecDeque::new(),
+            })
+            .collect();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let Some(cursor) = topics.iter().position(|x| x.id == chunk.topic_id) else {
+                bail!("chunk at {} in '{}' has an unknown topic", chunk.offset, path.display());
+            };
+            cursors[cursor].chunks.push(index);
+        }
+        for cursor in &mut cursors {
+            cursor.chunks.sort_by_key(|x| (chunks[*x].start_ns, chunks[*x].end_ns));
+        }
+
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            file,
+            topics,
+            chunks,
+            recovered,
+            cursors,
+        };
+        reader.seek(i64::MIN)?;
+        Ok(reader)
+    }
+
+    /// Whether the file had no summary and was read by scanning it
+    pub fn is_recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// Every topic and schema in the container with its message count and
+    /// time range
+    pub fn summary(&self) -> Vec<TopicSummary> {
+        self.topics
+            .iter()
+            .map(|topic| {
+                let chunks: Vec<_> = self.chunks.iter().filter(|x| x.topic_id == topic.id).collect();
+                let start = chunks.iter().map(|x| x.start_ns).min();
+                let end = chunks.iter().map(|x| x.end_ns).max();
+                TopicSummary {
+                    topic: topic.topic.clone(),
+                    schema: topic.schema.clone(),
+                    messages: chunks.iter().map(|x| x.messages as u64).sum(),
+                    chunks: chunks.len(),
+                    time_range: start.zip(end),
+                }
+            })
+            .collect()
+    }
+
+    /// Receive time of the first and last message in the container
+    pub fn time_range(&self) -> Option<(i64, i64)> {
+        let start = self.chunks.iter().map(|x| x.start_ns).min();
+        let end = self.chunks.iter().map(|x| x.end_ns).max();
+        start.zip(end)
+    }
+
+    /// Receive time of the next entry
+    pub fn peek_time(&self) -> Option<i64> {
+        self.cursors.iter().filter_map(|x| x.entries.front()).map(|x| x.log_time_ns).min()
+    }
+
+    /// Continue from the first entry received at or after `time_ns`
+    ///
+    /// Finds the chunk of every topic by binary search over its time index,
+    /// so only one chunk per topic is read.
+    pub fn seek(&mut self, time_ns: i64) -> Result<()> {
+        for index in 0..self.cursors.len() {
+            let cursor = &mut self.cursors[index];
+            cursor.position = cursor.chunks.partition_point(|x| self.chunks[*x].end_ns < time_ns);
+            cursor.entries.clear();
+            loop {
+                self.fill(index)?;
+                let entries = &mut self.cursors[index].entries;
+                while entries.front().is_some_and(|x| x.log_time_ns < time_ns) {
+                    entries.pop_front();
+                }
+                let cursor = &self.cursors[index];
+                if !cursor.entries.is_empty() || cursor.position == cursor.chunks.len() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<LogEntry>> {
+        let next = self
+            .cursors
+            .iter()
+            .enumerate()
+            .filter_map(|(index, x)| x.entries.front().map(|x| (x.log_time_ns, index)))
+            .min();
+        let Some((_, index)) = next else {
+            return Ok(None);
+        };
+        let entry = self.cursors[index].entries.pop_front();
+        self.fill(index)?;
+        Ok(entry)
+    }
+
+    /// Load chunks of a topic until it has entries or none are left
+    fn fill(&mut self, index: usize) -> Result<()> {
+        let cursor = &mut self.cursors[index];
+        while cursor.entries.is_empty() && cursor.position < cursor.chunks.len() {
+            let chunk = self.chunks[cursor.chunks[cursor.position]];
+            cursor.position += 1;
+            cursor.entries = read_chunk(&mut self.file, &chunk, &cursor.topic)
+                .map_err(|e| anyhow!("failed to read chunk at {} in '{}': {:#}", chunk.offset, self.path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ContainerReader {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+fn read_chunk(file: &mut BufReader<File>, chunk: &ChunkIndex, topic: &str) -> Result<VecDeque<LogEntry>> {
+    file.seek(SeekFrom::Start(chunk.offset))?;
+    let mut bytes = vec![0; chunk.length as usize];
+    file.read_exact(&mut bytes)?;
+    let (opcode, body) = decode_record(&bytes)?;
+    if opcode != Opcode::Chunk {
+        bail!("expected a chunk record, found {:?}", opcode);
+    }
+    let (_, payload) = ChunkIndex::decode_chunk(body, chunk.offset)?;
+    let batches = StreamReader::try_new(payload, None)?;
+    batches
+        .filter(|x| !x.as_ref().is_ok_and(|x| x.num_rows() == 0))
+        .map(|x| to_entry(topic, &x?))
+        .collect()
+}
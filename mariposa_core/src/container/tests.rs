@@ -0,0 +1,120 @@
+use arrow::record_batch::RecordBatch;
+use arrow_array::StringArray;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::container::{recover, ContainerConfig, ContainerReader, ContainerWriter};
+use crate::logfile::LogEntry;
+use crate::test_util::{entry, speed_batch};
+
+/// Entries 10ns apart alternating between two topics
+fn entries(count: i64) -> Vec<LogEntry> {
+    (0..count)
+        .map(|time| {
+            let topic = if time % 2 == 0 { "vehicle/speed" } else { "vehicle/odometry" };
+            entry(topic, time * 10, speed_batch(vec![time as i32], vec![time as f64]))
+        })
+        .collect()
+}
+
+fn chunked(duration_ns: u64) -> ContainerConfig {
+    ContainerConfig {
+        chunk_bytes: usize::MAX,
+        chunk_duration: Some(Duration::from_nanos(duration_ns)),
+    }
+}
+
+fn assert_same(read: &[LogEntry], written: &[LogEntry]) {
+    assert_eq!(read.len(), written.len());
+    for (read, written) in read.iter().zip(written) {
+        assert_eq!(read.topic, written.topic);
+        assert_eq!(read.log_time_ns, written.log_time_ns);
+        assert_eq!(read.publish_time_ns, written.publish_time_ns);
+        assert_eq!(read.publisher_id, written.publisher_id);
+        assert_eq!(read.batch, written.batch);
+    }
+}
+
+#[test]
+fn test_container_round_trip_and_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("drive.mlog");
+    let written = entries(100);
+    let mut writer = ContainerWriter::create(&path, chunked(80)).unwrap();
+    for entry in &written {
+        writer.write(entry).unwrap();
+    }
+    let names = RecordBatch::try_from_iter([("name", Arc::new(StringArray::from(vec!["a"])) as _)]).unwrap();
+    writer.write(&entry("vehicle/speed", 1000, names)).unwrap();
+    writer.finish().unwrap();
+
+    let reader = ContainerReader::open(&path).unwrap();
+    assert!(!reader.is_recovered());
+    assert_eq!(reader.time_range(), Some((0, 1000)));
+    let summary = reader.summary();
+    assert_eq!(summary.len(), 3);
+    assert_eq!(summary[0].topic, "vehicle/speed");
+    assert_eq!(summary[0].messages, 50);
+    assert_eq!(summary[0].chunks, 10);
+    assert_eq!(summary[0].time_range, Some((0, 980)));
+    assert_eq!(summary[1].topic, "vehicle/odometry");
+    assert_eq!(summary[2].topic, "vehicle/speed");
+    assert_eq!(summary[2].schema.field(0).name(), "name");
+    assert_eq!(summary[2].messages, 1);
+
+    let read: Vec<_> = reader.map(|x| x.unwrap()).collect();
+    assert_same(&read[..100], &written);
+    assert_eq!(read[100].batch.num_columns(), 1);
+}
+
+#[test]
+fn test_container_seeks_by_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("drive.mlog");
+    let written = entries(200);
+    let mut writer = ContainerWriter::create(&path, chunked(45)).unwrap();
+    for entry in &written {
+        writer.write(entry).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut reader = ContainerReader::open(&path).unwrap();
+    reader.seek(1234).unwrap();
+    assert_eq!(reader.peek_time(), Some(1240));
+    let read: Vec<_> = reader.by_ref().map(|x| x.unwrap()).collect();
+    assert_same(&read, &written[124..]);
+
+    // Seeking back works too, and past the end leaves nothing
+    reader.seek(0).unwrap();
+    assert_eq!(reader.by_ref().count(), 200);
+    reader.seek(5000).unwrap();
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn test_container_recovers_from_a_crash() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("drive.mlog");
+    let written = entries(45);
+    let mut writer = ContainerWriter::create(&path, chunked(80)).unwrap();
+    for entry in &written {
+        writer.write(entry).unwrap();
+    }
+    // The process dies before the open chunks and the summary are written,
+    // halfway through another record
+    std::mem::forget(writer);
+    OpenOptions::new().append(true).open(&path).unwrap().write_all(&[2, 255, 0, 0, 0, 1, 2]).unwrap();
+
+    let reader = ContainerReader::open(&path).unwrap();
+    assert!(reader.is_recovered());
+    let read: Vec<_> = reader.map(|x| x.unwrap()).collect();
+    assert_same(&read, &written[..40]);
+
+    assert!(recover(&path).unwrap());
+    let reader = ContainerReader::open(&path).unwrap();
+    assert!(!reader.is_recovered());
+    assert_eq!(reader.summary()[0].messages, 20);
+    assert_eq!(reader.count(), 40);
+    assert!(!recover(&path).unwrap());
+}
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Result};
+use arrow::datatypes::SchemaRef;
+use arrow::ipc::writer::StreamWriter;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::container::format::{
+    check_header, decode_footer, encode_record, file_header, record_len, scan, write_summary, ChunkIndex, Opcode,
+    TopicRecord, FOOTER_LEN, HEADER_LEN,
+};
+use crate::logfile::{logged_batch, logged_schema, LogEntry};
+
+/// When to close a chunk and start the next one
+#[derive(Debug, Clone)]
+pub struct ContainerConfig {
+    /// Close a chunk once its Arrow IPC stream holds this many bytes
+    pub chunk_bytes: usize,
+    /// Close a chunk once it spans this much time
+    pub chunk_duration: Option<Duration>,
+}
+
+impl Default for ContainerConfig {
+    fn default() -> Self {
+        Self {
+            chunk_bytes: 1024 * 1024,
+            chunk_duration: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+/// Writes entries into a container file, one chunk at a time per topic
+///
+/// Entries of a topic should be written in the order they were received so
+/// the chunks of each topic follow each other in time.
+pub struct ContainerWriter {
+    out: Option<BufWriter<File>>,
+    config: ContainerConfig,
+    /// Where the next record goes
+    offset: u64,
+    topics: Vec<OpenTopic>,
+    /// Id of the latest schema of each topic
+    current: HashMap<String, u16>,
+    chunks: Vec<ChunkIndex>,
+}
+
+struct OpenTopic {
+    record: TopicRecord,
+    /// Schema of the chunks, with the log columns
+    logged: SchemaRef,
+    chunk: Option<OpenChunk>,
+}
+
+struct OpenChunk {
+    writer: StreamWriter<Vec<u8>>,
+    start_ns: i64,
+    end_ns: i64,
+    messages: u32,
+}
+
+impl ContainerWriter {
+    pub fn create(path: &Path, config: ContainerConfig) -> Result<Self> {
+        let mut out = BufWriter::new(File::create_new(path)?);
+        out.write_all(&file_header())?;
+        out.flush()?;
+        Ok(Self {
+            out: Some(out),
+            config,
+            offset: HEADER_LEN as u64,
+            topics: Vec::new(),
+            current: HashMap::new(),
+            chunks: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        let schema = entry.batch.schema();
+        let id = match self.current.get(&entry.topic).copied() {
+            Some(id) if self.topics[id as usize].record.schema == schema => id,
+            previous => {
+                if let Some(id) = previous {
+                    self.close_chunk(id)?;
+                }
+                self.add_topic(&entry.topic, schema)?
+            }
+        };
+
+        let topic = &mut self.topics[id as usize];
+        let chunk = match &mut topic.chunk {
+            Some(chunk) => chunk,
+            None => topic.chunk.insert(OpenChunk {
+                writer: StreamWriter::try_new(Vec::new(), &topic.logged)?,
+                start_ns: entry.log_time_ns,
+                end_ns: entry.log_time_ns,
+                messages: 0,
+            }),
+        };
+        chunk.writer.write(&logged_batch(topic.logged.clone(), entry)?)?;
+        chunk.messages += 1;
+        chunk.start_ns = chunk.start_ns.min(entry.log_time_ns);
+        chunk.end_ns = chunk.end_ns.max(entry.log_time_ns);
+
+        let too_big = chunk.writer.get_ref().len() >= self.config.chunk_bytes;
+        let too_long = self
+            .config
+            .chunk_duration
+            .is_some_and(|x| chunk.end_ns.saturating_sub(chunk.start_ns) >= x.as_nanos() as i64);
+        if too_big || too_long || chunk.messages == u32::MAX {
+            self.close_chunk(id)?;
+        }
+        Ok(())
+    }
+
+    /// Write out the open chunks and the summary
+    pub fn finish(mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn add_topic(&mut self, topic: &str, schema: SchemaRef) -> Result<u16> {
+        let id = u16::try_from(self.topics.len()).map_err(|_| anyhow!("too many topics in one container"))?;
+        let record = TopicRecord {
+            id,
+            topic: topic.to_string(),
+            schema,
+        };
+        let logged = Arc::new(logged_schema(topic, &record.schema)?);
+        self.write_record(Opcode::Topic, &record.encode()?)?;
+        self.topics.push(OpenTopic {
+            record,
+            logged,
+            chunk: None,
+        });
+        self.current.insert(topic.to_string(), id);
+        Ok(id)
+    }
+
+    fn close_chunk(&mut self, id: u16) -> Result<()> {
+        let Some(chunk) = self.topics[id as usize].chunk.take() else {
+            return Ok(());
+        };
+        let mut writer = chunk.writer;
+        writer.finish()?;
+        let mut index = ChunkIndex {
+            topic_id: id,
+            start_ns: chunk.start_ns,
+            end_ns: chunk.end_ns,
+            messages: chunk.messages,
+            offset: self.offset,
+            length: 0,
+        };
+        index.length = self.write_record(Opcode::Chunk, &index.encode_chunk(&writer.into_inner()?)?)?;
+        self.chunks.push(index);
+        // A crash from here on no longer loses the chunk
+        self.out()?.flush()?;
+        Ok(())
+    }
+
+    /// Append a record and return its length
+    fn write_record(&mut self, opcode: Opcode, body: &[u8]) -> Result<u64> {
+        self.out()?.write_all(&encode_record(opcode, body)?)?;
+        let length = record_len(body.len());
+        self.offset += length;
+        Ok(length)
+    }
+
+    fn out(&mut self) -> Result<&mut BufWriter<File>> {
+        self.out.as_mut().ok_or_else(|| anyhow!("container is already finished"))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if self.out.is_none() {
+            return Ok(());
+        }
+        for id in 0..self.topics.len() {
+            self.close_chunk(id as u16)?;
+        }
+        let topics: Vec<_> = self.topics.iter().map(|x| x.record.clone()).collect();
+        if let Some(mut out) = self.out.take() {
+            write_summary(&mut out, self.offset, &topics, &self.chunks)?;
+            out.get_ref().sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ContainerWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::warn!("failed to finish log container: {:#}", e);
+        }
+    }
+}
+
+/// Make a container cut short by a crash readable by index again
+///
+/// Drops whatever follows the last whole chunk and writes a new summary for
+/// the chunks before it. Returns false if the file was complete already.
+pub fn recover(path: &Path) -> Result<bool> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut header = [0; HEADER_LEN];
+    file.read_exact(&mut header)?;
+    check_header(&header)?;
+    if len >= (HEADER_LEN + FOOTER_LEN) as u64 {
+        let mut tail = [0; FOOTER_LEN];
+        file.seek(SeekFrom::Start(len - FOOTER_LEN as u64))?;
+        file.read_exact(&mut tail)?;
+        if decode_footer(&tail).is_some() {
+            return Ok(false);
+        }
+    }
+
+    let contents = scan(&mut BufReader::new(&file), len)?;
+    log::info!(
+        "recovered {} chunks from '{}', dropping {} bytes",
+        contents.chunks.len(),
+        path.display(),
+        len - contents.end
+    );
+    file.set_len(contents.end)?;
+    file.seek(SeekFrom::Start(contents.end))?;
+    let mut out = BufWriter::new(&file);
+    write_summary(&mut out, contents.end, &contents.topics, &contents.chunks)?;
+    drop(out);
+    file.sync_all()?;
+    Ok(true)
+}
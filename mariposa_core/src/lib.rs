@@ -5,6 +5,35 @@ pub fn add(left: u64, right: u64) -> u64 {
 // Protobuf to Arrow and Arrow to Protobuf conversion
 pub mod ptars;
 
+// Framing of Arrow IPC messages exchanged between clients and the broker
+pub mod wire;
+
+// Topic names and wildcard subscription matching
+pub mod topic;
+
+// Fragmented, best-effort delivery of batches over UDP and multicast
+pub mod udp;
+
+// Recording of topics to Arrow IPC files
+pub mod logfile;
+
+// Single file recordings with a seekable time index
+pub mod container;
+
+// Import of ROS 1 bags as recordings
+pub mod rosbag;
+
+// Typed values of the parameters hosted by the broker
+pub mod parameter;
+
+// Memory-mapped rings for passing batches between processes on one host
+#[cfg(target_os = "linux")]
+pub mod shm;
+
+// Fixtures shared by the tests of several modules
+#[cfg(test)]
+pub(crate) mod test_util;
+
 /// Example of using the ptars module to convert between protobuf and arrow
 /// 
 /// ```rust,ignore
@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::logfile::{LogEntry, LogWriter};
+
+/// Runs a `LogWriter` on its own thread so recording never blocks the caller
+///
+/// Entries that arrive while `capacity` of them are still waiting to be
+/// written are dropped and counted.
+pub struct BackgroundLogWriter {
+    sender: SyncSender<LogEntry>,
+    dropped: Arc<AtomicU64>,
+    thread: JoinHandle<Result<Vec<PathBuf>>>,
+}
+
+impl BackgroundLogWriter {
+    pub fn spawn(mut writer: LogWriter, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<LogEntry>(capacity.max(1));
+        let thread = std::thread::spawn(move || {
+            for entry in receiver {
+                if let Err(e) = writer.write(&entry) {
+                    log::warn!("failed to record a batch on '{}': {:#}", entry.topic, e);
+                }
+            }
+            writer.finish()
+        });
+        Self {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+            thread,
+        }
+    }
+
+    /// Queue an entry, returning false if it had to be dropped
+    pub fn try_write(&self, entry: LogEntry) -> bool {
+        match self.sender.try_send(entry) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Number of entries dropped because the writer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Write out what is queued, close every file and return the segment
+    /// directories
+    ///
+    /// Blocks until the writer thread is done.
+    pub fn finish(self) -> Result<Vec<PathBuf>> {
+        drop(self.sender);
+        self.thread.join().map_err(|_| anyhow!("log writer thread panicked"))?
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Context, Result};
+use mcap::records::MessageHeader;
+use mcap::{MessageStream, WriteOptions, Writer};
+use memmap2::Mmap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::logfile::{LogConfig, LogEntry, LogReader, LogWriter, PROTOBUF_ENCODING};
+use crate::ptars::{message_type, with_message_type, MessageHandler, ProtoCache};
+
+/// A channel being exported to and how to turn its batches into messages
+struct ExportChannel {
+    id: u16,
+    handler: MessageHandler,
+    sequence: u32,
+}
+
+/// Export a recording as an MCAP file and return the number of messages
+/// written
+///
+/// Every topic published as protobuf messages becomes a protobuf channel whose
+/// schema is the descriptor set of its message type from `types`. Each row of
+/// a batch is written as one message, stamped with the times of the batch.
+/// Topics without a message type have no MCAP encoding and are skipped.
+pub fn export_mcap(log: &Path, path: &Path, types: &ProtoCache) -> Result<u64> {
+    let reader = LogReader::open(log)?;
+    let mut writer = Writer::with_options(
+        BufWriter::new(File::create_new(path)?),
+        WriteOptions::new().library("mariposa"),
+    )?;
+    let mut channels: HashMap<(String, String), ExportChannel> = HashMap::new();
+    let mut skipped = HashSet::new();
+    let mut written = 0;
+    for entry in reader {
+        let entry = entry?;
+        let Some(name) = message_type(&entry.batch.schema()).map(|x| x.to_string()) else {
+            if skipped.insert(entry.topic.clone()) {
+                log::warn!("skipping '{}', its batches have no protobuf message type", entry.topic);
+            }
+            continue;
+        };
+        let key = (entry.topic.clone(), name);
+        let channel = match channels.get_mut(&key) {
+            Some(channel) => channel,
+            None => {
+                let (topic, name) = &key;
+                let descriptors = types
+                    .descriptor_set(name)
+                    .with_context(|| format!("no descriptor for topic '{}'", topic))?;
+                let schema_id = writer.add_schema(name, PROTOBUF_ENCODING, &descriptors)?;
+                let id = writer.add_channel(schema_id, topic, PROTOBUF_ENCODING, &BTreeMap::new())?;
+                let channel = ExportChannel {
+                    id,
+                    handler: types.handler_for(name)?,
+                    sequence: 0,
+                };
+                channels.entry(key).or_insert(channel)
+            }
+        };
+        for payload in channel.handler.record_batch_to_array(&entry.batch) {
+            let header = MessageHeader {
+                channel_id: channel.id,
+                sequence: channel.sequence,
+                log_time: entry.log_time_ns as u64,
+                publish_time: entry.publish_time_ns as u64,
+            };
+            writer.write_to_known_channel(&header, &payload)?;
+            channel.sequence = channel.sequence.wrapping_add(1);
+            written += 1;
+        }
+    }
+    writer.finish()?;
+    Ok(written)
+}
+
+/// Import the protobuf channels of an MCAP file as a recording and return its
+/// segment directories
+///
+/// Messages are converted to RecordBatches with the descriptors the file
+/// carries. Consecutive messages on a channel with the same log and publish
+/// time become one batch, so files written by `export_mcap` come back as they
+/// were recorded. The publisher of every entry is 0, MCAP has no equivalent.
+pub fn import_mcap(path: &Path, config: LogConfig) -> Result<Vec<PathBuf>> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is only read, and the file changing underneath an
+    // import is a caller error as it is for any other reader
+    let mapped = unsafe { Mmap::map(&file)? };
+    let mut writer = LogWriter::create(config)?;
+    let mut types = ProtoCache::new();
+    let mut handlers: HashMap<u16, (String, MessageHandler)> = HashMap::new();
+    let mut skipped = HashSet::new();
+    let mut pending: Option<Pending> = None;
+
+    for message in MessageStream::new(&mapped).map_err(|e| anyhow!("failed to read '{}': {}", path.display(), e))? {
+        let message = message.map_err(|e| anyhow!("failed to read '{}': {}", path.display(), e))?;
+        let channel = &message.channel;
+        let schema = channel
+            .schema
+            .as_ref()
+            .filter(|x| x.encoding == PROTOBUF_ENCODING && channel.message_encoding == PROTOBUF_ENCODING);
+        let Some(schema) = schema else {
+            if skipped.insert(channel.topic.clone()) {
+                log::warn!("skipping '{}', it is not a protobuf channel", channel.topic);
+            }
+            continue;
+        };
+        if let Entry::Vacant(handler) = handlers.entry(channel.id) {
+            types
+                .add_descriptor_set(&schema.data)
+                .with_context(|| format!("invalid descriptors for '{}'", channel.topic))?;
+            handler.insert((schema.name.clone(), types.handler_for(&schema.name)?));
+        }
+
+        let key = (channel.id, message.log_time as i64, message.publish_time as i64);
+        if pending.as_ref().is_some_and(|x| x.key != key) {
+            if let Some(batch) = pending.take() {
+                writer.write(&batch.to_entry(&handlers)?)?;
+            }
+        }
+        pending
+            .get_or_insert_with(|| Pending {
+                key,
+                topic: channel.topic.clone(),
+                payloads: Vec::new(),
+            })
+            .payloads
+            .push(message.data.into_owned());
+    }
+    if let Some(batch) = pending.take() {
+        writer.write(&batch.to_entry(&handlers)?)?;
+    }
+    writer.finish()
+}
+
+/// Messages collected into the next entry
+struct Pending {
+    /// Channel, log time and publish time shared by the messages
+    key: (u16, i64, i64),
+    topic: String,
+    payloads: Vec<Vec<u8>>,
+}
+
+impl Pending {
+    fn to_entry(&self, handlers: &HashMap<u16, (String, MessageHandler)>) -> Result<LogEntry> {
+        let (channel, log_time_ns, publish_time_ns) = self.key;
+        let (name, handler) = &handlers[&channel];
+        Ok(LogEntry {
+            topic: self.topic.clone(),
+            publisher_id: 0,
+            publish_time_ns,
+            log_time_ns,
+            batch: with_message_type(handler.slice_to_record_batch(&self.payloads), name)?,
+        })
+    }
+}
@@ -0,0 +1,57 @@
+// Recording of topics to Arrow IPC files, ROS bag style
+//
+// A recording is a series of segment directories, a new one started whenever
+// the current one grows past a size or time limit:
+//
+//   <dir>/<prefix>_<YYYYMMDD-HHMMSS>_<index>/<topic>.arrow
+//
+// Each topic gets one Arrow IPC file (Feather v2) per segment, named after the
+// topic with '%' and '/' percent-encoded. If a topic's schema changes, the file
+// is closed and the rest goes to `<topic>.<part>.arrow`. Rows carry three extra
+// leading columns: when the recorder received them, when they were published
+// and by whom. The topic is stored in the schema metadata, and the footer
+// holds the message count and the time range of the file. `LogReader` merges
+// the files of a recording back into a single stream ordered by receive time.
+//
+// A recording can also be exported as one Parquet file per topic, with the
+// same columns and the protobuf descriptor of the topic's message type in the
+// file metadata, and imported back from those files. For MCAP tooling such as
+// Foxglove, topics published as protobuf messages export to an MCAP file with
+// one message per row, and MCAP files with protobuf channels import as logs.
+
+mod background;
+mod mcap;
+mod parquet;
+mod reader;
+mod writer;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::mcap::{export_mcap, import_mcap};
+pub use self::parquet::{export_parquet, import_parquet, ParquetConfig};
+pub use background::BackgroundLogWriter;
+pub use reader::LogReader;
+pub use writer::{LogConfig, LogEntry, LogWriter};
+pub(crate) use reader::to_entry;
+pub(crate) use writer::{logged_batch, logged_schema};
+
+// Constants
+pub const FILE_EXTENSION: &str = "arrow";
+pub const PARQUET_EXTENSION: &str = "parquet";
+/// Schema metadata key holding the topic of a log file
+pub const TOPIC_KEY: &str = "mariposa.topic";
+/// Receive time of a row, as a UTC timestamp in nanoseconds
+pub const LOG_TIME_COLUMN: &str = "_log_time";
+/// Time the publisher stamped the batch of a row with
+pub const PUBLISH_TIME_COLUMN: &str = "_publish_time";
+pub const PUBLISHER_COLUMN: &str = "_publisher_id";
+/// Footer metadata keys summarising a file
+pub const MESSAGE_COUNT_KEY: &str = "mariposa.message_count";
+pub const START_TIME_KEY: &str = "mariposa.start_time";
+pub const END_TIME_KEY: &str = "mariposa.end_time";
+/// Parquet metadata key holding the base64 encoded `FileDescriptorSet` of a
+/// topic's message type
+pub const DESCRIPTOR_SET_KEY: &str = "mariposa.descriptor_set";
+/// Schema and message encoding of MCAP channels carrying protobuf messages
+pub const PROTOBUF_ENCODING: &str = "protobuf";
@@ -0,0 +1,271 @@
+use anyhow::{anyhow, bail, Result};
+use arrow::array::{AsArray, RecordBatch};
+use arrow::compute::concat_batches;
+use arrow::datatypes::{Schema, SchemaRef, TimestampNanosecondType, UInt64Type};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::logfile::reader::{log_files, to_entry, LOG_COLUMNS};
+use crate::logfile::writer::{file_name, logged_batch, logged_schema};
+use crate::logfile::{
+    LogConfig, LogEntry, LogReader, LogWriter, DESCRIPTOR_SET_KEY, END_TIME_KEY, MESSAGE_COUNT_KEY, PARQUET_EXTENSION,
+    START_TIME_KEY, TOPIC_KEY,
+};
+use crate::ptars::{message_type, ProtoCache, MESSAGE_TYPE_KEY};
+
+/// How exported Parquet files are laid out
+#[derive(Debug, Clone)]
+pub struct ParquetConfig {
+    /// Most rows in one row group
+    pub row_group_rows: usize,
+    pub compression: Compression,
+}
+
+impl Default for ParquetConfig {
+    fn default() -> Self {
+        Self {
+            row_group_rows: 64 * 1024,
+            compression: Compression::ZSTD(ZstdLevel::default()),
+        }
+    }
+}
+
+/// Log columns of a row, identifying the published batch it came from
+type RowKey = (i64, i64, u64);
+
+/// Export a recording as one Parquet file per topic in `dir` and return the
+/// files written
+///
+/// Rows keep the log columns of the recording. Topics published as protobuf
+/// messages also get the descriptor set of their message type from `types`,
+/// base64 encoded under `DESCRIPTOR_SET_KEY`, so the files can be decoded
+/// without the .proto sources.
+pub fn export_parquet(
+    log: &Path,
+    dir: &Path,
+    config: &ParquetConfig,
+    types: Option<&ProtoCache>,
+) -> Result<Vec<PathBuf>> {
+    let reader = LogReader::open(log)?;
+    fs::create_dir_all(dir)?;
+    let mut files: HashMap<String, ParquetFile> = HashMap::new();
+    let mut parts: HashMap<String, usize> = HashMap::new();
+    let mut paths = Vec::new();
+    for entry in reader {
+        let entry = entry?;
+        let schema = entry.batch.schema();
+        if files.get(&entry.topic).is_some_and(|x| x.schema != schema) {
+            if let Some(file) = files.remove(&entry.topic) {
+                file.finish()?;
+            }
+        }
+        let file = match files.get_mut(&entry.topic) {
+            Some(file) => file,
+            None => {
+                let part = parts.entry(entry.topic.clone()).or_insert(0);
+                let path = dir.join(file_name(&entry.topic, *part, PARQUET_EXTENSION));
+                *part += 1;
+                let file = ParquetFile::create(&path, &entry.topic, schema, config, types)?;
+                paths.push(path);
+                files.entry(entry.topic.clone()).or_insert(file)
+            }
+        };
+        file.write(&entry)?;
+    }
+    for (_, file) in files {
+        file.finish()?;
+    }
+    Ok(paths)
+}
+
+/// Import Parquet files written by `export_parquet`, a single file or a
+/// directory of them, as a recording and return its segment directories
+///
+/// Consecutive rows sharing their log columns become one batch again, so the
+/// recording plays back as it was published.
+pub fn import_parquet(path: &Path, config: LogConfig) -> Result<Vec<PathBuf>> {
+    let paths = log_files(path, PARQUET_EXTENSION)?;
+    if paths.is_empty() {
+        bail!("no parquet files in '{}'", path.display());
+    }
+    let mut files = paths.iter().map(|x| ParquetReader::open(x)).collect::<Result<Vec<_>>>()?;
+    let mut writer = LogWriter::create(config)?;
+    loop {
+        let next = files
+            .iter_mut()
+            .filter(|x| x.next.is_some())
+            .min_by_key(|x| x.next.as_ref().map(|x| x.log_time_ns));
+        let Some(file) = next else {
+            break;
+        };
+        if let Some(entry) = file.next.take() {
+            writer.write(&entry)?;
+        }
+        file.advance()?;
+    }
+    writer.finish()
+}
+
+/// A topic's Parquet file being exported to
+struct ParquetFile {
+    writer: ArrowWriter<File>,
+    /// Schema of the batches as published, without the log columns
+    schema: SchemaRef,
+    logged: SchemaRef,
+    messages: u64,
+    start_ns: i64,
+    end_ns: i64,
+}
+
+impl ParquetFile {
+    fn create(
+        path: &Path,
+        topic: &str,
+        schema: SchemaRef,
+        config: &ParquetConfig,
+        types: Option<&ProtoCache>,
+    ) -> Result<Self> {
+        // Also kept outside the Arrow schema for readers that ignore it
+        let mut metadata = vec![KeyValue::new(TOPIC_KEY.to_string(), topic.to_string())];
+        if let Some(name) = message_type(&schema) {
+            metadata.push(KeyValue::new(MESSAGE_TYPE_KEY.to_string(), name.to_string()));
+            match types.map(|x| x.descriptor_set(name)) {
+                Some(Ok(set)) => metadata.push(KeyValue::new(DESCRIPTOR_SET_KEY.to_string(), BASE64.encode(set))),
+                Some(Err(e)) => log::warn!("exporting '{}' without a descriptor: {:#}", topic, e),
+                None => {}
+            }
+        }
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(config.row_group_rows.max(1))
+            .set_compression(config.compression)
+            .set_key_value_metadata(Some(metadata))
+            .build();
+        let logged = Arc::new(logged_schema(topic, &schema)?);
+        let writer = ArrowWriter::try_new(File::create_new(path)?, logged.clone(), Some(properties))?;
+        Ok(Self {
+            writer,
+            schema,
+            logged,
+            messages: 0,
+            start_ns: i64::MAX,
+            end_ns: i64::MIN,
+        })
+    }
+
+    fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        self.writer.write(&logged_batch(self.logged.clone(), entry)?)?;
+        self.messages += 1;
+        self.start_ns = self.start_ns.min(entry.log_time_ns);
+        self.end_ns = self.end_ns.max(entry.log_time_ns);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        for (key, value) in [
+            (MESSAGE_COUNT_KEY, self.messages.to_string()),
+            (START_TIME_KEY, self.start_ns.to_string()),
+            (END_TIME_KEY, self.end_ns.to_string()),
+        ] {
+            self.writer.append_key_value_metadata(KeyValue::new(key.to_string(), value));
+        }
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+/// One topic's Parquet file being imported and the entry it will return next
+struct ParquetReader {
+    path: PathBuf,
+    topic: String,
+    /// Schema of the rows without the file summary keys
+    schema: SchemaRef,
+    batches: ParquetRecordBatchReader,
+    /// Rows read so far grouped by published batch; the last group may
+    /// continue in the next batch read
+    pending: VecDeque<(RowKey, RecordBatch)>,
+    next: Option<LogEntry>,
+}
+
+impl ParquetReader {
+    fn open(path: &Path) -> Result<Self> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .map_err(|e| anyhow!("failed to open '{}': {}", path.display(), e))?;
+        // The file's key value metadata is merged into the schema's
+        let mut metadata = builder.schema().metadata().clone();
+        let topic = metadata
+            .get(TOPIC_KEY)
+            .cloned()
+            .ok_or_else(|| anyhow!("'{}' is not an exported mariposa log", path.display()))?;
+        for key in [MESSAGE_COUNT_KEY, START_TIME_KEY, END_TIME_KEY, DESCRIPTOR_SET_KEY] {
+            metadata.remove(key);
+        }
+        let schema = Arc::new(Schema::new_with_metadata(builder.schema().fields().clone(), metadata));
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            topic,
+            schema,
+            batches: builder.build()?,
+            pending: VecDeque::new(),
+            next: None,
+        };
+        reader.advance()?;
+        Ok(reader)
+    }
+
+    /// Load the next entry
+    fn advance(&mut self) -> Result<()> {
+        // A group is only complete once a different one follows it
+        while self.pending.len() < 2 {
+            let Some(batch) = self.batches.next() else {
+                break;
+            };
+            let batch = batch.map_err(|e| anyhow!("failed to read '{}': {}", self.path.display(), e))?;
+            let batch = RecordBatch::try_new(self.schema.clone(), batch.columns().to_vec())?;
+            for (key, rows) in split_rows(&self.topic, &batch)? {
+                match self.pending.back_mut() {
+                    Some((last, group)) if *last == key => *group = concat_batches(&self.schema, [&*group, &rows])?,
+                    _ => self.pending.push_back((key, rows)),
+                }
+            }
+        }
+        self.next = match self.pending.pop_front() {
+            Some((_, rows)) => Some(to_entry(&self.topic, &rows)?),
+            None => None,
+        };
+        Ok(())
+    }
+}
+
+/// Split rows into runs that share their log columns
+fn split_rows(topic: &str, batch: &RecordBatch) -> Result<Vec<(RowKey, RecordBatch)>> {
+    if batch.num_columns() < LOG_COLUMNS {
+        bail!("parquet file for '{}' is missing its log columns", topic);
+    }
+    let log_time_ns = batch.column(0).as_primitive_opt::<TimestampNanosecondType>();
+    let publish_time_ns = batch.column(1).as_primitive_opt::<TimestampNanosecondType>();
+    let publisher_id = batch.column(2).as_primitive_opt::<UInt64Type>();
+    let (Some(log_time_ns), Some(publish_time_ns), Some(publisher_id)) = (log_time_ns, publish_time_ns, publisher_id)
+    else {
+        bail!("parquet file for '{}' has log columns of the wrong type", topic);
+    };
+    let key = |row: usize| (log_time_ns.value(row), publish_time_ns.value(row), publisher_id.value(row));
+
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for row in 1..=batch.num_rows() {
+        if row == batch.num_rows() || key(row) != key(start) {
+            runs.push((key(start), batch.slice(start, row - start)));
+            start = row;
+        }
+    }
+    Ok(runs)
+}
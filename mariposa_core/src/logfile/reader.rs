@@ -0,0 +1,157 @@
+use anyhow::{anyhow, bail, Result};
+use arrow::array::{AsArray, RecordBatch};
+use arrow::datatypes::{Schema, TimestampNanosecondType, UInt64Type};
+use arrow::ipc::reader::FileReader;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::logfile::{LogEntry, FILE_EXTENSION, LOG_TIME_COLUMN, PUBLISHER_COLUMN, PUBLISH_TIME_COLUMN, TOPIC_KEY};
+
+/// Number of leading columns a log file adds to every batch
+pub(crate) const LOG_COLUMNS: usize = 3;
+
+/// Reads a recording back as the entries it was written from, in the order
+/// they were received across all of its topics
+pub struct LogReader {
+    files: Vec<TopicReader>,
+}
+
+/// One topic file and the entry it will return next
+struct TopicReader {
+    path: PathBuf,
+    topic: String,
+    batches: FileReader<BufReader<File>>,
+    next: Option<LogEntry>,
+}
+
+impl LogReader {
+    /// Open a single log file, a segment directory or a directory holding
+    /// the segments of a recording
+    pub fn open(path: &Path) -> Result<Self> {
+        let paths = log_files(path, FILE_EXTENSION)?;
+        if paths.is_empty() {
+            bail!("no log files in '{}'", path.display());
+        }
+        let files = paths.iter().map(|x| TopicReader::open(x)).collect::<Result<_>>()?;
+        Ok(Self { files })
+    }
+
+    /// Every topic in the recording
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics: Vec<_> = self.files.iter().map(|x| x.topic.clone()).collect();
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// Receive time of the next entry
+    pub fn peek_time(&self) -> Option<i64> {
+        self.files.iter().filter_map(|x| x.next.as_ref()).map(|x| x.log_time_ns).min()
+    }
+
+    fn next_entry(&mut self) -> Result<Option<LogEntry>> {
+        let next = self
+            .files
+            .iter_mut()
+            .filter(|x| x.next.is_some())
+            .min_by_key(|x| x.next.as_ref().map(|x| x.log_time_ns));
+        let Some(file) = next else {
+            return Ok(None);
+        };
+        let entry = file.next.take();
+        file.advance()?;
+        Ok(entry)
+    }
+}
+
+impl Iterator for LogReader {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+impl TopicReader {
+    fn open(path: &Path) -> Result<Self> {
+        let batches = FileReader::try_new_buffered(File::open(path)?, None)
+            .map_err(|e| anyhow!("failed to open '{}': {}", path.display(), e))?;
+        let topic = batches
+            .schema()
+            .metadata()
+            .get(TOPIC_KEY)
+            .cloned()
+            .ok_or_else(|| anyhow!("'{}' is not a mariposa log file", path.display()))?;
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            topic,
+            batches,
+            next: None,
+        };
+        reader.advance()?;
+        Ok(reader)
+    }
+
+    /// Load the next non-empty batch
+    fn advance(&mut self) -> Result<()> {
+        self.next = None;
+        for batch in self.batches.by_ref() {
+            let batch = batch.map_err(|e| anyhow!("failed to read '{}': {}", self.path.display(), e))?;
+            if batch.num_rows() > 0 {
+                self.next = Some(to_entry(&self.topic, &batch)?);
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a stored batch into the log columns of its first row and the batch
+/// as it was published
+pub(crate) fn to_entry(topic: &str, batch: &RecordBatch) -> Result<LogEntry> {
+    let schema = batch.schema();
+    let names: Vec<_> = schema.fields().iter().take(LOG_COLUMNS).map(|x| x.name().as_str()).collect();
+    if names != [LOG_TIME_COLUMN, PUBLISH_TIME_COLUMN, PUBLISHER_COLUMN] {
+        bail!("log file for '{}' is missing its log columns", topic);
+    }
+    let log_time_ns = batch.column(0).as_primitive_opt::<TimestampNanosecondType>();
+    let publish_time_ns = batch.column(1).as_primitive_opt::<TimestampNanosecondType>();
+    let publisher_id = batch.column(2).as_primitive_opt::<UInt64Type>();
+    let (Some(log_time_ns), Some(publish_time_ns), Some(publisher_id)) = (log_time_ns, publish_time_ns, publisher_id)
+    else {
+        bail!("log file for '{}' has log columns of the wrong type", topic);
+    };
+
+    let mut metadata = schema.metadata().clone();
+    metadata.remove(TOPIC_KEY);
+    let fields: Vec<_> = schema.fields().iter().skip(LOG_COLUMNS).cloned().collect();
+    let published = Arc::new(Schema::new_with_metadata(fields, metadata));
+    Ok(LogEntry {
+        topic: topic.to_string(),
+        publisher_id: publisher_id.value(0),
+        publish_time_ns: publish_time_ns.value(0),
+        log_time_ns: log_time_ns.value(0),
+        batch: RecordBatch::try_new(published, batch.columns()[LOG_COLUMNS..].to_vec())?,
+    })
+}
+
+/// Files with `extension` under `path`, which is a file, a segment or a
+/// recording
+pub(crate) fn log_files(path: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut entries: Vec<_> = fs::read_dir(path)?.map(|x| x.map(|x| x.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(log_files(&entry, extension)?);
+        } else if entry.extension().is_some_and(|x| x == extension) {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,287 @@
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
+use arrow_array::{Int32Array, StringArray, TimestampNanosecondArray, UInt64Array};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::logfile::{
+    export_mcap, export_parquet, import_mcap, import_parquet, BackgroundLogWriter, LogConfig, LogReader, LogWriter, ParquetConfig,
+    DESCRIPTOR_SET_KEY, LOG_TIME_COLUMN, MESSAGE_COUNT_KEY, PUBLISHER_COLUMN, START_TIME_KEY, TOPIC_KEY,
+};
+use crate::ptars::{with_message_type, ProtoCache};
+use crate::test_util::{entry, speed_batch};
+use parquet::file::reader::{FileReader as _, SerializedFileReader};
+use protobuf::descriptor::field_descriptor_proto::{Label, Type};
+use protobuf::descriptor::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use protobuf::reflect::ReflectValueBox;
+use protobuf::Message;
+
+fn read_file(path: &Path) -> (FileReader<File>, Vec<RecordBatch>) {
+    let mut reader = FileReader::try_new(File::open(path).unwrap(), None).unwrap();
+    let batches = reader.by_ref().map(|x| x.unwrap()).collect();
+    (reader, batches)
+}
+
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_log_writes_one_file_per_topic() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut writer = LogWriter::create(LogConfig::new(dir.path())).unwrap();
+    writer.write(&entry("vehicle/speed", 100, speed_batch(vec![1, 2], vec![10.0, 20.0]))).unwrap();
+    writer.write(&entry("vehicle/speed", 200, speed_batch(vec![3], vec![30.0]))).unwrap();
+    writer.write(&entry("a%b", 150, speed_batch(vec![4], vec![40.0]))).unwrap();
+    let segments = writer.finish().unwrap();
+
+    assert_eq!(segments.len(), 1);
+    assert_eq!(file_names(&segments[0]), vec!["a%25b.arrow", "vehicle%2Fspeed.arrow"]);
+    let (reader, batches) = read_file(&segments[0].join("vehicle%2Fspeed.arrow"));
+    let schema = reader.schema();
+    assert_eq!(schema.metadata()[TOPIC_KEY], "vehicle/speed");
+    assert_eq!(reader.custom_metadata()[MESSAGE_COUNT_KEY], "2");
+    assert_eq!(reader.custom_metadata()[START_TIME_KEY], "100");
+
+    // Batch boundaries are kept and every row carries the log columns
+    assert_eq!(batches.len(), 2);
+    let log_times = batches[0].column_by_name(LOG_TIME_COLUMN).unwrap();
+    let log_times = log_times.as_any().downcast_ref::<TimestampNanosecondArray>().unwrap();
+    assert_eq!(log_times.values(), &[100, 100]);
+    let publishers = batches[1].column_by_name(PUBLISHER_COLUMN).unwrap();
+    assert_eq!(publishers.as_any().downcast_ref::<UInt64Array>().unwrap().values(), &[3]);
+    assert_eq!(batches[1].project(&[3, 4]).unwrap().columns(), speed_batch(vec![3], vec![30.0]).columns());
+}
+
+#[test]
+fn test_log_rotates_by_duration_and_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LogConfig {
+        max_segment_duration: Some(Duration::from_nanos(1000)),
+        ..LogConfig::new(dir.path())
+    };
+    let mut writer = LogWriter::create(config).unwrap();
+    for time in [0, 500, 999, 1000, 2500] {
+        writer.write(&entry("vehicle/speed", time, speed_batch(vec![1], vec![1.0]))).unwrap();
+    }
+    let segments = writer.finish().unwrap();
+    let counts: Vec<_> = segments
+        .iter()
+        .map(|x| read_file(&x.join("vehicle%2Fspeed.arrow")).1.len())
+        .collect();
+    assert_eq!(counts, vec![3, 1, 1]);
+
+    let config = LogConfig {
+        prefix: "small".to_string(),
+        max_segment_bytes: Some(1),
+        ..LogConfig::new(dir.path())
+    };
+    let mut writer = LogWriter::create(config).unwrap();
+    for time in 0..4 {
+        writer.write(&entry("vehicle/speed", time, speed_batch(vec![1], vec![1.0]))).unwrap();
+    }
+    let segments = writer.finish().unwrap();
+    assert_eq!(segments.len(), 4);
+    assert!(segments[3].file_name().unwrap().to_str().unwrap().starts_with("small_"));
+}
+
+#[test]
+fn test_log_schema_change_starts_new_part() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut writer = LogWriter::create(LogConfig::new(dir.path())).unwrap();
+    writer.write(&entry("vehicle/speed", 0, speed_batch(vec![1], vec![1.0]))).unwrap();
+    let names = RecordBatch::try_from_iter([("name", Arc::new(StringArray::from(vec!["a"])) as _)]).unwrap();
+    writer.write(&entry("vehicle/speed", 1, names)).unwrap();
+    let segments = writer.finish().unwrap();
+    assert_eq!(file_names(&segments[0]), vec!["vehicle%2Fspeed.1.arrow", "vehicle%2Fspeed.arrow"]);
+
+    let reserved = RecordBatch::try_from_iter([(PUBLISHER_COLUMN, Arc::new(Int32Array::from(vec![1])) as _)]).unwrap();
+    let mut writer = LogWriter::create(LogConfig::new(dir.path().join("other"))).unwrap();
+    assert!(writer.write(&entry("vehicle/speed", 0, reserved)).is_err());
+}
+
+#[test]
+fn test_background_writer_records_everything_queued() {
+    let dir = tempfile::tempdir().unwrap();
+    let writer = BackgroundLogWriter::spawn(LogWriter::create(LogConfig::new(dir.path())).unwrap(), 1024);
+    for time in 0..100 {
+        assert!(writer.try_write(entry("vehicle/speed", time, speed_batch(vec![time as i32], vec![0.0]))));
+    }
+    assert_eq!(writer.dropped(), 0);
+    let segments = writer.finish().unwrap();
+
+    let (reader, batches) = read_file(&segments[0].join("vehicle%2Fspeed.arrow"));
+    assert_eq!(batches.len(), 100);
+    assert_eq!(reader.custom_metadata()[MESSAGE_COUNT_KEY], "100");
+}
+
+#[test]
+fn test_log_reader_merges_topics_and_segments_by_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LogConfig {
+        max_segment_duration: Some(Duration::from_nanos(100)),
+        ..LogConfig::new(dir.path())
+    };
+    let mut writer = LogWriter::create(config).unwrap();
+    let mut entries = Vec::new();
+    for time in 0..20 {
+        let topic = if time % 3 == 0 { "vehicle/speed" } else { "vehicle/odometry" };
+        entries.push(entry(topic, time * 17, speed_batch(vec![time as i32], vec![time as f64])));
+        writer.write(entries.last().unwrap()).unwrap();
+    }
+    let segments = writer.finish().unwrap();
+    assert_eq!(segments.len(), 4);
+
+    let reader = LogReader::open(dir.path()).unwrap();
+    assert_eq!(reader.topics(), vec!["vehicle/odometry", "vehicle/speed"]);
+    assert_eq!(reader.peek_time(), Some(0));
+    let read: Vec<_> = reader.map(|x| x.unwrap()).collect();
+    assert_eq!(read.len(), entries.len());
+    for (read, written) in read.iter().zip(&entries) {
+        assert_eq!(read.topic, written.topic);
+        assert_eq!(read.log_time_ns, written.log_time_ns);
+        assert_eq!(read.publish_time_ns, written.publish_time_ns);
+        assert_eq!(read.publisher_id, 3);
+        assert_eq!(read.batch, written.batch);
+    }
+
+    // A single segment reads on its own
+    assert_eq!(LogReader::open(&segments[1]).unwrap().count(), 6);
+}
+
+/// Descriptor set declaring a `mariposa.test.Speed` message with the columns
+/// of `speed_batch`
+fn speed_types() -> ProtoCache {
+    let mut message = DescriptorProto::new();
+    message.set_name("Speed".to_string());
+    for (name, number, field_type) in [("id", 1, Type::TYPE_INT32), ("speed_kph", 2, Type::TYPE_DOUBLE)] {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_string());
+        field.set_number(number);
+        field.set_type(field_type);
+        field.set_label(Label::LABEL_OPTIONAL);
+        message.field.push(field);
+    }
+    let mut file = FileDescriptorProto::new();
+    file.set_name("speed.proto".to_string());
+    file.set_package("mariposa.test".to_string());
+    file.message_type.push(message);
+    let mut set = FileDescriptorSet::new();
+    set.file.push(file);
+    let mut types = ProtoCache::new();
+    types.add_descriptor_set(&set.write_to_bytes().unwrap()).unwrap();
+    types
+}
+
+#[test]
+fn test_parquet_export_and_import_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut writer = LogWriter::create(LogConfig::new(dir.path().join("log"))).unwrap();
+    let mut entries = Vec::new();
+    for time in 0..10 {
+        let batch = speed_batch((0..500).collect(), vec![time as f64; 500]);
+        entries.push(entry("vehicle/speed", time * 10, with_message_type(batch, "mariposa.test.Speed").unwrap()));
+        entries.push(entry("vehicle/odometry", time * 10 + 5, speed_batch(vec![time as i32], vec![0.0])));
+    }
+    for entry in &entries {
+        writer.write(entry).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let config = ParquetConfig {
+        row_group_rows: 2000,
+        ..ParquetConfig::default()
+    };
+    let exported = dir.path().join("parquet");
+    let files = export_parquet(&dir.path().join("log"), &exported, &config, Some(&speed_types())).unwrap();
+    assert_eq!(file_names(&exported), vec!["vehicle%2Fodometry.parquet", "vehicle%2Fspeed.parquet"]);
+    assert_eq!(files.len(), 2);
+
+    let file = SerializedFileReader::new(File::open(exported.join("vehicle%2Fspeed.parquet")).unwrap()).unwrap();
+    let metadata = file.metadata();
+    assert_eq!(metadata.file_metadata().num_rows(), 5000);
+    assert_eq!(metadata.num_row_groups(), 3);
+    let kv = metadata.file_metadata().key_value_metadata().unwrap();
+    let value = |key: &str| kv.iter().find(|x| x.key == key).and_then(|x| x.value.clone());
+    assert_eq!(value(TOPIC_KEY).as_deref(), Some("vehicle/speed"));
+    assert_eq!(value(MESSAGE_COUNT_KEY).as_deref(), Some("10"));
+    assert!(value(DESCRIPTOR_SET_KEY).is_some());
+
+    // Batches split across row groups and reads come back whole
+    let imported = dir.path().join("imported");
+    import_parquet(&exported, LogConfig::new(&imported)).unwrap();
+    let read: Vec<_> = LogReader::open(&imported).unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(read.len(), entries.len());
+    for (read, written) in read.iter().zip(&entries) {
+        assert_eq!(read.topic, written.topic);
+        assert_eq!(read.log_time_ns, written.log_time_ns);
+        assert_eq!(read.publish_time_ns, written.publish_time_ns);
+        assert_eq!(read.batch, written.batch);
+    }
+}
+
+/// `Speed` messages converted to a batch by ptars, tagged with their type
+fn proto_speed_batch(types: &ProtoCache, ids: Vec<i32>) -> RecordBatch {
+    let handler = types.handler_for("mariposa.test.Speed").unwrap();
+    let descriptor = handler.get_message_descriptor();
+    let payloads: Vec<_> = ids
+        .into_iter()
+        .map(|id| {
+            let mut message = descriptor.new_instance();
+            let field = descriptor.field_by_name("id").unwrap();
+            field.set_singular_field(&mut *message, ReflectValueBox::I32(id));
+            let field = descriptor.field_by_name("speed_kph").unwrap();
+            field.set_singular_field(&mut *message, ReflectValueBox::F64(id as f64 / 2.0));
+            message.write_to_bytes_dyn().unwrap()
+        })
+        .collect();
+    with_message_type(handler.list_to_record_batch(payloads), "mariposa.test.Speed").unwrap()
+}
+
+#[test]
+fn test_mcap_export_and_import_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let types = speed_types();
+    let mut writer = LogWriter::create(LogConfig::new(dir.path().join("log"))).unwrap();
+    let mut entries = Vec::new();
+    for time in 0..5 {
+        let mut speed = entry("vehicle/speed", time * 10, proto_speed_batch(&types, vec![time as i32, 100]));
+        speed.publisher_id = 0;
+        entries.push(speed);
+        writer.write(entries.last().unwrap()).unwrap();
+        // Plain Arrow topics have no MCAP encoding
+        writer.write(&entry("vehicle/odometry", time * 10 + 5, speed_batch(vec![1], vec![1.0]))).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let path = dir.path().join("drive.mcap");
+    assert_eq!(export_mcap(&dir.path().join("log"), &path, &types).unwrap(), 10);
+    let bytes = std::fs::read(&path).unwrap();
+    let messages: Vec<_> = mcap::MessageStream::new(&bytes).unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(messages.len(), 10);
+    assert_eq!(messages[2].channel.topic, "vehicle/speed");
+    assert_eq!(messages[2].channel.message_encoding, "protobuf");
+    assert_eq!(messages[2].log_time, 10);
+    assert_eq!(messages[2].publish_time, 0);
+    let schema = messages[2].channel.schema.as_ref().unwrap();
+    assert_eq!(schema.name, "mariposa.test.Speed");
+    assert_eq!(FileDescriptorSet::parse_from_bytes(&schema.data).unwrap().file.len(), 1);
+
+    let imported = dir.path().join("imported");
+    import_mcap(&path, LogConfig::new(&imported)).unwrap();
+    let read: Vec<_> = LogReader::open(&imported).unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(read.len(), entries.len());
+    for (read, written) in read.iter().zip(&entries) {
+        assert_eq!(read.topic, written.topic);
+        assert_eq!(read.log_time_ns, written.log_time_ns);
+        assert_eq!(read.publish_time_ns, written.publish_time_ns);
+        assert_eq!(read.publisher_id, 0);
+        assert_eq!(read.batch, written.batch);
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::{bail, Result};
+use arrow::array::{ArrayRef, TimestampNanosecondArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::logfile::{
+    END_TIME_KEY, FILE_EXTENSION, LOG_TIME_COLUMN, MESSAGE_COUNT_KEY, PUBLISHER_COLUMN, PUBLISH_TIME_COLUMN,
+    START_TIME_KEY, TOPIC_KEY,
+};
+use crate::topic::topic_file_name;
+
+/// Where and how to split a recording
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Directory the segment directories are created in
+    pub dir: PathBuf,
+    /// Start of every segment directory name
+    pub prefix: String,
+    /// Start a new segment once the current one holds this many bytes
+    pub max_segment_bytes: Option<u64>,
+    /// Start a new segment once the current one spans this much time
+    pub max_segment_duration: Option<Duration>,
+}
+
+impl LogConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: "mariposa".to_string(),
+            max_segment_bytes: None,
+            max_segment_duration: None,
+        }
+    }
+}
+
+/// A batch as it was received on a topic
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub topic: String,
+    pub publisher_id: u64,
+    /// Time the publisher stamped the batch with
+    pub publish_time_ns: i64,
+    /// Time the recorder received the batch
+    pub log_time_ns: i64,
+    pub batch: RecordBatch,
+}
+
+/// Writes received batches into per-topic Arrow IPC files, splitting the
+/// recording into segments
+pub struct LogWriter {
+    config: LogConfig,
+    /// Name shared by the segments of this recording
+    session: String,
+    segment: Option<Segment>,
+    segments: Vec<PathBuf>,
+}
+
+/// The directory being written to and its open files
+struct Segment {
+    dir: PathBuf,
+    start_ns: i64,
+    bytes: u64,
+    files: HashMap<String, TopicFile>,
+    /// Number of files opened per topic, for naming the next part
+    parts: HashMap<String, usize>,
+}
+
+struct TopicFile {
+    writer: FileWriter<Counted<BufWriter<File>>>,
+    /// Schema of the batches as published, without the log columns
+    schema: SchemaRef,
+    messages: u64,
+    start_ns: i64,
+    end_ns: i64,
+}
+
+impl LogWriter {
+    pub fn create(config: LogConfig) -> Result<Self> {
+        if config.prefix.is_empty() || config.prefix.contains('/') {
+            bail!("invalid log prefix '{}'", config.prefix);
+        }
+        fs::create_dir_all(&config.dir)?;
+        Ok(Self {
+            session: format!("{}_{}", config.prefix, Utc::now().format("%Y%m%d-%H%M%S")),
+            config,
+            segment: None,
+            segments: Vec::new(),
+        })
+    }
+
+    /// Segment directories created so far, the last one possibly still open
+    pub fn segments(&self) -> &[PathBuf] {
+        &self.segments
+    }
+
+    pub fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        if self.segment.as_ref().is_some_and(|x| self.is_full(x, entry.log_time_ns)) {
+            self.close()?;
+        }
+        let segment = match &mut self.segment {
+            Some(segment) => segment,
+            None => {
+                let dir = self.config.dir.join(format!("{}_{:04}", self.session, self.segments.len()));
+                fs::create_dir(&dir)?;
+                self.segments.push(dir.clone());
+                self.segment.insert(Segment {
+                    dir,
+                    start_ns: entry.log_time_ns,
+                    bytes: 0,
+                    files: HashMap::new(),
+                    parts: HashMap::new(),
+                })
+            }
+        };
+
+        let schema = entry.batch.schema();
+        if segment.files.get(&entry.topic).is_some_and(|x| x.schema != schema) {
+            if let Some(file) = segment.files.remove(&entry.topic) {
+                file.finish()?;
+            }
+        }
+        let file = match segment.files.get_mut(&entry.topic) {
+            Some(file) => file,
+            None => {
+                let part = segment.parts.entry(entry.topic.clone()).or_insert(0);
+                let path = segment.dir.join(file_name(&entry.topic, *part, FILE_EXTENSION));
+                *part += 1;
+                let file = TopicFile::create(&path, &entry.topic, schema, entry.log_time_ns)?;
+                segment.files.entry(entry.topic.clone()).or_insert(file)
+            }
+        };
+        let before = file.writer.get_ref().written;
+        file.write(entry)?;
+        segment.bytes += file.writer.get_ref().written - before;
+        Ok(())
+    }
+
+    /// Close every file and return the segment directories
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        self.close()?;
+        Ok(std::mem::take(&mut self.segments))
+    }
+
+    fn is_full(&self, segment: &Segment, log_time_ns: i64) -> bool {
+        let too_big = self.config.max_segment_bytes.is_some_and(|x| segment.bytes >= x);
+        let too_long = self
+            .config
+            .max_segment_duration
+            .is_some_and(|x| log_time_ns.saturating_sub(segment.start_ns) >= x.as_nanos() as i64);
+        too_big || too_long
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(segment) = self.segment.take() {
+            for (_, file) in segment.files {
+                file.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::warn!("failed to close log files: {:#}", e);
+        }
+    }
+}
+
+impl TopicFile {
+    fn create(path: &Path, topic: &str, schema: SchemaRef, log_time_ns: i64) -> Result<Self> {
+        let writer = Counted {
+            inner: BufWriter::new(File::create_new(path)?),
+            written: 0,
+        };
+        let writer = FileWriter::try_new(writer, &logged_schema(topic, &schema)?)?;
+        Ok(Self {
+            writer,
+            schema,
+            messages: 0,
+            start_ns: log_time_ns,
+            end_ns: log_time_ns,
+        })
+    }
+
+    fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        self.writer.write(&logged_batch(self.writer.schema().clone(), entry)?)?;
+        self.messages += 1;
+        self.start_ns = self.start_ns.min(entry.log_time_ns);
+        self.end_ns = self.end_ns.max(entry.log_time_ns);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.writer.write_metadata(MESSAGE_COUNT_KEY, self.messages.to_string());
+        self.writer.write_metadata(START_TIME_KEY, self.start_ns.to_string());
+        self.writer.write_metadata(END_TIME_KEY, self.end_ns.to_string());
+        self.writer.finish()?;
+        self.writer.get_mut().inner.flush()?;
+        Ok(())
+    }
+}
+
+/// Schema of a topic's file: the log columns, then the published ones
+pub(crate) fn logged_schema(topic: &str, schema: &Schema) -> Result<Schema> {
+    let timestamp = DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()));
+    let mut fields = vec![
+        Field::new(LOG_TIME_COLUMN, timestamp.clone(), false),
+        Field::new(PUBLISH_TIME_COLUMN, timestamp, false),
+        Field::new(PUBLISHER_COLUMN, DataType::UInt64, false),
+    ];
+    for field in schema.fields() {
+        if fields.iter().any(|x| x.name() == field.name()) {
+            bail!("topic '{}' has a column named '{}', which logs reserve", topic, field.name());
+        }
+        fields.push(field.as_ref().clone());
+    }
+    let mut metadata = schema.metadata().clone();
+    metadata.insert(TOPIC_KEY.to_string(), topic.to_string());
+    Ok(Schema::new_with_metadata(fields, metadata))
+}
+
+/// An entry's batch with the log columns added, laid out as `schema`
+pub(crate) fn logged_batch(schema: SchemaRef, entry: &LogEntry) -> Result<RecordBatch> {
+    let rows = entry.batch.num_rows();
+    let utc = Some(Arc::from("UTC"));
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(TimestampNanosecondArray::from(vec![entry.log_time_ns; rows]).with_timezone_opt(utc.clone())),
+        Arc::new(TimestampNanosecondArray::from(vec![entry.publish_time_ns; rows]).with_timezone_opt(utc)),
+        Arc::new(UInt64Array::from(vec![entry.publisher_id; rows])),
+    ];
+    columns.extend(entry.batch.columns().iter().cloned());
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+pub(crate) fn file_name(topic: &str, part: usize, extension: &str) -> String {
+    let name = topic_file_name(topic);
+    match part {
+        0 => format!("{}.{}", name, extension),
+        part => format!("{}.{}.{}", name, part, extension),
+    }
+}
+
+/// Counts the bytes written through it, for size based rotation
+struct Counted<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
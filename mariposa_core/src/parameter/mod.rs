@@ -0,0 +1,25 @@
+// Hierarchical parameters hosted by the broker
+//
+// Parameters are named like topics, such as `camera/exposure` or
+// `speed/limit_kph`, and hold a bool, an integer, a float or a string. A
+// parameter keeps the type it was first set with.
+//
+// Every change is published by the broker as a batch with `name` and `value`
+// columns on `_parameters/<name>`. These topics are latched, so watchers get
+// the current value of a parameter as soon as they subscribe.
+//
+// Values are written as literals when the broker persists its store:
+//
+//   true | -3 | 0.5 | "text with \"escapes\""
+
+mod value;
+
+#[cfg(test)]
+mod tests;
+
+pub use value::{parameter_name, parameter_topic, parameters_from_batch, ParameterValue};
+
+// Constants
+pub const PARAMETER_PREFIX: &str = "_parameters";
+pub const NAME_COLUMN: &str = "name";
+pub const VALUE_COLUMN: &str = "value";
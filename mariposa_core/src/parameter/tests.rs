@@ -0,0 +1,61 @@
+use arrow::compute::concat_batches;
+use crate::parameter::{parameter_name, parameter_topic, parameters_from_batch, ParameterValue};
+
+#[test]
+fn test_literals_round_trip() {
+    let values = [
+        ParameterValue::Bool(false),
+        ParameterValue::Int(-42),
+        ParameterValue::Float(50.0),
+        ParameterValue::Float(1e-7),
+        ParameterValue::String("front \"left\"\n\tcam \u{7f}".to_string()),
+        ParameterValue::String(String::new()),
+    ];
+    for value in values {
+        let literal = value.to_string();
+        assert_eq!(literal.parse::<ParameterValue>().unwrap(), value, "{}", literal);
+    }
+    assert_eq!(" 7 ".parse::<ParameterValue>().unwrap(), ParameterValue::Int(7));
+    assert!("fast".parse::<ParameterValue>().is_err());
+    assert!("\"open".parse::<ParameterValue>().is_err());
+    assert!("\"a\" b".parse::<ParameterValue>().is_err());
+}
+
+#[test]
+fn test_typed_conversions() {
+    assert_eq!(f64::try_from(ParameterValue::Int(50)).unwrap(), 50.0);
+    assert_eq!(String::try_from(ParameterValue::from("map")).unwrap(), "map");
+    assert!(bool::try_from(ParameterValue::from(1)).is_err());
+    let error = i64::try_from(ParameterValue::from(0.5)).unwrap_err().to_string();
+    assert_eq!(error, "expected an int, got float 0.5");
+    assert!(ParameterValue::from(1).same_type(&ParameterValue::Int(2)));
+    assert!(!ParameterValue::from(1).same_type(&ParameterValue::Float(2.0)));
+}
+
+#[test]
+fn test_batches_carry_name_and_value() {
+    let first = ParameterValue::Float(0.01).to_batch("camera/exposure").unwrap();
+    let second = ParameterValue::Float(0.02).to_batch("camera/exposure").unwrap();
+    let merged = concat_batches(&first.schema(), [&first, &second]).unwrap();
+    let parameters = parameters_from_batch(&merged).unwrap();
+    assert_eq!(
+        parameters,
+        [
+            ("camera/exposure".to_string(), ParameterValue::Float(0.01)),
+            ("camera/exposure".to_string(), ParameterValue::Float(0.02)),
+        ]
+    );
+    let batch = ParameterValue::from("imx490").to_batch("camera/model").unwrap();
+    assert_eq!(parameters_from_batch(&batch).unwrap()[0].1, ParameterValue::from("imx490"));
+}
+
+#[test]
+fn test_names_are_normalized() {
+    assert_eq!(parameter_name("/speed/limit_kph").unwrap(), "speed/limit_kph");
+    assert!(parameter_name("speed//limit").is_err());
+    assert!(parameter_name("speed/*").is_err());
+    assert!(parameter_name("speed=fast").is_err());
+    assert!(parameter_name("speed limit").is_err());
+    assert!(parameter_name("#speed").is_err());
+    assert_eq!(parameter_topic("/speed/limit_kph"), "_parameters/speed/limit_kph");
+}
@@ -0,0 +1,277 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use arrow::array::{Array, ArrayRef, AsArray, BooleanArray, Float64Array, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Float64Type, Int64Type};
+use arrow::record_batch::RecordBatch;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::parameter::{NAME_COLUMN, PARAMETER_PREFIX, VALUE_COLUMN};
+use crate::topic::{validate_topic, SEPARATOR};
+use crate::wire::{put_string, ByteReader};
+
+/// Value held by a parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl ParameterValue {
+    /// Name of the value's type, as used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ParameterValue::Bool(_) => "bool",
+            ParameterValue::Int(_) => "int",
+            ParameterValue::Float(_) => "float",
+            ParameterValue::String(_) => "string",
+        }
+    }
+
+    pub fn same_type(&self, other: &ParameterValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// One-row batch announcing that parameter `name` holds this value
+    pub fn to_batch(&self, name: &str) -> Result<RecordBatch> {
+        let value: ArrayRef = match self {
+            ParameterValue::Bool(x) => Arc::new(BooleanArray::from(vec![*x])),
+            ParameterValue::Int(x) => Arc::new(Int64Array::from(vec![*x])),
+            ParameterValue::Float(x) => Arc::new(Float64Array::from(vec![*x])),
+            ParameterValue::String(x) => Arc::new(StringArray::from(vec![x.as_str()])),
+        };
+        Ok(RecordBatch::try_from_iter([
+            (NAME_COLUMN, Arc::new(StringArray::from(vec![name])) as ArrayRef),
+            (VALUE_COLUMN, value),
+        ])?)
+    }
+
+    pub(crate) fn put(&self, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            ParameterValue::Bool(x) => out.extend_from_slice(&[0, *x as u8]),
+            ParameterValue::Int(x) => {
+                out.push(1);
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+            ParameterValue::Float(x) => {
+                out.push(2);
+                out.extend_from_slice(&x.to_bits().to_le_bytes());
+            }
+            ParameterValue::String(x) => {
+                out.push(3);
+                put_string(out, x)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read(reader: &mut ByteReader) -> Result<Self> {
+        Ok(match reader.u8()? {
+            0 => ParameterValue::Bool(reader.u8()? != 0),
+            1 => ParameterValue::Int(reader.u64()? as i64),
+            2 => ParameterValue::Float(f64::from_bits(reader.u64()?)),
+            3 => ParameterValue::String(reader.string()?),
+            tag => bail!("unknown parameter type {}", tag),
+        })
+    }
+}
+
+/// Writes the value as a literal that `from_str` reads back
+impl fmt::Display for ParameterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterValue::Bool(x) => write!(f, "{}", x),
+            ParameterValue::Int(x) => write!(f, "{}", x),
+            // Debug keeps the fraction of whole numbers, so 50.0 stays a float
+            ParameterValue::Float(x) => write!(f, "{:?}", x),
+            ParameterValue::String(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+impl FromStr for ParameterValue {
+    type Err = anyhow::Error;
+
+    /// Parse a literal: `true` or `false`, an integer, a float or a double
+    /// quoted string
+    fn from_str(literal: &str) -> Result<Self> {
+        let literal = literal.trim();
+        if let Some(quoted) = literal.strip_prefix('"') {
+            return Ok(ParameterValue::String(unquote(quoted, literal)?));
+        }
+        match literal {
+            "true" => return Ok(ParameterValue::Bool(true)),
+            "false" => return Ok(ParameterValue::Bool(false)),
+            _ => {}
+        }
+        if let Ok(x) = literal.parse::<i64>() {
+            return Ok(ParameterValue::Int(x));
+        }
+        literal
+            .parse::<f64>()
+            .map(ParameterValue::Float)
+            .map_err(|_| anyhow!("'{}' is not a bool, a number or a quoted string", literal))
+    }
+}
+
+/// Read the rest of a quoted string, undoing the escapes `{:?}` writes
+fn unquote(quoted: &str, literal: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                ensure!(chars.as_str().is_empty(), "unexpected text after the string in {}", literal);
+                return Ok(out);
+            }
+            '\\' => out.push(match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                Some('u') => {
+                    let rest = chars.as_str();
+                    let code = rest
+                        .strip_prefix('{')
+                        .and_then(|x| x.split_once('}'))
+                        .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| anyhow!("invalid unicode escape in {}", literal))?;
+                    let end = rest.find('}').unwrap_or_default();
+                    chars = rest[end + 1..].chars();
+                    code
+                }
+                _ => bail!("invalid escape in {}", literal),
+            }),
+            c => out.push(c),
+        }
+    }
+    bail!("unterminated string {}", literal)
+}
+
+impl From<bool> for ParameterValue {
+    fn from(value: bool) -> Self {
+        ParameterValue::Bool(value)
+    }
+}
+
+impl From<i64> for ParameterValue {
+    fn from(value: i64) -> Self {
+        ParameterValue::Int(value)
+    }
+}
+
+impl From<i32> for ParameterValue {
+    fn from(value: i32) -> Self {
+        ParameterValue::Int(value as i64)
+    }
+}
+
+impl From<f64> for ParameterValue {
+    fn from(value: f64) -> Self {
+        ParameterValue::Float(value)
+    }
+}
+
+impl From<String> for ParameterValue {
+    fn from(value: String) -> Self {
+        ParameterValue::String(value)
+    }
+}
+
+impl From<&str> for ParameterValue {
+    fn from(value: &str) -> Self {
+        ParameterValue::String(value.to_string())
+    }
+}
+
+impl TryFrom<ParameterValue> for bool {
+    type Error = anyhow::Error;
+
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::Bool(x) => Ok(x),
+            other => bail!("expected a bool, got {} {}", other.type_name(), other),
+        }
+    }
+}
+
+impl TryFrom<ParameterValue> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::Int(x) => Ok(x),
+            other => bail!("expected an int, got {} {}", other.type_name(), other),
+        }
+    }
+}
+
+impl TryFrom<ParameterValue> for f64 {
+    type Error = anyhow::Error;
+
+    /// Integers are widened, so a limit written as `50` reads as a float
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::Float(x) => Ok(x),
+            ParameterValue::Int(x) => Ok(x as f64),
+            other => bail!("expected a float, got {} {}", other.type_name(), other),
+        }
+    }
+}
+
+impl TryFrom<ParameterValue> for String {
+    type Error = anyhow::Error;
+
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::String(x) => Ok(x),
+            other => bail!("expected a string, got {} {}", other.type_name(), other),
+        }
+    }
+}
+
+/// Check a parameter name and drop its leading `/`, so that
+/// `/camera/exposure` and `camera/exposure` name the same parameter
+///
+/// Names cannot hold `=`, `#` or whitespace, which the parameter file uses.
+pub fn parameter_name(name: &str) -> Result<String> {
+    validate_topic(name)?;
+    if let Some(x) = name.chars().find(|x| *x == '=' || *x == '#' || x.is_whitespace()) {
+        bail!("parameter name '{}' cannot contain {:?}", name, x);
+    }
+    Ok(name.strip_prefix(SEPARATOR).unwrap_or(name).to_string())
+}
+
+/// Topic that changes of a parameter are published on
+pub fn parameter_topic(name: &str) -> String {
+    format!("{}/{}", PARAMETER_PREFIX, name.strip_prefix(SEPARATOR).unwrap_or(name))
+}
+
+/// Every parameter announced in a batch of change notifications, which may
+/// hold several rows once merged
+pub fn parameters_from_batch(batch: &RecordBatch) -> Result<Vec<(String, ParameterValue)>> {
+    let names = batch
+        .column_by_name(NAME_COLUMN)
+        .and_then(|x| x.as_string_opt::<i32>())
+        .ok_or_else(|| anyhow!("parameter batch has no '{}' column", NAME_COLUMN))?;
+    let values = batch
+        .column_by_name(VALUE_COLUMN)
+        .ok_or_else(|| anyhow!("parameter batch has no '{}' column", VALUE_COLUMN))?;
+    (0..batch.num_rows())
+        .map(|row| {
+            let value = match values.data_type() {
+                DataType::Boolean => ParameterValue::Bool(values.as_boolean().value(row)),
+                DataType::Int64 => ParameterValue::Int(values.as_primitive::<Int64Type>().value(row)),
+                DataType::Float64 => ParameterValue::Float(values.as_primitive::<Float64Type>().value(row)),
+                DataType::Utf8 => ParameterValue::String(values.as_string::<i32>().value(row).to_string()),
+                other => bail!("parameter values cannot be {}", other),
+            };
+            ensure!(!values.is_null(row), "parameter '{}' has no value", names.value(row));
+            Ok((names.value(row).to_string(), value))
+        })
+        .collect()
+}
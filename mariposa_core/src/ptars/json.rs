@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use arrow::record_batch::RecordBatch;
+use protobuf_json_mapping::{parse_dyn_from_str, print_to_string_with_options, PrintOptions};
+use std::io::{BufRead, Write};
+
+use crate::ptars::message_handler::MessageHandler;
+
+impl MessageHandler {
+    /// Parse proto3 JSON messages, one per line, into an Arrow RecordBatch
+    ///
+    /// Both the original proto field names (the names ptars uses for columns) and
+    /// the lowerCamelCase JSON names are accepted. Blank lines are skipped.
+    pub fn json_lines_to_record_batch<R: BufRead>(&self, reader: R) -> Result<RecordBatch> {
+        let mut values = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message = parse_dyn_from_str(self.get_message_descriptor(), &line)
+                .with_context(|| format!("line {}: invalid JSON", index + 1))?;
+            values.push(message.write_to_bytes_dyn()?);
+        }
+        Ok(self.list_to_record_batch(values))
+    }
+
+    /// Write each row of a RecordBatch as a proto3 JSON object on its own line
+    ///
+    /// Keys use the proto field names so they match the RecordBatch columns.
+    pub fn record_batch_to_json_lines<W: Write>(&self, record_batch: &RecordBatch, mut writer: W) -> Result<()> {
+        let options = PrintOptions {
+            proto_field_name: true,
+            ..Default::default()
+        };
+        for bytes in self.record_batch_to_array(record_batch) {
+            let message = self.get_message_descriptor().parse_from_bytes(&bytes)?;
+            writeln!(writer, "{}", print_to_string_with_options(message.as_ref(), &options)?)?;
+        }
+        Ok(())
+    }
+}
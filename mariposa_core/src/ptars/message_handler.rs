@@ -20,6 +20,11 @@ impl MessageHandler {
     
     /// Convert a list of serialized protobuf messages to an Arrow RecordBatch
     pub fn list_to_record_batch(&self, values: Vec<Vec<u8>>) -> RecordBatch {
+        self.slice_to_record_batch(&values)
+    }
+
+    /// Convert a borrowed slice of serialized protobuf messages to an Arrow RecordBatch
+    pub fn slice_to_record_batch(&self, values: &[Vec<u8>]) -> RecordBatch {
         let messages: Vec<Box<dyn MessageDyn>> = values
             .iter()
             .map(|x| {
@@ -0,0 +1,20 @@
+use anyhow::Result;
+use arrow::record_batch::RecordBatch;
+use arrow_schema::Schema;
+use std::sync::Arc;
+
+/// Schema metadata key naming the protobuf message each row was converted from
+pub const MESSAGE_TYPE_KEY: &str = "mariposa.message_type";
+
+/// Tag a batch's schema with the full name of its protobuf message type
+pub fn with_message_type(batch: RecordBatch, message_name: &str) -> Result<RecordBatch> {
+    let mut metadata = batch.schema().metadata().clone();
+    metadata.insert(MESSAGE_TYPE_KEY.to_string(), message_name.trim_start_matches('.').to_string());
+    let schema = Schema::new_with_metadata(batch.schema().fields().clone(), metadata);
+    Ok(batch.with_schema(Arc::new(schema))?)
+}
+
+/// Full name of the protobuf message type a schema was tagged with
+pub fn message_type(schema: &Schema) -> Option<&str> {
+    schema.metadata().get(MESSAGE_TYPE_KEY).map(|x| x.as_str())
+}
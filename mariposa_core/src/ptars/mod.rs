@@ -5,6 +5,10 @@ mod proto_cache;
 mod converters;
 mod builders;
 mod examples;
+mod parallel;
+mod json;
+mod text_format;
+mod metadata;
 
 #[cfg(test)]
 mod tests;
@@ -12,6 +16,8 @@ mod tests;
 pub use message_handler::MessageHandler;
 pub use proto_cache::ProtoCache;
 pub use examples::usage_example;
+pub use parallel::DEFAULT_CHUNK_ROWS;
+pub use metadata::{message_type, with_message_type, MESSAGE_TYPE_KEY};
 
 // Constants
 static CE_OFFSET: i32 = 719163; // Offset for date conversion 
\ No newline at end of file
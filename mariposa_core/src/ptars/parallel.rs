@@ -0,0 +1,33 @@
+use arrow::compute::concat_batches;
+use arrow::record_batch::RecordBatch;
+use rayon::prelude::*;
+
+use crate::ptars::message_handler::MessageHandler;
+
+/// Default number of rows converted per chunk by the parallel helpers
+pub const DEFAULT_CHUNK_ROWS: usize = 8192;
+
+impl MessageHandler {
+    /// Convert serialized protobuf messages to Arrow RecordBatches in parallel
+    ///
+    /// The input is split into chunks of at most `chunk_rows` messages, each chunk
+    /// is converted on the current rayon pool and the batches are returned in
+    /// input order. Run inside `ThreadPool::install` to use a dedicated pool.
+    pub fn convert_parallel(&self, values: &[Vec<u8>], chunk_rows: usize) -> Vec<RecordBatch> {
+        values
+            .par_chunks(chunk_rows.max(1))
+            .map(|chunk| self.slice_to_record_batch(chunk))
+            .collect()
+    }
+
+    /// Convert serialized protobuf messages in parallel and concatenate the chunks
+    /// into a single RecordBatch
+    pub fn convert_parallel_concat(&self, values: &[Vec<u8>], chunk_rows: usize) -> RecordBatch {
+        let batches = self.convert_parallel(values, chunk_rows);
+        match batches.first() {
+            None => self.slice_to_record_batch(&[]),
+            Some(first) => concat_batches(&first.schema(), &batches)
+                .expect("chunks converted by one handler share a schema"),
+        }
+    }
+}
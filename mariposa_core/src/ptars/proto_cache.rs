@@ -1,4 +1,6 @@
-use protobuf::descriptor::FileDescriptorProto;
+use anyhow::{anyhow, bail, Result};
+use protobuf::descriptor::{FileDescriptorProto, FileDescriptorSet};
+use protobuf::Message;
 use protobuf::reflect::{FileDescriptor, MessageDescriptor};
 use std::collections::HashMap;
 
@@ -72,4 +74,74 @@ impl ProtoCache {
             
         MessageHandler::new(message_descriptor)
     }
-} 
\ No newline at end of file
+
+    /// Register every file of a serialized `FileDescriptorSet`, such as the one
+    /// prost-build writes with `file_descriptor_set_path`
+    pub fn add_descriptor_set(&mut self, descriptor_set: &[u8]) -> Result<()> {
+        let mut pending = FileDescriptorSet::parse_from_bytes(descriptor_set)?.file;
+
+        // Files may appear in any order, so keep adding those whose
+        // dependencies are already known until none are left
+        while !pending.is_empty() {
+            let before = pending.len();
+            let mut index = 0;
+            while index < pending.len() {
+                let ready = pending[index]
+                    .dependency
+                    .iter()
+                    .all(|x| self.cache.contains_key(x.as_str()));
+                if !ready {
+                    index += 1;
+                    continue;
+                }
+                let file = pending.swap_remove(index);
+                if !self.cache.contains_key(file.name()) {
+                    let dependencies: Vec<FileDescriptor> = file
+                        .dependency
+                        .iter()
+                        .map(|x| self.cache[x.as_str()].clone())
+                        .collect();
+                    let name = file.name().to_string();
+                    let descriptor = FileDescriptor::new_dynamic(file, &dependencies)?;
+                    self.cache.insert(name, descriptor);
+                }
+            }
+            if pending.len() == before {
+                let missing: Vec<&str> = pending.iter().map(|x| x.name()).collect();
+                bail!("descriptor set is missing dependencies of {:?}", missing);
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a MessageHandler for a message in any registered file
+    ///
+    /// The name may be given with or without the leading dot.
+    pub fn handler_for(&self, message_name: &str) -> Result<MessageHandler> {
+        let full_name = format!(".{}", message_name.trim_start_matches('.'));
+        self.cache
+            .values()
+            .find_map(|file| file.message_by_full_name(&full_name))
+            .map(MessageHandler::new)
+            .ok_or_else(|| anyhow!("message type '{}' is not registered", message_name.trim_start_matches('.')))
+    }
+
+    /// Serialized `FileDescriptorSet` with the file defining a registered
+    /// message type and every file it imports, dependencies first
+    pub fn descriptor_set(&self, message_name: &str) -> Result<Vec<u8>> {
+        let handler = self.handler_for(message_name)?;
+        let mut set = FileDescriptorSet::new();
+        add_with_dependencies(handler.get_message_descriptor().file_descriptor(), &mut set.file);
+        Ok(set.write_to_bytes()?)
+    }
+}
+
+fn add_with_dependencies(file: &FileDescriptor, files: &mut Vec<FileDescriptorProto>) {
+    if files.iter().any(|x| x.name() == file.proto().name()) {
+        return;
+    }
+    for dependency in file.deps() {
+        add_with_dependencies(dependency, files);
+    }
+    files.push(file.proto().clone());
+}
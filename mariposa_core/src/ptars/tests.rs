@@ -5,6 +5,64 @@ mod tests {
     use arrow::compute;
     use crate::ptars::converters::convert_timestamps;
     use arrow_schema::Field;
+    use protobuf::descriptor::field_descriptor_proto::{Label, Type};
+    use protobuf::descriptor::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+    use protobuf::reflect::ReflectValueBox;
+    use protobuf::Message;
+    use crate::ptars::{message_type, with_message_type, MessageHandler, ProtoCache};
+
+    const SAMPLE_MESSAGE: &str = ".mariposa.test.Sample";
+
+    fn scalar_field(name: &str, number: i32, field_type: Type) -> FieldDescriptorProto {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_string());
+        field.set_number(number);
+        field.set_type(field_type);
+        field.set_label(Label::LABEL_OPTIONAL);
+        field
+    }
+
+    /// Serialized descriptor for a small `Sample` message used by the conversion tests
+    fn sample_descriptor() -> Vec<u8> {
+        let mut message = DescriptorProto::new();
+        message.set_name("Sample".to_string());
+        message.field.push(scalar_field("id", 1, Type::TYPE_INT32));
+        message.field.push(scalar_field("speed_kph", 2, Type::TYPE_DOUBLE));
+        message.field.push(scalar_field("plate", 3, Type::TYPE_STRING));
+
+        let mut file = FileDescriptorProto::new();
+        file.set_name("sample.proto".to_string());
+        file.set_package("mariposa.test".to_string());
+        file.set_syntax("proto3".to_string());
+        file.message_type.push(message);
+        file.write_to_bytes().unwrap()
+    }
+
+    fn sample_handler() -> MessageHandler {
+        ProtoCache::new().create_for_message(SAMPLE_MESSAGE.to_string(), vec![sample_descriptor()])
+    }
+
+    fn sample_messages(handler: &MessageHandler, count: i32) -> Vec<Vec<u8>> {
+        let descriptor = handler.get_message_descriptor();
+        (0..count)
+            .map(|i| {
+                let mut message = descriptor.new_instance();
+                descriptor
+                    .field_by_name("id")
+                    .unwrap()
+                    .set_singular_field(&mut *message, ReflectValueBox::I32(i));
+                descriptor
+                    .field_by_name("speed_kph")
+                    .unwrap()
+                    .set_singular_field(&mut *message, ReflectValueBox::F64(i as f64 * 1.5));
+                descriptor
+                    .field_by_name("plate")
+                    .unwrap()
+                    .set_singular_field(&mut *message, ReflectValueBox::String(format!("MRP-{}", i)));
+                message.write_to_bytes_dyn().unwrap()
+            })
+            .collect()
+    }
 
     #[test]
     fn test_convert_timestamps() {
@@ -61,4 +119,130 @@ mod tests {
         
         assert_eq!(results.as_ref().to_data(), expected.to_data());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_convert_parallel_matches_sequential() {
+        let handler = sample_handler();
+        let messages = sample_messages(&handler, 1000);
+
+        let sequential = handler.list_to_record_batch(messages.clone());
+        let chunks = handler.convert_parallel(&messages, 128);
+
+        assert_eq!(chunks.len(), 8);
+        assert_eq!(chunks.iter().map(|x| x.num_rows()).sum::<usize>(), 1000);
+        assert_eq!(chunks[7].num_rows(), 1000 - 7 * 128);
+
+        let concatenated = handler.convert_parallel_concat(&messages, 128);
+        assert_eq!(concatenated, sequential);
+    }
+
+    #[test]
+    fn test_convert_parallel_empty() {
+        let handler = sample_handler();
+
+        assert!(handler.convert_parallel(&[], 16).is_empty());
+
+        let batch = handler.convert_parallel_concat(&[], 16);
+        assert_eq!(batch.num_rows(), 0);
+        assert_eq!(batch.schema(), handler.slice_to_record_batch(&[]).schema());
+    }
+
+    #[test]
+    fn test_json_lines_round_trip() {
+        let handler = sample_handler();
+        let input = concat!(
+            "{\"id\": 1, \"speed_kph\": 104.5, \"plate\": \"MRP-1\"}\n",
+            "\n",
+            "{\"id\": 2, \"speedKph\": 131.0, \"plate\": \"MRP-2\"}\n",
+        );
+
+        let batch = handler.json_lines_to_record_batch(input.as_bytes()).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(1).name(), "speed_kph");
+
+        let mut output = Vec::new();
+        handler.record_batch_to_json_lines(&batch, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "{\"id\": 1, \"speed_kph\": 104.5, \"plate\": \"MRP-1\"}\n",
+                "{\"id\": 2, \"speed_kph\": 131.0, \"plate\": \"MRP-2\"}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_lines_rejects_unknown_field() {
+        let handler = sample_handler();
+        let error = handler
+            .json_lines_to_record_batch("{\"id\": 1}\n{\"lane\": 3}\n".as_bytes())
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("line 2"));
+    }
+
+    #[test]
+    fn test_text_lines_round_trip() {
+        let handler = sample_handler();
+        let input = "# recorded at the gantry\nid: 7 speed_kph: 98.25 plate: \"MRP-7\"\n";
+
+        let batch = handler.text_lines_to_record_batch(input.as_bytes()).unwrap();
+        assert_eq!(batch, handler.list_to_record_batch(sample_text_expected(&handler)));
+
+        let mut output = Vec::new();
+        handler.record_batch_to_text_lines(&batch, &mut output).unwrap();
+        let reparsed = handler.text_lines_to_record_batch(output.as_slice()).unwrap();
+        assert_eq!(reparsed, batch);
+    }
+
+    fn sample_text_expected(handler: &MessageHandler) -> Vec<Vec<u8>> {
+        let descriptor = handler.get_message_descriptor();
+        let mut message = descriptor.new_instance();
+        descriptor.field_by_name("id").unwrap().set_singular_field(&mut *message, ReflectValueBox::I32(7));
+        descriptor.field_by_name("speed_kph").unwrap().set_singular_field(&mut *message, ReflectValueBox::F64(98.25));
+        descriptor
+            .field_by_name("plate")
+            .unwrap()
+            .set_singular_field(&mut *message, ReflectValueBox::String("MRP-7".to_string()));
+        vec![message.write_to_bytes_dyn().unwrap()]
+    }
+
+    #[test]
+    fn test_descriptor_set_registration() {
+        let mut imported = FileDescriptorProto::new();
+        imported.set_name("units.proto".to_string());
+        imported.set_package("mariposa.units".to_string());
+        imported.set_syntax("proto3".to_string());
+        let mut sample = FileDescriptorProto::parse_from_bytes(&sample_descriptor()).unwrap();
+        sample.dependency.push("units.proto".to_string());
+
+        // Dependents first, to check the set does not have to be sorted
+        let mut set = FileDescriptorSet::new();
+        set.file.push(sample);
+        set.file.push(imported);
+
+        let mut cache = ProtoCache::new();
+        cache.add_descriptor_set(&set.write_to_bytes().unwrap()).unwrap();
+        let handler = cache.handler_for("mariposa.test.Sample").unwrap();
+        assert_eq!(handler.get_message_descriptor().full_name(), "mariposa.test.Sample");
+        assert!(cache.handler_for(SAMPLE_MESSAGE).is_ok());
+        assert!(cache.handler_for("mariposa.test.Missing").is_err());
+
+        let mut broken = FileDescriptorSet::new();
+        let mut orphan = FileDescriptorProto::new();
+        orphan.set_name("orphan.proto".to_string());
+        orphan.dependency.push("nowhere.proto".to_string());
+        broken.file.push(orphan);
+        assert!(ProtoCache::new().add_descriptor_set(&broken.write_to_bytes().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_message_type_metadata() {
+        let handler = sample_handler();
+        let batch = handler.list_to_record_batch(sample_messages(&handler, 2));
+        assert_eq!(message_type(&batch.schema()), None);
+
+        let tagged = with_message_type(batch, SAMPLE_MESSAGE).unwrap();
+        assert_eq!(message_type(&tagged.schema()), Some("mariposa.test.Sample"));
+        assert_eq!(tagged.num_rows(), 2);
+    }
+}
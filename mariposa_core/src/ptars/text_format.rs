@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use arrow::record_batch::RecordBatch;
+use protobuf::text_format;
+use std::io::{BufRead, Write};
+
+use crate::ptars::message_handler::MessageHandler;
+
+impl MessageHandler {
+    /// Parse protobuf text format messages, one per line, into an Arrow RecordBatch
+    ///
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn text_lines_to_record_batch<R: BufRead>(&self, reader: R) -> Result<RecordBatch> {
+        let mut values = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let mut message = self.get_message_descriptor().new_instance();
+            text_format::merge_from_str(message.as_mut(), trimmed)
+                .with_context(|| format!("line {}: invalid text format", index + 1))?;
+            values.push(message.write_to_bytes_dyn()?);
+        }
+        Ok(self.list_to_record_batch(values))
+    }
+
+    /// Write each row of a RecordBatch as a single line of protobuf text format
+    pub fn record_batch_to_text_lines<W: Write>(&self, record_batch: &RecordBatch, mut writer: W) -> Result<()> {
+        for bytes in self.record_batch_to_array(record_batch) {
+            let message = self.get_message_descriptor().parse_from_bytes(&bytes)?;
+            writeln!(writer, "{}", text_format::print_to_string(message.as_ref()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{bail, ensure, Result};
+use arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit, TimestampNanosecondType};
+use arrow::record_batch::RecordBatch;
+use arrow_array::cast::AsArray;
+use arrow_array::{
+    ArrayRef, FixedSizeListArray, Float64Array, Int8Array, StringArray, StructArray, TimestampNanosecondArray,
+    UInt16Array, UInt32Array, UInt8Array,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::rosbag::ROS_TYPE_KEY;
+use crate::wire::ByteReader;
+
+const VECTOR3: [&str; 3] = ["x", "y", "z"];
+const QUATERNION: [&str; 4] = ["x", "y", "z", "w"];
+const COVARIANCE_LEN: i32 = 9;
+
+/// Arrow schema of a supported ROS message type
+///
+/// Fields follow the order of the ROS message definition, which is also the
+/// order they are serialized in, so decoding walks the schema.
+pub fn ros_schema(message_type: &str) -> Option<SchemaRef> {
+    let fields = match message_type {
+        "sensor_msgs/Imu" => vec![
+            header(),
+            float_struct("orientation", &QUATERNION),
+            covariance("orientation_covariance"),
+            float_struct("angular_velocity", &VECTOR3),
+            covariance("angular_velocity_covariance"),
+            float_struct("linear_acceleration", &VECTOR3),
+            covariance("linear_acceleration_covariance"),
+        ],
+        "sensor_msgs/NavSatFix" => vec![
+            header(),
+            Field::new_struct(
+                "status",
+                vec![
+                    Field::new("status", DataType::Int8, false),
+                    Field::new("service", DataType::UInt16, false),
+                ],
+                false,
+            ),
+            Field::new("latitude", DataType::Float64, false),
+            Field::new("longitude", DataType::Float64, false),
+            Field::new("altitude", DataType::Float64, false),
+            covariance("position_covariance"),
+            Field::new("position_covariance_type", DataType::UInt8, false),
+        ],
+        "geometry_msgs/PoseStamped" => vec![
+            header(),
+            Field::new_struct(
+                "pose",
+                vec![float_struct("position", &VECTOR3), float_struct("orientation", &QUATERNION)],
+                false,
+            ),
+        ],
+        _ => return None,
+    };
+    let metadata = HashMap::from([(ROS_TYPE_KEY.to_string(), message_type.to_string())]);
+    Some(Arc::new(Schema::new(fields).with_metadata(metadata)))
+}
+
+/// Decode a serialized ROS message into a one-row batch, returning it with
+/// the stamp of its header, or None if the type is not supported
+pub fn decode_message(message_type: &str, data: &[u8]) -> Result<Option<(i64, RecordBatch)>> {
+    let Some(schema) = ros_schema(message_type) else {
+        return Ok(None);
+    };
+    let mut reader = ByteReader::new(data);
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|x| decode_value(&mut reader, x.data_type()))
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(
+        reader.remaining() == 0,
+        "{} has {} bytes left over, the bag may use another definition",
+        message_type,
+        reader.remaining()
+    );
+    let batch = RecordBatch::try_new(schema, columns)?;
+    let stamp_ns = batch
+        .column(0)
+        .as_struct()
+        .column_by_name("stamp")
+        .map(|x| x.as_primitive::<TimestampNanosecondType>().value(0));
+    Ok(Some((stamp_ns.unwrap_or_default(), batch)))
+}
+
+fn decode_value(reader: &mut ByteReader, data_type: &DataType) -> Result<ArrayRef> {
+    Ok(match data_type {
+        DataType::Struct(fields) => {
+            let columns = fields
+                .iter()
+                .map(|x| decode_value(reader, x.data_type()))
+                .collect::<Result<Vec<_>>>()?;
+            Arc::new(StructArray::try_new(fields.clone(), columns, None)?)
+        }
+        DataType::FixedSizeList(field, len) => {
+            let values = decode_value_n(reader, field.data_type(), *len as usize)?;
+            Arc::new(FixedSizeListArray::try_new(field.clone(), *len, values, None)?)
+        }
+        DataType::Timestamp(_, zone) => {
+            let sec = reader.u32()? as i64;
+            let nsec = reader.u32()? as i64;
+            Arc::new(TimestampNanosecondArray::from(vec![sec * 1_000_000_000 + nsec]).with_timezone_opt(zone.clone()))
+        }
+        DataType::Utf8 => Arc::new(StringArray::from(vec![String::from_utf8(reader.bytes()?)?])),
+        other => decode_value_n(reader, other, 1)?,
+    })
+}
+
+/// Decode `len` consecutive primitives into one array
+fn decode_value_n(reader: &mut ByteReader, data_type: &DataType, len: usize) -> Result<ArrayRef> {
+    fn read<T>(len: usize, mut value: impl FnMut() -> Result<T>) -> Result<Vec<T>> {
+        (0..len).map(|_| value()).collect()
+    }
+    Ok(match data_type {
+        DataType::Float64 => Arc::new(Float64Array::from(read(len, || Ok(f64::from_bits(reader.u64()?)))?)),
+        DataType::UInt32 => Arc::new(UInt32Array::from(read(len, || reader.u32())?)),
+        DataType::UInt16 => Arc::new(UInt16Array::from(read(len, || reader.u16())?)),
+        DataType::UInt8 => Arc::new(UInt8Array::from(read(len, || reader.u8())?)),
+        DataType::Int8 => Arc::new(Int8Array::from(read(len, || Ok(reader.u8()? as i8))?)),
+        other => bail!("no ROS decoding for {}", other),
+    })
+}
+
+fn header() -> Field {
+    Field::new_struct(
+        "header",
+        vec![
+            Field::new("seq", DataType::UInt32, false),
+            Field::new("stamp", DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())), false),
+            Field::new("frame_id", DataType::Utf8, false),
+        ],
+        false,
+    )
+}
+
+fn float_struct(name: &str, members: &[&str]) -> Field {
+    let fields: Fields = members.iter().map(|x| Field::new(*x, DataType::Float64, false)).collect();
+    Field::new(name, DataType::Struct(fields), false)
+}
+
+fn covariance(name: &str) -> Field {
+    let item = Arc::new(Field::new("item", DataType::Float64, false));
+    Field::new(name, DataType::FixedSizeList(item, COVARIANCE_LEN), false)
+}
@@ -0,0 +1,69 @@
+// Import of ROS 1 bag files (format version 2.0)
+//
+// A bag starts with the line "#ROSBAG V2.0" followed by records:
+//
+//   record: header_len u32 | header | data_len u32 | data
+//   header: fields of field_len u32 | name '=' value, with `op` giving the kind
+//
+//   0x03 bag header   index position, connection and chunk counts
+//   0x05 chunk        `compression` (none, bz2 or lz4) and `size`; the data
+//                     holds connection and message records
+//   0x07 connection   `conn` id and `topic`; the data is the connection header
+//                     with `type`, `md5sum` and `message_definition`
+//   0x02 message      `conn` id and receive `time` (sec u32, nsec u32); the
+//                     data is the serialized message
+//   0x04, 0x06        index records, not needed for a linear read
+//
+// All integers are little endian. Messages of the types in `SUPPORTED_TYPES`
+// are decoded into one-row RecordBatches with nested struct columns mirroring
+// the ROS message; the topic loses its leading '/'. The resulting log plays
+// back through the broker like any other recording.
+
+mod messages;
+mod reader;
+
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::logfile::{LogConfig, LogEntry, LogWriter};
+
+pub use messages::{decode_message, ros_schema};
+pub use reader::{BagMessage, BagReader, Connection};
+
+// Constants
+pub const MAGIC: &[u8] = b"#ROSBAG V2.0\n";
+/// Schema metadata key naming the ROS message type a batch was decoded from
+pub const ROS_TYPE_KEY: &str = "mariposa.ros_type";
+pub const SUPPORTED_TYPES: [&str; 3] = ["sensor_msgs/Imu", "sensor_msgs/NavSatFix", "geometry_msgs/PoseStamped"];
+
+/// Import the messages of a bag as a recording and return its segment
+/// directories
+///
+/// The bag's receive times become log times and the header stamps publish
+/// times. Messages of unsupported types are skipped.
+pub fn import_bag(path: &Path, config: LogConfig) -> Result<Vec<PathBuf>> {
+    let mut writer = LogWriter::create(config)?;
+    let mut skipped = HashSet::new();
+    for message in BagReader::open(path)? {
+        let message = message?;
+        let connection = &message.connection;
+        let Some((stamp_ns, batch)) = decode_message(&connection.message_type, &message.data)? else {
+            if skipped.insert(connection.topic.clone()) {
+                log::warn!("skipping '{}', {} is not supported", connection.topic, connection.message_type);
+            }
+            continue;
+        };
+        writer.write(&LogEntry {
+            topic: connection.topic.trim_start_matches('/').to_string(),
+            publisher_id: 0,
+            publish_time_ns: stamp_ns,
+            log_time_ns: message.time_ns,
+            batch,
+        })?;
+    }
+    writer.finish()
+}
@@ -0,0 +1,228 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::rosbag::MAGIC;
+use crate::wire::ByteReader;
+
+const OP_MESSAGE: u8 = 0x02;
+const OP_BAG_HEADER: u8 = 0x03;
+const OP_INDEX: u8 = 0x04;
+const OP_CHUNK: u8 = 0x05;
+const OP_CHUNK_INFO: u8 = 0x06;
+const OP_CONNECTION: u8 = 0x07;
+
+/// A topic as recorded in the bag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub id: u32,
+    pub topic: String,
+    /// ROS message type, such as `sensor_msgs/Imu`
+    pub message_type: String,
+    pub md5sum: String,
+}
+
+/// A serialized message and when the bag received it
+#[derive(Debug, Clone)]
+pub struct BagMessage {
+    pub connection: Arc<Connection>,
+    pub time_ns: i64,
+    pub data: Vec<u8>,
+}
+
+/// Reads the messages of a bag in the order they were written
+pub struct BagReader {
+    path: PathBuf,
+    data: Mmap,
+    position: usize,
+    /// Decompressed records of the chunk being read and the position in them
+    chunk: Option<(Vec<u8>, usize)>,
+    connections: HashMap<u32, Arc<Connection>>,
+}
+
+/// Header fields and data of one record
+struct Record<'a> {
+    fields: HashMap<&'a str, &'a [u8]>,
+    data: &'a [u8],
+}
+
+impl BagReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is only read, and the file changing underneath
+        // the reader is a caller error as it is for any other reader
+        let data = unsafe { Mmap::map(&file)? };
+        if !data.starts_with(MAGIC) {
+            bail!("'{}' is not a ROS bag of version 2.0", path.display());
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            data,
+            position: MAGIC.len(),
+            chunk: None,
+            connections: HashMap::new(),
+        })
+    }
+
+    /// Connections seen so far, all of them once the bag has been read
+    pub fn connections(&self) -> Vec<Arc<Connection>> {
+        let mut connections: Vec<_> = self.connections.values().cloned().collect();
+        connections.sort_by_key(|x| x.id);
+        connections
+    }
+
+    fn next_message(&mut self) -> Result<Option<BagMessage>> {
+        loop {
+            if let Some((records, position)) = &mut self.chunk {
+                if *position < records.len() {
+                    let (record, next) = read_record(records, *position)?;
+                    *position = next;
+                    let op = record.op()?;
+                    match op {
+                        OP_CONNECTION => add_connection(&mut self.connections, &record)?,
+                        OP_MESSAGE => return Ok(Some(to_message(&self.connections, &record)?)),
+                        _ => bail!("unexpected record {:#04x} in a chunk", op),
+                    }
+                    continue;
+                }
+                self.chunk = None;
+            }
+
+            if self.position >= self.data.len() {
+                return Ok(None);
+            }
+            let (record, next) = read_record(&self.data, self.position)?;
+            self.position = next;
+            match record.op()? {
+                OP_CHUNK => self.chunk = Some((decompress(&record)?, 0)),
+                OP_CONNECTION => add_connection(&mut self.connections, &record)?,
+                OP_MESSAGE => return Ok(Some(to_message(&self.connections, &record)?)),
+                OP_BAG_HEADER | OP_INDEX | OP_CHUNK_INFO => {}
+                op => bail!("unknown record {:#04x}", op),
+            }
+        }
+    }
+}
+
+impl Iterator for BagReader {
+    type Item = Result<BagMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = self.path.display().to_string();
+        let message = self.next_message().with_context(|| format!("failed to read '{}'", path));
+        if message.is_err() {
+            // Nothing after a damaged record can be trusted
+            self.position = self.data.len();
+            self.chunk = None;
+        }
+        message.transpose()
+    }
+}
+
+impl<'a> Record<'a> {
+    fn field(&self, name: &str) -> Result<&'a [u8]> {
+        self.fields
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("record is missing its '{}' field", name))
+    }
+
+    fn op(&self) -> Result<u8> {
+        let op = self.field("op")?;
+        ensure!(op.len() == 1, "invalid op field");
+        Ok(op[0])
+    }
+
+    fn u32(&self, name: &str) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.field(name)?.try_into()?))
+    }
+
+    fn string(&self, name: &str) -> Result<String> {
+        Ok(String::from_utf8(self.field(name)?.to_vec())?)
+    }
+}
+
+/// Read the record at `position`, returning it and where the next one starts
+fn read_record(bytes: &[u8], position: usize) -> Result<(Record<'_>, usize)> {
+    let mut reader = ByteReader::new(&bytes[position..]);
+    let header_len = reader.u32()? as usize;
+    let fields = read_fields(reader.take(header_len)?)?;
+    let data_len = reader.u32()? as usize;
+    let data = reader.take(data_len)?;
+    Ok((Record { fields, data }, bytes.len() - reader.remaining()))
+}
+
+/// Split a record or connection header into its `name=value` fields
+fn read_fields(bytes: &[u8]) -> Result<HashMap<&str, &[u8]>> {
+    let mut reader = ByteReader::new(bytes);
+    let mut fields = HashMap::new();
+    while reader.remaining() > 0 {
+        let len = reader.u32()? as usize;
+        let field = reader.take(len)?;
+        let split = field
+            .iter()
+            .position(|x| *x == b'=')
+            .ok_or_else(|| anyhow!("header field without '='"))?;
+        fields.insert(std::str::from_utf8(&field[..split])?, &field[split + 1..]);
+    }
+    Ok(fields)
+}
+
+fn add_connection(connections: &mut HashMap<u32, Arc<Connection>>, record: &Record) -> Result<()> {
+    let id = record.u32("conn")?;
+    let header = Record {
+        fields: read_fields(record.data)?,
+        data: &[],
+    };
+    let connection = Connection {
+        id,
+        topic: record.string("topic")?,
+        message_type: header.string("type")?,
+        md5sum: header.string("md5sum")?,
+    };
+    connections.insert(id, Arc::new(connection));
+    Ok(())
+}
+
+fn to_message(connections: &HashMap<u32, Arc<Connection>>, record: &Record) -> Result<BagMessage> {
+    let id = record.u32("conn")?;
+    let connection = connections
+        .get(&id)
+        .ok_or_else(|| anyhow!("message on connection {} before its connection record", id))?;
+    let time = record.field("time")?;
+    ensure!(time.len() == 8, "invalid time field");
+    let sec = u32::from_le_bytes(time[..4].try_into()?);
+    let nsec = u32::from_le_bytes(time[4..].try_into()?);
+    Ok(BagMessage {
+        connection: connection.clone(),
+        time_ns: sec as i64 * 1_000_000_000 + nsec as i64,
+        data: record.data.to_vec(),
+    })
+}
+
+fn decompress(chunk: &Record) -> Result<Vec<u8>> {
+    let size = chunk.u32("size")? as usize;
+    let compression = chunk.string("compression")?;
+    let mut records = Vec::with_capacity(size);
+    match compression.as_str() {
+        "none" => records.extend_from_slice(chunk.data),
+        "bz2" => {
+            bzip2::read::BzDecoder::new(chunk.data).read_to_end(&mut records)?;
+        }
+        "lz4" => {
+            lz4_flex::frame::FrameDecoder::new(chunk.data).read_to_end(&mut records)?;
+        }
+        other => bail!("unsupported chunk compression '{}'", other),
+    }
+    ensure!(
+        records.len() == size,
+        "chunk holds {} bytes instead of {}",
+        records.len(),
+        size
+    );
+    Ok(records)
+}
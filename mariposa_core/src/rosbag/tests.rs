@@ -0,0 +1,170 @@
+use arrow::datatypes::{Float64Type, TimestampNanosecondType};
+use arrow_array::cast::AsArray;
+use std::io::Write;
+use std::path::Path;
+use crate::logfile::{LogConfig, LogReader};
+use crate::rosbag::{import_bag, BagReader, MAGIC, ROS_TYPE_KEY};
+
+fn field(name: &str, value: &[u8]) -> Vec<u8> {
+    let mut out = ((name.len() + 1 + value.len()) as u32).to_le_bytes().to_vec();
+    out.extend_from_slice(name.as_bytes());
+    out.push(b'=');
+    out.extend_from_slice(value);
+    out
+}
+
+fn record(fields: &[(&str, &[u8])], data: &[u8]) -> Vec<u8> {
+    let header: Vec<u8> = fields.iter().flat_map(|(name, value)| field(name, value)).collect();
+    let mut out = (header.len() as u32).to_le_bytes().to_vec();
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+fn connection(id: u32, topic: &str, message_type: &str) -> Vec<u8> {
+    let header = [field("type", message_type.as_bytes()), field("md5sum", b"0123456789abcdef")].concat();
+    record(
+        &[("op", &[0x07]), ("conn", &id.to_le_bytes()), ("topic", topic.as_bytes())],
+        &header,
+    )
+}
+
+fn message(id: u32, time_ns: i64, data: &[u8]) -> Vec<u8> {
+    let time = [
+        ((time_ns / 1_000_000_000) as u32).to_le_bytes(),
+        ((time_ns % 1_000_000_000) as u32).to_le_bytes(),
+    ]
+    .concat();
+    record(&[("op", &[0x02]), ("conn", &id.to_le_bytes()), ("time", &time)], data)
+}
+
+fn chunk(compression: &str, records: &[u8]) -> Vec<u8> {
+    let data = match compression {
+        "bz2" => {
+            let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            encoder.write_all(records).unwrap();
+            encoder.finish().unwrap()
+        }
+        _ => records.to_vec(),
+    };
+    let size = (records.len() as u32).to_le_bytes();
+    record(
+        &[("op", &[0x05]), ("compression", compression.as_bytes()), ("size", &size)],
+        &data,
+    )
+}
+
+fn ros_header(seq: u32, stamp_ns: i64, frame_id: &str) -> Vec<u8> {
+    let mut out = seq.to_le_bytes().to_vec();
+    out.extend_from_slice(&((stamp_ns / 1_000_000_000) as u32).to_le_bytes());
+    out.extend_from_slice(&((stamp_ns % 1_000_000_000) as u32).to_le_bytes());
+    out.extend_from_slice(&(frame_id.len() as u32).to_le_bytes());
+    out.extend_from_slice(frame_id.as_bytes());
+    out
+}
+
+fn floats(values: &[f64]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn pose(seq: u32, stamp_ns: i64, x: f64) -> Vec<u8> {
+    [ros_header(seq, stamp_ns, "map"), floats(&[x, 2.0, 3.0, 0.0, 0.0, 0.0, 1.0])].concat()
+}
+
+fn fix(stamp_ns: i64) -> Vec<u8> {
+    let mut out = ros_header(0, stamp_ns, "gps");
+    out.push(0);
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend(floats(&[48.1, 11.5, 520.0]));
+    out.extend(floats(&[0.5; 9]));
+    out.push(2);
+    out
+}
+
+fn imu(stamp_ns: i64) -> Vec<u8> {
+    let mut out = ros_header(7, stamp_ns, "imu_link");
+    for values in [&[0.0, 0.0, 0.0, 1.0][..], &[0.0; 9], &[0.1, 0.2, 0.3], &[0.0; 9], &[0.0, 0.0, 9.81], &[0.0; 9]] {
+        out.extend(floats(values));
+    }
+    out
+}
+
+/// A bag with an uncompressed and a bz2 chunk, and a topic of a type that is
+/// not supported
+fn write_bag(path: &Path) {
+    let first = [
+        connection(0, "/pose", "geometry_msgs/PoseStamped"),
+        connection(1, "/chatter", "std_msgs/String"),
+        message(0, 1_000_000_100, &pose(0, 1_000_000_000, 1.0)),
+        message(1, 1_000_000_200, &[5, 0, 0, 0, b'h', b'e', b'l', b'l', b'o']),
+    ]
+    .concat();
+    let second = [
+        connection(2, "/fix", "sensor_msgs/NavSatFix"),
+        connection(3, "/imu", "sensor_msgs/Imu"),
+        message(2, 2_000_000_100, &fix(2_000_000_000)),
+        message(3, 2_000_000_200, &imu(2_000_000_050)),
+        message(0, 2_000_000_300, &pose(1, 2_000_000_250, 4.0)),
+    ]
+    .concat();
+    let bag_header = record(&[("op", &[0x03]), ("conn_count", &4u32.to_le_bytes())], &[b' '; 16]);
+    let bytes = [MAGIC.to_vec(), bag_header, chunk("none", &first), chunk("bz2", &second)].concat();
+    std::fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn test_bag_reader_reads_compressed_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("drive.bag");
+    write_bag(&path);
+
+    let mut reader = BagReader::open(&path).unwrap();
+    let messages: Vec<_> = reader.by_ref().map(|x| x.unwrap()).collect();
+    let topics: Vec<_> = messages.iter().map(|x| x.connection.topic.as_str()).collect();
+    assert_eq!(topics, ["/pose", "/chatter", "/fix", "/imu", "/pose"]);
+    assert_eq!(messages[2].time_ns, 2_000_000_100);
+    assert_eq!(messages[1].data.len(), 9);
+    let connections = reader.connections();
+    assert_eq!(connections.len(), 4);
+    assert_eq!(connections[3].message_type, "sensor_msgs/Imu");
+
+    std::fs::write(&path, b"#ROSBAG V1.2\n").unwrap();
+    assert!(BagReader::open(&path).is_err());
+}
+
+#[test]
+fn test_import_bag_maps_supported_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("drive.bag");
+    write_bag(&path);
+    let segments = import_bag(&path, LogConfig::new(dir.path().join("log"))).unwrap();
+    assert_eq!(segments.len(), 1);
+
+    let entries: Vec<_> = LogReader::open(&segments[0]).unwrap().map(|x| x.unwrap()).collect();
+    let topics: Vec<_> = entries.iter().map(|x| x.topic.as_str()).collect();
+    assert_eq!(topics, ["pose", "fix", "imu", "pose"]);
+    assert_eq!(entries[2].log_time_ns, 2_000_000_200);
+    assert_eq!(entries[2].publish_time_ns, 2_000_000_050);
+
+    let pose = &entries[3].batch;
+    assert_eq!(pose.schema().metadata()[ROS_TYPE_KEY], "geometry_msgs/PoseStamped");
+    let header = pose.column_by_name("header").unwrap().as_struct();
+    let stamp = header.column_by_name("stamp").unwrap().as_primitive::<TimestampNanosecondType>();
+    assert_eq!(stamp.value(0), 2_000_000_250);
+    assert_eq!(header.column_by_name("frame_id").unwrap().as_string::<i32>().value(0), "map");
+    let position = pose.column_by_name("pose").unwrap().as_struct().column_by_name("position").unwrap();
+    let x = position.as_struct().column_by_name("x").unwrap().as_primitive::<Float64Type>();
+    assert_eq!(x.value(0), 4.0);
+
+    let fix = &entries[1].batch;
+    let latitude = fix.column_by_name("latitude").unwrap().as_primitive::<Float64Type>();
+    assert_eq!(latitude.value(0), 48.1);
+    let covariance = fix.column_by_name("position_covariance").unwrap().as_fixed_size_list();
+    assert_eq!(covariance.value(0).len(), 9);
+
+    let imu = &entries[2].batch;
+    let acceleration = imu.column_by_name("linear_acceleration").unwrap().as_struct();
+    let z = acceleration.column_by_name("z").unwrap().as_primitive::<Float64Type>();
+    assert_eq!(z.value(0), 9.81);
+}
@@ -0,0 +1,41 @@
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+/// Sleep while `word` still holds `expected`, for at most `timeout`
+///
+/// The word lives in a shared mapping, so the process-shared variant of the
+/// futex is used. Returns early on wakeups, signals and spurious wakeups alike.
+pub(crate) fn wait(word: &AtomicU32, expected: u32, timeout: Duration) {
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    };
+    // SAFETY: `word` is a valid, aligned u32 for the duration of the call
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAIT,
+            expected,
+            &timeout as *const libc::timespec,
+            std::ptr::null::<u32>(),
+            0,
+        );
+    }
+}
+
+/// Wake every process sleeping on `word`
+pub(crate) fn wake_all(word: &AtomicU32) {
+    // SAFETY: `word` is a valid, aligned u32 for the duration of the call
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAKE,
+            i32::MAX,
+            std::ptr::null::<libc::timespec>(),
+            std::ptr::null::<u32>(),
+            0,
+        );
+    }
+}
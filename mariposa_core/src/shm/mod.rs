@@ -0,0 +1,42 @@
+// Shared-memory transport for nodes on the same host
+//
+// A publisher owns one ring file per topic, usually under /dev/shm, and
+// appends every batch to it as a self-contained Arrow IPC stream. Readers map
+// the same file and decode batches in place, so the data is never copied.
+//
+//   header (1024 bytes): magic "MRPSHM02" | capacity u64 | reserved u64 |
+//                        written u64 | signal u32 | sleepers u32 |
+//                        MAX_READERS x (pid u32 | padding u32 | held u64)
+//   data (capacity bytes): records aligned to 64 bytes
+//
+//   record: marker u32 | topic_len u32 | ipc_len u64 | publisher_id u64 |
+//           timestamp_ns i64 | topic | padding | Arrow IPC stream | padding
+//
+// `written` is the end of the last complete record and `reserved` the end of
+// the one being written. Readers sleep on `signal` as a futex, which the
+// writer bumps after each record, and detect that the writer overwrote what
+// they were reading by checking `reserved` afterwards.
+//
+// Every reader claims a slot and keeps `held` at the oldest record its
+// batches still point into. The writer refuses to reserve space over a held
+// record rather than overwrite it, and frees slots whose process is gone.
+// Linux only.
+
+mod futex;
+mod ring;
+
+#[cfg(test)]
+mod tests;
+
+pub use ring::{ring_path, ShmReader, ShmRecord, ShmWriter};
+
+// Constants
+pub const MAGIC: [u8; 8] = *b"MRPSHM02";
+pub const HEADER_LEN: usize = 1024;
+/// Readers that can map a ring at the same time
+pub const MAX_READERS: usize = 32;
+/// Alignment of records and of the Arrow buffers inside them
+pub const ALIGNMENT: usize = 64;
+pub const MIN_CAPACITY: usize = 4096;
+/// Where nodes look for ring files unless configured otherwise
+pub const DEFAULT_SHM_DIR: &str = "/dev/shm/mariposa";
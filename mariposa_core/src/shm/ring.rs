@@ -0,0 +1,508 @@
+use anyhow::{bail, ensure, Context, Result};
+use arrow::buffer::Buffer;
+use arrow::ipc::reader::StreamDecoder;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use memmap2::{MmapOptions, MmapRaw};
+use std::fs::{self, File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::shm::futex;
+use crate::shm::{ALIGNMENT, HEADER_LEN, MAGIC, MAX_READERS, MIN_CAPACITY};
+use crate::topic::topic_file_name;
+
+/// Marks a record that holds a batch
+const RECORD: u32 = 1;
+/// Marks the unused tail of the ring, the next record starts at offset zero
+const WRAP: u32 = 2;
+/// marker u32 | topic_len u32 | ipc_len u64 | publisher_id u64 | timestamp_ns i64
+const RECORD_HEADER_LEN: usize = 32;
+
+/// Control block at the start of every ring file
+#[repr(C)]
+struct RingHeader {
+    magic: [u8; 8],
+    capacity: u64,
+    /// End of the region the writer may be overwriting right now
+    reserved: AtomicU64,
+    /// End of the last complete record
+    written: AtomicU64,
+    /// Bumped after every record; readers sleep on it as a futex
+    signal: AtomicU32,
+    /// Readers currently sleeping on `signal`
+    sleepers: AtomicU32,
+    readers: [ReaderSlot; MAX_READERS],
+}
+
+const _: () = assert!(std::mem::size_of::<RingHeader>() <= HEADER_LEN);
+
+/// What one reader still points into, so the writer leaves it alone
+#[repr(C)]
+struct ReaderSlot {
+    /// Process owning the slot, zero when free
+    pid: AtomicU32,
+    _padding: u32,
+    /// One past the position of the oldest record the reader's batches point
+    /// into, zero when they point into none
+    held: AtomicU64,
+}
+
+fn header(map: &MmapRaw) -> &RingHeader {
+    // SAFETY: the mapping is page aligned and at least HEADER_LEN long, and
+    // every field of the header is valid for any bit pattern
+    unsafe { &*(map.as_ptr() as *const RingHeader) }
+}
+
+fn align(len: usize) -> usize {
+    len.div_ceil(ALIGNMENT) * ALIGNMENT
+}
+
+/// Try to take an flock on the file without blocking
+fn try_lock(file: &File, operation: libc::c_int) -> Result<bool> {
+    // SAFETY: plain syscall on a file descriptor we own
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(error.into()),
+    }
+}
+
+/// Whether the process that claimed a reader slot still runs
+fn process_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+/// Path of the ring file for a topic inside `dir`
+pub fn ring_path(dir: &Path, topic: &str) -> PathBuf {
+    dir.join(format!("{}.ring", topic_file_name(topic)))
+}
+
+/// Single producer of a ring file
+///
+/// Holds an exclusive flock on the file for as long as it lives, which is
+/// how readers tell that the publisher went away, and removes the file when
+/// dropped.
+pub struct ShmWriter {
+    path: PathBuf,
+    _file: File,
+    map: MmapRaw,
+    capacity: u64,
+    position: u64,
+}
+
+impl ShmWriter {
+    /// Create a ring with room for `capacity` bytes of records at `path`
+    ///
+    /// A file left behind by a writer that is gone is replaced. Fails if
+    /// another writer still owns the file.
+    pub fn create(path: &Path, capacity: usize) -> Result<Self> {
+        ensure!(
+            capacity >= MIN_CAPACITY && capacity.is_multiple_of(ALIGNMENT),
+            "ring capacity must be a multiple of {} of at least {} bytes",
+            ALIGNMENT,
+            MIN_CAPACITY
+        );
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Ok(existing) = File::open(path) {
+            if !try_lock(&existing, libc::LOCK_EX)? {
+                bail!("'{}' already has a writer", path.display());
+            }
+            fs::remove_file(path)?;
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| format!("failed to create '{}'", path.display()))?;
+        ensure!(try_lock(&file, libc::LOCK_EX)?, "'{}' already has a writer", path.display());
+        file.set_len((HEADER_LEN + capacity) as u64)?;
+        let map = MmapOptions::new().map_raw(&file)?;
+
+        // The file starts out zeroed, so only the constant fields need writing
+        // SAFETY: the mapping is writable and longer than the header
+        unsafe {
+            let header = map.as_mut_ptr() as *mut RingHeader;
+            (*header).capacity = capacity as u64;
+            (*header).magic = MAGIC;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            _file: file,
+            map,
+            capacity: capacity as u64,
+            position: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a batch to the ring and wake sleeping readers
+    ///
+    /// Readers that are more than a ring's worth of bytes behind lose the
+    /// oldest records. Records that batches handed out by a reader still point
+    /// into are never overwritten though: the write fails instead until those
+    /// batches are dropped. A single record may use at most half of the ring.
+    pub fn write(&mut self, topic: &str, publisher_id: u64, timestamp_ns: i64, batch: &RecordBatch) -> Result<()> {
+        let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema())?;
+        writer.write(batch)?;
+        writer.finish()?;
+        let ipc = writer.into_inner()?;
+
+        let head_len = align(RECORD_HEADER_LEN + topic.len());
+        let total = (head_len + align(ipc.len())) as u64;
+        ensure!(
+            total <= self.capacity / 2,
+            "batch of {} bytes does not fit a ring of {} bytes",
+            total,
+            self.capacity
+        );
+
+        let mut start = self.position;
+        let offset = start % self.capacity;
+        let wraps = offset + total > self.capacity;
+        if wraps {
+            start += self.capacity - offset;
+        }
+
+        let header = header(&self.map);
+        header.reserved.store(start + total, Ordering::SeqCst);
+        // Readers take their hold before checking `reserved`, so either they
+        // see this reservation or the writer sees their hold
+        if let Err(e) = self.check_holds(header, start + total) {
+            header.reserved.store(self.position, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        let mut head = Vec::with_capacity(head_len);
+        head.extend_from_slice(&RECORD.to_le_bytes());
+        head.extend_from_slice(&(topic.len() as u32).to_le_bytes());
+        head.extend_from_slice(&(ipc.len() as u64).to_le_bytes());
+        head.extend_from_slice(&publisher_id.to_le_bytes());
+        head.extend_from_slice(&timestamp_ns.to_le_bytes());
+        head.extend_from_slice(topic.as_bytes());
+        head.resize(head_len, 0);
+
+        // SAFETY: every write stays inside the data region, as offsets are
+        // taken modulo the capacity and records never straddle its end
+        unsafe {
+            let data = self.map.as_mut_ptr().add(HEADER_LEN);
+            if wraps {
+                std::ptr::copy_nonoverlapping(WRAP.to_le_bytes().as_ptr(), data.add(offset as usize), 4);
+            }
+            let record = data.add((start % self.capacity) as usize);
+            std::ptr::copy_nonoverlapping(head.as_ptr(), record, head.len());
+            std::ptr::copy_nonoverlapping(ipc.as_ptr(), record.add(head_len), ipc.len());
+        }
+
+        self.position = start + total;
+        header.written.store(self.position, Ordering::Release);
+        header.signal.fetch_add(1, Ordering::SeqCst);
+        if header.sleepers.load(Ordering::SeqCst) > 0 {
+            futex::wake_all(&header.signal);
+        }
+        Ok(())
+    }
+}
+
+impl ShmWriter {
+    /// Fail if writing up to `end` would overwrite a record a reader holds
+    fn check_holds(&self, header: &RingHeader, end: u64) -> Result<()> {
+        for slot in &header.readers {
+            let pid = slot.pid.load(Ordering::SeqCst);
+            let held = slot.held.load(Ordering::SeqCst);
+            if pid == 0 || held == 0 || end <= held - 1 + self.capacity {
+                continue;
+            }
+            if !process_alive(pid) {
+                // Whatever the reader held went away with it
+                let _ = slot.pid.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+                continue;
+            }
+            bail!(
+                "ring is full: process {} still holds batches read {} bytes ago",
+                pid,
+                self.position + 1 - held
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ShmWriter {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A batch read from a ring
+///
+/// The batch points straight into the shared mapping, and the writer leaves
+/// the record alone until the batch and every array taken from it have been
+/// dropped. Writes to the ring fail in the meantime once they would reach it,
+/// so copy batches that are kept around for long.
+#[derive(Debug, Clone)]
+pub struct ShmRecord {
+    pub topic: String,
+    pub publisher_id: u64,
+    pub timestamp_ns: i64,
+    pub batch: RecordBatch,
+}
+
+/// The slot of a reader and the records its batches still point into
+///
+/// Shared by the reader and its batches, so the mapping stays alive and the
+/// slot stays claimed until the last of them is gone.
+struct Holds {
+    map: MmapRaw,
+    slot: usize,
+    /// Number of live batches per record position
+    positions: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl Holds {
+    fn slot(&self) -> &ReaderSlot {
+        &header(&self.map).readers[self.slot]
+    }
+
+    fn take(&self, position: u64) {
+        let mut positions = self.positions.lock().unwrap();
+        *positions.entry(position).or_default() += 1;
+        let oldest = positions.keys().next().map_or(0, |x| x + 1);
+        self.slot().held.store(oldest, Ordering::SeqCst);
+    }
+
+    fn release(&self, position: u64) {
+        let mut positions = self.positions.lock().unwrap();
+        if let Some(count) = positions.get_mut(&position) {
+            *count -= 1;
+            if *count == 0 {
+                positions.remove(&position);
+            }
+        }
+        let oldest = positions.keys().next().map_or(0, |x| x + 1);
+        self.slot().held.store(oldest, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Holds {
+    fn drop(&mut self) {
+        let slot = self.slot();
+        slot.held.store(0, Ordering::SeqCst);
+        slot.pid.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Owner of the buffers decoded from one record, releasing it once dropped
+struct Hold {
+    holds: Arc<Holds>,
+    position: u64,
+}
+
+impl Hold {
+    fn take(holds: &Arc<Holds>, position: u64) -> Self {
+        holds.take(position);
+        Self {
+            holds: holds.clone(),
+            position,
+        }
+    }
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        self.holds.release(self.position);
+    }
+}
+
+/// Claim a free reader slot, or one whose process is gone
+fn claim_slot(header: &RingHeader) -> Option<usize> {
+    let pid = std::process::id();
+    let claim = |index: usize, owner: u32| {
+        let slot = &header.readers[index];
+        let claimed = slot.pid.compare_exchange(owner, pid, Ordering::SeqCst, Ordering::SeqCst).is_ok();
+        if claimed {
+            slot.held.store(0, Ordering::SeqCst);
+        }
+        claimed
+    };
+    (0..MAX_READERS).find(|&index| claim(index, 0)).or_else(|| {
+        (0..MAX_READERS).find(|&index| {
+            let owner = header.readers[index].pid.load(Ordering::SeqCst);
+            owner != 0 && !process_alive(owner) && claim(index, owner)
+        })
+    })
+}
+
+/// One consumer of a ring file, reading only records written after it opened
+pub struct ShmReader {
+    file: File,
+    holds: Arc<Holds>,
+    capacity: u64,
+    position: u64,
+}
+
+impl ShmReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("failed to open '{}'", path.display()))?;
+        let len = file.metadata()?.len() as usize;
+        ensure!(len >= HEADER_LEN, "'{}' is not a ring file", path.display());
+        let map = MmapOptions::new().map_raw(&file)?;
+
+        let header = header(&map);
+        ensure!(header.magic == MAGIC, "'{}' is not a ring file", path.display());
+        let capacity = header.capacity;
+        ensure!(len == HEADER_LEN + capacity as usize, "'{}' has an unexpected size", path.display());
+        let Some(slot) = claim_slot(header) else {
+            bail!("'{}' already has {} readers", path.display(), MAX_READERS);
+        };
+        let position = header.written.load(Ordering::Acquire);
+        Ok(Self {
+            file,
+            holds: Arc::new(Holds {
+                map,
+                slot,
+                positions: Mutex::new(BTreeMap::new()),
+            }),
+            capacity,
+            position,
+        })
+    }
+
+    fn map(&self) -> &MmapRaw {
+        &self.holds.map
+    }
+
+    /// Whether the writer has gone away, in which case nothing new will arrive
+    pub fn writer_closed(&self) -> Result<bool> {
+        let closed = try_lock(&self.file, libc::LOCK_SH)?;
+        if closed {
+            // SAFETY: plain syscall on a file descriptor we own
+            unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+        }
+        Ok(closed)
+    }
+
+    /// Sleep until a record is available or `timeout` passes, returning
+    /// whether there is something to read
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let header = header(self.map());
+        let signal = header.signal.load(Ordering::SeqCst);
+        if header.written.load(Ordering::Acquire) != self.position {
+            return true;
+        }
+        header.sleepers.fetch_add(1, Ordering::SeqCst);
+        futex::wait(&header.signal, signal, timeout);
+        header.sleepers.fetch_sub(1, Ordering::SeqCst);
+        header.written.load(Ordering::Acquire) != self.position
+    }
+
+    /// Take the next record if one is available
+    ///
+    /// Errors if the writer overwrote records this reader had not read yet;
+    /// the reader then carries on from the newest record.
+    pub fn read(&mut self) -> Result<Option<ShmRecord>> {
+        loop {
+            let written = header(self.map()).written.load(Ordering::Acquire);
+            if self.position == written {
+                return Ok(None);
+            }
+            self.check_overrun()?;
+
+            let offset = (self.position % self.capacity) as usize;
+            let mut head = [0u8; RECORD_HEADER_LEN];
+            // SAFETY: offsets are aligned and the data region ends on an
+            // aligned boundary, so a record header always fits before it
+            let record = unsafe { self.map().as_ptr().add(HEADER_LEN + offset) };
+            unsafe { std::ptr::copy_nonoverlapping(record, head.as_mut_ptr(), RECORD_HEADER_LEN) };
+
+            let marker = u32::from_le_bytes(head[0..4].try_into()?);
+            if marker == WRAP {
+                self.position += self.capacity - offset as u64;
+                continue;
+            }
+            let topic_len = u32::from_le_bytes(head[4..8].try_into()?) as usize;
+            let ipc_len = u64::from_le_bytes(head[8..16].try_into()?) as usize;
+            let head_len = align(RECORD_HEADER_LEN + topic_len);
+            let total = head_len.saturating_add(align(ipc_len));
+            if marker != RECORD || total > self.capacity as usize - offset {
+                self.check_overrun()?;
+                bail!("corrupt record at position {}", self.position);
+            }
+
+            // SAFETY: the record lies inside the data region as checked above
+            let topic = unsafe { std::slice::from_raw_parts(record.add(RECORD_HEADER_LEN), topic_len) };
+            let topic = String::from_utf8_lossy(topic).into_owned();
+            // Hold the record before checking that the writer did not touch it,
+            // so from then on it leaves the record alone
+            let hold = Hold::take(&self.holds, self.position);
+            self.check_overrun()?;
+            let payload = NonNull::new(unsafe { record.add(head_len) } as *mut u8).expect("mapping is not null");
+            // SAFETY: the payload lies inside the mapping as checked above, which
+            // the hold keeps alive and the writer leaves alone while it lives
+            let decoded = decode(unsafe { Buffer::from_custom_allocation(payload, ipc_len, Arc::new(hold)) });
+
+            self.position += total as u64;
+            return Ok(Some(ShmRecord {
+                topic,
+                publisher_id: u64::from_le_bytes(head[16..24].try_into()?),
+                timestamp_ns: i64::from_le_bytes(head[24..32].try_into()?),
+                batch: decoded?,
+            }));
+        }
+    }
+
+    /// Skip ahead to the newest record if the writer has lapped this reader
+    fn check_overrun(&mut self) -> Result<()> {
+        fence(Ordering::Acquire);
+        let header = header(self.map());
+        if header.reserved.load(Ordering::SeqCst) > self.position + self.capacity {
+            let written = header.written.load(Ordering::Acquire);
+            let skipped = written - self.position;
+            self.position = written;
+            bail!("reader fell behind the writer and skipped {} bytes", skipped);
+        }
+        Ok(())
+    }
+}
+
+/// Decode the IPC stream of a record without copying its buffers
+fn decode(mut buffer: Buffer) -> Result<RecordBatch> {
+    let mut decoder = StreamDecoder::new();
+    while !buffer.is_empty() {
+        if let Some(batch) = decoder.decode(&mut buffer)? {
+            return Ok(batch);
+        }
+    }
+    bail!("record holds no batch")
+}
+
+#[cfg(test)]
+impl ShmReader {
+    pub(crate) fn mapping(&self) -> std::ops::Range<usize> {
+        let start = self.map().as_ptr() as usize;
+        start..start + self.map().len()
+    }
+}
@@ -0,0 +1,141 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+use crate::shm::{ring_path, ShmReader, ShmWriter, MAX_READERS, MIN_CAPACITY};
+use crate::test_util::speed_batch;
+
+#[test]
+fn test_ring_path_escapes_levels() {
+    let dir = Path::new("/dev/shm/mariposa");
+    assert_eq!(ring_path(dir, "vehicle/speed"), dir.join("vehicle%2Fspeed.ring"));
+    assert_eq!(ring_path(dir, "/a%b"), dir.join("a%25b.ring"));
+}
+
+#[test]
+fn test_ring_round_trip_without_copies() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = ring_path(dir.path(), "camera/front");
+    let mut writer = ShmWriter::create(&path, 8 * 1024).unwrap();
+    let mut reader = ShmReader::open(&path).unwrap();
+    assert!(reader.read().unwrap().is_none());
+
+    let batches: Vec<_> = (0..50).map(|x| speed_batch(vec![x, x + 1], vec![x as f64, 0.5])).collect();
+    for (index, batch) in batches.iter().enumerate() {
+        writer.write("camera/front", 7, index as i64, batch).unwrap();
+        // Read every other batch right away so some reads cross the wrap point
+        if index % 2 == 0 {
+            continue;
+        }
+        for expected in [index - 1, index] {
+            let record = reader.read().unwrap().unwrap();
+            assert_eq!(record.topic, "camera/front");
+            assert_eq!(record.publisher_id, 7);
+            assert_eq!(record.timestamp_ns, expected as i64);
+            assert_eq!(record.batch, batches[expected]);
+
+            let values = record.batch.column(1).to_data().buffers()[0].as_ptr() as usize;
+            assert!(reader.mapping().contains(&values), "batch was copied out of the ring");
+        }
+    }
+    assert!(reader.read().unwrap().is_none());
+}
+
+#[test]
+fn test_held_batches_are_never_overwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = ring_path(dir.path(), "lidar");
+    let mut writer = ShmWriter::create(&path, MIN_CAPACITY).unwrap();
+    let mut reader = ShmReader::open(&path).unwrap();
+
+    writer.write("lidar", 1, 0, &speed_batch(vec![1], vec![2.0])).unwrap();
+    let held = reader.read().unwrap().unwrap();
+    // Writes stop short of lapping the record the batch points into
+    let mut written = 0;
+    while writer.write("lidar", 1, 0, &speed_batch(vec![written], vec![9.0])).is_ok() {
+        written += 1;
+        assert!(written < 100, "the writer lapped a held record");
+    }
+    assert_eq!(held.batch, speed_batch(vec![1], vec![2.0]));
+
+    // Arrays taken from the batch hold the record just the same
+    let column = held.batch.column(1).clone();
+    drop(held);
+    assert!(writer.write("lidar", 1, 0, &speed_batch(vec![0], vec![9.0])).is_err());
+    drop(column);
+    writer.write("lidar", 1, 0, &speed_batch(vec![0], vec![9.0])).unwrap();
+}
+
+#[test]
+fn test_readers_claim_slots() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = ring_path(dir.path(), "lidar");
+    let _writer = ShmWriter::create(&path, MIN_CAPACITY).unwrap();
+    let mut readers: Vec<_> = (0..MAX_READERS).map(|_| ShmReader::open(&path).unwrap()).collect();
+    assert!(ShmReader::open(&path).is_err());
+
+    readers.pop();
+    ShmReader::open(&path).unwrap();
+}
+
+#[test]
+fn test_lagging_reader_skips_ahead() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = ring_path(dir.path(), "lidar");
+    let mut writer = ShmWriter::create(&path, MIN_CAPACITY).unwrap();
+    let mut reader = ShmReader::open(&path).unwrap();
+
+    for id in 0..100 {
+        writer.write("lidar", 1, 0, &speed_batch(vec![id], vec![0.0])).unwrap();
+    }
+    assert!(reader.read().is_err());
+    assert!(reader.read().unwrap().is_none());
+
+    writer.write("lidar", 1, 0, &speed_batch(vec![100], vec![0.0])).unwrap();
+    let record = reader.read().unwrap().unwrap();
+    assert_eq!(record.batch, speed_batch(vec![100], vec![0.0]));
+}
+
+#[test]
+fn test_oversized_batch_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = ring_path(dir.path(), "lidar");
+    let mut writer = ShmWriter::create(&path, MIN_CAPACITY).unwrap();
+    let ids: Vec<i32> = (0..MIN_CAPACITY as i32).collect();
+    let speeds = vec![0.0; ids.len()];
+    assert!(writer.write("lidar", 1, 0, &speed_batch(ids, speeds)).is_err());
+}
+
+#[test]
+fn test_wait_wakes_on_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = ring_path(dir.path(), "imu");
+    let mut writer = ShmWriter::create(&path, MIN_CAPACITY).unwrap();
+    let mut reader = ShmReader::open(&path).unwrap();
+    assert!(!reader.wait(Duration::from_millis(10)));
+
+    let started = Instant::now();
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        writer.write("imu", 1, 0, &speed_batch(vec![1], vec![2.0])).unwrap();
+        writer
+    });
+    assert!(reader.wait(Duration::from_secs(10)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(reader.read().unwrap().is_some());
+    drop(thread.join().unwrap());
+}
+
+#[test]
+fn test_single_writer_per_ring() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = ring_path(dir.path(), "imu");
+    let writer = ShmWriter::create(&path, MIN_CAPACITY).unwrap();
+    let reader = ShmReader::open(&path).unwrap();
+    assert!(ShmWriter::create(&path, MIN_CAPACITY).is_err());
+    assert!(!reader.writer_closed().unwrap());
+
+    drop(writer);
+    assert!(reader.writer_closed().unwrap());
+    assert!(!path.exists());
+    // A new writer can take over once the old one is gone
+    ShmWriter::create(&path, MIN_CAPACITY).unwrap();
+}
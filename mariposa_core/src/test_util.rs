@@ -0,0 +1,25 @@
+// Fixtures shared by the tests of several modules
+
+use arrow::record_batch::RecordBatch;
+use arrow_array::{Float64Array, Int32Array};
+use std::sync::Arc;
+
+use crate::logfile::LogEntry;
+
+/// Batch of vehicle ids and speeds, the sample data most tests send around
+pub(crate) fn speed_batch(ids: Vec<i32>, speeds: Vec<f64>) -> RecordBatch {
+    let ids = Arc::new(Int32Array::from(ids)) as _;
+    let speeds = Arc::new(Float64Array::from(speeds)) as _;
+    RecordBatch::try_from_iter([("id", ids), ("speed_kph", speeds)]).unwrap()
+}
+
+/// Entry on `topic` from publisher 3, logged 10ns after it was published
+pub(crate) fn entry(topic: &str, log_time_ns: i64, batch: RecordBatch) -> LogEntry {
+    LogEntry {
+        topic: topic.to_string(),
+        publisher_id: 3,
+        publish_time_ns: log_time_ns - 10,
+        log_time_ns,
+        batch,
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::topic::{MULTI_WILDCARDS, SEPARATOR, SINGLE_WILDCARD};
+
+/// Split a topic or pattern into its levels
+fn levels(name: &str) -> impl Iterator<Item = &str> {
+    name.strip_prefix(SEPARATOR).unwrap_or(name).split(SEPARATOR)
+}
+
+fn is_multi_wildcard(level: &str) -> bool {
+    MULTI_WILDCARDS.contains(&level)
+}
+
+/// Check that a published topic name is well formed and has no wildcards
+pub fn validate_topic(topic: &str) -> Result<()> {
+    for level in levels(topic) {
+        if level.is_empty() {
+            bail!("topic '{}' has an empty level", topic);
+        }
+        if level.contains('*') || level.contains('#') {
+            bail!("topic '{}' may not contain wildcards", topic);
+        }
+    }
+    Ok(())
+}
+
+/// Encode a topic as a single file name, dropping its leading `/` and
+/// escaping `%` and `/` so that distinct topics never share a name
+pub fn topic_file_name(topic: &str) -> String {
+    topic.strip_prefix(SEPARATOR).unwrap_or(topic).replace('%', "%25").replace(SEPARATOR, "%2F")
+}
+
+/// Check that a subscription pattern is well formed
+pub fn validate_pattern(pattern: &str) -> Result<()> {
+    let levels: Vec<&str> = levels(pattern).collect();
+    for (index, level) in levels.iter().enumerate() {
+        if level.is_empty() {
+            bail!("pattern '{}' has an empty level", pattern);
+        }
+        if is_multi_wildcard(level) {
+            if index != levels.len() - 1 {
+                bail!("pattern '{}' may only use '{}' as its last level", pattern, level);
+            }
+        } else if *level != SINGLE_WILDCARD && (level.contains('*') || level.contains('#')) {
+            bail!("pattern '{}': wildcards must occupy a whole level", pattern);
+        }
+    }
+    Ok(())
+}
+
+/// Check a single pattern against a topic without building a matcher
+pub fn pattern_matches(pattern: &str, topic: &str) -> bool {
+    let mut topic_levels = levels(topic);
+    for level in levels(pattern) {
+        if is_multi_wildcard(level) {
+            return true;
+        }
+        match topic_levels.next() {
+            Some(name) if level == SINGLE_WILDCARD || level == name => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// Trie node for one level of the topic hierarchy
+struct Node<K> {
+    children: HashMap<String, Node<K>>,
+    single: Option<Box<Node<K>>>,
+    /// Keys whose pattern ends exactly at this node
+    exact: HashSet<K>,
+    /// Keys whose pattern ends with a multi-level wildcard below this node
+    remaining: HashSet<K>,
+}
+
+impl<K: Eq + Hash + Clone> Node<K> {
+    fn new() -> Self {
+        Self {
+            children: HashMap::new(),
+            single: None,
+            exact: HashSet::new(),
+            remaining: HashSet::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.single.is_none() && self.exact.is_empty() && self.remaining.is_empty()
+    }
+
+    fn collect(&self, levels: &[&str], out: &mut HashSet<K>) {
+        out.extend(self.remaining.iter().cloned());
+        match levels.split_first() {
+            None => out.extend(self.exact.iter().cloned()),
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.collect(rest, out);
+                }
+                if let Some(single) = &self.single {
+                    single.collect(rest, out);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, levels: &[&str], key: &K) -> bool {
+        match levels.split_first() {
+            None => self.exact.remove(key),
+            Some((level, _)) if is_multi_wildcard(level) => self.remaining.remove(key),
+            Some((level, rest)) if *level == SINGLE_WILDCARD => {
+                let Some(single) = self.single.as_mut() else {
+                    return false;
+                };
+                let removed = single.remove(rest, key);
+                if single.is_empty() {
+                    self.single = None;
+                }
+                removed
+            }
+            Some((level, rest)) => {
+                let Some(child) = self.children.get_mut(*level) else {
+                    return false;
+                };
+                let removed = child.remove(rest, key);
+                if child.is_empty() {
+                    self.children.remove(*level);
+                }
+                removed
+            }
+        }
+    }
+}
+
+/// Trie of subscription patterns for finding every key that matches a topic
+///
+/// Lookup walks one path per wildcard branch, so its cost grows with the depth
+/// of the topic rather than with the number of subscriptions.
+pub struct TopicMatcher<K> {
+    root: Node<K>,
+    len: usize,
+}
+
+impl<K: Eq + Hash + Clone> TopicMatcher<K> {
+    pub fn new() -> Self {
+        Self {
+            root: Node::new(),
+            len: 0,
+        }
+    }
+
+    /// Register `key` under `pattern`
+    ///
+    /// Returns false if the key was already registered under this pattern.
+    pub fn insert(&mut self, pattern: &str, key: K) -> Result<bool> {
+        validate_pattern(pattern)?;
+        let mut node = &mut self.root;
+        for level in levels(pattern) {
+            if is_multi_wildcard(level) {
+                let inserted = node.remaining.insert(key);
+                self.len += inserted as usize;
+                return Ok(inserted);
+            }
+            node = if level == SINGLE_WILDCARD {
+                node.single.get_or_insert_with(|| Box::new(Node::new()))
+            } else {
+                node.children.entry(level.to_string()).or_insert_with(Node::new)
+            };
+        }
+        let inserted = node.exact.insert(key);
+        self.len += inserted as usize;
+        Ok(inserted)
+    }
+
+    /// Remove `key` from `pattern`, pruning nodes that become empty
+    pub fn remove(&mut self, pattern: &str, key: &K) -> bool {
+        let levels: Vec<&str> = levels(pattern).collect();
+        let removed = self.root.remove(&levels, key);
+        self.len -= removed as usize;
+        removed
+    }
+
+    /// Every key registered under a pattern matching `topic`
+    pub fn matches(&self, topic: &str) -> HashSet<K> {
+        let levels: Vec<&str> = levels(topic).collect();
+        let mut out = HashSet::new();
+        self.root.collect(&levels, &mut out);
+        out
+    }
+
+    /// Number of (pattern, key) registrations
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<K: Eq + Hash + Clone> Default for TopicMatcher<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,19 @@
+// Hierarchical topic names and wildcard subscription matching
+//
+// Topics are `/` separated levels such as `vehicle/12/speed`. A leading `/` is
+// ignored. Subscription patterns may use wildcards that occupy a whole level:
+//
+//   `*`          matches exactly one level     (`vehicle/*/speed`)
+//   `**` or `#`  matches any remaining levels  (`vehicle/**`), must be last
+
+mod matcher;
+
+#[cfg(test)]
+mod tests;
+
+pub use matcher::{pattern_matches, topic_file_name, validate_pattern, validate_topic, TopicMatcher};
+
+// Constants
+pub const SEPARATOR: char = '/';
+pub const SINGLE_WILDCARD: &str = "*";
+pub const MULTI_WILDCARDS: [&str; 2] = ["**", "#"];
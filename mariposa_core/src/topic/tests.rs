@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+use crate::topic::{pattern_matches, topic_file_name, validate_pattern, validate_topic, TopicMatcher};
+
+const PATTERNS: [&str; 12] = [
+    "topic/a",
+    "topic/*",
+    "topic/**",
+    "topic/#",
+    "*/a",
+    "*/*",
+    "**",
+    "topic/*/speed",
+    "topic/a/**",
+    "other",
+    "/topic/a",
+    "*",
+];
+
+const TOPICS: [&str; 8] = [
+    "topic",
+    "topic/a",
+    "topic/b",
+    "topic/a/speed",
+    "topic/b/speed",
+    "topic/b/speed/raw",
+    "other",
+    "other/a",
+];
+
+/// (pattern, topic) pairs that are expected to match; everything else must not
+fn expected_matches() -> HashSet<(&'static str, &'static str)> {
+    [
+        ("topic/a", "topic/a"),
+        ("/topic/a", "topic/a"),
+        ("topic/*", "topic/a"),
+        ("topic/*", "topic/b"),
+        ("*/a", "topic/a"),
+        ("*/a", "other/a"),
+        ("*/*", "topic/a"),
+        ("*/*", "topic/b"),
+        ("*/*", "other/a"),
+        ("topic/*/speed", "topic/a/speed"),
+        ("topic/*/speed", "topic/b/speed"),
+        ("topic/a/**", "topic/a"),
+        ("topic/a/**", "topic/a/speed"),
+        ("other", "other"),
+        ("*", "topic"),
+        ("*", "other"),
+    ]
+    .into_iter()
+    .chain(TOPICS.iter().map(|topic| ("**", *topic)))
+    .chain(TOPICS.iter().filter(|x| x.starts_with("topic")).map(|topic| ("topic/**", *topic)))
+    .chain(TOPICS.iter().filter(|x| x.starts_with("topic")).map(|topic| ("topic/#", *topic)))
+    .collect()
+}
+
+#[test]
+fn test_pattern_matches_exhaustive() {
+    let expected = expected_matches();
+    for pattern in PATTERNS {
+        for topic in TOPICS {
+            assert_eq!(
+                pattern_matches(pattern, topic),
+                expected.contains(&(pattern, topic)),
+                "pattern '{}' against topic '{}'",
+                pattern,
+                topic
+            );
+        }
+    }
+}
+
+#[test]
+fn test_matcher_exhaustive() {
+    let expected = expected_matches();
+    let mut matcher = TopicMatcher::new();
+    for (index, pattern) in PATTERNS.iter().enumerate() {
+        assert!(matcher.insert(pattern, index).unwrap());
+    }
+    assert_eq!(matcher.len(), PATTERNS.len());
+
+    for topic in TOPICS {
+        let found = matcher.matches(topic);
+        let wanted: HashSet<usize> = PATTERNS
+            .iter()
+            .enumerate()
+            .filter(|(_, pattern)| expected.contains(&(**pattern, topic)))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(found, wanted, "topic '{}'", topic);
+    }
+}
+
+#[test]
+fn test_matcher_agrees_with_pattern_matches() {
+    let mut matcher = TopicMatcher::new();
+    for pattern in PATTERNS {
+        matcher.insert(pattern, pattern).unwrap();
+    }
+    for topic in TOPICS {
+        let from_matcher = matcher.matches(topic);
+        let from_patterns: HashSet<&str> = PATTERNS.into_iter().filter(|x| pattern_matches(x, topic)).collect();
+        assert_eq!(from_matcher, from_patterns, "topic '{}'", topic);
+    }
+}
+
+#[test]
+fn test_matcher_deduplicates_keys() {
+    let mut matcher = TopicMatcher::new();
+    matcher.insert("topic/*", 1).unwrap();
+    matcher.insert("topic/a", 1).unwrap();
+    matcher.insert("topic/a", 2).unwrap();
+    assert!(!matcher.insert("topic/a", 2).unwrap());
+
+    assert_eq!(matcher.matches("topic/a"), HashSet::from([1, 2]));
+    assert_eq!(matcher.len(), 3);
+}
+
+#[test]
+fn test_matcher_remove() {
+    let mut matcher = TopicMatcher::new();
+    matcher.insert("topic/*", 1).unwrap();
+    matcher.insert("topic/a", 2).unwrap();
+    matcher.insert("topic/**", 3).unwrap();
+
+    assert!(matcher.remove("topic/*", &1));
+    assert!(!matcher.remove("topic/*", &1));
+    assert!(!matcher.remove("topic/b", &2));
+    assert_eq!(matcher.matches("topic/a"), HashSet::from([2, 3]));
+
+    assert!(matcher.remove("topic/a", &2));
+    assert!(matcher.remove("topic/**", &3));
+    assert!(matcher.is_empty());
+    assert!(matcher.matches("topic/a").is_empty());
+}
+
+#[test]
+fn test_invalid_names() {
+    assert!(validate_pattern("topic/**/a").is_err());
+    assert!(validate_pattern("topic/#/a").is_err());
+    assert!(validate_pattern("topic/a*").is_err());
+    assert!(validate_pattern("topic//a").is_err());
+    assert!(validate_pattern("").is_err());
+    assert!(validate_pattern("/topic/*/**").is_ok());
+
+    assert!(validate_topic("topic/*").is_err());
+    assert!(validate_topic("topic/#").is_err());
+    assert!(validate_topic("topic/").is_err());
+    assert!(validate_topic("/topic/a").is_ok());
+
+    let mut matcher = TopicMatcher::new();
+    assert!(matcher.insert("topic/**/a", 1).is_err());
+    assert!(matcher.is_empty());
+}
+
+#[test]
+fn test_topic_file_names_are_distinct() {
+    assert_eq!(topic_file_name("/vehicle/speed"), "vehicle%2Fspeed");
+    assert_eq!(topic_file_name("vehicle/speed"), "vehicle%2Fspeed");
+    assert_eq!(topic_file_name("vehicle%2Fspeed"), "vehicle%252Fspeed");
+}
@@ -0,0 +1,222 @@
+use anyhow::{bail, ensure, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::udp::{DATAGRAM_HEADER_LEN, MAGIC, MAX_MESSAGE_LEN, MAX_PENDING_BYTES, MAX_PENDING_MESSAGES, VERSION};
+
+/// Splits messages into numbered datagrams
+pub struct Fragmenter {
+    datagram_len: usize,
+    next_message_id: u32,
+}
+
+impl Fragmenter {
+    /// Create a fragmenter producing datagrams of at most `datagram_len` bytes
+    pub fn new(datagram_len: usize) -> Result<Self> {
+        ensure!(
+            datagram_len > DATAGRAM_HEADER_LEN,
+            "datagrams of {} bytes leave no room for data",
+            datagram_len
+        );
+        Ok(Self {
+            datagram_len,
+            next_message_id: 0,
+        })
+    }
+
+    pub fn fragment(&mut self, message: &[u8]) -> Result<Vec<Vec<u8>>> {
+        ensure!(
+            message.len() <= MAX_MESSAGE_LEN,
+            "message of {} bytes is too large",
+            message.len()
+        );
+        let chunk_len = self.datagram_len - DATAGRAM_HEADER_LEN;
+        let count = message.len().div_ceil(chunk_len).max(1);
+        let count = u16::try_from(count).map_err(|_| anyhow::anyhow!("message needs too many datagrams"))?;
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+
+        let mut datagrams = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let start = index as usize * chunk_len;
+            let chunk = &message[start..(start + chunk_len).min(message.len())];
+            let mut datagram = Vec::with_capacity(DATAGRAM_HEADER_LEN + chunk.len());
+            datagram.extend_from_slice(&MAGIC);
+            datagram.push(VERSION);
+            datagram.push(0);
+            datagram.extend_from_slice(&count.to_le_bytes());
+            datagram.extend_from_slice(&message_id.to_le_bytes());
+            datagram.extend_from_slice(&index.to_le_bytes());
+            datagram.extend_from_slice(&0u16.to_le_bytes());
+            datagram.extend_from_slice(&(message.len() as u32).to_le_bytes());
+            datagram.extend_from_slice(&(start as u32).to_le_bytes());
+            datagram.extend_from_slice(chunk);
+            datagrams.push(datagram);
+        }
+        Ok(datagrams)
+    }
+}
+
+struct DatagramHeader {
+    count: u16,
+    message_id: u32,
+    index: u16,
+    message_len: usize,
+    offset: usize,
+}
+
+impl DatagramHeader {
+    fn decode(datagram: &[u8]) -> Result<Self> {
+        ensure!(datagram.len() >= DATAGRAM_HEADER_LEN, "datagram of {} bytes is too short", datagram.len());
+        ensure!(datagram[0..4] == MAGIC, "bad datagram magic");
+        if datagram[4] != VERSION {
+            bail!("unsupported datagram version {}", datagram[4]);
+        }
+        let header = Self {
+            count: u16::from_le_bytes([datagram[6], datagram[7]]),
+            message_id: u32::from_le_bytes(datagram[8..12].try_into()?),
+            index: u16::from_le_bytes([datagram[12], datagram[13]]),
+            message_len: u32::from_le_bytes(datagram[16..20].try_into()?) as usize,
+            offset: u32::from_le_bytes(datagram[20..24].try_into()?) as usize,
+        };
+        ensure!(header.index < header.count, "fragment {} of {}", header.index, header.count);
+        ensure!(header.message_len <= MAX_MESSAGE_LEN, "message of {} bytes is too large", header.message_len);
+        Ok(header)
+    }
+}
+
+/// Fragments received so far for one message
+struct Partial {
+    started: Instant,
+    message: Vec<u8>,
+    received: Vec<bool>,
+    missing: usize,
+}
+
+/// Puts fragmented messages back together, dropping those that stay incomplete
+///
+/// Incomplete messages are bounded in number and in total size. Once a new
+/// message would exceed either limit the oldest ones are dropped, so a sender
+/// announcing many large messages cannot exhaust memory.
+pub struct Reassembler {
+    timeout: Duration,
+    max_messages: usize,
+    max_bytes: usize,
+    pending: HashMap<(SocketAddr, u32), Partial>,
+    pending_bytes: usize,
+    completed: u64,
+    dropped: u64,
+}
+
+impl Reassembler {
+    /// Create a reassembler that gives up on a message `timeout` after its
+    /// first fragment arrived
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            max_messages: MAX_PENDING_MESSAGES,
+            max_bytes: MAX_PENDING_BYTES,
+            pending: HashMap::new(),
+            pending_bytes: 0,
+            completed: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Keep at most `max_messages` incomplete messages holding `max_bytes`
+    /// in total
+    pub fn with_limits(self, max_messages: usize, max_bytes: usize) -> Self {
+        Self {
+            max_messages: max_messages.max(1),
+            max_bytes,
+            ..self
+        }
+    }
+
+    /// Add a datagram from `source`, returning the message it completes
+    pub fn push(&mut self, source: SocketAddr, datagram: &[u8], now: Instant) -> Result<Option<Vec<u8>>> {
+        self.expire(now);
+        let header = DatagramHeader::decode(datagram)?;
+        let chunk = &datagram[DATAGRAM_HEADER_LEN..];
+        let start = header.offset;
+        ensure!(
+            start + chunk.len() <= header.message_len,
+            "fragment {} of message {} overflows it",
+            header.index,
+            header.message_id
+        );
+
+        let key = (source, header.message_id);
+        if !self.pending.contains_key(&key) {
+            if header.message_len > self.max_bytes {
+                self.dropped += 1;
+                return Ok(None);
+            }
+            self.make_room(header.message_len);
+            self.pending_bytes += header.message_len;
+            self.pending.insert(
+                key,
+                Partial {
+                    started: now,
+                    message: vec![0; header.message_len],
+                    received: vec![false; header.count as usize],
+                    missing: header.count as usize,
+                },
+            );
+        }
+        let partial = self.pending.get_mut(&key).unwrap();
+        ensure!(
+            partial.message.len() == header.message_len && partial.received.len() == header.count as usize,
+            "fragments of message {} disagree on its size",
+            header.message_id
+        );
+        if partial.received[header.index as usize] {
+            return Ok(None);
+        }
+        partial.received[header.index as usize] = true;
+        partial.missing -= 1;
+        partial.message[start..start + chunk.len()].copy_from_slice(chunk);
+
+        if partial.missing > 0 {
+            return Ok(None);
+        }
+        self.completed += 1;
+        let message = self.pending.remove(&key).map(|x| x.message);
+        self.pending_bytes -= header.message_len;
+        Ok(message)
+    }
+
+    /// Drop messages whose fragments did not all arrive in time
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending.retain(|_, x| now.duration_since(x.started) < timeout);
+        self.dropped += (before - self.pending.len()) as u64;
+        self.pending_bytes = self.pending.values().map(|x| x.message.len()).sum();
+    }
+
+    /// Drop the oldest incomplete messages until one more of `message_len`
+    /// bytes fits the limits
+    fn make_room(&mut self, message_len: usize) {
+        while !self.pending.is_empty()
+            && (self.pending.len() >= self.max_messages || self.pending_bytes + message_len > self.max_bytes)
+        {
+            let oldest = self.pending.iter().min_by_key(|(_, x)| x.started).map(|(key, _)| *key);
+            if let Some(partial) = oldest.and_then(|key| self.pending.remove(&key)) {
+                self.pending_bytes -= partial.message.len();
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Number of messages put back together
+    pub fn completed(&self) -> u64 {
+        self.completed
+    }
+
+    /// Number of messages dropped because fragments were missing
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
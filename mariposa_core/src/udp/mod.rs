@@ -0,0 +1,37 @@
+// Best-effort transport of Arrow batches over UDP, unicast or multicast
+//
+// Every batch is sent as a self-contained message: the schema frame and the
+// batch frame of `crate::wire`, so a receiver can decode it without having
+// seen anything before. Messages larger than a datagram are split into
+// fragments, each carrying a small header:
+//
+//   magic "MRPU" | version u8 | reserved u8 | fragment_count u16 |
+//   message_id u32 | fragment_index u16 | reserved u16 | message_len u32 |
+//   offset u32
+//
+// All integers are little endian. `message_id` counts messages per sender, so
+// receivers can tell how many were lost. Fragments of one message may arrive in
+// any order; messages still incomplete after a timeout are dropped, as are the
+// oldest ones once too many are waiting for fragments.
+
+mod fragment;
+mod socket;
+
+#[cfg(test)]
+mod tests;
+
+pub use fragment::{Fragmenter, Reassembler};
+pub use socket::{UdpPublisher, UdpSubscriber};
+
+// Constants
+pub const MAGIC: [u8; 4] = *b"MRPU";
+pub const VERSION: u8 = 1;
+pub const DATAGRAM_HEADER_LEN: usize = 24;
+/// Datagram size that fits the usual 1500 byte Ethernet MTU with IP and UDP headers
+pub const DEFAULT_DATAGRAM_LEN: usize = 1400;
+/// Largest message that is split into datagrams
+pub const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+/// Incomplete messages a receiver keeps by default
+pub const MAX_PENDING_MESSAGES: usize = 64;
+/// Total size of the incomplete messages a receiver keeps by default
+pub const MAX_PENDING_BYTES: usize = 2 * MAX_MESSAGE_LEN;
@@ -0,0 +1,162 @@
+use anyhow::{bail, Result};
+use arrow::record_batch::RecordBatch;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+use crate::udp::{Fragmenter, Reassembler, DEFAULT_DATAGRAM_LEN};
+use crate::wire::{BatchDecoder, BatchEncoder, Frame, FrameHeader, FrameKind};
+
+/// Largest datagram a UDP socket can receive
+const MAX_DATAGRAM_LEN: usize = 65536;
+
+/// How long the fragments of one message may take to arrive
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Sends batches to one unicast or multicast address
+///
+/// Sending takes `&self`, so one publisher can be shared by many tasks.
+pub struct UdpPublisher {
+    socket: UdpSocket,
+    target: SocketAddr,
+    /// Only locked while a message is encoded, never while it is sent
+    framing: Mutex<Framing>,
+}
+
+struct Framing {
+    encoder: BatchEncoder,
+    fragmenter: Fragmenter,
+}
+
+impl UdpPublisher {
+    /// Send to `target`; multicast datagrams travel at most `ttl` hops
+    pub async fn bind(target: SocketAddr, ttl: u32) -> Result<Self> {
+        let local: SocketAddr = match target {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        if target.ip().is_multicast() {
+            match target.ip() {
+                IpAddr::V4(_) => socket.set_multicast_ttl_v4(ttl)?,
+                // IPv6 multicast hops are left to the system default
+                IpAddr::V6(_) => {}
+            }
+        }
+        Ok(Self {
+            socket,
+            target,
+            framing: Mutex::new(Framing {
+                encoder: BatchEncoder::new(0),
+                fragmenter: Fragmenter::new(DEFAULT_DATAGRAM_LEN)?,
+            }),
+        })
+    }
+
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+
+    /// Send a batch on behalf of `publisher_id`
+    ///
+    /// The schema goes along with every batch so each message decodes on its
+    /// own, whichever ones were lost before it.
+    pub async fn send(&self, publisher_id: u64, topic: &str, timestamp_ns: i64, batch: &RecordBatch) -> Result<()> {
+        let datagrams = {
+            let mut framing = self.framing.lock().unwrap();
+            framing.encoder.reset_topic(topic);
+            let mut message = Vec::new();
+            for frame in framing.encoder.encode_from(publisher_id, topic, batch, timestamp_ns, &[])? {
+                message.extend_from_slice(&frame.encode()?);
+            }
+            framing.fragmenter.fragment(&message)?
+        };
+        for datagram in datagrams {
+            self.socket.send_to(&datagram, self.target).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives batches sent by `UdpPublisher`s
+pub struct UdpSubscriber {
+    socket: UdpSocket,
+    reassembler: Reassembler,
+    decoder: BatchDecoder,
+    buffer: Vec<u8>,
+}
+
+impl UdpSubscriber {
+    /// Listen on `addr`, joining its group on every interface if it is a
+    /// multicast address
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let socket = match addr.ip() {
+            IpAddr::V4(group) if group.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, addr.port())).await?;
+                socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+                socket
+            }
+            IpAddr::V6(group) if group.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, addr.port())).await?;
+                socket.join_multicast_v6(&group, 0)?;
+                socket
+            }
+            _ => UdpSocket::bind(addr).await?,
+        };
+        Ok(Self {
+            socket,
+            reassembler: Reassembler::new(REASSEMBLY_TIMEOUT),
+            decoder: BatchDecoder::new(),
+            buffer: vec![0; MAX_DATAGRAM_LEN],
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Number of messages lost because some of their fragments never arrived
+    pub fn dropped(&self) -> u64 {
+        self.reassembler.dropped()
+    }
+
+    /// Wait for the next complete batch
+    ///
+    /// Malformed datagrams are logged and skipped.
+    pub async fn recv(&mut self) -> Result<(FrameHeader, RecordBatch)> {
+        loop {
+            let (len, source) = self.socket.recv_from(&mut self.buffer).await?;
+            let message = match self.reassembler.push(source, &self.buffer[..len], Instant::now()) {
+                Ok(Some(message)) => message,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::debug!("ignoring datagram from {}: {}", source, e);
+                    continue;
+                }
+            };
+            match self.decode(&message) {
+                Ok(Some(x)) => return Ok(x),
+                Ok(None) => log::debug!("message from {} holds no batch", source),
+                Err(e) => log::warn!("failed to decode message from {}: {:#}", source, e),
+            }
+        }
+    }
+
+    fn decode(&mut self, mut message: &[u8]) -> Result<Option<(FrameHeader, RecordBatch)>> {
+        let mut decoded = None;
+        while !message.is_empty() {
+            let Some((frame, used)) = Frame::decode(message)? else {
+                bail!("message ends in the middle of a frame");
+            };
+            message = &message[used..];
+            if frame.header.kind == FrameKind::Control {
+                bail!("control frames are not sent over UDP");
+            }
+            if let Some(batch) = self.decoder.decode(&frame.header, frame.payload)? {
+                decoded = Some((frame.header, batch));
+            }
+        }
+        Ok(decoded)
+    }
+}
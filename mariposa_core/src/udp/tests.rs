@@ -0,0 +1,99 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use crate::test_util::speed_batch;
+use crate::udp::{Fragmenter, Reassembler, UdpPublisher, UdpSubscriber, DATAGRAM_HEADER_LEN};
+
+fn source(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[test]
+fn test_reassemble_out_of_order() {
+    let message: Vec<u8> = (0..1000).map(|x| x as u8).collect();
+    let mut fragmenter = Fragmenter::new(DATAGRAM_HEADER_LEN + 64).unwrap();
+    let mut datagrams = fragmenter.fragment(&message).unwrap();
+    assert_eq!(datagrams.len(), 16);
+    datagrams.reverse();
+
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(Duration::from_secs(1));
+    let (last, rest) = datagrams.split_last().unwrap();
+    for datagram in rest {
+        assert_eq!(reassembler.push(source(1), datagram, now).unwrap(), None);
+        // Duplicates are ignored
+        assert_eq!(reassembler.push(source(1), datagram, now).unwrap(), None);
+    }
+    // The same message id from another sender is a different message
+    assert_eq!(reassembler.push(source(2), last, now).unwrap(), None);
+    assert_eq!(reassembler.push(source(1), last, now).unwrap(), Some(message));
+    assert_eq!(reassembler.completed(), 1);
+}
+
+#[test]
+fn test_incomplete_messages_are_dropped() {
+    let mut fragmenter = Fragmenter::new(DATAGRAM_HEADER_LEN + 16).unwrap();
+    let first = fragmenter.fragment(&[1; 40]).unwrap();
+    let second = fragmenter.fragment(&[2; 8]).unwrap();
+
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(Duration::from_millis(100));
+    assert_eq!(reassembler.push(source(1), &first[0], now).unwrap(), None);
+    let later = now + Duration::from_millis(200);
+    assert_eq!(reassembler.push(source(1), &second[0], later).unwrap(), Some(vec![2; 8]));
+    // The rest of the first message arrives too late to complete it
+    assert_eq!(reassembler.push(source(1), &first[1], later).unwrap(), None);
+    assert_eq!(reassembler.dropped(), 1);
+
+    assert!(reassembler.push(source(1), &[0; 4], later).is_err());
+}
+
+#[test]
+fn test_pending_messages_are_bounded() {
+    let mut fragmenter = Fragmenter::new(DATAGRAM_HEADER_LEN + 16).unwrap();
+    let messages: Vec<_> = (0..4u8).map(|x| fragmenter.fragment(&[x; 32]).unwrap()).collect();
+
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new(Duration::from_secs(1)).with_limits(2, 1024);
+    for (age, message) in messages[..3].iter().enumerate() {
+        let arrival = now + Duration::from_millis(age as u64);
+        assert_eq!(reassembler.push(source(1), &message[0], arrival).unwrap(), None);
+    }
+    // The oldest message made room for the third
+    assert_eq!(reassembler.dropped(), 1);
+    assert_eq!(reassembler.push(source(1), &messages[0][1], now).unwrap(), None);
+    assert_eq!(reassembler.dropped(), 2);
+    assert_eq!(reassembler.push(source(1), &messages[2][1], now).unwrap(), Some(vec![2; 32]));
+
+    // Messages larger than every pending byte allowed are never started
+    let mut reassembler = Reassembler::new(Duration::from_secs(1)).with_limits(8, 48);
+    assert_eq!(reassembler.push(source(1), &messages[0][0], now).unwrap(), None);
+    assert_eq!(reassembler.push(source(1), &messages[1][0], now).unwrap(), None);
+    assert_eq!(reassembler.dropped(), 1);
+    let mut large = Fragmenter::new(DATAGRAM_HEADER_LEN + 16).unwrap();
+    let large = large.fragment(&[9; 64]).unwrap();
+    assert_eq!(reassembler.push(source(2), &large[0], now).unwrap(), None);
+    assert_eq!(reassembler.dropped(), 2);
+    assert_eq!(reassembler.push(source(1), &messages[1][1], now).unwrap(), Some(vec![1; 32]));
+}
+
+#[tokio::test]
+async fn test_udp_round_trip() {
+    let mut subscriber = UdpSubscriber::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let publisher = UdpPublisher::bind(subscriber.local_addr().unwrap(), 1).await.unwrap();
+
+    // Large enough to need several datagrams
+    let ids: Vec<i32> = (0..2000).collect();
+    let speeds: Vec<f64> = ids.iter().map(|x| *x as f64 / 2.0).collect();
+    let large = speed_batch(ids, speeds);
+    publisher.send(7, "telemetry/attitude", 42, &large).await.unwrap();
+    publisher.send(7, "telemetry/attitude", 43, &speed_batch(vec![1], vec![2.0])).await.unwrap();
+
+    let (header, batch) = subscriber.recv().await.unwrap();
+    assert_eq!(header.topic, "telemetry/attitude");
+    assert_eq!(header.publisher_id, 7);
+    assert_eq!(header.timestamp_ns, 42);
+    assert_eq!(batch, large);
+    let (header, batch) = subscriber.recv().await.unwrap();
+    assert_eq!(header.timestamp_ns, 43);
+    assert_eq!(batch, speed_batch(vec![1], vec![2.0]));
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, bail, Result};
+use arrow::buffer::Buffer;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::ipc::reader::{StreamDecoder, StreamReader};
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use crate::wire::frame::{Frame, FrameHeader, FrameKind};
+
+/// Encode a schema on its own, as the header of an Arrow IPC stream
+pub fn encode_schema(schema: &Schema) -> Result<Vec<u8>> {
+    let writer = StreamWriter::try_new(Vec::new(), schema)?;
+    Ok(writer.into_inner()?)
+}
+
+/// Decode a schema written by `encode_schema`
+pub fn decode_schema(bytes: &[u8]) -> Result<SchemaRef> {
+    Ok(StreamReader::try_new(Cursor::new(bytes), None)?.schema())
+}
+
+/// Encode a single batch as a complete Arrow IPC stream, for payloads that
+/// travel inside control messages
+pub fn encode_batch(batch: &RecordBatch) -> Result<Vec<u8>> {
+    let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema())?;
+    writer.write(batch)?;
+    Ok(writer.into_inner()?)
+}
+
+/// Decode a batch written by `encode_batch`
+pub fn decode_batch(bytes: &[u8]) -> Result<RecordBatch> {
+    StreamReader::try_new(Cursor::new(bytes), None)?
+        .next()
+        .ok_or_else(|| anyhow!("payload holds no record batch"))?
+        .map_err(Into::into)
+}
+
+/// Open Arrow IPC stream for a single topic
+struct TopicStream {
+    schema: SchemaRef,
+    writer: StreamWriter<Vec<u8>>,
+    sequence: u64,
+}
+
+/// Turns RecordBatches into frames, sending each topic's schema only once
+pub struct BatchEncoder {
+    publisher_id: u64,
+    topics: HashMap<(u64, String), TopicStream>,
+}
+
+impl BatchEncoder {
+    /// Create an encoder that stamps every frame with `publisher_id`
+    pub fn new(publisher_id: u64) -> Self {
+        Self {
+            publisher_id,
+            topics: HashMap::new(),
+        }
+    }
+
+    /// Encode a batch for a topic
+    ///
+    /// The first batch on a topic, or the first after its schema changed, is
+    /// preceded by a schema frame.
+    pub fn encode(
+        &mut self,
+        topic: &str,
+        batch: &RecordBatch,
+        timestamp_ns: i64,
+        subscription_ids: &[u32],
+    ) -> Result<Vec<Frame>> {
+        self.encode_from(self.publisher_id, topic, batch, timestamp_ns, subscription_ids)
+    }
+
+    /// Encode a batch on behalf of another publisher, as the broker does when
+    /// forwarding. Each publisher gets its own stream so the decoder on the
+    /// other side can tell them apart.
+    pub fn encode_from(
+        &mut self,
+        publisher_id: u64,
+        topic: &str,
+        batch: &RecordBatch,
+        timestamp_ns: i64,
+        subscription_ids: &[u32],
+    ) -> Result<Vec<Frame>> {
+        let mut frames = Vec::with_capacity(2);
+        let key = (publisher_id, topic.to_string());
+
+        let restart = match self.topics.get(&key) {
+            Some(stream) => stream.schema != batch.schema(),
+            None => true,
+        };
+        if restart {
+            let writer = StreamWriter::try_new(Vec::new(), &batch.schema())?;
+            let sequence = self.topics.get(&key).map(|x| x.sequence).unwrap_or(0);
+            let mut stream = TopicStream {
+                schema: batch.schema(),
+                writer,
+                sequence,
+            };
+            let payload = std::mem::take(stream.writer.get_mut());
+            frames.push(Frame::new(
+                header(FrameKind::Schema, publisher_id, topic, stream.sequence, timestamp_ns, subscription_ids),
+                payload,
+            ));
+            self.topics.insert(key.clone(), stream);
+        }
+
+        let stream = self.topics.get_mut(&key).unwrap();
+        stream.writer.write(batch)?;
+        let payload = std::mem::take(stream.writer.get_mut());
+        let sequence = stream.sequence;
+        stream.sequence += 1;
+        frames.push(Frame::new(
+            header(FrameKind::Batch, publisher_id, topic, sequence, timestamp_ns, subscription_ids),
+            payload,
+        ));
+        Ok(frames)
+    }
+
+    /// Forget the stream state of a topic so its schema is sent again
+    pub fn reset_topic(&mut self, topic: &str) {
+        self.topics.retain(|(_, name), _| name != topic);
+    }
+
+    /// Forget the stream state of every topic, e.g. after a reconnect
+    pub fn reset(&mut self) {
+        self.topics.clear();
+    }
+}
+
+fn header(
+    kind: FrameKind,
+    publisher_id: u64,
+    topic: &str,
+    sequence: u64,
+    timestamp_ns: i64,
+    subscription_ids: &[u32],
+) -> FrameHeader {
+    FrameHeader {
+        kind,
+        topic: topic.to_string(),
+        sequence,
+        publisher_id,
+        timestamp_ns,
+        subscription_ids: subscription_ids.to_vec(),
+    }
+}
+
+/// Turns frames back into RecordBatches, keeping one IPC stream per publisher and topic
+pub struct BatchDecoder {
+    streams: HashMap<(u64, String), StreamDecoder>,
+}
+
+impl BatchDecoder {
+    pub fn new() -> Self {
+        Self {
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Decode the payload of a frame, taking ownership of it to avoid a copy
+    ///
+    /// Schema frames (re)start the stream for their topic and return `None`.
+    /// Batch frames return the decoded RecordBatch.
+    pub fn decode(&mut self, header: &FrameHeader, payload: Vec<u8>) -> Result<Option<RecordBatch>> {
+        let key = (header.publisher_id, header.topic.clone());
+        let mut buffer = Buffer::from_vec(payload);
+
+        let decoder = match header.kind {
+            FrameKind::Schema => {
+                self.streams.insert(key.clone(), StreamDecoder::new());
+                self.streams.get_mut(&key).unwrap()
+            }
+            FrameKind::Batch => self
+                .streams
+                .get_mut(&key)
+                .ok_or_else(|| anyhow!("batch for topic '{}' arrived before its schema", header.topic))?,
+            FrameKind::Control => bail!("control frames do not carry record batches"),
+        };
+
+        let mut result = None;
+        while !buffer.is_empty() {
+            if let Some(batch) = decoder.decode(&mut buffer)? {
+                result = Some(batch);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Whether a schema frame has been received for a publisher and topic
+    pub fn has_schema(&self, publisher_id: u64, topic: &str) -> bool {
+        self.streams.contains_key(&(publisher_id, topic.to_string()))
+    }
+
+    /// Drop the stream state of a publisher, e.g. when its connection closes
+    pub fn remove_publisher(&mut self, publisher_id: u64) {
+        self.streams.retain(|(id, _), _| *id != publisher_id);
+    }
+}
+
+impl Default for BatchDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
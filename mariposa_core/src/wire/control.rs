@@ -0,0 +1,311 @@
+use anyhow::{bail, ensure, Result};
+use arrow::datatypes::{Schema, SchemaRef};
+
+use crate::parameter::ParameterValue;
+use crate::wire::codec::{decode_schema, encode_schema};
+use crate::wire::frame::{put_bytes, put_string, ByteReader, Frame, FrameHeader, FrameKind};
+
+/// Version of the session protocol spoken over the wire
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Session control messages carried in `FrameKind::Control` frames
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlMessage {
+    /// First message sent by a client after connecting
+    Hello { client_name: String, protocol_version: u16 },
+    /// Broker accepted the handshake and assigned the client an id
+    Welcome { client_id: u64, broker_name: String },
+    /// Broker refused the handshake and will close the connection
+    Reject { reason: String },
+    /// Start delivering topics matching `pattern` under `subscription_id`
+    Subscribe { subscription_id: u32, pattern: String },
+    /// Stop delivering for a subscription
+    Unsubscribe { subscription_id: u32 },
+    /// The sender is closing the connection
+    Goodbye,
+    /// A client announces that it publishes on `topic`; a non-zero
+    /// `latch_depth` asks the broker to keep that many of the latest rows and
+    /// hand them to subscribers that join later
+    Advertise { topic: String, latch_depth: u32 },
+    /// Ask the broker for every topic it has seen a publish on
+    ListTopics { request_id: u32 },
+    /// Answer to `ListTopics` with the same `request_id`
+    TopicList { request_id: u32, topics: Vec<TopicInfo> },
+    /// The broker refused a batch published on `topic`
+    PublishRejected { topic: String, reason: String },
+    /// A client answers calls to `service` from now on
+    AdvertiseService { service: String },
+    /// A client stops answering calls to `service`
+    UnadvertiseService { service: String },
+    /// Call a service with a request batch encoded with `encode_batch`
+    ///
+    /// Callers pick `call_id`; the broker forwards the call under an id of its
+    /// own and fails it if no response arrives within `timeout_ms`.
+    CallService {
+        call_id: u32,
+        service: String,
+        timeout_ms: u32,
+        request: Vec<u8>,
+    },
+    /// Response batch to the call with the same `call_id`
+    ServiceResponse { call_id: u32, response: Vec<u8> },
+    /// The call with the same `call_id` failed
+    ServiceError { call_id: u32, reason: String },
+    /// Ask the broker for every parameter whose name matches `pattern`
+    GetParameters { request_id: u32, pattern: String },
+    /// Set a parameter, answered with a `ParameterList` holding it or a
+    /// `ParameterError`
+    SetParameter {
+        request_id: u32,
+        name: String,
+        value: ParameterValue,
+    },
+    /// Answer to `GetParameters` or `SetParameter` with the same `request_id`
+    ParameterList {
+        request_id: u32,
+        parameters: Vec<(String, ParameterValue)>,
+    },
+    /// The request with the same `request_id` failed
+    ParameterError { request_id: u32, reason: String },
+    /// A client asks the broker to hold back batches until `ResumeBatches`;
+    /// control messages keep flowing and held batches count towards the
+    /// client's queue
+    PauseBatches,
+    /// A client is ready for batches again
+    ResumeBatches,
+}
+
+/// A topic registered with the broker and the type of its messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicInfo {
+    pub topic: String,
+    /// Full protobuf name of the messages, if the publisher tagged its batches
+    pub message_type: Option<String>,
+    /// Arrow schema of the topic, encoded with `encode_schema`
+    pub schema: Vec<u8>,
+}
+
+impl TopicInfo {
+    pub fn new(topic: &str, message_type: Option<&str>, schema: &Schema) -> Result<Self> {
+        Ok(Self {
+            topic: topic.to_string(),
+            message_type: message_type.map(|x| x.to_string()),
+            schema: encode_schema(schema)?,
+        })
+    }
+
+    pub fn schema(&self) -> Result<SchemaRef> {
+        decode_schema(&self.schema)
+    }
+}
+
+impl ControlMessage {
+    /// Wrap the message in a control frame
+    pub fn to_frame(&self) -> Result<Frame> {
+        let mut payload = Vec::new();
+        match self {
+            ControlMessage::Hello { client_name, protocol_version } => {
+                payload.push(0);
+                put_string(&mut payload, client_name)?;
+                payload.extend_from_slice(&protocol_version.to_le_bytes());
+            }
+            ControlMessage::Welcome { client_id, broker_name } => {
+                payload.push(1);
+                payload.extend_from_slice(&client_id.to_le_bytes());
+                put_string(&mut payload, broker_name)?;
+            }
+            ControlMessage::Reject { reason } => {
+                payload.push(2);
+                put_string(&mut payload, reason)?;
+            }
+            ControlMessage::Subscribe { subscription_id, pattern } => {
+                payload.push(3);
+                payload.extend_from_slice(&subscription_id.to_le_bytes());
+                put_string(&mut payload, pattern)?;
+            }
+            ControlMessage::Unsubscribe { subscription_id } => {
+                payload.push(4);
+                payload.extend_from_slice(&subscription_id.to_le_bytes());
+            }
+            ControlMessage::Goodbye => payload.push(5),
+            ControlMessage::Advertise { topic, latch_depth } => {
+                payload.push(6);
+                put_string(&mut payload, topic)?;
+                payload.extend_from_slice(&latch_depth.to_le_bytes());
+            }
+            ControlMessage::ListTopics { request_id } => {
+                payload.push(7);
+                payload.extend_from_slice(&request_id.to_le_bytes());
+            }
+            ControlMessage::TopicList { request_id, topics } => {
+                payload.push(8);
+                payload.extend_from_slice(&request_id.to_le_bytes());
+                payload.extend_from_slice(&u32::try_from(topics.len())?.to_le_bytes());
+                for info in topics {
+                    put_string(&mut payload, &info.topic)?;
+                    // An empty name stands for an untagged topic
+                    put_string(&mut payload, info.message_type.as_deref().unwrap_or(""))?;
+                    put_bytes(&mut payload, &info.schema)?;
+                }
+            }
+            ControlMessage::PublishRejected { topic, reason } => {
+                payload.push(9);
+                put_string(&mut payload, topic)?;
+                put_string(&mut payload, reason)?;
+            }
+            ControlMessage::AdvertiseService { service } => {
+                payload.push(10);
+                put_string(&mut payload, service)?;
+            }
+            ControlMessage::UnadvertiseService { service } => {
+                payload.push(11);
+                put_string(&mut payload, service)?;
+            }
+            ControlMessage::CallService {
+                call_id,
+                service,
+                timeout_ms,
+                request,
+            } => {
+                payload.push(12);
+                payload.extend_from_slice(&call_id.to_le_bytes());
+                put_string(&mut payload, service)?;
+                payload.extend_from_slice(&timeout_ms.to_le_bytes());
+                put_bytes(&mut payload, request)?;
+            }
+            ControlMessage::ServiceResponse { call_id, response } => {
+                payload.push(13);
+                payload.extend_from_slice(&call_id.to_le_bytes());
+                put_bytes(&mut payload, response)?;
+            }
+            ControlMessage::ServiceError { call_id, reason } => {
+                payload.push(14);
+                payload.extend_from_slice(&call_id.to_le_bytes());
+                put_string(&mut payload, reason)?;
+            }
+            ControlMessage::GetParameters { request_id, pattern } => {
+                payload.push(15);
+                payload.extend_from_slice(&request_id.to_le_bytes());
+                put_string(&mut payload, pattern)?;
+            }
+            ControlMessage::SetParameter {
+                request_id,
+                name,
+                value,
+            } => {
+                payload.push(16);
+                payload.extend_from_slice(&request_id.to_le_bytes());
+                put_string(&mut payload, name)?;
+                value.put(&mut payload)?;
+            }
+            ControlMessage::ParameterList { request_id, parameters } => {
+                payload.push(17);
+                payload.extend_from_slice(&request_id.to_le_bytes());
+                payload.extend_from_slice(&u32::try_from(parameters.len())?.to_le_bytes());
+                for (name, value) in parameters {
+                    put_string(&mut payload, name)?;
+                    value.put(&mut payload)?;
+                }
+            }
+            ControlMessage::ParameterError { request_id, reason } => {
+                payload.push(18);
+                payload.extend_from_slice(&request_id.to_le_bytes());
+                put_string(&mut payload, reason)?;
+            }
+            ControlMessage::PauseBatches => payload.push(19),
+            ControlMessage::ResumeBatches => payload.push(20),
+        }
+        Ok(Frame::new(FrameHeader::new(FrameKind::Control, ""), payload))
+    }
+
+    /// Parse the payload of a control frame
+    pub fn from_frame(frame: &Frame) -> Result<Self> {
+        ensure!(frame.header.kind == FrameKind::Control, "expected a control frame, got {:?}", frame.header.kind);
+        let mut reader = ByteReader::new(&frame.payload);
+        let message = match reader.u8()? {
+            0 => ControlMessage::Hello {
+                client_name: reader.string()?,
+                protocol_version: reader.u16()?,
+            },
+            1 => ControlMessage::Welcome {
+                client_id: reader.u64()?,
+                broker_name: reader.string()?,
+            },
+            2 => ControlMessage::Reject { reason: reader.string()? },
+            3 => ControlMessage::Subscribe {
+                subscription_id: reader.u32()?,
+                pattern: reader.string()?,
+            },
+            4 => ControlMessage::Unsubscribe { subscription_id: reader.u32()? },
+            5 => ControlMessage::Goodbye,
+            6 => ControlMessage::Advertise {
+                topic: reader.string()?,
+                latch_depth: reader.u32()?,
+            },
+            7 => ControlMessage::ListTopics { request_id: reader.u32()? },
+            8 => {
+                let request_id = reader.u32()?;
+                let count = reader.u32()?;
+                let mut topics = Vec::new();
+                for _ in 0..count {
+                    let topic = reader.string()?;
+                    let message_type = Some(reader.string()?).filter(|x| !x.is_empty());
+                    topics.push(TopicInfo {
+                        topic,
+                        message_type,
+                        schema: reader.bytes()?,
+                    });
+                }
+                ControlMessage::TopicList { request_id, topics }
+            }
+            9 => ControlMessage::PublishRejected {
+                topic: reader.string()?,
+                reason: reader.string()?,
+            },
+            10 => ControlMessage::AdvertiseService { service: reader.string()? },
+            11 => ControlMessage::UnadvertiseService { service: reader.string()? },
+            12 => ControlMessage::CallService {
+                call_id: reader.u32()?,
+                service: reader.string()?,
+                timeout_ms: reader.u32()?,
+                request: reader.bytes()?,
+            },
+            13 => ControlMessage::ServiceResponse {
+                call_id: reader.u32()?,
+                response: reader.bytes()?,
+            },
+            14 => ControlMessage::ServiceError {
+                call_id: reader.u32()?,
+                reason: reader.string()?,
+            },
+            15 => ControlMessage::GetParameters {
+                request_id: reader.u32()?,
+                pattern: reader.string()?,
+            },
+            16 => ControlMessage::SetParameter {
+                request_id: reader.u32()?,
+                name: reader.string()?,
+                value: ParameterValue::read(&mut reader)?,
+            },
+            17 => {
+                let request_id = reader.u32()?;
+                let count = reader.u32()?;
+                let mut parameters = Vec::new();
+                for _ in 0..count {
+                    let name = reader.string()?;
+                    parameters.push((name, ParameterValue::read(&mut reader)?));
+                }
+                ControlMessage::ParameterList { request_id, parameters }
+            }
+            18 => ControlMessage::ParameterError {
+                request_id: reader.u32()?,
+                reason: reader.string()?,
+            },
+            19 => ControlMessage::PauseBatches,
+            20 => ControlMessage::ResumeBatches,
+            tag => bail!("unknown control message {}", tag),
+        };
+        ensure!(reader.remaining() == 0, "trailing bytes in control message");
+        Ok(message)
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{ensure, Result};
+use std::fmt;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+/// Scheme of endpoints that name a Unix domain socket
+pub const UNIX_SCHEME: &str = "unix://";
+/// Optional scheme of TCP endpoints
+pub const TCP_SCHEME: &str = "tcp://";
+
+/// Where a broker listens, as given in configs and on the command line
+///
+/// `unix:///run/mariposa.sock` names a Unix domain socket, anything else such
+/// as `127.0.0.1:7400` or `tcp://broker.local:7400` a TCP address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    pub fn parse(endpoint: &str) -> Result<Self> {
+        if let Some(path) = endpoint.strip_prefix(UNIX_SCHEME) {
+            ensure!(!path.is_empty(), "endpoint '{}' has no socket path", endpoint);
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+        let addr = endpoint.strip_prefix(TCP_SCHEME).unwrap_or(endpoint);
+        ensure!(!addr.is_empty(), "endpoint '{}' has no address", endpoint);
+        Ok(Endpoint::Tcp(addr.to_string()))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            Endpoint::Unix(path) => write!(f, "{}{}", UNIX_SCHEME, path.display()),
+        }
+    }
+}
+
+/// Byte stream frames are exchanged over, whatever the socket type
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+/// Open a connection to an endpoint
+pub async fn connect(endpoint: &Endpoint) -> Result<Box<dyn Transport>> {
+    match endpoint {
+        Endpoint::Tcp(addr) => {
+            let stream = TcpStream::connect(addr).await?;
+            stream.set_nodelay(true)?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => anyhow::bail!("unix domain sockets are not supported on this platform"),
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{bail, ensure, Result};
+
+use crate::wire::{MAGIC, VERSION};
+
+/// Length of the fixed prefix at the start of every frame
+pub const FRAME_PREFIX_LEN: usize = 16;
+
+/// Upper bound on the encoded size of a single frame
+pub const MAX_FRAME_LEN: usize = 256 * 1024 * 1024;
+
+const CHECKSUM_LEN: usize = 4;
+
+/// What the payload of a frame contains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// Arrow IPC schema message that starts a topic stream
+    Schema = 0,
+    /// Arrow IPC record batch (and dictionary) messages for a topic stream
+    Batch = 1,
+    /// Session control message such as the handshake or a subscription
+    Control = 2,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(FrameKind::Schema),
+            1 => Ok(FrameKind::Batch),
+            2 => Ok(FrameKind::Control),
+            _ => bail!("unknown frame kind {}", value),
+        }
+    }
+}
+
+/// Routing information sent in front of every payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub kind: FrameKind,
+    pub topic: String,
+    /// Per-topic sequence number assigned by the sender
+    pub sequence: u64,
+    pub publisher_id: u64,
+    /// Nanoseconds since the unix epoch at which the sender produced the frame
+    pub timestamp_ns: i64,
+    /// Subscriptions on the receiving client that this frame should be delivered to
+    pub subscription_ids: Vec<u32>,
+}
+
+impl FrameHeader {
+    pub fn new(kind: FrameKind, topic: &str) -> Self {
+        Self {
+            kind,
+            topic: topic.to_string(),
+            sequence: 0,
+            publisher_id: 0,
+            timestamp_ns: 0,
+            subscription_ids: Vec::new(),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        let ids_len = u16::try_from(self.subscription_ids.len())?;
+        put_string(out, &self.topic)?;
+        out.extend_from_slice(&self.sequence.to_le_bytes());
+        out.extend_from_slice(&self.publisher_id.to_le_bytes());
+        out.extend_from_slice(&self.timestamp_ns.to_le_bytes());
+        out.extend_from_slice(&ids_len.to_le_bytes());
+        for id in &self.subscription_ids {
+            out.extend_from_slice(&id.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn decode(kind: FrameKind, bytes: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(bytes);
+        let topic = reader.string()?;
+        let sequence = reader.u64()?;
+        let publisher_id = reader.u64()?;
+        let timestamp_ns = reader.u64()? as i64;
+        let ids_len = reader.u16()? as usize;
+        let subscription_ids = (0..ids_len).map(|_| reader.u32()).collect::<Result<Vec<u32>>>()?;
+        ensure!(reader.remaining() == 0, "trailing bytes in frame header");
+        Ok(Self {
+            kind,
+            topic,
+            sequence,
+            publisher_id,
+            timestamp_ns,
+            subscription_ids,
+        })
+    }
+}
+
+/// A single unit on the wire: a header and an Arrow IPC payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub header: FrameHeader,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(header: FrameHeader, payload: Vec<u8>) -> Self {
+        Self { header, payload }
+    }
+
+    /// Encode the frame, including prefix and checksum
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut header = Vec::new();
+        self.header.encode(&mut header)?;
+
+        let total = FRAME_PREFIX_LEN + header.len() + self.payload.len() + CHECKSUM_LEN;
+        ensure!(total <= MAX_FRAME_LEN, "frame of {} bytes exceeds the {} byte limit", total, MAX_FRAME_LEN);
+
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        out.push(self.header.kind as u8);
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&self.payload);
+        let checksum = crc32fast::hash(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        Ok(out)
+    }
+
+    /// Decode a frame from the start of `bytes`
+    ///
+    /// Returns `None` if more bytes are needed, otherwise the frame and the
+    /// number of bytes it occupied.
+    pub fn decode(bytes: &[u8]) -> Result<Option<(Frame, usize)>> {
+        if bytes.len() < FRAME_PREFIX_LEN {
+            return Ok(None);
+        }
+        let total = frame_length(&bytes[..FRAME_PREFIX_LEN])?;
+        if bytes.len() < total {
+            return Ok(None);
+        }
+
+        let (body, checksum) = bytes[..total].split_at(total - CHECKSUM_LEN);
+        let expected = u32::from_le_bytes(checksum.try_into()?);
+        let actual = crc32fast::hash(body);
+        ensure!(expected == actual, "frame checksum mismatch: expected {:08x}, got {:08x}", expected, actual);
+
+        let kind = FrameKind::try_from(body[5])?;
+        let header_len = u32::from_le_bytes(body[8..12].try_into()?) as usize;
+        let header_end = FRAME_PREFIX_LEN + header_len;
+        let header = FrameHeader::decode(kind, &body[FRAME_PREFIX_LEN..header_end])?;
+        let payload = body[header_end..].to_vec();
+        Ok(Some((Frame { header, payload }, total)))
+    }
+}
+
+/// Validate a frame prefix and return the total length of the frame it starts
+pub fn frame_length(prefix: &[u8]) -> Result<usize> {
+    ensure!(prefix.len() >= FRAME_PREFIX_LEN, "frame prefix needs {} bytes", FRAME_PREFIX_LEN);
+    ensure!(prefix[..4] == MAGIC, "bad frame magic {:?}", &prefix[..4]);
+    ensure!(prefix[4] == VERSION, "unsupported frame version {}", prefix[4]);
+    let header_len = u32::from_le_bytes(prefix[8..12].try_into()?) as usize;
+    let payload_len = u32::from_le_bytes(prefix[12..16].try_into()?) as usize;
+    let total = FRAME_PREFIX_LEN + header_len + payload_len + CHECKSUM_LEN;
+    ensure!(total <= MAX_FRAME_LEN, "frame of {} bytes exceeds the {} byte limit", total, MAX_FRAME_LEN);
+    Ok(total)
+}
+
+/// Little endian cursor over a byte slice
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(self.bytes.len() >= len, "unexpected end of data");
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+/// Append a string with a u16 length prefix
+pub(crate) fn put_string(out: &mut Vec<u8>, value: &str) -> Result<()> {
+    out.extend_from_slice(&u16::try_from(value.len())?.to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// Append a byte string with a u32 length prefix
+pub(crate) fn put_bytes(out: &mut Vec<u8>, value: &[u8]) -> Result<()> {
+    out.extend_from_slice(&u32::try_from(value.len())?.to_le_bytes());
+    out.extend_from_slice(value);
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use anyhow::{bail, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::wire::frame::{frame_length, Frame, FRAME_PREFIX_LEN};
+
+/// Read the next frame from an async byte stream
+///
+/// Returns `None` if the stream ended cleanly on a frame boundary, and an
+/// `UnexpectedEof` error if it ended anywhere else.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Frame>> {
+    let mut prefix = [0u8; FRAME_PREFIX_LEN];
+    if reader.read(&mut prefix[..1]).await? == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut prefix[1..]).await?;
+
+    let total = frame_length(&prefix)?;
+    let mut bytes = vec![0u8; total];
+    bytes[..FRAME_PREFIX_LEN].copy_from_slice(&prefix);
+    reader.read_exact(&mut bytes[FRAME_PREFIX_LEN..]).await?;
+
+    match Frame::decode(&bytes)? {
+        Some((frame, _)) => Ok(Some(frame)),
+        None => bail!("incomplete frame after reading {} bytes", total),
+    }
+}
+
+/// Write a frame to an async byte stream
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &Frame) -> Result<()> {
+    writer.write_all(&frame.encode()?).await?;
+    Ok(())
+}
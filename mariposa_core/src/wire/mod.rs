@@ -0,0 +1,37 @@
+// Framing for Arrow RecordBatches sent between clients and the broker
+//
+// Every frame is a fixed prefix, a small binary header and an Arrow IPC payload,
+// followed by a CRC32 of everything before it:
+//
+//   magic "MRPW" | version u8 | kind u8 | reserved u16 | header_len u32 | payload_len u32
+//   header (topic, sequence, publisher id, timestamp, subscription ids)
+//   payload (Arrow IPC stream messages)
+//   crc32 u32
+//
+// All integers are little endian. For each topic a schema frame is sent once,
+// followed by batch frames that only carry the record batch messages. Control
+// frames carry the session handshake, subscription requests, topic queries,
+// parameter requests and service calls, whose requests and responses are
+// self-contained IPC streams.
+//
+// Frames travel over TCP or over a Unix domain socket, see `Endpoint`.
+
+mod frame;
+mod codec;
+mod control;
+mod io;
+mod endpoint;
+
+#[cfg(test)]
+mod tests;
+
+pub use frame::{frame_length, Frame, FrameHeader, FrameKind, FRAME_PREFIX_LEN, MAX_FRAME_LEN};
+pub(crate) use frame::{put_bytes, put_string, ByteReader};
+pub use codec::{decode_batch, decode_schema, encode_batch, encode_schema, BatchDecoder, BatchEncoder};
+pub use control::{ControlMessage, TopicInfo, PROTOCOL_VERSION};
+pub use io::{read_frame, write_frame};
+pub use endpoint::{connect, Endpoint, Transport, TCP_SCHEME, UNIX_SCHEME};
+
+// Constants
+pub const MAGIC: [u8; 4] = *b"MRPW";
+pub const VERSION: u8 = 1;
@@ -0,0 +1,262 @@
+use arrow::record_batch::RecordBatch;
+use arrow_array::StringArray;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use crate::parameter::ParameterValue;
+use crate::test_util::speed_batch;
+use crate::wire::{
+    decode_batch, encode_batch, frame_length, read_frame, write_frame, BatchDecoder, BatchEncoder, ControlMessage,
+    Frame, FrameHeader, Endpoint, FrameKind, TopicInfo, FRAME_PREFIX_LEN,
+};
+
+fn round_trip(frame: &Frame) -> Frame {
+    let bytes = frame.encode().unwrap();
+    let (decoded, used) = Frame::decode(&bytes).unwrap().unwrap();
+    assert_eq!(used, bytes.len());
+    decoded
+}
+
+#[test]
+fn test_frame_round_trip() {
+    let header = FrameHeader {
+        kind: FrameKind::Batch,
+        topic: "vehicle/speed".to_string(),
+        sequence: 42,
+        publisher_id: 7,
+        timestamp_ns: 1_710_330_693_000_000_123,
+        subscription_ids: vec![0, 3, 9],
+    };
+    let frame = Frame::new(header, vec![1, 2, 3, 4, 5]);
+
+    assert_eq!(round_trip(&frame), frame);
+}
+
+#[test]
+fn test_frame_decode_partial() {
+    let frame = Frame::new(FrameHeader::new(FrameKind::Schema, "topic/a"), vec![9; 32]);
+    let bytes = frame.encode().unwrap();
+
+    assert!(Frame::decode(&bytes[..FRAME_PREFIX_LEN - 1]).unwrap().is_none());
+    assert!(Frame::decode(&bytes[..bytes.len() - 1]).unwrap().is_none());
+    assert_eq!(frame_length(&bytes[..FRAME_PREFIX_LEN]).unwrap(), bytes.len());
+
+    let mut two = bytes.clone();
+    two.extend_from_slice(&bytes);
+    let (_, used) = Frame::decode(&two).unwrap().unwrap();
+    assert_eq!(used, bytes.len());
+}
+
+#[test]
+fn test_frame_checksum_mismatch() {
+    let frame = Frame::new(FrameHeader::new(FrameKind::Batch, "topic/a"), vec![1, 2, 3]);
+    let mut bytes = frame.encode().unwrap();
+    let index = bytes.len() - 6;
+    bytes[index] ^= 0xff;
+
+    let error = Frame::decode(&bytes).unwrap_err();
+    assert!(error.to_string().contains("checksum"));
+}
+
+#[test]
+fn test_frame_bad_magic() {
+    let frame = Frame::new(FrameHeader::new(FrameKind::Batch, "topic/a"), vec![]);
+    let mut bytes = frame.encode().unwrap();
+    bytes[0] = b'X';
+
+    assert!(Frame::decode(&bytes).is_err());
+}
+
+#[test]
+fn test_schema_sent_once() {
+    let mut encoder = BatchEncoder::new(11);
+    let mut decoder = BatchDecoder::new();
+
+    let first = encoder.encode("vehicle/speed", &speed_batch(vec![1], vec![88.0]), 100, &[]).unwrap();
+    assert_eq!(first.len(), 2);
+    assert_eq!(first[0].header.kind, FrameKind::Schema);
+    assert_eq!(first[1].header.kind, FrameKind::Batch);
+
+    let second = encoder.encode("vehicle/speed", &speed_batch(vec![2, 3], vec![91.5, 120.25]), 200, &[4]).unwrap();
+    assert_eq!(second.len(), 1);
+    assert_eq!(second[0].header.sequence, 1);
+    assert_eq!(second[0].header.subscription_ids, vec![4]);
+
+    let mut batches = Vec::new();
+    for frame in first.iter().chain(second.iter()) {
+        let Frame { header, payload } = round_trip(frame);
+        assert_eq!(header.publisher_id, 11);
+        if let Some(batch) = decoder.decode(&header, payload).unwrap() {
+            batches.push(batch);
+        }
+    }
+    assert_eq!(batches, vec![speed_batch(vec![1], vec![88.0]), speed_batch(vec![2, 3], vec![91.5, 120.25])]);
+}
+
+#[test]
+fn test_schema_change_restarts_stream() {
+    let mut encoder = BatchEncoder::new(1);
+    let mut decoder = BatchDecoder::new();
+
+    let plates = Arc::new(StringArray::from(vec!["MRP-1"])) as _;
+    let plate_batch = RecordBatch::try_from_iter([("plate", plates)]).unwrap();
+
+    let mut frames = encoder.encode("topic/a", &speed_batch(vec![1], vec![1.0]), 0, &[]).unwrap();
+    frames.extend(encoder.encode("topic/a", &plate_batch, 0, &[]).unwrap());
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[2].header.kind, FrameKind::Schema);
+
+    let decoded: Vec<RecordBatch> = frames
+        .into_iter()
+        .filter_map(|frame| decoder.decode(&frame.header, frame.payload).unwrap())
+        .collect();
+    assert_eq!(decoded[1], plate_batch);
+}
+
+#[test]
+fn test_batch_before_schema() {
+    let mut encoder = BatchEncoder::new(1);
+    let frames = encoder.encode("topic/a", &speed_batch(vec![1], vec![1.0]), 0, &[]).unwrap();
+
+    let mut decoder = BatchDecoder::new();
+    assert!(decoder.decode(&frames[1].header, frames[1].payload.clone()).is_err());
+}
+
+#[test]
+fn test_control_round_trip() {
+    let messages = vec![
+        ControlMessage::Hello {
+            client_name: "camera".to_string(),
+            protocol_version: 1,
+        },
+        ControlMessage::Welcome {
+            client_id: 12,
+            broker_name: "mariposa".to_string(),
+        },
+        ControlMessage::Reject {
+            reason: "nope".to_string(),
+        },
+        ControlMessage::Subscribe {
+            subscription_id: 3,
+            pattern: "vehicle/*".to_string(),
+        },
+        ControlMessage::Unsubscribe { subscription_id: 3 },
+        ControlMessage::Goodbye,
+        ControlMessage::Advertise {
+            topic: "mission/state".to_string(),
+            latch_depth: 1,
+        },
+        ControlMessage::ListTopics { request_id: 5 },
+        ControlMessage::TopicList {
+            request_id: 5,
+            topics: vec![
+                TopicInfo::new("vehicle/speed", Some("mariposa.test.Speed"), &speed_batch(vec![], vec![]).schema())
+                    .unwrap(),
+                TopicInfo::new("vehicle/raw", None, &speed_batch(vec![], vec![]).schema()).unwrap(),
+            ],
+        },
+        ControlMessage::PublishRejected {
+            topic: "vehicle/speed".to_string(),
+            reason: "schema mismatch".to_string(),
+        },
+        ControlMessage::AdvertiseService {
+            service: "camera/set_exposure".to_string(),
+        },
+        ControlMessage::UnadvertiseService {
+            service: "camera/set_exposure".to_string(),
+        },
+        ControlMessage::CallService {
+            call_id: 9,
+            service: "camera/set_exposure".to_string(),
+            timeout_ms: 500,
+            request: encode_batch(&speed_batch(vec![1], vec![2.0])).unwrap(),
+        },
+        ControlMessage::ServiceResponse {
+            call_id: 9,
+            response: vec![1, 2, 3],
+        },
+        ControlMessage::ServiceError {
+            call_id: 9,
+            reason: "no provider".to_string(),
+        },
+        ControlMessage::GetParameters {
+            request_id: 10,
+            pattern: "camera/**".to_string(),
+        },
+        ControlMessage::SetParameter {
+            request_id: 11,
+            name: "camera/exposure".to_string(),
+            value: ParameterValue::Float(0.01),
+        },
+        ControlMessage::ParameterList {
+            request_id: 10,
+            parameters: vec![
+                ("camera/enabled".to_string(), ParameterValue::Bool(true)),
+                ("camera/gain".to_string(), ParameterValue::Int(-2)),
+                ("camera/model".to_string(), ParameterValue::String("imx490".to_string())),
+            ],
+        },
+        ControlMessage::ParameterError {
+            request_id: 11,
+            reason: "type mismatch".to_string(),
+        },
+        ControlMessage::PauseBatches,
+        ControlMessage::ResumeBatches,
+    ];
+    for message in messages {
+        let frame = round_trip(&message.to_frame().unwrap());
+        assert_eq!(frame.header.kind, FrameKind::Control);
+        assert_eq!(ControlMessage::from_frame(&frame).unwrap(), message);
+    }
+}
+
+#[tokio::test]
+async fn test_async_frame_io() {
+    let (mut client, mut server) = tokio::io::duplex(64);
+    let frame = Frame::new(FrameHeader::new(FrameKind::Batch, "topic/a"), vec![7; 300]);
+
+    let expected = frame.clone();
+    let writer = tokio::spawn(async move {
+        write_frame(&mut client, &frame).await.unwrap();
+        write_frame(&mut client, &frame).await.unwrap();
+    });
+
+    assert_eq!(read_frame(&mut server).await.unwrap(), Some(expected.clone()));
+    assert_eq!(read_frame(&mut server).await.unwrap(), Some(expected));
+    writer.await.unwrap();
+    assert_eq!(read_frame(&mut server).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_stream_ending_inside_a_frame() {
+    let frame = Frame::new(FrameHeader::new(FrameKind::Batch, "topic/a"), vec![7; 300]);
+    let bytes = frame.encode().unwrap();
+    // Cut inside the length prefix and inside the frame after it
+    for cut in [1, FRAME_PREFIX_LEN - 1, FRAME_PREFIX_LEN + 10] {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&bytes[..cut]).await.unwrap();
+        drop(client);
+        let error = read_frame(&mut server).await.unwrap_err();
+        let kind = error.downcast_ref::<std::io::Error>().map(|x| x.kind());
+        assert_eq!(kind, Some(std::io::ErrorKind::UnexpectedEof), "cut at {}", cut);
+    }
+}
+
+#[test]
+fn test_schema_round_trip() {
+    let batch = speed_batch(vec![1], vec![2.0]);
+    let info = TopicInfo::new("vehicle/speed", None, &batch.schema()).unwrap();
+    assert_eq!(info.schema().unwrap(), batch.schema());
+    assert_eq!(decode_batch(&encode_batch(&batch).unwrap()).unwrap(), batch);
+}
+
+#[test]
+fn test_endpoint_parse() {
+    assert_eq!(
+        Endpoint::parse("unix:///run/mariposa.sock").unwrap(),
+        Endpoint::Unix("/run/mariposa.sock".into())
+    );
+    assert_eq!(Endpoint::parse("tcp://10.0.0.2:7400").unwrap(), Endpoint::Tcp("10.0.0.2:7400".to_string()));
+    assert_eq!(Endpoint::parse("127.0.0.1:7400").unwrap(), Endpoint::Tcp("127.0.0.1:7400".to_string()));
+    assert!(Endpoint::parse("unix://").is_err());
+    assert_eq!(Endpoint::parse("unix:///run/mariposa.sock").unwrap().to_string(), "unix:///run/mariposa.sock");
+}
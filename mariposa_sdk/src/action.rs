@@ -0,0 +1,669 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use arrow::array::{Array, ArrayRef, AsArray, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, UInt64Type};
+use arrow::record_batch::RecordBatch;
+use futures::future::BoxFuture;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::dispatch::Received;
+use crate::node::{send_batch, Node, Outbound};
+use crate::service::{MessageCodec, ServiceHandler};
+use mariposa_core::topic::validate_topic;
+
+/// Column added to goal, feedback, result and status batches to tell the
+/// goals of an action apart
+pub const GOAL_ID_COLUMN: &str = "goal_id";
+
+/// Column numbering the status, feedback and result batches of a goal
+///
+/// The broker may merge batches queued for a client, which can reorder them
+/// across topics, so clients put the events of a goal back in order by it.
+pub const SEQUENCE_COLUMN: &str = "sequence";
+
+// Services and topics under the name of an action
+const SEND_GOAL: &str = "send_goal";
+const CANCEL_GOAL: &str = "cancel_goal";
+const GOAL_STATUS: &str = "goal_status";
+const STATUS: &str = "status";
+const FEEDBACK: &str = "feedback";
+const RESULT: &str = "result";
+
+/// How long a client waits for news of a goal before asking the server for
+/// its status, in case the batch ending it was dropped on the way
+const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the server answers status requests for goals that have ended
+const FINISHED_GOALS: usize = 64;
+
+/// Where a goal is in its lifecycle
+///
+/// A goal is accepted, then executes until its handler returns. It succeeds
+/// with a result, is aborted when the handler fails, or is canceled. A cancel
+/// request moves an accepted or executing goal to canceling first, and the
+/// handler may still finish before it takes effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoalState {
+    Accepted,
+    Executing,
+    Canceling,
+    Succeeded,
+    Aborted,
+    Canceled,
+}
+
+impl GoalState {
+    pub fn is_terminal(self) -> bool {
+        matches!(self, GoalState::Succeeded | GoalState::Aborted | GoalState::Canceled)
+    }
+
+    /// Whether a goal in this state may move to `next`
+    pub fn can_become(self, next: GoalState) -> bool {
+        match self {
+            GoalState::Accepted => matches!(next, GoalState::Executing | GoalState::Canceling),
+            GoalState::Executing => matches!(next, GoalState::Canceling | GoalState::Succeeded | GoalState::Aborted),
+            GoalState::Canceling => matches!(next, GoalState::Canceled | GoalState::Succeeded | GoalState::Aborted),
+            GoalState::Succeeded | GoalState::Aborted | GoalState::Canceled => false,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            GoalState::Accepted => "accepted",
+            GoalState::Executing => "executing",
+            GoalState::Canceling => "canceling",
+            GoalState::Succeeded => "succeeded",
+            GoalState::Aborted => "aborted",
+            GoalState::Canceled => "canceled",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "accepted" => GoalState::Accepted,
+            "executing" => GoalState::Executing,
+            "canceling" => GoalState::Canceling,
+            "succeeded" => GoalState::Succeeded,
+            "aborted" => GoalState::Aborted,
+            "canceled" => GoalState::Canceled,
+            other => bail!("unknown goal state '{}'", other),
+        })
+    }
+}
+
+impl fmt::Display for GoalState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Lets the handler of a goal publish feedback on it
+pub struct GoalHandle<F> {
+    id: u64,
+    topic: String,
+    sequence: Arc<AtomicU64>,
+    codec: Arc<MessageCodec<F>>,
+    outbound: mpsc::WeakSender<Outbound>,
+}
+
+impl<F: prost::Message + prost::Name + Default> GoalHandle<F> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Publish progress to the client that sent the goal
+    pub async fn publish_feedback(&self, feedback: &F) -> Result<()> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let batch = event_batch(self.codec.encode(feedback)?, self.id, sequence)?;
+        publish(&self.outbound, &self.topic, batch).await
+    }
+}
+
+/// A goal being run by an action server
+struct ServerGoal {
+    state: GoalState,
+    /// Next sequence number of the goal's events, shared with its handle
+    sequence: Arc<AtomicU64>,
+    /// Taken when the client asks to cancel
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+/// Runs the goals sent to one advertised action
+struct ActionServer<G, F, R, H> {
+    action: String,
+    goals: Mutex<HashMap<u64, ServerGoal>>,
+    /// Final state and message of the goals that ended last, oldest first
+    finished: Mutex<VecDeque<(u64, GoalState, String)>>,
+    /// Weak so that an advertised action does not keep the node open
+    outbound: mpsc::WeakSender<Outbound>,
+    goal_codec: MessageCodec<G>,
+    feedback_codec: Arc<MessageCodec<F>>,
+    result_codec: MessageCodec<R>,
+    handler: H,
+}
+
+/// Services answering the goal and cancel requests of an action, by name
+pub(crate) fn action_services<G, F, R, H, Fut>(
+    node: &Node,
+    action: &str,
+    handler: H,
+) -> Result<Vec<(String, ServiceHandler)>>
+where
+    G: prost::Message + prost::Name + Default + 'static,
+    F: prost::Message + prost::Name + Default + 'static,
+    R: prost::Message + prost::Name + Default + 'static,
+    H: Fn(G, GoalHandle<F>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R>> + Send + 'static,
+{
+    validate_topic(action)?;
+    let server = Arc::new(ActionServer {
+        action: action.to_string(),
+        goals: Mutex::new(HashMap::new()),
+        finished: Mutex::new(VecDeque::new()),
+        outbound: node.outbound().downgrade(),
+        goal_codec: node.codec::<G>()?,
+        feedback_codec: Arc::new(node.codec::<F>()?),
+        result_codec: node.codec::<R>()?,
+        handler,
+    });
+    let send_goal: ServiceHandler = {
+        let server = server.clone();
+        Arc::new(move |batch| server.clone().send_goal(batch))
+    };
+    let cancel_goal: ServiceHandler = {
+        let server = server.clone();
+        Arc::new(move |batch| server.clone().cancel_goal(batch))
+    };
+    let goal_status: ServiceHandler = Arc::new(move |batch| server.clone().goal_status(batch));
+    Ok(vec![
+        (format!("{}/{}", action, SEND_GOAL), send_goal),
+        (format!("{}/{}", action, CANCEL_GOAL), cancel_goal),
+        (format!("{}/{}", action, GOAL_STATUS), goal_status),
+    ])
+}
+
+impl<G, F, R, H, Fut> ActionServer<G, F, R, H>
+where
+    G: prost::Message + prost::Name + Default + 'static,
+    F: prost::Message + prost::Name + Default + 'static,
+    R: prost::Message + prost::Name + Default + 'static,
+    H: Fn(G, GoalHandle<F>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R>> + Send + 'static,
+{
+    /// Accept a goal and start running it
+    fn send_goal(self: Arc<Self>, batch: RecordBatch) -> BoxFuture<'static, Result<RecordBatch>> {
+        Box::pin(async move {
+            let id = goal_id(&batch)?;
+            let goal = self.goal_codec.decode(&strip_goal_columns(&batch)?)?;
+            let (cancel, canceled) = oneshot::channel();
+            // Acceptance is the first event and also the reply
+            let sequence = Arc::new(AtomicU64::new(1));
+            match self.goals.lock().unwrap().entry(id) {
+                Entry::Occupied(_) => bail!("goal {} already exists", id),
+                Entry::Vacant(entry) => entry.insert(ServerGoal {
+                    state: GoalState::Accepted,
+                    sequence: sequence.clone(),
+                    cancel: Some(cancel),
+                }),
+            };
+            let status = status_batch(id, 0, GoalState::Accepted, "")?;
+            self.publish(STATUS, status.clone()).await?;
+            tokio::spawn(self.execute(id, goal, sequence, canceled));
+            Ok(status)
+        })
+    }
+
+    /// Ask the handler of a goal to stop
+    fn cancel_goal(self: Arc<Self>, batch: RecordBatch) -> BoxFuture<'static, Result<RecordBatch>> {
+        Box::pin(async move {
+            let id = goal_id(&batch)?;
+            let (sequence, cancel) = {
+                let mut goals = self.goals.lock().unwrap();
+                let goal = goals.get_mut(&id).ok_or_else(|| anyhow!("goal {} is not active", id))?;
+                if !goal.state.can_become(GoalState::Canceling) {
+                    bail!("goal {} is {} and cannot be canceled", id, goal.state);
+                }
+                goal.state = GoalState::Canceling;
+                (goal.sequence.fetch_add(1, Ordering::Relaxed), goal.cancel.take())
+            };
+            // Canceling goes out before the handler can end the goal
+            let status = status_batch(id, sequence, GoalState::Canceling, "")?;
+            self.publish(STATUS, status.clone()).await?;
+            if let Some(cancel) = cancel {
+                let _ = cancel.send(());
+            }
+            Ok(status)
+        })
+    }
+
+    /// Report the current state of a goal, or the final one of a goal that
+    /// ended lately
+    fn goal_status(self: Arc<Self>, batch: RecordBatch) -> BoxFuture<'static, Result<RecordBatch>> {
+        Box::pin(async move {
+            let id = goal_id(&batch)?;
+            if let Some(goal) = self.goals.lock().unwrap().get(&id) {
+                return status_batch(id, 0, goal.state, "");
+            }
+            let finished = self.finished.lock().unwrap();
+            match finished.iter().find(|(x, _, _)| *x == id) {
+                Some((_, state, message)) => status_batch(id, 0, *state, message),
+                None => bail!("goal {} is not known", id),
+            }
+        })
+    }
+
+    /// Run the handler of a goal until it returns or the goal is canceled,
+    /// which drops the handler's future
+    async fn execute(self: Arc<Self>, id: u64, goal: G, sequence: Arc<AtomicU64>, canceled: oneshot::Receiver<()>) {
+        let handle = GoalHandle {
+            id,
+            topic: format!("{}/{}", self.action, FEEDBACK),
+            sequence: sequence.clone(),
+            codec: self.feedback_codec.clone(),
+            outbound: self.outbound.clone(),
+        };
+        let ended = async {
+            // A goal canceled before it started stays canceling
+            self.transition(id, GoalState::Executing, "").await?;
+            let work = (self.handler)(goal, handle);
+            let outcome = tokio::select! {
+                biased;
+                Ok(()) = canceled => None,
+                result = work => Some(result),
+            };
+            match outcome {
+                None => self.transition(id, GoalState::Canceled, "").await,
+                Some(Ok(result)) => {
+                    let result = self.result_codec.encode(&result)?;
+                    let batch = event_batch(result, id, sequence.fetch_add(1, Ordering::Relaxed))?;
+                    self.publish(RESULT, batch).await?;
+                    self.transition(id, GoalState::Succeeded, "").await
+                }
+                Some(Err(e)) => self.transition(id, GoalState::Aborted, &format!("{:#}", e)).await,
+            }
+        };
+        if let Err(e) = ended.await {
+            log::warn!("failed to report goal {} of action '{}': {:#}", id, self.action, e);
+        }
+        self.goals.lock().unwrap().remove(&id);
+    }
+
+    /// Move a goal to `next` and publish its status, skipping moves its
+    /// state machine does not allow
+    async fn transition(&self, id: u64, next: GoalState, message: &str) -> Result<()> {
+        let sequence = {
+            let mut goals = self.goals.lock().unwrap();
+            let Some(goal) = goals.get_mut(&id).filter(|x| x.state.can_become(next)) else {
+                return Ok(());
+            };
+            goal.state = next;
+            goal.sequence.fetch_add(1, Ordering::Relaxed)
+        };
+        if next.is_terminal() {
+            let mut finished = self.finished.lock().unwrap();
+            if finished.len() == FINISHED_GOALS {
+                finished.pop_front();
+            }
+            finished.push_back((id, next, message.to_string()));
+        }
+        self.publish(STATUS, status_batch(id, sequence, next, message)?).await
+    }
+
+    async fn publish(&self, suffix: &str, batch: RecordBatch) -> Result<()> {
+        publish(&self.outbound, &format!("{}/{}", self.action, suffix), batch).await
+    }
+}
+
+async fn publish(outbound: &mpsc::WeakSender<Outbound>, topic: &str, batch: RecordBatch) -> Result<()> {
+    let outbound = outbound.upgrade().ok_or_else(|| anyhow!("node is closed"))?;
+    send_batch(&outbound, topic, batch).await
+}
+
+/// What an action client hears about one of its goals
+enum GoalEvent {
+    Status(GoalState, String),
+    Feedback(RecordBatch),
+    Result(RecordBatch),
+}
+
+/// Channels of the goals an action client is waiting on, by goal id, carrying
+/// events with their sequence numbers
+type GoalChannels = Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<(u64, GoalEvent)>>>>;
+
+/// Sends goals to an action and follows them
+///
+/// The client subscribes to the action's status, feedback and result topics
+/// when it is created, and unsubscribes when dropped.
+pub struct ActionClient<G, F, R> {
+    node: Node,
+    action: String,
+    subscription_id: u32,
+    goals: GoalChannels,
+    goal_codec: MessageCodec<G>,
+    feedback_codec: Arc<MessageCodec<F>>,
+    result_codec: Arc<MessageCodec<R>>,
+}
+
+impl<G, F, R> ActionClient<G, F, R>
+where
+    G: prost::Message + prost::Name + Default,
+    F: prost::Message + prost::Name + Default,
+    R: prost::Message + prost::Name + Default,
+{
+    pub(crate) async fn new(node: &Node, action: &str) -> Result<Self> {
+        validate_topic(action)?;
+        let goal_codec = node.codec::<G>()?;
+        let feedback_codec = Arc::new(node.codec::<F>()?);
+        let result_codec = Arc::new(node.codec::<R>()?);
+        let goals = GoalChannels::default();
+        let subscription_id = {
+            let goals = goals.clone();
+            node.subscribe(&format!("{}/*", action), move |received: &Received| route_event(&goals, received))
+                .await?
+        };
+        Ok(Self {
+            node: node.clone(),
+            action: action.to_string(),
+            subscription_id,
+            goals,
+            goal_codec,
+            feedback_codec,
+            result_codec,
+        })
+    }
+
+    /// Send a goal and return once the action server has accepted it
+    ///
+    /// `timeout` bounds the wait for the acceptance, not the goal itself.
+    pub async fn send_goal(&self, goal: &G, timeout: Duration) -> Result<Goal<F, R>> {
+        let id = (self.node.client_id() << 32) | self.node.next_request_id() as u64;
+        let (sender, events) = mpsc::unbounded_channel();
+        self.goals.lock().unwrap().insert(id, sender);
+        let handle = Goal {
+            id,
+            state: GoalState::Accepted,
+            message: String::new(),
+            result: None,
+            next_sequence: 1,
+            early: BTreeMap::new(),
+            lost: None,
+            events,
+            goals: self.goals.clone(),
+            node: self.node.clone(),
+            action: self.action.clone(),
+            feedback_codec: self.feedback_codec.clone(),
+            result_codec: self.result_codec.clone(),
+        };
+        // Dropping the goal on failure forgets its channel again
+        let request = with_goal_id(self.goal_codec.encode(goal)?, id)?;
+        let service = format!("{}/{}", self.action, SEND_GOAL);
+        self.node.call_batch(&service, request, timeout).await?;
+        Ok(handle)
+    }
+}
+
+impl<G, F, R> Drop for ActionClient<G, F, R> {
+    fn drop(&mut self) {
+        self.node.forget_subscription(self.subscription_id);
+    }
+}
+
+/// Hand the rows of a batch from an action's topics to the goals they belong
+/// to, as merged batches can hold events of several goals
+fn route_event(goals: &GoalChannels, received: &Received) {
+    let kind = received.topic.rsplit('/').next();
+    if !matches!(kind, Some(STATUS | FEEDBACK | RESULT)) {
+        return;
+    }
+    for row in 0..received.batch.num_rows() {
+        let batch = received.batch.slice(row, 1);
+        let routed = (|| {
+            let event = match kind {
+                Some(STATUS) => parse_status(&batch).map(|(state, message)| GoalEvent::Status(state, message))?,
+                Some(FEEDBACK) => GoalEvent::Feedback(batch.clone()),
+                _ => GoalEvent::Result(batch.clone()),
+            };
+            let id = goal_id(&batch)?;
+            let sequence = sequence(&batch)?;
+            if let Some(sender) = goals.lock().unwrap().get(&id) {
+                let _ = sender.send((sequence, event));
+            }
+            Ok::<_, anyhow::Error>(())
+        })();
+        if let Err(e) = routed {
+            log::warn!("ignoring batch on '{}': {:#}", received.topic, e);
+        }
+    }
+}
+
+/// A goal sent by an `ActionClient`
+pub struct Goal<F, R> {
+    id: u64,
+    state: GoalState,
+    /// Error message of an aborted goal
+    message: String,
+    result: Option<RecordBatch>,
+    /// Sequence number of the next event to apply
+    next_sequence: u64,
+    /// Events that arrived ahead of their turn
+    early: BTreeMap<u64, GoalEvent>,
+    /// Why the goal can no longer be followed
+    lost: Option<String>,
+    events: mpsc::UnboundedReceiver<(u64, GoalEvent)>,
+    goals: GoalChannels,
+    node: Node,
+    action: String,
+    feedback_codec: Arc<MessageCodec<F>>,
+    result_codec: Arc<MessageCodec<R>>,
+}
+
+impl<F, R> Goal<F, R>
+where
+    F: prost::Message + prost::Name + Default,
+    R: prost::Message + prost::Name + Default,
+{
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Latest state reported by the action server
+    pub fn state(&self) -> GoalState {
+        self.state
+    }
+
+    /// Wait for the next feedback, or None once the goal has ended
+    pub async fn next_feedback(&mut self) -> Option<Result<F>> {
+        while !self.state.is_terminal() {
+            match self.next_event().await? {
+                GoalEvent::Feedback(batch) => {
+                    return Some(strip_goal_columns(&batch).and_then(|x| self.feedback_codec.decode(&x)))
+                }
+                event => self.apply(event),
+            }
+        }
+        None
+    }
+
+    /// Wait for the goal to end, skipping any feedback, and return its result
+    ///
+    /// Fails if the goal was aborted or canceled, or if the connection to the
+    /// broker or the action server is lost first.
+    pub async fn result(mut self) -> Result<R> {
+        while !self.state.is_terminal() {
+            let Some(event) = self.next_event().await else {
+                let reason = self.lost.as_deref().unwrap_or("connection to the broker was lost");
+                bail!("lost track of goal {} before it ended: {}", self.id, reason);
+            };
+            self.apply(event);
+        }
+        match self.state {
+            GoalState::Succeeded => {
+                let batch = self
+                    .result
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("goal {} succeeded without a result", self.id))?;
+                self.result_codec.decode(&strip_goal_columns(batch)?)
+            }
+            GoalState::Aborted => bail!("goal {} of '{}' was aborted: {}", self.id, self.action, self.message),
+            state => bail!("goal {} of '{}' was {}", self.id, self.action, state),
+        }
+    }
+
+    /// Ask the action server to cancel the goal
+    ///
+    /// Returns once the server has moved the goal to canceling; the goal ends
+    /// as canceled unless its handler finishes first.
+    pub async fn cancel(&self, timeout: Duration) -> Result<()> {
+        let service = format!("{}/{}", self.action, CANCEL_GOAL);
+        self.node.call_batch(&service, id_batch(self.id)?, timeout).await?;
+        Ok(())
+    }
+
+    /// Wait for the next event in sequence order, or None if the goal can no
+    /// longer be followed
+    ///
+    /// Events may be dropped on the way. Nothing comes after a terminal
+    /// status though, so once one has arrived the missing events before it
+    /// are skipped. When nothing arrives for a while, the server is asked for
+    /// the state of the goal in case the terminal status was the one dropped.
+    async fn next_event(&mut self) -> Option<GoalEvent> {
+        loop {
+            if let Some(event) = self.early.remove(&self.next_sequence) {
+                self.next_sequence = self.next_sequence.saturating_add(1);
+                return Some(event);
+            }
+            let ended = self
+                .early
+                .values()
+                .any(|x| matches!(x, GoalEvent::Status(state, _) if state.is_terminal()));
+            if let Some((&sequence, _)) = self.early.first_key_value().filter(|_| ended) {
+                self.next_sequence = sequence;
+                continue;
+            }
+            match tokio::time::timeout(STATUS_POLL_INTERVAL, self.events.recv()).await {
+                Ok(Some((sequence, event))) if sequence >= self.next_sequence => {
+                    self.early.insert(sequence, event);
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return None,
+                Err(_) => match self.poll_status().await {
+                    // Goes after whatever else did arrive
+                    Ok((state, message)) if state.is_terminal() => {
+                        self.early.insert(u64::MAX, GoalEvent::Status(state, message));
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        self.lost = Some(format!("{:#}", e));
+                        return None;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Ask the action server for the current state of the goal
+    async fn poll_status(&self) -> Result<(GoalState, String)> {
+        let service = format!("{}/{}", self.action, GOAL_STATUS);
+        let status = self.node.call_batch(&service, id_batch(self.id)?, STATUS_POLL_INTERVAL * 5).await?;
+        parse_status(&status)
+    }
+
+    fn apply(&mut self, event: GoalEvent) {
+        match event {
+            GoalEvent::Status(state, message) => {
+                self.state = state;
+                self.message = message;
+            }
+            GoalEvent::Result(batch) => self.result = Some(batch),
+            GoalEvent::Feedback(_) => {}
+        }
+    }
+}
+
+impl<F, R> Drop for Goal<F, R> {
+    fn drop(&mut self) {
+        self.goals.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Append a column of one value to a batch
+fn with_column(batch: RecordBatch, name: &str, value: u64) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    ensure!(
+        schema.column_with_name(name).is_none(),
+        "messages of an action cannot have a '{}' field",
+        name
+    );
+    let mut fields: Vec<_> = schema.fields().iter().cloned().collect();
+    fields.push(Arc::new(Field::new(name, DataType::UInt64, false)));
+    let mut columns = batch.columns().to_vec();
+    columns.push(Arc::new(UInt64Array::from(vec![value; batch.num_rows()])));
+    let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn with_goal_id(batch: RecordBatch, id: u64) -> Result<RecordBatch> {
+    with_column(batch, GOAL_ID_COLUMN, id)
+}
+
+/// Tag a feedback or result batch with its goal and sequence number
+fn event_batch(batch: RecordBatch, id: u64, sequence: u64) -> Result<RecordBatch> {
+    with_column(with_goal_id(batch, id)?, SEQUENCE_COLUMN, sequence)
+}
+
+/// Value of a column in the first row of a batch
+fn first_value(batch: &RecordBatch, name: &str) -> Result<u64> {
+    let values = batch
+        .column_by_name(name)
+        .and_then(|x| x.as_primitive_opt::<UInt64Type>())
+        .ok_or_else(|| anyhow!("batch has no '{}' column", name))?;
+    ensure!(!values.is_empty(), "batch has no rows");
+    Ok(values.value(0))
+}
+
+fn goal_id(batch: &RecordBatch) -> Result<u64> {
+    first_value(batch, GOAL_ID_COLUMN)
+}
+
+fn sequence(batch: &RecordBatch) -> Result<u64> {
+    first_value(batch, SEQUENCE_COLUMN)
+}
+
+/// A batch without the goal id and sequence columns
+fn strip_goal_columns(batch: &RecordBatch) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    let indices: Vec<usize> = (0..batch.num_columns())
+        .filter(|x| ![GOAL_ID_COLUMN, SEQUENCE_COLUMN].contains(&schema.field(*x).name().as_str()))
+        .collect();
+    Ok(batch.project(&indices)?)
+}
+
+fn id_batch(id: u64) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_from_iter([(GOAL_ID_COLUMN, Arc::new(UInt64Array::from(vec![id])) as ArrayRef)])?)
+}
+
+fn status_batch(id: u64, sequence: u64, state: GoalState, message: &str) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_from_iter([
+        (GOAL_ID_COLUMN, Arc::new(UInt64Array::from(vec![id])) as ArrayRef),
+        (SEQUENCE_COLUMN, Arc::new(UInt64Array::from(vec![sequence])) as _),
+        ("state", Arc::new(StringArray::from(vec![state.as_str()])) as _),
+        ("message", Arc::new(StringArray::from(vec![message])) as _),
+    ])?)
+}
+
+fn parse_status(batch: &RecordBatch) -> Result<(GoalState, String)> {
+    let column = |name: &str| {
+        batch
+            .column_by_name(name)
+            .and_then(|x| x.as_string_opt::<i32>())
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| anyhow!("status batch has no '{}' column", name))
+    };
+    let state = GoalState::parse(column("state")?.value(0))?;
+    Ok((state, column("message")?.value(0).to_string()))
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Address of a broker running on the same machine with its default settings
+pub const DEFAULT_BROKER_ADDR: &str = "127.0.0.1:7400";
+
+/// Settings for a node's connection to the broker
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Endpoint of the broker to connect to, a TCP address or a Unix socket
+    /// such as `unix:///run/mariposa.sock`
+    pub broker_addr: String,
+    /// Name the node introduces itself with
+    pub name: String,
+    /// Number of outgoing messages buffered while the connection is busy or
+    /// being re-established; publishing waits once it is full
+    pub queue_len: usize,
+    /// Number of batches a stream subscription buffers before the node stops
+    /// taking batches from the broker
+    pub stream_capacity: usize,
+    /// How long the broker has to answer the handshake
+    pub handshake_timeout: Duration,
+    /// Delay before the first reconnect attempt, doubled after every failure
+    pub reconnect_delay: Duration,
+    /// Upper bound for the delay between reconnect attempts
+    pub max_reconnect_delay: Duration,
+    /// Directory of the shared-memory rings used between nodes on this host,
+    /// e.g. `mariposa_core::shm::DEFAULT_SHM_DIR`. `None` keeps everything on TCP
+    pub shm_dir: Option<PathBuf>,
+    /// Size in bytes of the ring each shared-memory publisher creates
+    pub shm_capacity: usize,
+    /// Whether shared-memory publishers also send every batch to the broker,
+    /// for subscribers on other hosts
+    pub shm_forward: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            broker_addr: DEFAULT_BROKER_ADDR.to_string(),
+            name: "mariposa_node".to_string(),
+            queue_len: 1024,
+            stream_capacity: 64,
+            handshake_timeout: Duration::from_secs(5),
+            reconnect_delay: Duration::from_millis(100),
+            max_reconnect_delay: Duration::from_secs(5),
+            shm_dir: None,
+            shm_capacity: 64 * 1024 * 1024,
+            shm_forward: true,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+use arrow::record_batch::RecordBatch;
+use std::collections::BTreeMap;
+use tokio::sync::mpsc;
+
+use mariposa_core::topic::{pattern_matches, validate_pattern};
+
+/// A batch delivered by the broker
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub topic: String,
+    pub publisher_id: u64,
+    pub timestamp_ns: i64,
+    pub batch: RecordBatch,
+}
+
+/// Callback run for every batch delivered to a subscription
+pub(crate) type Handler = Box<dyn FnMut(&Received) + Send>;
+
+/// Channel feeding a stream subscription
+pub(crate) type StreamSender = mpsc::Sender<Result<Received>>;
+
+/// Where the batches of a subscription go
+pub(crate) enum Sink {
+    Callback(Handler),
+    Stream(StreamSender),
+}
+
+struct Subscription {
+    pattern: String,
+    sink: Sink,
+}
+
+/// Local subscriptions of a client, keyed by the id the broker tags batches with
+///
+/// The broker sends a batch once per client along with the ids of every
+/// subscription that matched, so one received batch can feed several handlers.
+#[derive(Default)]
+pub(crate) struct Dispatcher {
+    subscriptions: BTreeMap<u32, Subscription>,
+    next_id: u32,
+}
+
+impl Dispatcher {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a sink for `pattern` and return its subscription id
+    pub(crate) fn add(&mut self, pattern: &str, sink: Sink) -> Result<u32> {
+        validate_pattern(pattern)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscriptions.insert(
+            id,
+            Subscription {
+                pattern: pattern.to_string(),
+                sink,
+            },
+        );
+        Ok(id)
+    }
+
+    pub(crate) fn remove(&mut self, id: u32) -> bool {
+        self.subscriptions.remove(&id).is_some()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.subscriptions.clear();
+    }
+
+    /// Every registered subscription as (id, pattern)
+    pub(crate) fn patterns(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.subscriptions.iter().map(|(id, x)| (*id, x.pattern.as_str()))
+    }
+
+    /// Run the handlers of the tagged subscriptions and return the channels of
+    /// tagged stream subscriptions
+    ///
+    /// Streams are handed back rather than fed here so the caller can wait on
+    /// them without holding the dispatcher, and subscriptions sharing a stream
+    /// hand it back once. Untagged batches are matched against the local
+    /// patterns instead, and ids of subscriptions removed in the meantime are
+    /// skipped.
+    pub(crate) fn dispatch(&mut self, subscription_ids: &[u32], received: &Received) -> Vec<(u32, StreamSender)> {
+        let matched: Vec<u32> = if subscription_ids.is_empty() {
+            self.subscriptions
+                .iter()
+                .filter(|(_, x)| pattern_matches(&x.pattern, &received.topic))
+                .map(|(id, _)| *id)
+                .collect()
+        } else {
+            subscription_ids.to_vec()
+        };
+
+        let mut streams: Vec<(u32, StreamSender)> = Vec::new();
+        for id in matched {
+            match self.subscriptions.get_mut(&id).map(|x| &mut x.sink) {
+                Some(Sink::Callback(handler)) => handler(received),
+                Some(Sink::Stream(sender)) if !streams.iter().any(|(_, x)| x.same_channel(sender)) => {
+                    streams.push((id, sender.clone()))
+                }
+                Some(Sink::Stream(_)) | None => {}
+            }
+        }
+        streams
+    }
+
+    /// Tell every stream subscription that batches may have been missed
+    pub(crate) fn notify_disconnected(&self) {
+        for subscription in self.subscriptions.values() {
+            if let Sink::Stream(sender) = &subscription.sink {
+                let _ = sender.try_send(Err(anyhow!("connection to the broker was lost, batches may be missing")));
+            }
+        }
+    }
+}
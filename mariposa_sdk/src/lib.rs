@@ -1,14 +1,34 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
+// Client library for publishing and subscribing through a mariposa broker
+
+mod action;
+pub mod config;
+mod dispatch;
+mod node;
+mod player;
+mod publisher;
+mod recorder;
+mod service;
+#[cfg(target_os = "linux")]
+mod shm;
+mod stream;
+mod typed;
+mod udp;
 
 #[cfg(test)]
-mod tests {
-    use super::*;
+mod tests;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub use action::{ActionClient, Goal, GoalHandle, GoalState, GOAL_ID_COLUMN, SEQUENCE_COLUMN};
+pub use config::{NodeConfig, DEFAULT_BROKER_ADDR};
+pub use dispatch::Received;
+pub use node::Node;
+pub use player::PlayerConfig;
+pub use publisher::Publisher;
+pub use recorder::Recorder;
+#[cfg(target_os = "linux")]
+pub use shm::{LocalSubscription, ShmPublisher};
+pub use stream::SubscriptionStream;
+pub use typed::TypedStream;
+pub use udp::UdpSubscription;
+pub use mariposa_core::logfile::LogConfig;
+pub use mariposa_core::parameter::ParameterValue;
+pub use mariposa_core::wire::TopicInfo;
@@ -0,0 +1,842 @@
+use anyhow::{anyhow, bail, Result};
+use arrow::record_batch::RecordBatch;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWriteExt, BufWriter, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::action::{action_services, ActionClient, GoalHandle};
+use crate::config::NodeConfig;
+use crate::dispatch::{Dispatcher, Received, Sink, StreamSender};
+use crate::publisher::Publisher;
+use crate::service::{service_handler, MessageCodec, ServiceHandler};
+use crate::stream::SubscriptionStream;
+use crate::typed::TypedStream;
+use mariposa_core::parameter::{parameter_name, parameter_topic, parameters_from_batch, ParameterValue};
+use mariposa_core::ptars::ProtoCache;
+use mariposa_core::topic::{validate_pattern, validate_topic};
+use mariposa_core::wire::{
+    connect, decode_batch, encode_batch, read_frame, write_frame, BatchDecoder, BatchEncoder, ControlMessage, Endpoint,
+    FrameKind, TopicInfo, Transport, PROTOCOL_VERSION,
+};
+
+type Reader = ReadHalf<Box<dyn Transport>>;
+type Writer = WriteHalf<Box<dyn Transport>>;
+
+/// Answer of the broker to a parameter request
+type ParameterReply = oneshot::Sender<Result<Vec<(String, ParameterValue)>>>;
+
+/// Extra time a caller waits for the broker to report a timed out call
+const CALL_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+/// Message waiting to be written to the broker
+pub(crate) enum Outbound {
+    Batch {
+        topic: String,
+        timestamp_ns: i64,
+        batch: RecordBatch,
+    },
+    Control(ControlMessage),
+}
+
+/// Queue a batch for the broker, stamped with the current time
+pub(crate) async fn send_batch(outbound: &mpsc::Sender<Outbound>, topic: &str, batch: RecordBatch) -> Result<()> {
+    let message = Outbound::Batch {
+        topic: topic.to_string(),
+        timestamp_ns: now_ns(),
+        batch,
+    };
+    outbound.send(message).await.map_err(|_| anyhow!("node is closed"))
+}
+
+pub(crate) fn now_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+/// State shared between node handles and the connection task
+struct Shared {
+    config: NodeConfig,
+    dispatcher: Mutex<Dispatcher>,
+    /// Latch depth of every advertised topic, sent again after reconnecting
+    advertised: Mutex<HashMap<String, u32>>,
+    types: Mutex<ProtoCache>,
+    client_id: AtomicU64,
+    /// `list_topics` calls waiting for the broker, by request id
+    topic_queries: Mutex<HashMap<u32, oneshot::Sender<Vec<TopicInfo>>>>,
+    /// Parameter requests waiting for the broker, by request id
+    parameter_queries: Mutex<HashMap<u32, ParameterReply>>,
+    /// Services this node answers, advertised again after reconnecting
+    services: Mutex<HashMap<String, ServiceHandler>>,
+    /// `call`s waiting for a response, by call id
+    calls: Mutex<HashMap<u32, oneshot::Sender<Result<RecordBatch>>>>,
+    next_request_id: AtomicU32,
+    /// Queue to the broker for answering calls, weak so that it still closes
+    /// once every node handle is gone
+    outbound: mpsc::WeakSender<Outbound>,
+}
+
+impl Shared {
+    /// Control messages that rebuild this node's session on a new connection
+    fn session_messages(&self) -> Vec<ControlMessage> {
+        let mut messages: Vec<ControlMessage> = self
+            .advertised
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(topic, latch_depth)| ControlMessage::Advertise {
+                topic: topic.clone(),
+                latch_depth: *latch_depth,
+            })
+            .collect();
+        messages.extend(
+            self.services
+                .lock()
+                .unwrap()
+                .keys()
+                .map(|service| ControlMessage::AdvertiseService { service: service.clone() }),
+        );
+        messages.extend(self.dispatcher.lock().unwrap().patterns().map(|(subscription_id, pattern)| {
+            ControlMessage::Subscribe {
+                subscription_id,
+                pattern: pattern.to_string(),
+            }
+        }));
+        messages
+    }
+}
+
+/// A client of the broker that publishes and subscribes to topics
+///
+/// The connection is kept in a background task that reconnects on its own
+/// and restores advertisements and subscriptions when it does. Clones share
+/// the same connection, which closes once every clone has been dropped.
+#[derive(Clone)]
+pub struct Node {
+    shared: Arc<Shared>,
+    outbound: mpsc::Sender<Outbound>,
+}
+
+impl Node {
+    /// Connect to the broker at `addr` with default settings
+    pub async fn connect(addr: &str, name: &str) -> Result<Self> {
+        Self::connect_with(NodeConfig {
+            broker_addr: addr.to_string(),
+            name: name.to_string(),
+            ..NodeConfig::default()
+        })
+        .await
+    }
+
+    /// Connect to the broker described by the config
+    ///
+    /// Fails if the first connection cannot be made; later disconnects are
+    /// retried in the background.
+    pub async fn connect_with(config: NodeConfig) -> Result<Self> {
+        let (stream, client_id) = open(&config).await?;
+        let (outbound, queue) = mpsc::channel(config.queue_len.max(1));
+        let shared = Arc::new(Shared {
+            config,
+            dispatcher: Mutex::new(Dispatcher::new()),
+            advertised: Mutex::new(HashMap::new()),
+            types: Mutex::new(ProtoCache::new()),
+            client_id: AtomicU64::new(client_id),
+            topic_queries: Mutex::new(HashMap::new()),
+            parameter_queries: Mutex::new(HashMap::new()),
+            services: Mutex::new(HashMap::new()),
+            calls: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU32::new(0),
+            outbound: outbound.downgrade(),
+        });
+        tokio::spawn(run(shared.clone(), queue, stream, client_id));
+        Ok(Self { shared, outbound })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.shared.config.name
+    }
+
+    pub(crate) fn config(&self) -> &NodeConfig {
+        &self.shared.config
+    }
+
+    pub(crate) fn outbound(&self) -> &mpsc::Sender<Outbound> {
+        &self.outbound
+    }
+
+    /// Id the broker assigned to the current connection, which is also the
+    /// publisher id on every batch this node sends
+    pub fn client_id(&self) -> u64 {
+        self.shared.client_id.load(Ordering::Relaxed)
+    }
+
+    /// Make the message types of a serialized `FileDescriptorSet` available to
+    /// typed publishers, e.g. the set prost-build writes next to its output
+    pub fn register_descriptors(&self, descriptor_set: &[u8]) -> Result<()> {
+        self.shared.types.lock().unwrap().add_descriptor_set(descriptor_set)
+    }
+
+    /// Create a publisher of prost messages on `topic`
+    ///
+    /// The descriptor of `T` must have been registered with
+    /// `register_descriptors` first.
+    pub fn publisher<T: prost::Message + prost::Name>(&self, topic: &str) -> Result<Publisher<T>> {
+        validate_topic(topic)?;
+        let message_type = T::full_name();
+        let handler = self.shared.types.lock().unwrap().handler_for(&message_type)?;
+        Ok(Publisher::new(topic, message_type, handler, self.outbound.clone()))
+    }
+
+    /// Announce a topic to the broker, asking it to keep the latest
+    /// `latch_depth` rows for late subscribers when non-zero
+    pub async fn advertise(&self, topic: &str, latch_depth: u32) -> Result<()> {
+        validate_topic(topic)?;
+        self.shared.advertised.lock().unwrap().insert(topic.to_string(), latch_depth);
+        self.send(ControlMessage::Advertise {
+            topic: topic.to_string(),
+            latch_depth,
+        })
+        .await
+    }
+
+    /// Publish an Arrow batch as is
+    pub async fn publish(&self, topic: &str, batch: RecordBatch) -> Result<()> {
+        validate_topic(topic)?;
+        send_batch(&self.outbound, topic, batch).await
+    }
+
+    /// Run `handler` for every batch on a topic matching `pattern` and
+    /// return the subscription id
+    ///
+    /// Handlers run on the connection task, so a slow handler holds back
+    /// delivery to this node. They must not subscribe or unsubscribe
+    /// themselves.
+    pub async fn subscribe<F>(&self, pattern: &str, handler: F) -> Result<u32>
+    where
+        F: FnMut(&Received) + Send + 'static,
+    {
+        self.add_subscription(pattern, Sink::Callback(Box::new(handler))).await
+    }
+
+    /// Subscribe to topics matching `pattern` as a stream of batches
+    ///
+    /// The stream applies backpressure: once `NodeConfig::stream_capacity`
+    /// batches are waiting, the node asks the broker to hold back batches
+    /// until the stream is polled again, and the broker's queue for this
+    /// client fills up instead. Replies of the broker still come through in
+    /// the meantime. An error item means the connection dropped and batches
+    /// may have been missed. Dropping the stream unsubscribes.
+    pub async fn subscribe_stream(&self, pattern: &str) -> Result<SubscriptionStream> {
+        let (sender, receiver) = self.stream_channel();
+        let subscription_id = self.add_subscription(pattern, Sink::Stream(sender)).await?;
+        Ok(SubscriptionStream::new(self.clone(), subscription_id, receiver))
+    }
+
+    /// Subscribe to topics matching `pattern` and decode every row into a `T`
+    ///
+    /// Like `publisher`, this needs the descriptor of `T` to be registered.
+    /// Batches tagged with another message type, or with columns that do not
+    /// fit `T`, show up as errors on the stream.
+    pub async fn subscribe_typed<T>(&self, pattern: &str) -> Result<TypedStream<T>>
+    where
+        T: prost::Message + prost::Name + Default,
+    {
+        let message_type = T::full_name();
+        let handler = self.shared.types.lock().unwrap().handler_for(&message_type)?;
+        let stream = self.subscribe_stream(pattern).await?;
+        Ok(TypedStream::new(stream, handler, message_type))
+    }
+
+    /// Channel feeding a stream subscription
+    pub(crate) fn stream_channel(&self) -> (StreamSender, mpsc::Receiver<Result<Received>>) {
+        mpsc::channel(self.shared.config.stream_capacity.max(1))
+    }
+
+    pub(crate) async fn add_subscription(&self, pattern: &str, sink: Sink) -> Result<u32> {
+        let subscription_id = self.shared.dispatcher.lock().unwrap().add(pattern, sink)?;
+        self.send(ControlMessage::Subscribe {
+            subscription_id,
+            pattern: pattern.to_string(),
+        })
+        .await?;
+        Ok(subscription_id)
+    }
+
+    /// Drop a subscription without waiting, for use in destructors
+    pub(crate) fn forget_subscription(&self, subscription_id: u32) {
+        if self.shared.dispatcher.lock().unwrap().remove(subscription_id) {
+            let _ = self
+                .outbound
+                .try_send(Outbound::Control(ControlMessage::Unsubscribe { subscription_id }));
+        }
+    }
+
+    pub async fn unsubscribe(&self, subscription_id: u32) -> Result<()> {
+        if self.shared.dispatcher.lock().unwrap().remove(subscription_id) {
+            self.send(ControlMessage::Unsubscribe { subscription_id }).await?;
+        }
+        Ok(())
+    }
+
+    /// Ask the broker for every topic that has been published on, along with
+    /// its schema and message type
+    ///
+    /// Fails if the connection drops before the broker answers.
+    pub async fn list_topics(&self) -> Result<Vec<TopicInfo>> {
+        let request_id = self.next_request_id();
+        let (sender, receiver) = oneshot::channel();
+        self.shared.topic_queries.lock().unwrap().insert(request_id, sender);
+        if let Err(e) = self.send(ControlMessage::ListTopics { request_id }).await {
+            self.shared.topic_queries.lock().unwrap().remove(&request_id);
+            return Err(e);
+        }
+        receiver
+            .await
+            .map_err(|_| anyhow!("connection to the broker was lost before it listed the topics"))
+    }
+
+    /// Read a parameter from the broker, or None if it has not been set
+    ///
+    /// Fails if the parameter holds another type than `T`; integers are read
+    /// as floats too.
+    pub async fn get_parameter<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: TryFrom<ParameterValue, Error = anyhow::Error>,
+    {
+        let name = parameter_name(name)?;
+        let request_id = self.next_request_id();
+        let message = ControlMessage::GetParameters {
+            request_id,
+            pattern: name.clone(),
+        };
+        let value = self.parameter_request(request_id, message).await?.into_iter().find(|(x, _)| *x == name);
+        value
+            .map(|(_, value)| T::try_from(value).map_err(|e| e.context(format!("parameter '{}'", name))))
+            .transpose()
+    }
+
+    /// Set a parameter on the broker, which tells every watcher
+    ///
+    /// Fails if the parameter already holds a value of another type.
+    pub async fn set_parameter(&self, name: &str, value: impl Into<ParameterValue>) -> Result<()> {
+        let request_id = self.next_request_id();
+        let message = ControlMessage::SetParameter {
+            request_id,
+            name: parameter_name(name)?,
+            value: value.into(),
+        };
+        self.parameter_request(request_id, message).await?;
+        Ok(())
+    }
+
+    /// Every parameter whose name matches `pattern`, sorted by name
+    pub async fn list_parameters(&self, pattern: &str) -> Result<Vec<(String, ParameterValue)>> {
+        validate_pattern(pattern)?;
+        let request_id = self.next_request_id();
+        let message = ControlMessage::GetParameters {
+            request_id,
+            pattern: pattern.to_string(),
+        };
+        self.parameter_request(request_id, message).await
+    }
+
+    /// Run `handler` with the name and value of every parameter matching
+    /// `pattern` and return the subscription id, which `unsubscribe` takes
+    ///
+    /// The handler first gets the current values, then every change. It runs
+    /// on the connection task like the handlers of `subscribe`.
+    pub async fn watch_parameters<F>(&self, pattern: &str, mut handler: F) -> Result<u32>
+    where
+        F: FnMut(&str, &ParameterValue) + Send + 'static,
+    {
+        validate_pattern(pattern)?;
+        self.subscribe(&parameter_topic(pattern), move |received: &Received| {
+            match parameters_from_batch(&received.batch) {
+                Ok(parameters) => parameters.iter().for_each(|(name, value)| handler(name, value)),
+                Err(e) => log::warn!("ignoring parameter change on '{}': {:#}", received.topic, e),
+            }
+        })
+        .await
+    }
+
+    async fn parameter_request(
+        &self,
+        request_id: u32,
+        message: ControlMessage,
+    ) -> Result<Vec<(String, ParameterValue)>> {
+        let (sender, receiver) = oneshot::channel();
+        self.shared.parameter_queries.lock().unwrap().insert(request_id, sender);
+        if let Err(e) = self.send(message).await {
+            self.shared.parameter_queries.lock().unwrap().remove(&request_id);
+            return Err(e);
+        }
+        receiver
+            .await
+            .map_err(|_| anyhow!("connection to the broker was lost before it answered"))?
+    }
+
+    /// Answer calls to `service` with `handler`
+    ///
+    /// Requests and responses are prost messages whose descriptors must have
+    /// been registered with `register_descriptors`. Every call runs in a task
+    /// of its own, and an error returned by the handler fails the call with
+    /// its message. The broker ignores the advertisement while another node
+    /// provides the service.
+    pub async fn advertise_service<Req, Resp, F, Fut>(&self, service: &str, handler: F) -> Result<()>
+    where
+        Req: prost::Message + prost::Name + Default + 'static,
+        Resp: prost::Message + prost::Name + Default + 'static,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp>> + Send + 'static,
+    {
+        let handler = service_handler(&self.shared.types.lock().unwrap(), handler)?;
+        self.advertise_batch_service(service, handler).await
+    }
+
+    /// Answer calls to `service` with a handler of request and response batches
+    pub(crate) async fn advertise_batch_service(&self, service: &str, handler: ServiceHandler) -> Result<()> {
+        validate_topic(service)?;
+        self.shared.services.lock().unwrap().insert(service.to_string(), handler);
+        self.send(ControlMessage::AdvertiseService {
+            service: service.to_string(),
+        })
+        .await
+    }
+
+    /// Stop answering calls to `service`
+    pub async fn unadvertise_service(&self, service: &str) -> Result<()> {
+        if self.shared.services.lock().unwrap().remove(service).is_some() {
+            self.send(ControlMessage::UnadvertiseService {
+                service: service.to_string(),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Call a service through the broker and wait for its response
+    ///
+    /// Fails if no node provides the service, if the provider fails the call,
+    /// if no response arrives within `timeout` or if the connection drops
+    /// first. Like `publisher`, this needs the descriptors of both message
+    /// types to be registered.
+    pub async fn call<Req, Resp>(&self, service: &str, request: &Req, timeout: Duration) -> Result<Resp>
+    where
+        Req: prost::Message + prost::Name + Default,
+        Resp: prost::Message + prost::Name + Default,
+    {
+        let (requests, responses) = (self.codec::<Req>()?, self.codec::<Resp>()?);
+        let response = self.call_batch(service, requests.encode(request)?, timeout).await?;
+        responses.decode(&response)
+    }
+
+    /// Call a service with a request batch and wait for the response batch
+    pub(crate) async fn call_batch(
+        &self,
+        service: &str,
+        request: RecordBatch,
+        timeout: Duration,
+    ) -> Result<RecordBatch> {
+        validate_topic(service)?;
+        let request = encode_batch(&request)?;
+        let call_id = self.next_request_id();
+        let (sender, receiver) = oneshot::channel();
+        self.shared.calls.lock().unwrap().insert(call_id, sender);
+        let message = ControlMessage::CallService {
+            call_id,
+            service: service.to_string(),
+            timeout_ms: u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX),
+            request,
+        };
+        if let Err(e) = self.send(message).await {
+            self.shared.calls.lock().unwrap().remove(&call_id);
+            return Err(e);
+        }
+        // The broker fails the call once the timeout passes, this only guards
+        // against a broker that stopped answering altogether
+        match tokio::time::timeout(timeout + CALL_TIMEOUT_MARGIN, receiver).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => bail!("connection to the broker was lost before '{}' answered", service),
+            Err(_) => {
+                self.shared.calls.lock().unwrap().remove(&call_id);
+                bail!("service '{}' did not answer within {:?}", service, timeout);
+            }
+        }
+    }
+
+    /// Run goals sent to `action` with `handler`
+    ///
+    /// The handler gets each goal along with a handle to publish feedback on,
+    /// and the goal ends with its result or error. Canceling a goal drops the
+    /// handler's future. The goal, feedback and result types need registered
+    /// descriptors, and the action is reachable through the services and
+    /// topics under its name.
+    pub async fn advertise_action<G, F, R, H, Fut>(&self, action: &str, handler: H) -> Result<()>
+    where
+        G: prost::Message + prost::Name + Default + 'static,
+        F: prost::Message + prost::Name + Default + 'static,
+        R: prost::Message + prost::Name + Default + 'static,
+        H: Fn(G, GoalHandle<F>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+    {
+        for (service, handler) in action_services(self, action, handler)? {
+            self.advertise_batch_service(&service, handler).await?;
+        }
+        Ok(())
+    }
+
+    /// Create a client that sends goals to `action` and follows them
+    pub async fn action_client<G, F, R>(&self, action: &str) -> Result<ActionClient<G, F, R>>
+    where
+        G: prost::Message + prost::Name + Default,
+        F: prost::Message + prost::Name + Default,
+        R: prost::Message + prost::Name + Default,
+    {
+        ActionClient::new(self, action).await
+    }
+
+    /// Converter for messages of type `T`, whose descriptor must be registered
+    pub(crate) fn codec<T: prost::Message + prost::Name + Default>(&self) -> Result<MessageCodec<T>> {
+        MessageCodec::new(&self.shared.types.lock().unwrap())
+    }
+
+    /// Id for a request that is unique for this node
+    pub(crate) fn next_request_id(&self) -> u32 {
+        self.shared.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Say goodbye to the broker and stop the connection for every clone
+    pub async fn close(&self) -> Result<()> {
+        self.send(ControlMessage::Goodbye).await
+    }
+
+    async fn send(&self, message: ControlMessage) -> Result<()> {
+        self.outbound
+            .send(Outbound::Control(message))
+            .await
+            .map_err(|_| anyhow!("node is closed"))
+    }
+}
+
+/// Connect and complete the handshake, returning the assigned client id
+async fn open(config: &NodeConfig) -> Result<(Box<dyn Transport>, u64)> {
+    let mut stream = connect(&Endpoint::parse(&config.broker_addr)?).await?;
+    let hello = ControlMessage::Hello {
+        client_name: config.name.clone(),
+        protocol_version: PROTOCOL_VERSION,
+    };
+    write_frame(&mut stream, &hello.to_frame()?).await?;
+
+    let frame = match tokio::time::timeout(config.handshake_timeout, read_frame(&mut stream)).await {
+        Err(_) => bail!("broker did not answer the handshake within {:?}", config.handshake_timeout),
+        Ok(frame) => frame?.ok_or_else(|| anyhow!("broker closed the connection during the handshake"))?,
+    };
+    match ControlMessage::from_frame(&frame)? {
+        ControlMessage::Welcome { client_id, broker_name } => {
+            log::info!("connected to broker '{}' as client {}", broker_name, client_id);
+            Ok((stream, client_id))
+        }
+        ControlMessage::Reject { reason } => bail!("broker rejected the connection: {}", reason),
+        other => bail!("expected welcome, got {:?}", other),
+    }
+}
+
+/// Why a connection ended
+enum Ended {
+    /// The node was closed or dropped
+    Closed,
+    /// The broker went away
+    Disconnected,
+}
+
+/// Serve connections for the lifetime of the node
+async fn run(shared: Arc<Shared>, queue: mpsc::Receiver<Outbound>, stream: Box<dyn Transport>, client_id: u64) {
+    keep_connected(&shared, queue, stream, client_id).await;
+    // Dropping the sinks ends every stream subscription
+    shared.dispatcher.lock().unwrap().clear();
+    shared.topic_queries.lock().unwrap().clear();
+    shared.parameter_queries.lock().unwrap().clear();
+    shared.calls.lock().unwrap().clear();
+}
+
+/// Serve the current connection and reconnect whenever it drops
+async fn keep_connected(
+    shared: &Arc<Shared>,
+    mut queue: mpsc::Receiver<Outbound>,
+    stream: Box<dyn Transport>,
+    client_id: u64,
+) {
+    let mut connection = Some((stream, client_id));
+    let mut delay = shared.config.reconnect_delay;
+    loop {
+        if let Some((stream, client_id)) = connection.take() {
+            delay = shared.config.reconnect_delay;
+            shared.client_id.store(client_id, Ordering::Relaxed);
+            match serve(shared, &mut queue, stream, client_id).await {
+                Ok(Ended::Closed) => return,
+                Ok(Ended::Disconnected) => log::warn!("broker closed the connection, reconnecting"),
+                Err(e) => log::warn!("lost connection to broker: {:#}, reconnecting", e),
+            }
+            shared.dispatcher.lock().unwrap().notify_disconnected();
+            // Answers to queries sent on the old connection will never come
+            shared.topic_queries.lock().unwrap().clear();
+            shared.parameter_queries.lock().unwrap().clear();
+            shared.calls.lock().unwrap().clear();
+        }
+        // Nobody is left to publish or subscribe
+        if queue.is_closed() {
+            return;
+        }
+
+        tokio::time::sleep(delay).await;
+        match open(&shared.config).await {
+            Ok(x) => connection = Some(x),
+            Err(e) => {
+                log::debug!("reconnect to {} failed: {:#}", shared.config.broker_addr, e);
+                delay = (delay * 2).min(shared.config.max_reconnect_delay);
+            }
+        }
+    }
+}
+
+async fn serve(
+    shared: &Arc<Shared>,
+    queue: &mut mpsc::Receiver<Outbound>,
+    stream: Box<dyn Transport>,
+    client_id: u64,
+) -> Result<Ended> {
+    let (reader, writer) = tokio::io::split(stream);
+    let mut writer = BufWriter::new(writer);
+    let mut encoder = BatchEncoder::new(client_id);
+
+    for message in shared.session_messages() {
+        write_frame(&mut writer, &message.to_frame()?).await?;
+    }
+    writer.flush().await?;
+
+    let (control, mut flow) = mpsc::unbounded_channel();
+    let (deliveries, pending) = mpsc::unbounded_channel();
+    let gate = Arc::new(Gate::new(shared.config.stream_capacity.max(1), control));
+    let mut receiver = tokio::spawn(receive_loop(shared.clone(), reader, deliveries, gate.clone()));
+    let deliverer = tokio::spawn(deliver(shared.clone(), pending, gate));
+    let ended = async {
+        loop {
+            tokio::select! {
+                received = &mut receiver => {
+                    received??;
+                    return Ok(Ended::Disconnected);
+                }
+                Some(message) = flow.recv() => {
+                    write_frame(&mut writer, &message.to_frame()?).await?;
+                    writer.flush().await?;
+                }
+                message = queue.recv() => {
+                    let Some(message) = message else {
+                        write_frame(&mut writer, &ControlMessage::Goodbye.to_frame()?).await?;
+                        writer.shutdown().await?;
+                        return Ok(Ended::Closed);
+                    };
+                    let mut closed = write_outbound(&mut writer, &mut encoder, message).await?;
+                    // Write whatever else is already queued before flushing
+                    while !closed {
+                        let Ok(message) = queue.try_recv() else {
+                            break;
+                        };
+                        closed = write_outbound(&mut writer, &mut encoder, message).await?;
+                    }
+                    writer.flush().await?;
+                    if closed {
+                        writer.shutdown().await?;
+                        return Ok(Ended::Closed);
+                    }
+                }
+            }
+        }
+    }
+    .await;
+    receiver.abort();
+    deliverer.abort();
+    ended
+}
+
+/// Write one queued message, returning true if it closes the session
+async fn write_outbound(
+    writer: &mut BufWriter<Writer>,
+    encoder: &mut BatchEncoder,
+    message: Outbound,
+) -> Result<bool> {
+    match message {
+        Outbound::Batch {
+            topic,
+            timestamp_ns,
+            batch,
+        } => {
+            for frame in encoder.encode(&topic, &batch, timestamp_ns, &[])? {
+                write_frame(writer, &frame).await?;
+            }
+            Ok(false)
+        }
+        Outbound::Control(message) => {
+            write_frame(writer, &message.to_frame()?).await?;
+            Ok(message == ControlMessage::Goodbye)
+        }
+    }
+}
+
+/// A batch read from the broker along with the subscriptions it was tagged with
+type Delivery = (Vec<u32>, Received);
+
+/// Asks the broker to hold back batches while too many wait to be delivered
+///
+/// Reading never stops, so replies of the broker are never stuck behind a
+/// full stream; the held back batches fill the broker's queue for this client
+/// instead, where its overflow policy applies.
+struct Gate {
+    /// Batches read but not delivered yet, and whether the broker was paused
+    state: Mutex<(usize, bool)>,
+    limit: usize,
+    control: mpsc::UnboundedSender<ControlMessage>,
+}
+
+impl Gate {
+    fn new(limit: usize, control: mpsc::UnboundedSender<ControlMessage>) -> Self {
+        Self {
+            state: Mutex::new((0, false)),
+            limit,
+            control,
+        }
+    }
+
+    fn received(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        if state.0 >= self.limit && !state.1 {
+            state.1 = true;
+            let _ = self.control.send(ControlMessage::PauseBatches);
+        }
+    }
+
+    fn delivered(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 -= 1;
+        if state.0 == 0 && state.1 {
+            state.1 = false;
+            let _ = self.control.send(ControlMessage::ResumeBatches);
+        }
+    }
+}
+
+/// Hand batches to the subscriptions in the order they were read
+///
+/// Waiting on a full stream holds back the batches after it, and the gate
+/// then has the broker hold back the rest.
+async fn deliver(shared: Arc<Shared>, mut pending: mpsc::UnboundedReceiver<Delivery>, gate: Arc<Gate>) {
+    while let Some((subscription_ids, received)) = pending.recv().await {
+        let streams = shared.dispatcher.lock().unwrap().dispatch(&subscription_ids, &received);
+        for (subscription_id, sender) in streams {
+            if sender.send(Ok(received.clone())).await.is_err() {
+                shared.dispatcher.lock().unwrap().remove(subscription_id);
+            }
+        }
+        gate.delivered();
+    }
+}
+
+/// Read frames from the broker, answering replies right away and queueing
+/// batches for delivery
+async fn receive_loop(
+    shared: Arc<Shared>,
+    mut reader: Reader,
+    deliveries: mpsc::UnboundedSender<Delivery>,
+    gate: Arc<Gate>,
+) -> Result<()> {
+    let mut decoder = BatchDecoder::new();
+    while let Some(frame) = read_frame(&mut reader).await? {
+        match frame.header.kind {
+            FrameKind::Control => match ControlMessage::from_frame(&frame)? {
+                ControlMessage::Goodbye => return Ok(()),
+                ControlMessage::TopicList { request_id, topics } => {
+                    if let Some(sender) = shared.topic_queries.lock().unwrap().remove(&request_id) {
+                        let _ = sender.send(topics);
+                    }
+                }
+                ControlMessage::ParameterList { request_id, parameters } => {
+                    if let Some(sender) = shared.parameter_queries.lock().unwrap().remove(&request_id) {
+                        let _ = sender.send(Ok(parameters));
+                    }
+                }
+                ControlMessage::ParameterError { request_id, reason } => {
+                    if let Some(sender) = shared.parameter_queries.lock().unwrap().remove(&request_id) {
+                        let _ = sender.send(Err(anyhow!(reason)));
+                    }
+                }
+                ControlMessage::CallService {
+                    call_id,
+                    service,
+                    request,
+                    ..
+                } => answer_call(&shared, call_id, service, request),
+                ControlMessage::ServiceResponse { call_id, response } => {
+                    if let Some(sender) = shared.calls.lock().unwrap().remove(&call_id) {
+                        let _ = sender.send(decode_batch(&response));
+                    }
+                }
+                ControlMessage::ServiceError { call_id, reason } => {
+                    if let Some(sender) = shared.calls.lock().unwrap().remove(&call_id) {
+                        let _ = sender.send(Err(anyhow!(reason)));
+                    }
+                }
+                ControlMessage::PublishRejected { topic, reason } => {
+                    log::warn!("broker refused batches published on '{}': {}", topic, reason)
+                }
+                other => log::warn!("broker sent unexpected {:?}", other),
+            },
+            FrameKind::Schema | FrameKind::Batch => {
+                let header = frame.header;
+                let Some(batch) = decoder.decode(&header, frame.payload)? else {
+                    continue;
+                };
+                let received = Received {
+                    topic: header.topic,
+                    publisher_id: header.publisher_id,
+                    timestamp_ns: header.timestamp_ns,
+                    batch,
+                };
+                gate.received();
+                let _ = deliveries.send((header.subscription_ids, received));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the handler of a service for a call forwarded by the broker and send
+/// back its response, without holding up the receive loop
+fn answer_call(shared: &Arc<Shared>, call_id: u32, service: String, request: Vec<u8>) {
+    let handler = shared.services.lock().unwrap().get(&service).cloned();
+    let outbound = shared.outbound.clone();
+    tokio::spawn(async move {
+        let response = match handler {
+            Some(handler) => match decode_batch(&request) {
+                Ok(request) => handler(request).await.and_then(|x| encode_batch(&x)),
+                Err(e) => Err(e),
+            },
+            None => Err(anyhow!("this node no longer provides it")),
+        };
+        let answer = match response {
+            Ok(response) => ControlMessage::ServiceResponse { call_id, response },
+            Err(e) => ControlMessage::ServiceError {
+                call_id,
+                reason: format!("service '{}' failed: {:#}", service, e),
+            },
+        };
+        if let Some(outbound) = outbound.upgrade() {
+            let _ = outbound.send(Outbound::Control(answer)).await;
+        }
+    });
+}
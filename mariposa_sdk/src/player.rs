@@ -0,0 +1,140 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::node::Node;
+use mariposa_core::logfile::{LogEntry, LogReader};
+use mariposa_core::topic::{pattern_matches, validate_pattern, validate_topic};
+
+/// Entries read ahead of playback
+const READ_AHEAD: usize = 16;
+
+/// How to play a recording back
+#[derive(Debug, Clone)]
+pub struct PlayerConfig {
+    /// Speed relative to the recording, 2.0 playing twice as fast. `None`
+    /// publishes as fast as possible
+    pub rate: Option<f64>,
+    /// How far into the recording to start, from its first message
+    pub start: Duration,
+    /// How far into the recording to stop. `None` plays to the end
+    pub end: Option<Duration>,
+    /// Only play topics matching one of these patterns; empty plays everything
+    pub topics: Vec<String>,
+    /// Publish recorded topics under other names
+    pub remap: HashMap<String, String>,
+    /// Start over once the end is reached
+    pub looped: bool,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            rate: Some(1.0),
+            start: Duration::ZERO,
+            end: None,
+            topics: Vec::new(),
+            remap: HashMap::new(),
+            looped: false,
+        }
+    }
+}
+
+impl PlayerConfig {
+    fn plays(&self, topic: &str) -> bool {
+        self.topics.is_empty() || self.topics.iter().any(|x| pattern_matches(x, topic))
+    }
+}
+
+impl Node {
+    /// Republish a recording made by a `Recorder` or the broker, keeping the
+    /// time between messages, and return the number of batches published
+    ///
+    /// With `PlayerConfig::looped` this only returns on error; drop the
+    /// future to stop it.
+    pub async fn play(&self, path: &Path, config: PlayerConfig) -> Result<u64> {
+        if let Some(rate) = config.rate {
+            if !(rate.is_finite() && rate > 0.0) {
+                bail!("invalid playback rate {}", rate);
+            }
+        }
+        for pattern in &config.topics {
+            validate_pattern(pattern)?;
+        }
+        for topic in config.remap.values() {
+            validate_topic(topic)?;
+        }
+
+        let mut published = 0;
+        loop {
+            let played = self.play_once(path, &config).await?;
+            published += played;
+            // Nothing in the window, looping would only spin
+            if !config.looped || played == 0 {
+                return Ok(published);
+            }
+        }
+    }
+
+    async fn play_once(&self, path: &Path, config: &PlayerConfig) -> Result<u64> {
+        let (sender, mut receiver) = mpsc::channel(READ_AHEAD);
+        let reader_config = config.clone();
+        let path = path.to_path_buf();
+        std::thread::spawn(move || read_log(path, reader_config, sender));
+
+        let mut published = 0;
+        let mut started: Option<(Instant, i64)> = None;
+        while let Some(entry) = receiver.recv().await {
+            let entry = entry?;
+            if let Some(rate) = config.rate {
+                let (wall, log) = *started.get_or_insert((Instant::now(), entry.log_time_ns));
+                let offset = Duration::from_nanos(entry.log_time_ns.saturating_sub(log).max(0) as u64);
+                tokio::time::sleep_until(wall + offset.div_f64(rate)).await;
+            }
+            let topic = config.remap.get(&entry.topic).unwrap_or(&entry.topic);
+            self.publish(topic, entry.batch).await?;
+            published += 1;
+        }
+        Ok(published)
+    }
+}
+
+/// Feed the entries of a recording within the configured window to the player
+///
+/// Runs on its own thread since reading the files blocks.
+fn read_log(path: PathBuf, config: PlayerConfig, sender: mpsc::Sender<Result<LogEntry>>) {
+    let mut reader = match LogReader::open(&path) {
+        Ok(reader) => reader,
+        Err(e) => {
+            let _ = sender.blocking_send(Err(e));
+            return;
+        }
+    };
+    let Some(first) = reader.peek_time() else {
+        return;
+    };
+    let start = first.saturating_add(config.start.as_nanos() as i64);
+    let end = config.end.map(|x| first.saturating_add(x.as_nanos() as i64));
+
+    for entry in reader.by_ref() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let _ = sender.blocking_send(Err(e));
+                return;
+            }
+        };
+        if entry.log_time_ns < start || !config.plays(&entry.topic) {
+            continue;
+        }
+        if end.is_some_and(|x| entry.log_time_ns > x) {
+            return;
+        }
+        if sender.blocking_send(Ok(entry)).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use std::marker::PhantomData;
+use tokio::sync::mpsc;
+
+use crate::node::{send_batch, Outbound};
+use mariposa_core::ptars::{with_message_type, MessageHandler};
+
+/// Publishes prost messages of type `T` on one topic
+///
+/// Messages are converted to Arrow rows with `mariposa_core::ptars` and the
+/// batch schema is tagged with the message's full protobuf name.
+pub struct Publisher<T> {
+    topic: String,
+    message_type: String,
+    handler: MessageHandler,
+    outbound: mpsc::Sender<Outbound>,
+    _message: PhantomData<fn(&T)>,
+}
+
+impl<T: prost::Message> Publisher<T> {
+    pub(crate) fn new(topic: &str, message_type: String, handler: MessageHandler, outbound: mpsc::Sender<Outbound>) -> Self {
+        Self {
+            topic: topic.to_string(),
+            message_type,
+            handler,
+            outbound,
+            _message: PhantomData,
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Publish a single message as a one-row batch
+    pub async fn publish(&self, message: &T) -> Result<()> {
+        self.publish_all(std::slice::from_ref(message)).await
+    }
+
+    /// Publish several messages as one batch, one row per message
+    pub async fn publish_all(&self, messages: &[T]) -> Result<()> {
+        let encoded: Vec<Vec<u8>> = messages.iter().map(|x| x.encode_to_vec()).collect();
+        let batch = self.handler.slice_to_record_batch(&encoded);
+        let batch = with_message_type(batch, &self.message_type)?;
+        send_batch(&self.outbound, &self.topic, batch).await
+    }
+}
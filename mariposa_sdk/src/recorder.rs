@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::dispatch::Sink;
+use crate::node::{now_ns, Node};
+use mariposa_core::logfile::{BackgroundLogWriter, LogConfig, LogEntry, LogWriter};
+
+/// Batches waiting to be written before newer ones are dropped
+const QUEUE_LEN: usize = 1024;
+
+/// Records the topics a node subscribes to into Arrow IPC log files, see
+/// `mariposa_core::logfile`
+///
+/// Batches are written on a thread of their own; if the disk cannot keep up
+/// they are dropped and counted rather than slowing the subscription down.
+pub struct Recorder {
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<BackgroundLogWriter>,
+}
+
+impl Recorder {
+    /// Stop recording, close every file and return the segment directories
+    pub async fn stop(mut self) -> Result<Vec<PathBuf>> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        let writer = (&mut self.task).await?;
+        if writer.dropped() > 0 {
+            log::warn!("recorder dropped {} batches", writer.dropped());
+        }
+        tokio::task::spawn_blocking(move || writer.finish()).await?
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // The task then drops the writer, whose thread closes the files
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+impl Node {
+    /// Record every topic matching one of `patterns`
+    ///
+    /// The patterns share a single stream, so a batch on a topic matching
+    /// several of them is still recorded once.
+    pub async fn record(&self, patterns: &[&str], config: LogConfig) -> Result<Recorder> {
+        if patterns.is_empty() {
+            return Err(anyhow!("nothing to record"));
+        }
+        let writer = LogWriter::create(config)?;
+        let (sender, mut received) = self.stream_channel();
+        let mut subscription_ids = Vec::with_capacity(patterns.len());
+        for pattern in patterns {
+            match self.add_subscription(pattern, Sink::Stream(sender.clone())).await {
+                Ok(id) => subscription_ids.push(id),
+                Err(e) => {
+                    for id in subscription_ids {
+                        self.forget_subscription(id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let (stop, mut stopped) = oneshot::channel();
+        let node = self.clone();
+        let task = tokio::spawn(async move {
+            let writer = BackgroundLogWriter::spawn(writer, QUEUE_LEN);
+            loop {
+                let item = tokio::select! {
+                    _ = &mut stopped => break,
+                    item = received.recv() => item,
+                };
+                match item {
+                    Some(Ok(x)) => {
+                        writer.try_write(LogEntry {
+                            topic: x.topic,
+                            publisher_id: x.publisher_id,
+                            publish_time_ns: x.timestamp_ns,
+                            log_time_ns: now_ns(),
+                            batch: x.batch,
+                        });
+                    }
+                    Some(Err(e)) => log::warn!("recorder missed batches: {:#}", e),
+                    None => break,
+                }
+            }
+            for id in subscription_ids {
+                node.forget_subscription(id);
+            }
+            writer
+        });
+        Ok(Recorder { stop: Some(stop), task })
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use arrow::record_batch::RecordBatch;
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use mariposa_core::ptars::{message_type, with_message_type, MessageHandler, ProtoCache};
+
+/// Answers one service call, taking the request batch and producing the
+/// response batch
+pub(crate) type ServiceHandler = Arc<dyn Fn(RecordBatch) -> BoxFuture<'static, Result<RecordBatch>> + Send + Sync>;
+
+/// Converts prost messages of type `T` to and from one-row batches
+pub(crate) struct MessageCodec<T> {
+    handler: MessageHandler,
+    message_type: String,
+    _message: PhantomData<fn(T) -> T>,
+}
+
+impl<T: prost::Message + prost::Name + Default> MessageCodec<T> {
+    /// Look up the descriptor of `T`, which must have been registered
+    pub(crate) fn new(types: &ProtoCache) -> Result<Self> {
+        let message_type = T::full_name();
+        Ok(Self {
+            handler: types.handler_for(&message_type)?,
+            message_type,
+            _message: PhantomData,
+        })
+    }
+
+    /// Encode a message as a batch tagged with its type
+    pub(crate) fn encode(&self, message: &T) -> Result<RecordBatch> {
+        let batch = self.handler.slice_to_record_batch(&[message.encode_to_vec()]);
+        with_message_type(batch, &self.message_type)
+    }
+
+    /// Decode the single row of a batch, refusing batches tagged with
+    /// another type
+    pub(crate) fn decode(&self, batch: &RecordBatch) -> Result<T> {
+        if let Some(name) = message_type(&batch.schema()) {
+            if name != self.message_type {
+                bail!("expected a '{}' message, got '{}'", self.message_type, name);
+            }
+        }
+        ensure!(batch.num_rows() == 1, "expected one '{}' message, got {}", self.message_type, batch.num_rows());
+        let payload = self.handler.record_batch_to_array(batch).remove(0);
+        T::decode(payload.as_slice()).map_err(|e| anyhow!("failed to decode '{}': {}", self.message_type, e))
+    }
+}
+
+/// Wrap a handler of prost messages into one of batches
+pub(crate) fn service_handler<Req, Resp, F, Fut>(types: &ProtoCache, handler: F) -> Result<ServiceHandler>
+where
+    Req: prost::Message + prost::Name + Default + 'static,
+    Resp: prost::Message + prost::Name + Default + 'static,
+    F: Fn(Req) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Resp>> + Send + 'static,
+{
+    let requests = MessageCodec::<Req>::new(types)?;
+    let responses = Arc::new(MessageCodec::<Resp>::new(types)?);
+    Ok(Arc::new(move |batch| match requests.decode(&batch) {
+        Ok(request) => {
+            let response = handler(request);
+            let responses = responses.clone();
+            Box::pin(async move { responses.encode(&response.await?) })
+        }
+        Err(e) => Box::pin(std::future::ready(Err(e))),
+    }))
+}
@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Result};
+use arrow::record_batch::RecordBatch;
+use futures::Stream;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::dispatch::Received;
+use crate::node::{now_ns, send_batch, Node};
+use crate::stream::SubscriptionStream;
+use mariposa_core::shm::{ring_path, ShmReader, ShmWriter};
+use mariposa_core::topic::validate_topic;
+
+/// How long the reader thread sleeps before checking whether it is still needed
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Publishes batches on one topic through a shared-memory ring
+///
+/// Local subscribers read the batches straight from the ring. With
+/// `NodeConfig::shm_forward` set, every batch also goes to the broker so
+/// nodes on other hosts get it over TCP.
+pub struct ShmPublisher {
+    node: Node,
+    topic: String,
+    writer: Mutex<ShmWriter>,
+}
+
+impl ShmPublisher {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Fails while local subscribers still hold batches from the part of the
+    /// ring this one would reuse
+    pub async fn publish(&self, batch: RecordBatch) -> Result<()> {
+        let publisher_id = self.node.client_id();
+        self.writer.lock().unwrap().write(&self.topic, publisher_id, now_ns(), &batch)?;
+        if self.node.config().shm_forward {
+            send_batch(self.node.outbound(), &self.topic, batch).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Batches on one topic, read from a shared-memory ring when a publisher on
+/// this host has one and from the broker otherwise
+///
+/// Batches read from a ring point straight into shared memory, and the
+/// publisher cannot reuse that part of the ring while they are alive, so copy
+/// the ones kept around for longer. An error item means batches were skipped
+/// because the subscriber fell behind, or the connection to the broker dropped.
+pub enum LocalSubscription {
+    Shm(mpsc::Receiver<Result<Received>>),
+    Tcp(SubscriptionStream),
+}
+
+impl LocalSubscription {
+    pub fn is_shared_memory(&self) -> bool {
+        matches!(self, LocalSubscription::Shm(_))
+    }
+}
+
+impl Stream for LocalSubscription {
+    type Item = Result<Received>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            LocalSubscription::Shm(receiver) => receiver.poll_recv(cx),
+            LocalSubscription::Tcp(stream) => Pin::new(stream).poll_next(cx),
+        }
+    }
+}
+
+impl Node {
+    /// Create a shared-memory publisher on `topic`
+    ///
+    /// Needs `NodeConfig::shm_dir`, and fails if another publisher on this
+    /// host already owns the topic's ring.
+    pub fn shm_publisher(&self, topic: &str) -> Result<ShmPublisher> {
+        validate_topic(topic)?;
+        let dir = self
+            .config()
+            .shm_dir
+            .as_ref()
+            .ok_or_else(|| anyhow!("shared memory is not configured for this node"))?;
+        let writer = ShmWriter::create(&ring_path(dir, topic), self.config().shm_capacity)?;
+        Ok(ShmPublisher {
+            node: self.clone(),
+            topic: topic.to_string(),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Subscribe to a single topic, through shared memory if a publisher on
+    /// this host has already created its ring and through the broker otherwise
+    pub async fn subscribe_local(&self, topic: &str) -> Result<LocalSubscription> {
+        validate_topic(topic)?;
+        if let Some(dir) = &self.config().shm_dir {
+            match open_ring(&ring_path(dir, topic)) {
+                Ok(Some(reader)) => {
+                    let (sender, receiver) = mpsc::channel(self.config().stream_capacity.max(1));
+                    std::thread::spawn(move || read_ring(reader, sender));
+                    return Ok(LocalSubscription::Shm(receiver));
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("falling back to TCP for '{}': {:#}", topic, e),
+            }
+        }
+        Ok(LocalSubscription::Tcp(self.subscribe_stream(topic).await?))
+    }
+}
+
+fn open_ring(path: &Path) -> Result<Option<ShmReader>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let reader = ShmReader::open(path)?;
+    Ok(if reader.writer_closed()? { None } else { Some(reader) })
+}
+
+/// Feed the records of a ring to a subscription until either side goes away
+///
+/// Runs on its own thread since waiting on the ring blocks.
+fn read_ring(mut reader: ShmReader, sender: mpsc::Sender<Result<Received>>) {
+    while !sender.is_closed() {
+        let item = match reader.read() {
+            Ok(Some(record)) => Ok(Received {
+                topic: record.topic,
+                publisher_id: record.publisher_id,
+                timestamp_ns: record.timestamp_ns,
+                batch: record.batch,
+            }),
+            Ok(None) => {
+                if !reader.wait(POLL_INTERVAL) && reader.writer_closed().unwrap_or(true) {
+                    return;
+                }
+                continue;
+            }
+            Err(e) => Err(e),
+        };
+        if sender.blocking_send(item).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use futures::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+use crate::dispatch::Received;
+use crate::node::Node;
+
+/// Batches of a subscription as a `futures::Stream`
+///
+/// Ends when the node is closed. Dropping it removes the subscription.
+pub struct SubscriptionStream {
+    node: Node,
+    subscription_id: u32,
+    receiver: mpsc::Receiver<Result<Received>>,
+}
+
+impl SubscriptionStream {
+    pub(crate) fn new(node: Node, subscription_id: u32, receiver: mpsc::Receiver<Result<Received>>) -> Self {
+        Self {
+            node,
+            subscription_id,
+            receiver,
+        }
+    }
+
+    pub fn subscription_id(&self) -> u32 {
+        self.subscription_id
+    }
+}
+
+impl Stream for SubscriptionStream {
+    type Item = Result<Received>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for SubscriptionStream {
+    fn drop(&mut self) {
+        self.node.forget_subscription(self.subscription_id);
+    }
+}
@@ -0,0 +1,993 @@
+use arrow::array::{ArrayRef, AsArray, Float64Array, Int32Array, StringArray, UInt64Array};
+use arrow::datatypes::UInt64Type;
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
+use futures::StreamExt;
+use prost::{Message, Name};
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::dispatch::{Dispatcher, Sink};
+use crate::service::ServiceHandler;
+use crate::{
+    GoalHandle, GoalState, LogConfig, Node, NodeConfig, ParameterValue, PlayerConfig, Received, UdpSubscription,
+    GOAL_ID_COLUMN, SEQUENCE_COLUMN,
+};
+use mariposa_broker::{Broker, BrokerConfig, OverflowPolicy};
+use mariposa_core::logfile::{LogEntry, LogWriter, TOPIC_KEY};
+use mariposa_core::ptars::{message_type, with_message_type, ProtoCache};
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Speed {
+    #[prost(int32, tag = "1")]
+    id: i32,
+    #[prost(double, tag = "2")]
+    speed_kph: f64,
+}
+
+impl prost::Name for Speed {
+    const NAME: &'static str = "Speed";
+    const PACKAGE: &'static str = "mariposa.test";
+}
+
+/// Descriptor set for `Speed`, as prost-build would write it
+fn speed_descriptor_set() -> Vec<u8> {
+    let field = |name: &str, number: i32, field_type: Type| FieldDescriptorProto {
+        name: Some(name.to_string()),
+        number: Some(number),
+        label: Some(Label::Optional as i32),
+        r#type: Some(field_type as i32),
+        ..Default::default()
+    };
+    let file = FileDescriptorProto {
+        name: Some("speed.proto".to_string()),
+        package: Some("mariposa.test".to_string()),
+        syntax: Some("proto3".to_string()),
+        message_type: vec![DescriptorProto {
+            name: Some("Speed".to_string()),
+            field: vec![field("id", 1, Type::Int32), field("speed_kph", 2, Type::Double)],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    FileDescriptorSet { file: vec![file] }.encode_to_vec()
+}
+
+async fn start_broker(bind_addr: &str) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    start_broker_with(BrokerConfig {
+        bind_addr: bind_addr.to_string(),
+        ..BrokerConfig::default()
+    })
+    .await
+}
+
+async fn start_broker_with(config: BrokerConfig) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    let broker = Broker::bind(config).await.unwrap();
+    let addr = broker.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        broker
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+    });
+    (addr, stop, task)
+}
+
+async fn connect(addr: SocketAddr, name: &str) -> Node {
+    Node::connect_with(NodeConfig {
+        broker_addr: addr.to_string(),
+        name: name.to_string(),
+        reconnect_delay: Duration::from_millis(20),
+        ..NodeConfig::default()
+    })
+    .await
+    .unwrap()
+}
+
+/// Subscribe with a handler that forwards every batch to a channel
+async fn subscribe_channel(node: &Node, pattern: &str) -> mpsc::UnboundedReceiver<Received> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    node.subscribe(pattern, move |received: &Received| {
+        let _ = sender.send(received.clone());
+    })
+    .await
+    .unwrap();
+    receiver
+}
+
+async fn next(receiver: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+    tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("timed out waiting for a batch")
+        .unwrap()
+}
+
+fn id_batch(id: i32) -> RecordBatch {
+    let ids = Arc::new(Int32Array::from(vec![id])) as _;
+    RecordBatch::try_from_iter([("id", ids)]).unwrap()
+}
+
+#[tokio::test]
+async fn test_typed_publish_reaches_subscriber() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let subscriber = connect(addr, "tracker").await;
+    let mut received = subscribe_channel(&subscriber, "vehicle/*").await;
+    // Once our own publish comes back the subscription is active
+    subscriber.publish("vehicle/sync", id_batch(0)).await.unwrap();
+    assert_eq!(next(&mut received).await.topic, "vehicle/sync");
+
+    let node = connect(addr, "camera").await;
+    assert!(node.publisher::<Speed>("vehicle/speed").is_err());
+    node.register_descriptors(&speed_descriptor_set()).unwrap();
+    let publisher = node.publisher::<Speed>("vehicle/speed").unwrap();
+    let messages = vec![
+        Speed { id: 1, speed_kph: 42.5 },
+        Speed { id: 2, speed_kph: 88.0 },
+    ];
+    publisher.publish_all(&messages).await.unwrap();
+
+    let batch = next(&mut received).await;
+    assert_eq!(batch.topic, "vehicle/speed");
+    assert_eq!(batch.publisher_id, node.client_id());
+    assert_eq!(message_type(&batch.batch.schema()), Some("mariposa.test.Speed"));
+
+    let mut cache = ProtoCache::new();
+    cache.add_descriptor_set(&speed_descriptor_set()).unwrap();
+    let decoded: Vec<Speed> = cache
+        .handler_for("mariposa.test.Speed")
+        .unwrap()
+        .record_batch_to_array(&batch.batch)
+        .iter()
+        .map(|x| Speed::decode(x.as_slice()).unwrap())
+        .collect();
+    assert_eq!(decoded, messages);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reconnect_restores_subscriptions() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let subscriber = connect(addr, "tracker").await;
+    let mut received = subscribe_channel(&subscriber, "mission/state").await;
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+    let (_, stop, task) = start_broker(&addr.to_string()).await;
+
+    // Keep publishing until the subscriber is back and subscribed again
+    let publisher = connect(addr, "planner").await;
+    let batch = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            publisher.publish("mission/state", id_batch(7)).await.unwrap();
+            tokio::select! {
+                batch = received.recv() => return batch.unwrap(),
+                _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+            }
+        }
+    })
+    .await
+    .expect("subscriber did not reconnect");
+    assert_eq!(batch.batch, id_batch(7));
+
+    subscriber.close().await.unwrap();
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_streams_select_across_topics() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let subscriber = connect(addr, "tracker").await;
+    let mut speeds = subscriber.subscribe_stream("vehicle/speed").await.unwrap();
+    let mut plates = subscriber.subscribe_stream("vehicle/plate").await.unwrap();
+    subscriber.publish("vehicle/speed", id_batch(0)).await.unwrap();
+    subscriber.publish("vehicle/plate", id_batch(0)).await.unwrap();
+    assert_eq!(speeds.next().await.unwrap().unwrap().batch, id_batch(0));
+    assert_eq!(plates.next().await.unwrap().unwrap().batch, id_batch(0));
+
+    let publisher = connect(addr, "camera").await;
+    publisher.publish("vehicle/plate", id_batch(1)).await.unwrap();
+    publisher.publish("vehicle/speed", id_batch(2)).await.unwrap();
+    let mut seen = Vec::new();
+    while seen.len() < 2 {
+        tokio::select! {
+            Some(received) = speeds.next() => seen.push(received.unwrap().topic),
+            Some(received) = plates.next() => seen.push(received.unwrap().topic),
+        }
+    }
+    seen.sort();
+    assert_eq!(seen, vec!["vehicle/plate", "vehicle/speed"]);
+
+    // Combinators work as on any other stream. Batches queued together at the
+    // broker may arrive merged, so flatten them into rows
+    for id in 3..6 {
+        publisher.publish("vehicle/speed", id_batch(id)).await.unwrap();
+    }
+    let ids: Vec<i32> = speeds
+        .by_ref()
+        .flat_map(|x| {
+            let batch = x.unwrap().batch;
+            let ids = batch.column(0).as_any().downcast_ref::<Int32Array>().unwrap().clone();
+            futures::stream::iter(ids.values().to_vec())
+        })
+        .take(3)
+        .collect()
+        .await;
+    assert_eq!(ids, vec![3, 4, 5]);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_full_streams_do_not_hold_back_replies() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let node = Node::connect_with(NodeConfig {
+        broker_addr: addr.to_string(),
+        name: "tracker".to_string(),
+        stream_capacity: 1,
+        ..NodeConfig::default()
+    })
+    .await
+    .unwrap();
+
+    // Nobody polls this stream while batches keep coming
+    let mut speeds = node.subscribe_stream("vehicle/speed").await.unwrap();
+    for id in 0..10 {
+        node.publish("vehicle/speed", id_batch(id)).await.unwrap();
+    }
+    let topics = tokio::time::timeout(Duration::from_secs(5), node.list_topics()).await.unwrap();
+    assert_eq!(topics.unwrap().len(), 1);
+    assert!(speeds.next().await.unwrap().is_ok());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_full_streams_fill_the_broker_queue() {
+    let (addr, stop, task) = start_broker_with(BrokerConfig {
+        bind_addr: "127.0.0.1:0".to_string(),
+        client_queue_len: 4,
+        overflow_policy: OverflowPolicy::DropNewest,
+        ..BrokerConfig::default()
+    })
+    .await;
+    let subscriber = Node::connect_with(NodeConfig {
+        broker_addr: addr.to_string(),
+        name: "tracker".to_string(),
+        stream_capacity: 1,
+        ..NodeConfig::default()
+    })
+    .await
+    .unwrap();
+    let mut speeds = subscriber.subscribe_stream("vehicle/speed").await.unwrap();
+    subscriber.list_topics().await.unwrap();
+
+    // Answered once every batch before it has been queued for the subscriber
+    let publisher = connect(addr, "camera").await;
+    for id in 0..50 {
+        publisher.publish("vehicle/speed", id_batch(id)).await.unwrap();
+    }
+    publisher.list_topics().await.unwrap();
+
+    let mut ids = Vec::new();
+    while let Ok(Some(received)) = tokio::time::timeout(Duration::from_millis(200), speeds.next()).await {
+        let column = received.unwrap().batch.column(0).clone();
+        ids.push(column.as_any().downcast_ref::<Int32Array>().unwrap().value(0));
+    }
+    // The broker dropped the newest batches whenever its queue was full, and
+    // the ones it kept arrive in order
+    assert!(!ids.is_empty() && ids.len() < 50, "{:?}", ids);
+    assert!(ids.windows(2).all(|x| x[0] < x[1]), "{:?}", ids);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_stream_ends_when_node_closes() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let node = connect(addr, "tracker").await;
+    let mut stream = node.subscribe_stream("vehicle/*").await.unwrap();
+    node.close().await.unwrap();
+    let ended = tokio::time::timeout(Duration::from_secs(5), stream.next()).await.unwrap();
+    assert!(ended.is_none());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Plate {
+    #[prost(string, tag = "1")]
+    plate: String,
+}
+
+impl prost::Name for Plate {
+    const NAME: &'static str = "Plate";
+    const PACKAGE: &'static str = "mariposa.test";
+}
+
+#[tokio::test]
+async fn test_typed_subscription_decodes_rows() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let subscriber = connect(addr, "tracker").await;
+    assert!(subscriber.subscribe_typed::<Speed>("vehicle/speed").await.is_err());
+    subscriber.register_descriptors(&speed_descriptor_set()).unwrap();
+    let mut speeds = subscriber.subscribe_typed::<Speed>("vehicle/speed").await.unwrap();
+
+    let publisher = subscriber.publisher::<Speed>("vehicle/speed").unwrap();
+    let messages = vec![
+        Speed { id: 1, speed_kph: 42.5 },
+        Speed { id: 2, speed_kph: 88.0 },
+    ];
+    publisher.publish_all(&messages).await.unwrap();
+    publisher.publish(&Speed { id: 3, speed_kph: 12.0 }).await.unwrap();
+
+    let mut decoded = Vec::new();
+    while decoded.len() < 3 {
+        decoded.push(speeds.next().await.unwrap().unwrap());
+    }
+    assert_eq!(&decoded[..2], &messages[..]);
+    assert_eq!(decoded[2], Speed { id: 3, speed_kph: 12.0 });
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_typed_subscription_rejects_other_types() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let subscriber = connect(addr, "tracker").await;
+    subscriber.register_descriptors(&speed_descriptor_set()).unwrap();
+    // The broker holds each topic to one schema, so every case gets its own
+    let mut speeds = subscriber.subscribe_typed::<Speed>("vehicle/#").await.unwrap();
+
+    // A batch tagged with another message type
+    let plates = Arc::new(StringArray::from(vec!["MRP-7"])) as _;
+    let batch = RecordBatch::try_from_iter([("plate", plates)]).unwrap();
+    let batch = with_message_type(batch, Plate::full_name().as_str()).unwrap();
+    subscriber.publish("vehicle/plate", batch).await.unwrap();
+    let error = speeds.next().await.unwrap().unwrap_err().to_string();
+    assert!(error.contains("mariposa.test.Plate"), "{}", error);
+
+    // An untagged batch whose columns do not fit
+    let speed = Arc::new(StringArray::from(vec!["fast"])) as _;
+    let batch = RecordBatch::try_from_iter([("speed_kph", speed)]).unwrap();
+    subscriber.publish("vehicle/label", batch).await.unwrap();
+    let error = speeds.next().await.unwrap().unwrap_err().to_string();
+    assert!(error.contains("speed_kph"), "{}", error);
+
+    // An untagged batch with a column the message does not have
+    let ids = Arc::new(Int32Array::from(vec![4])) as _;
+    let plates = Arc::new(StringArray::from(vec!["MRP-7"])) as _;
+    let batch = RecordBatch::try_from_iter([("id", ids), ("plate", plates)]).unwrap();
+    subscriber.publish("vehicle/tagged", batch).await.unwrap();
+    let error = speeds.next().await.unwrap().unwrap_err().to_string();
+    assert!(error.contains("'plate' on topic 'vehicle/tagged' is not a field"), "{}", error);
+
+    // An untagged batch that does fit is decoded, missing fields take defaults
+    subscriber.publish("vehicle/speed", id_batch(9)).await.unwrap();
+    assert_eq!(speeds.next().await.unwrap().unwrap(), Speed { id: 9, speed_kph: 0.0 });
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_list_topics_reports_types() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let node = connect(addr, "tracker").await;
+    node.register_descriptors(&speed_descriptor_set()).unwrap();
+    let mut speeds = node.subscribe_typed::<Speed>("vehicle/speed").await.unwrap();
+    let publisher = node.publisher::<Speed>("vehicle/speed").unwrap();
+    publisher.publish(&Speed { id: 1, speed_kph: 42.5 }).await.unwrap();
+    speeds.next().await.unwrap().unwrap();
+
+    let topics = node.list_topics().await.unwrap();
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0].topic, "vehicle/speed");
+    assert_eq!(topics[0].message_type.as_deref(), Some("mariposa.test.Speed"));
+    let schema = topics[0].schema().unwrap();
+    assert_eq!(message_type(&schema), Some("mariposa.test.Speed"));
+    assert!(schema.field_with_name("speed_kph").is_ok());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_service_call_round_trip() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let provider = connect(addr, "speedometer").await;
+    provider.register_descriptors(&speed_descriptor_set()).unwrap();
+    provider
+        .advertise_service("vehicle/double_speed", |request: Speed| async move {
+            if request.speed_kph < 0.0 {
+                anyhow::bail!("negative speed");
+            }
+            Ok(Speed {
+                id: request.id,
+                speed_kph: request.speed_kph * 2.0,
+            })
+        })
+        .await
+        .unwrap();
+    // Once the provider's own call is answered the broker knows the service
+    let request = Speed { id: 1, speed_kph: 21.0 };
+    let timeout = Duration::from_secs(5);
+    let response: Speed = provider.call("vehicle/double_speed", &request, timeout).await.unwrap();
+    assert_eq!(response.speed_kph, 42.0);
+
+    let caller = connect(addr, "planner").await;
+    caller.register_descriptors(&speed_descriptor_set()).unwrap();
+    let calls = (0..4).map(|id| {
+        let caller = caller.clone();
+        async move {
+            let request = Speed { id, speed_kph: id as f64 };
+            caller.call::<Speed, Speed>("vehicle/double_speed", &request, timeout).await.unwrap()
+        }
+    });
+    let responses = futures::future::join_all(calls).await;
+    for (id, response) in responses.iter().enumerate() {
+        assert_eq!(*response, Speed { id: id as i32, speed_kph: id as f64 * 2.0 });
+    }
+
+    let request = Speed { id: 5, speed_kph: -1.0 };
+    let error = caller.call::<Speed, Speed>("vehicle/double_speed", &request, timeout).await.unwrap_err();
+    assert!(error.to_string().contains("negative speed"), "{}", error);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_service_call_fails_without_provider_or_answer() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+
+    let caller = connect(addr, "planner").await;
+    caller.register_descriptors(&speed_descriptor_set()).unwrap();
+    let request = Speed { id: 1, speed_kph: 1.0 };
+    let error = caller
+        .call::<Speed, Speed>("camera/set_exposure", &request, Duration::from_secs(5))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("no provider"), "{}", error);
+
+    let provider = connect(addr, "camera").await;
+    provider.register_descriptors(&speed_descriptor_set()).unwrap();
+    provider
+        .advertise_service("camera/set_exposure", |request: Speed| async move {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(request)
+        })
+        .await
+        .unwrap();
+    let error = loop {
+        let error = caller
+            .call::<Speed, Speed>("camera/set_exposure", &request, Duration::from_millis(100))
+            .await
+            .unwrap_err()
+            .to_string();
+        // The advertisement may not have reached the broker yet
+        if !error.contains("no provider") {
+            break error;
+        }
+    };
+    assert!(error.contains("did not answer in time"), "{}", error);
+
+    // Calls still in flight fail once their provider goes away
+    let pending = tokio::spawn({
+        let caller = caller.clone();
+        async move {
+            caller
+                .call::<Speed, Speed>("camera/set_exposure", &request, Duration::from_secs(5))
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    provider.close().await.unwrap();
+    let error = pending.await.unwrap().unwrap_err();
+    assert!(error.to_string().contains("no provider"), "{}", error);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+/// Drive at the speed of the goal in three steps, reporting each as feedback
+#[tokio::test]
+async fn test_parameters_are_typed_and_watched() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let camera = connect(addr, "camera").await;
+    let tuner = connect(addr, "tuner").await;
+
+    let (sender, mut changes) = mpsc::unbounded_channel();
+    camera
+        .watch_parameters("/camera/*", move |name, value| {
+            let _ = sender.send((name.to_string(), value.clone()));
+        })
+        .await
+        .unwrap();
+    // Answered once the watch before it has been handled
+    assert!(camera.list_parameters("**").await.unwrap().is_empty());
+
+    tuner.set_parameter("/camera/exposure", 0.01).await.unwrap();
+    tuner.set_parameter("speed/limit_kph", 50).await.unwrap();
+    assert_eq!(changes.recv().await.unwrap(), ("camera/exposure".to_string(), ParameterValue::Float(0.01)));
+    assert_eq!(camera.get_parameter::<f64>("camera/exposure").await.unwrap(), Some(0.01));
+    assert_eq!(camera.get_parameter::<f64>("speed/limit_kph").await.unwrap(), Some(50.0));
+    assert_eq!(camera.get_parameter::<bool>("camera/enabled").await.unwrap(), None);
+    let error = format!("{:#}", camera.get_parameter::<String>("camera/exposure").await.unwrap_err());
+    assert_eq!(error, "parameter 'camera/exposure': expected a string, got float 0.01");
+
+    let error = tuner.set_parameter("camera/exposure", "auto").await.unwrap_err().to_string();
+    assert!(error.contains("holds a float, not a string"), "{}", error);
+    tuner.set_parameter("camera/exposure", 0.02).await.unwrap();
+    assert_eq!(changes.recv().await.unwrap().1, ParameterValue::Float(0.02));
+
+    let names: Vec<_> = tuner.list_parameters("**").await.unwrap().into_iter().map(|(x, _)| x).collect();
+    assert_eq!(names, ["camera/exposure", "speed/limit_kph"]);
+    assert!(tuner.get_parameter::<f64>("camera/*").await.is_err());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+async fn accelerate(goal: Speed, handle: GoalHandle<Speed>) -> anyhow::Result<Speed> {
+    if goal.speed_kph < 0.0 {
+        anyhow::bail!("cannot drive backwards");
+    }
+    for step in 1..=3 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let speed_kph = goal.speed_kph * step as f64 / 3.0;
+        handle.publish_feedback(&Speed { id: goal.id, speed_kph }).await?;
+    }
+    Ok(Speed { id: goal.id, speed_kph: goal.speed_kph })
+}
+
+async fn start_action_server(addr: SocketAddr) -> Node {
+    let server = connect(addr, "driver").await;
+    server.register_descriptors(&speed_descriptor_set()).unwrap();
+    server.advertise_action("vehicle/accelerate", accelerate).await.unwrap();
+    // Answered once the advertisements before it have been handled
+    server.list_topics().await.unwrap();
+    server
+}
+
+#[tokio::test]
+async fn test_action_reports_feedback_and_result() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let _server = start_action_server(addr).await;
+
+    let node = connect(addr, "planner").await;
+    node.register_descriptors(&speed_descriptor_set()).unwrap();
+    let client = node.action_client::<Speed, Speed, Speed>("vehicle/accelerate").await.unwrap();
+    let timeout = Duration::from_secs(5);
+    let mut goal = client.send_goal(&Speed { id: 1, speed_kph: 90.0 }, timeout).await.unwrap();
+    let mut feedback = Vec::new();
+    while let Some(speed) = goal.next_feedback().await {
+        feedback.push(speed.unwrap().speed_kph);
+    }
+    assert_eq!(feedback, [30.0, 60.0, 90.0]);
+    assert_eq!(goal.state(), GoalState::Succeeded);
+    assert_eq!(goal.result().await.unwrap(), Speed { id: 1, speed_kph: 90.0 });
+
+    let goal = client.send_goal(&Speed { id: 2, speed_kph: -1.0 }, timeout).await.unwrap();
+    let error = goal.result().await.unwrap_err().to_string();
+    assert!(error.contains("aborted") && error.contains("cannot drive backwards"), "{}", error);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+fn goal_event(id: u64, sequence: u64, columns: Vec<(&str, ArrayRef)>) -> RecordBatch {
+    let mut columns = columns;
+    columns.push((GOAL_ID_COLUMN, Arc::new(UInt64Array::from(vec![id]))));
+    columns.push((SEQUENCE_COLUMN, Arc::new(UInt64Array::from(vec![sequence]))));
+    RecordBatch::try_from_iter(columns).unwrap()
+}
+
+fn goal_status(id: u64, sequence: u64, state: &str) -> RecordBatch {
+    let state = Arc::new(StringArray::from(vec![state])) as _;
+    let message = Arc::new(StringArray::from(vec![""])) as _;
+    goal_event(id, sequence, vec![("state", state), ("message", message)])
+}
+
+fn speed_event(id: u64, sequence: u64, speed_kph: f64) -> RecordBatch {
+    let ids = Arc::new(Int32Array::from(vec![1])) as _;
+    let speeds = Arc::new(Float64Array::from(vec![speed_kph])) as _;
+    goal_event(id, sequence, vec![("id", ids), ("speed_kph", speeds)])
+}
+
+fn request_goal_id(request: &RecordBatch) -> u64 {
+    request.column_by_name(GOAL_ID_COLUMN).unwrap().as_primitive::<UInt64Type>().value(0)
+}
+
+/// An action server whose feedback at sequence 2 always gets lost, and so
+/// does the terminal status of every goal but the first
+async fn start_lossy_action_server(addr: SocketAddr) -> Node {
+    let server = connect(addr, "driver").await;
+    let goals = Arc::new(AtomicUsize::new(0));
+    let send_goal: ServiceHandler = {
+        let server = server.clone();
+        Arc::new(move |request| {
+            let id = request_goal_id(&request);
+            let first = goals.fetch_add(1, Ordering::Relaxed) == 0;
+            let server = server.clone();
+            tokio::spawn(async move {
+                server.publish("vehicle/accelerate/status", goal_status(id, 1, "executing")).await.unwrap();
+                server.publish("vehicle/accelerate/feedback", speed_event(id, 3, 60.0)).await.unwrap();
+                server.publish("vehicle/accelerate/result", speed_event(id, 4, 90.0)).await.unwrap();
+                if first {
+                    server.publish("vehicle/accelerate/status", goal_status(id, 5, "succeeded")).await.unwrap();
+                }
+            });
+            Box::pin(async move { Ok(goal_status(id, 0, "accepted")) })
+        })
+    };
+    let status: ServiceHandler =
+        Arc::new(|request| Box::pin(async move { Ok(goal_status(request_goal_id(&request), 0, "succeeded")) }));
+    server.advertise_batch_service("vehicle/accelerate/send_goal", send_goal).await.unwrap();
+    server.advertise_batch_service("vehicle/accelerate/goal_status", status).await.unwrap();
+    server.list_topics().await.unwrap();
+    server
+}
+
+#[tokio::test]
+async fn test_action_goals_survive_dropped_events() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let _server = start_lossy_action_server(addr).await;
+
+    let node = connect(addr, "planner").await;
+    node.register_descriptors(&speed_descriptor_set()).unwrap();
+    let client = node.action_client::<Speed, Speed, Speed>("vehicle/accelerate").await.unwrap();
+    let timeout = Duration::from_secs(5);
+
+    // The missing feedback is skipped once the goal has ended
+    let mut goal = client.send_goal(&Speed { id: 1, speed_kph: 90.0 }, timeout).await.unwrap();
+    assert_eq!(goal.next_feedback().await.unwrap().unwrap().speed_kph, 60.0);
+    assert!(goal.next_feedback().await.is_none());
+    assert_eq!(goal.state(), GoalState::Succeeded);
+    assert_eq!(goal.result().await.unwrap(), Speed { id: 1, speed_kph: 90.0 });
+
+    // Without a terminal status, the client asks the server how the goal went
+    let goal = client.send_goal(&Speed { id: 1, speed_kph: 90.0 }, timeout).await.unwrap();
+    let result = tokio::time::timeout(timeout, goal.result()).await.unwrap();
+    assert_eq!(result.unwrap(), Speed { id: 1, speed_kph: 90.0 });
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_action_goal_can_be_canceled() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let _server = start_action_server(addr).await;
+
+    let node = connect(addr, "planner").await;
+    node.register_descriptors(&speed_descriptor_set()).unwrap();
+    let client = node.action_client::<Speed, Speed, Speed>("vehicle/accelerate").await.unwrap();
+    let timeout = Duration::from_secs(5);
+    let mut goal = client.send_goal(&Speed { id: 1, speed_kph: 90.0 }, timeout).await.unwrap();
+    assert_eq!(goal.next_feedback().await.unwrap().unwrap().speed_kph, 30.0);
+    goal.cancel(timeout).await.unwrap();
+    // Feedback published before the cancel took effect may still arrive
+    while goal.next_feedback().await.is_some() {}
+    assert_eq!(goal.state(), GoalState::Canceled);
+
+    // Goals that have ended cannot be canceled again
+    let error = goal.cancel(timeout).await.unwrap_err().to_string();
+    assert!(error.contains("not active"), "{}", error);
+    let error = goal.result().await.unwrap_err().to_string();
+    assert!(error.contains("was canceled"), "{}", error);
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_local_subscription_reads_shared_memory() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let dir = tempfile::tempdir().unwrap();
+    let config = |name: &str| NodeConfig {
+        broker_addr: addr.to_string(),
+        name: name.to_string(),
+        shm_dir: Some(dir.path().to_path_buf()),
+        shm_capacity: 1024 * 1024,
+        ..NodeConfig::default()
+    };
+    let camera = Node::connect_with(config("camera")).await.unwrap();
+    let tracker = Node::connect_with(config("tracker")).await.unwrap();
+    let remote = connect(addr, "remote").await;
+    let mut forwarded = subscribe_channel(&remote, "camera/front").await;
+
+    // No ring exists yet, so this one goes through the broker
+    let mut over_tcp = tracker.subscribe_local("camera/front").await.unwrap();
+    assert!(!over_tcp.is_shared_memory());
+
+    let publisher = camera.shm_publisher("camera/front").unwrap();
+    assert!(camera.shm_publisher("camera/front").is_err());
+    let mut local = tracker.subscribe_local("camera/front").await.unwrap();
+    assert!(local.is_shared_memory());
+
+    publisher.publish(id_batch(1)).await.unwrap();
+    let received = local.next().await.unwrap().unwrap();
+    assert_eq!(received.topic, "camera/front");
+    assert_eq!(received.publisher_id, camera.client_id());
+    assert_eq!(received.batch, id_batch(1));
+
+    // The batch is forwarded for everyone else
+    assert_eq!(next(&mut forwarded).await.batch, id_batch(1));
+    assert_eq!(over_tcp.next().await.unwrap().unwrap().batch, id_batch(1));
+
+    // The stream ends once the publisher is gone
+    drop(publisher);
+    assert!(local.next().await.is_none());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_node_over_unix_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let endpoint = format!("unix://{}", dir.path().join("mariposa.sock").display());
+    let broker = Broker::bind(BrokerConfig {
+        bind_addr: endpoint.clone(),
+        ..BrokerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        broker
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+    });
+
+    let node = Node::connect(&endpoint, "autopilot").await.unwrap();
+    let mut receiver = subscribe_channel(&node, "vehicle/speed").await;
+    node.publish("vehicle/speed", id_batch(4)).await.unwrap();
+    let received = next(&mut receiver).await;
+    assert_eq!(received.publisher_id, node.client_id());
+    assert_eq!(received.batch, id_batch(4));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_udp_subscription_filters_topics() {
+    let mut telemetry = UdpSubscription::bind("127.0.0.1:0", "telemetry/*").await.unwrap();
+    let broker = Broker::bind(BrokerConfig {
+        bind_addr: "127.0.0.1:0".to_string(),
+        udp_target: Some(telemetry.local_addr().to_string()),
+        best_effort_topics: vec!["#".to_string()],
+        ..BrokerConfig::default()
+    })
+    .await
+    .unwrap();
+    let addr = broker.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        broker
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+    });
+
+    let node = connect(addr, "autopilot").await;
+    node.publish("mission/state", id_batch(1)).await.unwrap();
+    node.publish("telemetry/battery", id_batch(2)).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), telemetry.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(received.topic, "telemetry/battery");
+    assert_eq!(received.publisher_id, node.client_id());
+    assert_eq!(received.batch, id_batch(2));
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_recorder_writes_topics_to_log_files() {
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let dir = tempfile::tempdir().unwrap();
+
+    let node = connect(addr, "recorder").await;
+    let recorder = node.record(&["vehicle/*", "*/speed"], LogConfig::new(dir.path())).await.unwrap();
+    // The recorder subscribed before this one, so it is active once our own
+    // publish comes back
+    let mut received = subscribe_channel(&node, "vehicle/*").await;
+    node.publish("vehicle/sync", id_batch(0)).await.unwrap();
+    next(&mut received).await;
+
+    let publisher = connect(addr, "camera").await;
+    publisher.publish("mission/state", id_batch(3)).await.unwrap();
+    // One at a time, so the broker does not merge them into a single batch
+    for id in [1, 2] {
+        publisher.publish("vehicle/speed", id_batch(id)).await.unwrap();
+        next(&mut received).await;
+    }
+
+    let segments = recorder.stop().await.unwrap();
+    assert_eq!(segments.len(), 1);
+    let file = std::fs::File::open(segments[0].join("vehicle%2Fspeed.arrow")).unwrap();
+    let reader = FileReader::try_new(file, None).unwrap();
+    assert_eq!(reader.schema().metadata()[TOPIC_KEY], "vehicle/speed");
+    let ids: Vec<_> = reader
+        .map(|x| x.unwrap().column_by_name("id").unwrap().clone())
+        .map(|x| x.as_any().downcast_ref::<Int32Array>().unwrap().value(0))
+        .collect();
+    assert_eq!(ids, vec![1, 2]);
+    assert!(segments[0].join("vehicle%2Fsync.arrow").exists());
+    assert!(!segments[0].join("mission%2Fstate.arrow").exists());
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_player_republishes_recorded_window() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut writer = LogWriter::create(LogConfig::new(dir.path())).unwrap();
+    let ms = 1_000_000;
+    for (topic, time, id) in [
+        ("vehicle/speed", 0, 1),
+        ("vehicle/speed", 50, 2),
+        ("mission/state", 75, 3),
+        ("vehicle/speed", 100, 4),
+        ("vehicle/speed", 150, 5),
+    ] {
+        let entry = LogEntry {
+            topic: topic.to_string(),
+            publisher_id: 9,
+            publish_time_ns: time * ms,
+            log_time_ns: time * ms,
+            batch: id_batch(id),
+        };
+        writer.write(&entry).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let (addr, stop, task) = start_broker("127.0.0.1:0").await;
+    let subscriber = connect(addr, "perception").await;
+    let mut received = subscribe_channel(&subscriber, "#").await;
+    subscriber.publish("replay/sync", id_batch(0)).await.unwrap();
+    next(&mut received).await;
+
+    let player = connect(addr, "player").await;
+    let config = PlayerConfig {
+        rate: Some(2.0),
+        start: Duration::from_millis(50),
+        end: Some(Duration::from_millis(120)),
+        remap: [("vehicle/speed".to_string(), "replay/speed".to_string())].into(),
+        ..PlayerConfig::default()
+    };
+    let started = std::time::Instant::now();
+    assert_eq!(player.play(dir.path(), config).await.unwrap(), 3);
+    // 50ms of the recording between the first and last message, at twice the speed
+    assert!(started.elapsed() >= Duration::from_millis(25));
+    let topics: Vec<_> = [next(&mut received).await, next(&mut received).await, next(&mut received).await]
+        .into_iter()
+        .map(|x| (x.topic, x.batch))
+        .collect();
+    assert_eq!(
+        topics,
+        vec![
+            ("replay/speed".to_string(), id_batch(2)),
+            ("mission/state".to_string(), id_batch(3)),
+            ("replay/speed".to_string(), id_batch(4)),
+        ]
+    );
+
+    // Looping as fast as possible keeps going until dropped
+    let config = PlayerConfig {
+        rate: None,
+        topics: vec!["mission/*".to_string()],
+        looped: true,
+        ..PlayerConfig::default()
+    };
+    let looping = tokio::spawn(async move { player.play(dir.path(), config).await });
+    for _ in 0..3 {
+        assert_eq!(next(&mut received).await.topic, "mission/state");
+    }
+    looping.abort();
+
+    stop.send(()).unwrap();
+    task.await.unwrap();
+}
+
+fn received(topic: &str) -> Received {
+    let ids = Arc::new(Int32Array::from(vec![1])) as _;
+    Received {
+        topic: topic.to_string(),
+        publisher_id: 1,
+        timestamp_ns: 0,
+        batch: RecordBatch::try_from_iter([("id", ids)]).unwrap(),
+    }
+}
+
+fn counting_handler(count: &Arc<AtomicUsize>) -> Sink {
+    let count = count.clone();
+    Sink::Callback(Box::new(move |_| {
+        count.fetch_add(1, Ordering::Relaxed);
+    }))
+}
+
+#[test]
+fn test_dispatch_tagged_batch_to_each_subscription() {
+    let mut dispatcher = Dispatcher::new();
+    let wildcard = Arc::new(AtomicUsize::new(0));
+    let exact = Arc::new(AtomicUsize::new(0));
+    let other = Arc::new(AtomicUsize::new(0));
+    let wildcard_id = dispatcher.add("topic/*", counting_handler(&wildcard)).unwrap();
+    let exact_id = dispatcher.add("topic/a", counting_handler(&exact)).unwrap();
+    dispatcher.add("topic/b", counting_handler(&other)).unwrap();
+    let (sender, _receiver) = mpsc::channel(1);
+    let stream_id = dispatcher.add("topic/a", Sink::Stream(sender)).unwrap();
+
+    let streams = dispatcher.dispatch(&[wildcard_id, exact_id, stream_id], &received("topic/a"));
+    assert_eq!(streams.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![stream_id]);
+    assert_eq!(wildcard.load(Ordering::Relaxed), 1);
+    assert_eq!(exact.load(Ordering::Relaxed), 1);
+    assert_eq!(other.load(Ordering::Relaxed), 0);
+
+    // Removed subscriptions are skipped
+    assert!(dispatcher.remove(exact_id));
+    dispatcher.dispatch(&[wildcard_id, exact_id], &received("topic/a"));
+    assert_eq!(wildcard.load(Ordering::Relaxed), 2);
+    assert_eq!(exact.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_dispatch_untagged_batch_by_pattern() {
+    let mut dispatcher = Dispatcher::new();
+    let wildcard = Arc::new(AtomicUsize::new(0));
+    let other = Arc::new(AtomicUsize::new(0));
+    dispatcher.add("topic/**", counting_handler(&wildcard)).unwrap();
+    dispatcher.add("other", counting_handler(&other)).unwrap();
+
+    assert!(dispatcher.dispatch(&[], &received("topic/a/b")).is_empty());
+    assert_eq!(wildcard.load(Ordering::Relaxed), 1);
+    assert_eq!(other.load(Ordering::Relaxed), 0);
+    assert!(dispatcher.add("topic/**/a", counting_handler(&other)).is_err());
+}
+
+#[test]
+fn test_dispatch_hands_back_shared_streams_once() {
+    let mut dispatcher = Dispatcher::new();
+    let (sender, _receiver) = mpsc::channel(1);
+    let wildcard_id = dispatcher.add("vehicle/*", Sink::Stream(sender.clone())).unwrap();
+    let exact_id = dispatcher.add("*/speed", Sink::Stream(sender)).unwrap();
+
+    assert_eq!(dispatcher.dispatch(&[wildcard_id, exact_id], &received("vehicle/speed")).len(), 1);
+    assert_eq!(dispatcher.dispatch(&[], &received("vehicle/speed")).len(), 1);
+}
@@ -0,0 +1,122 @@
+use anyhow::{anyhow, bail, Result};
+use arrow::record_batch::RecordBatch;
+use arrow::datatypes::SchemaRef;
+use futures::Stream;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::dispatch::Received;
+use crate::stream::SubscriptionStream;
+use mariposa_core::ptars::{message_type, MessageHandler};
+
+/// Messages of type `T` decoded from the rows of a subscription's batches
+///
+/// A batch whose schema does not fit `T` yields an error instead of its rows,
+/// and the stream carries on with the next batch.
+pub struct TypedStream<T> {
+    inner: SubscriptionStream,
+    handler: MessageHandler,
+    message_type: String,
+    /// Schema the converter produces for `T`, to check untagged batches against
+    schema: SchemaRef,
+    pending: VecDeque<T>,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T: prost::Message + Default> TypedStream<T> {
+    pub(crate) fn new(inner: SubscriptionStream, handler: MessageHandler, message_type: String) -> Self {
+        let schema = handler.slice_to_record_batch(&[]).schema();
+        Self {
+            inner,
+            handler,
+            message_type,
+            schema,
+            pending: VecDeque::new(),
+            _message: PhantomData,
+        }
+    }
+
+    pub fn subscription_id(&self) -> u32 {
+        self.inner.subscription_id()
+    }
+
+    fn decode(&self, received: &Received) -> Result<Vec<T>> {
+        self.check_schema(received)?;
+        self.handler
+            .record_batch_to_array(&received.batch)
+            .iter()
+            .map(|x| T::decode(x.as_slice()).map_err(|e| anyhow!("failed to decode '{}' row: {}", self.message_type, e)))
+            .collect()
+    }
+
+    fn check_schema(&self, received: &Received) -> Result<()> {
+        let batch: &RecordBatch = &received.batch;
+        if let Some(name) = message_type(&batch.schema()) {
+            if name != self.message_type {
+                bail!(
+                    "topic '{}' carries '{}' messages, expected '{}'",
+                    received.topic,
+                    name,
+                    self.message_type
+                );
+            }
+        }
+        // Untagged batches, and tagged ones from an older definition of the
+        // message, still have to line up column by column
+        let schema = batch.schema();
+        for expected in self.schema.fields() {
+            match schema.field_with_name(expected.name()) {
+                Ok(field) if field.data_type() == expected.data_type() => {}
+                Ok(field) => bail!(
+                    "column '{}' on topic '{}' is {}, but '{}' needs {}",
+                    field.name(),
+                    received.topic,
+                    field.data_type(),
+                    self.message_type,
+                    expected.data_type()
+                ),
+                // Fields the codec can leave unset take their defaults
+                Err(_) if expected.is_nullable() => {}
+                Err(_) => bail!(
+                    "topic '{}' has no column '{}', which '{}' requires",
+                    received.topic,
+                    expected.name(),
+                    self.message_type
+                ),
+            }
+        }
+        if let Some(field) = schema.fields().iter().find(|x| self.schema.field_with_name(x.name()).is_err()) {
+            bail!(
+                "column '{}' on topic '{}' is not a field of '{}'",
+                field.name(),
+                received.topic,
+                self.message_type
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<T: prost::Message + Default + Unpin> Stream for TypedStream<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(message)));
+            }
+            let received = match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(received))) => received,
+            };
+            match self.decode(&received) {
+                Ok(messages) => self.pending.extend(messages),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
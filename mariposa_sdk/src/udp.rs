@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Result};
+use futures::Stream;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::dispatch::Received;
+use mariposa_core::topic::{pattern_matches, validate_pattern};
+use mariposa_core::udp::UdpSubscriber;
+
+/// Batches waiting to be taken from a UDP subscription before newer ones are dropped
+const QUEUE_LEN: usize = 64;
+
+/// Batches on best-effort topics received over UDP, straight from the
+/// broker's unicast or multicast target rather than through a connection
+///
+/// Batches that do not fit the queue are dropped instead of slowing the
+/// socket down. An error item reports that batches were lost on the way.
+pub struct UdpSubscription {
+    local_addr: SocketAddr,
+    receiver: mpsc::Receiver<Result<Received>>,
+    task: JoinHandle<()>,
+}
+
+impl UdpSubscription {
+    /// Listen on `addr` for batches on topics matching `pattern`, joining the
+    /// group if `addr` is a multicast address such as `239.255.0.1:7401`
+    pub async fn bind(addr: &str, pattern: &str) -> Result<Self> {
+        validate_pattern(pattern)?;
+        let subscriber = UdpSubscriber::bind(addr.parse()?).await?;
+        let local_addr = subscriber.local_addr()?;
+        let (sender, receiver) = mpsc::channel(QUEUE_LEN);
+        let task = tokio::spawn(receive_loop(subscriber, pattern.to_string(), sender));
+        Ok(Self {
+            local_addr,
+            receiver,
+            task,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Stream for UdpSubscription {
+    type Item = Result<Received>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for UdpSubscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn receive_loop(mut subscriber: UdpSubscriber, pattern: String, sender: mpsc::Sender<Result<Received>>) {
+    let mut dropped = 0;
+    loop {
+        let item = match subscriber.recv().await {
+            Ok((header, _)) if !pattern_matches(&pattern, &header.topic) => continue,
+            Ok((header, batch)) => Ok(Received {
+                topic: header.topic,
+                publisher_id: header.publisher_id,
+                timestamp_ns: header.timestamp_ns,
+                batch,
+            }),
+            Err(e) => Err(e),
+        };
+        if subscriber.dropped() > dropped {
+            let lost = subscriber.dropped() - dropped;
+            dropped = subscriber.dropped();
+            let _ = sender.try_send(Err(anyhow!("{} incomplete batches were dropped", lost)));
+        }
+        let failed = item.is_err();
+        if sender.try_send(item).is_err() && sender.is_closed() {
+            return;
+        }
+        // A socket error will not go away by itself
+        if failed {
+            return;
+        }
+    }
+}
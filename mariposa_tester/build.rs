@@ -10,6 +10,9 @@ fn main() -> Result<()> {
     // Save the file descriptor set for use with prost-reflect
     config.file_descriptor_set_path(out_dir.join("tester.bin"));
     
+    // Implement prost::Name so the messages can be published through mariposa_sdk
+    config.enable_type_names();
+
     // Compile the proto files
     config.compile_protos(&["tester_proto/tester.proto"], &["tester_proto/"])?;
     